@@ -0,0 +1,243 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+
+/// Name of the flash manifest in a bundle.
+const MANIFEST_NAME: &str = "manifest.toml";
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map_or(false, |e| e.eq_ignore_ascii_case(extension))
+}
+
+/// Decompress the `contents` of `path` if it's a `.gz` file, returns the
+/// name of the file without the `.gz` extension, e.g.: `app.hex` for
+/// `app.hex.gz`, so the format of the firmware can be told from it.
+fn decompress(path: &Path, contents: Vec<u8>) -> Result<(PathBuf, Vec<u8>)> {
+    if !has_extension(path, "gz") {
+        return Ok((path.to_path_buf(), contents));
+    }
+
+    let mut data = Vec::new();
+    GzDecoder::new(contents.as_slice())
+        .read_to_end(&mut data)
+        .with_context(|| format!("Couldn't decompress `{}`", path.display()))?;
+    log::info!(
+        "Decompressed `{}` ({} bytes)",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        data.len()
+    );
+
+    Ok((path.with_extension(""), data))
+}
+
+/// Path given to read the firmware from the standard input.
+pub const STDIN: &str = "-";
+
+/// Whether the firmware is read from the standard input.
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN)
+}
+
+/// The URL to download the firmware from, if `path` is an HTTP(S) URL.
+pub fn url(path: &Path) -> Option<&str> {
+    path.to_str().filter(|path| {
+        path.starts_with("http://") || path.starts_with("https://")
+    })
+}
+
+/// Read the firmware at `path`, a file, an HTTP(S) URL or the standard
+/// input, checking its SHA-256 digest if `sha256` is given. The digest is
+/// required for URLs.
+fn read_raw(path: &Path, sha256: Option<&str>) -> Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    if is_stdin(path) {
+        io::stdin()
+            .read_to_end(&mut contents)
+            .context("Failed to read the firmware from the standard input")?;
+    } else if let Some(url) = url(path) {
+        if sha256.is_none() {
+            bail!(
+                "Downloading `{}` needs its --sha256 digest to check it",
+                url
+            );
+        }
+        download(url, &mut contents)?;
+    } else {
+        let mut file = File::open(path).with_context(|| {
+            format!("Couldn't open firmware file: `{}`", path.display())
+        })?;
+        file.read_to_end(&mut contents)
+            .context("Failed to read firmware file contents")?;
+    }
+
+    if let Some(expected) = sha256 {
+        let digest = sha256_hex(&contents);
+        if !digest.eq_ignore_ascii_case(expected) {
+            bail!(
+                "The SHA-256 digest of `{}` is {}, expected {}",
+                path.display(),
+                digest,
+                expected
+            );
+        }
+        log::info!("SHA-256 digest of `{}` matches", path.display());
+    }
+
+    Ok(contents)
+}
+
+/// Download the firmware at `url` into `contents`.
+#[cfg(feature = "download")]
+fn download(url: &str, contents: &mut Vec<u8>) -> Result<()> {
+    log::info!("Downloading `{}`", url);
+    ureq::get(url)
+        .call()
+        .with_context(|| format!("Couldn't download `{}`", url))?
+        .into_reader()
+        .read_to_end(contents)
+        .with_context(|| format!("Couldn't download `{}`", url))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "download"))]
+fn download(url: &str, _contents: &mut Vec<u8>) -> Result<()> {
+    bail!(
+        "Can't download `{}`, ti-sbl-prog was built without the `download` feature",
+        url
+    )
+}
+
+/// SHA-256 digest of the `data`, in lowercase hexadecimal.
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Name of the firmware at `path`, without the query of a URL, so the format
+/// of the firmware can be told from its extension.
+fn name(path: &Path) -> PathBuf {
+    match url(path) {
+        Some(url) => {
+            PathBuf::from(url.split(|c| c == '?' || c == '#').next().unwrap())
+        }
+        None => path.to_path_buf(),
+    }
+}
+
+/// Read the firmware file at `path`, decompressing it if it's a `.gz` file.
+/// `path` can also be an HTTP(S) URL, or `-` to read the firmware from the
+/// standard input. The SHA-256 digest of the file, before decompressing it,
+/// is checked if `sha256` is given.
+pub fn read_file(
+    path: &Path,
+    sha256: Option<&str>,
+) -> Result<(PathBuf, Vec<u8>)> {
+    let contents = read_raw(path, sha256)?;
+    decompress(&name(path), contents)
+}
+
+/// A `.zip` release bundle, with the firmware files and optionally a flash
+/// manifest (`manifest.toml`), decompressed in memory.
+#[derive(Debug)]
+pub struct Bundle {
+    path: PathBuf,
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl Bundle {
+    /// Whether the file at `path` is a bundle.
+    pub fn is_bundle(path: &Path) -> bool {
+        has_extension(&name(path), "zip")
+    }
+
+    /// Open the bundle at `path`, a file or an HTTP(S) URL, and decompress
+    /// its files. Its SHA-256 digest is checked if `sha256` is given.
+    pub fn open(path: &Path, sha256: Option<&str>) -> Result<Bundle> {
+        let contents = read_raw(path, sha256)?;
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(contents))
+            .with_context(|| {
+                format!("Invalid zip file `{}`", path.display())
+            })?;
+
+        let mut files = BTreeMap::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if file.is_dir() {
+                continue;
+            }
+
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents).with_context(|| {
+                format!("Couldn't decompress `{}`", file.name())
+            })?;
+            files.insert(PathBuf::from(file.name()), contents);
+        }
+        log::info!(
+            "Bundle `{}` ({} files)",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            files.len()
+        );
+
+        Ok(Bundle {
+            path: path.to_path_buf(),
+            files,
+        })
+    }
+
+    /// Path of the flash manifest in the bundle.
+    pub fn manifest(&self) -> Option<&Path> {
+        self.files
+            .keys()
+            .find(|path| {
+                path.file_name().map_or(false, |name| name == MANIFEST_NAME)
+            })
+            .map(PathBuf::as_path)
+    }
+
+    /// The firmware files of the bundle, everything but the manifest.
+    pub fn images(&self) -> Vec<PathBuf> {
+        self.files
+            .keys()
+            .filter(|path| Some(path.as_path()) != self.manifest())
+            .cloned()
+            .collect()
+    }
+
+    /// Read the file at `path` of the bundle, decompressing it if it's a
+    /// `.gz` file.
+    pub fn read_file(&self, path: &Path) -> Result<(PathBuf, Vec<u8>)> {
+        match self.files.get(path) {
+            Some(contents) => decompress(path, contents.clone()),
+            None => bail!(
+                "`{}` isn't in the bundle `{}`",
+                path.display(),
+                self.path.display()
+            ),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use serial::SerialPort;
+use ti_sbl::cc32xx::Bootloader;
+
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+
+/// CC32xx subcommand entry point.
+pub fn cc32xx(port_name: &str, args: &ArgMatches<'_>) -> Result<()> {
+    log::info!("Opening serial port `{}`", port_name);
+    let mut port = serial::open(port_name).with_context(|| {
+        format!("Couldn't open serial port `{}`", port_name)
+    })?;
+    // Formatting the serial flash takes a few seconds.
+    port.set_timeout(Duration::from_secs(10))?;
+    port.configure(&ti_sbl::cc32xx::port_settings())?;
+
+    log::info!("Breaking into the bootloader");
+    let mut bootloader = Bootloader::new(port)
+        .context("Couldn't break into the CC32xx bootloader")?;
+    let version = bootloader
+        .version()
+        .context("Couldn't read the bootloader version")?;
+    log::info!("Bootloader version: {:02X?}", version);
+
+    if let Some(size) = args.value_of("format") {
+        let size = size.parse().context("Invalid --format size")?;
+        log::info!("Formatting the serial flash ({} KB)", size);
+        bootloader
+            .format_flash(size)
+            .context("Couldn't format the serial flash")?;
+    }
+
+    if let Some(path) = args.value_of("BIN") {
+        let name = args.value_of("file").unwrap();
+        let data = std::fs::read(path)
+            .with_context(|| format!("Couldn't read `{}`", path))?;
+        log::info!("Writing {} bytes to `{}`", data.len(), name);
+        bootloader
+            .write_file(name, &data)
+            .with_context(|| format!("Couldn't write `{}`", name))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,459 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{self, BufRead, Write};
+
+use serial::SystemPort;
+use ti_sbl::{
+    ccfg::{BootloaderConfig, Ccfg, CCFG_SIZE, PROT_SECTORS},
+    Device,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ArgMatches;
+
+use crate::{
+    check_diagnostics, connect, format_addr, format_ble_addr, read_error,
+    save_memory,
+};
+
+/// CCFG subcommand entry point.
+pub fn ccfg(global: &ArgMatches<'_>, args: &ArgMatches<'_>) -> Result<()> {
+    match args.subcommand() {
+        ("dump", Some(m)) => {
+            let mut device = connect(global)?;
+            dump(m, &mut device)?;
+            check_diagnostics(&device);
+            Ok(())
+        }
+        ("set", Some(m)) => set(global, m),
+        ("write", Some(m)) => write(global, m),
+        ("generate", Some(m)) => generate(m),
+        _ => {
+            println!("Error: Sub-command required");
+            println!("{}", args.usage());
+            Ok(())
+        }
+    }
+}
+
+fn dump(args: &ArgMatches<'_>, device: &mut Device<SystemPort>) -> Result<()> {
+    let ccfg = ti_sbl::util::read_ccfg(device)
+        .map_err(|e| read_error(device, e))
+        .context("Couldn't read the CCFG")?;
+
+    if let Some(path) = args.value_of("output") {
+        let flash_size = ti_sbl::util::read_flash_size(device)?;
+        let address = device.family().ccfg_address(flash_size).unwrap();
+        save_memory(path, address, &ccfg.to_bytes())?;
+        log::info!("CCFG saved to `{}`", path);
+    }
+
+    print_ccfg(&ccfg);
+    if let Err(e) = ccfg.validate() {
+        log::warn!("{}", e);
+    }
+
+    Ok(())
+}
+
+fn set(global: &ArgMatches<'_>, args: &ArgMatches<'_>) -> Result<()> {
+    let fields = args.values_of("FIELDS").unwrap().collect::<Vec<_>>();
+    let force = args.is_present("force");
+
+    if let Some(path) = args.value_of("image") {
+        let output = args.value_of("output").unwrap_or(path);
+        return patch_file(path, output, &fields, force);
+    }
+
+    let mut device = connect(global)?;
+    let old = ti_sbl::util::read_ccfg(&mut device)
+        .context("Couldn't read the CCFG")?;
+    let new = patch(&old, &fields, force)?;
+    if new == old {
+        log::info!("The CCFG is already up to date");
+        return Ok(());
+    }
+
+    if !args.is_present("yes")
+        && !confirm("Rewrite the CCFG of the device? [y/N] ")?
+    {
+        bail!("Cancelled");
+    }
+
+    write_ccfg(&mut device, &new)?;
+    check_diagnostics(&device);
+
+    Ok(())
+}
+
+fn generate(args: &ArgMatches<'_>) -> Result<()> {
+    let config_path = args.value_of("CONFIG").unwrap();
+    let config = std::fs::read_to_string(config_path)
+        .with_context(|| format!("Couldn't read `{}`", config_path))?;
+    let fields = config_fields(&config).with_context(|| {
+        format!("Invalid CCFG description `{}`", config_path)
+    })?;
+    let fields = fields.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let path = args
+        .value_of("image")
+        .or_else(|| args.value_of("base"))
+        .unwrap();
+    let output = args.value_of("output").unwrap_or(path);
+    patch_file(path, output, &fields, args.is_present("force"))
+}
+
+/// Convert a TOML description of the CCFG into `KEY=VALUE` fields, the
+/// keys are the ones of `ccfg set`, e.g.:
+///
+/// ```toml
+/// backdoor = true
+/// backdoor-pin = 13
+/// backdoor-level = "low"
+/// jtag = false
+/// protect = [0, 1]
+/// ```
+fn config_fields(config: &str) -> Result<Vec<String>> {
+    let table = config.parse::<toml::Value>()?;
+    let table = table
+        .as_table()
+        .ok_or_else(|| anyhow!("Expected a table"))?;
+
+    let mut fields = Vec::new();
+    for (key, value) in table {
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+
+        for value in values {
+            let value = match value {
+                toml::Value::Boolean(true) => "on".to_string(),
+                toml::Value::Boolean(false) => "off".to_string(),
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::String(value) => value.clone(),
+                _ => bail!("Invalid value of `{}`", key),
+            };
+            fields.push(format!("{}={}", key, value));
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Patch the CCFG at the end of the `path` file (an image or a raw CCFG)
+/// with the `fields`, saving the result to `output`.
+fn patch_file(
+    path: &str,
+    output: &str,
+    fields: &[&str],
+    force: bool,
+) -> Result<()> {
+    let mut image = std::fs::read(path)
+        .with_context(|| format!("Couldn't read `{}`", path))?;
+    if image.len() < CCFG_SIZE {
+        bail!("`{}` is too small to contain the CCFG", path);
+    }
+    let offset = image.len() - CCFG_SIZE;
+
+    let old = Ccfg::parse(&image[offset..])?;
+    let new = patch(&old, fields, force)?;
+    image[offset..].copy_from_slice(&new.to_bytes());
+
+    std::fs::write(output, &image)
+        .with_context(|| format!("Couldn't write `{}`", output))?;
+    log::info!("Patched CCFG saved to `{}`", output);
+
+    Ok(())
+}
+
+fn write(global: &ArgMatches<'_>, args: &ArgMatches<'_>) -> Result<()> {
+    let path = args.value_of("FILE").unwrap();
+    let data = std::fs::read(path)
+        .with_context(|| format!("Couldn't read `{}`", path))?;
+    let new = Ccfg::parse(&data).with_context(|| {
+        format!("`{}` isn't a CCFG of {} bytes", path, CCFG_SIZE)
+    })?;
+
+    let mut device = connect(global)?;
+    let old = ti_sbl::util::read_ccfg(&mut device)
+        .context("Couldn't read the CCFG")?;
+
+    let mut risks = lockout_risks(&old, &new);
+    if let Err(e) = new.validate() {
+        risks.push(format!("The CCFG is invalid: {}", e));
+    }
+    for risk in &risks {
+        log::warn!("{}", risk);
+    }
+    if !risks.is_empty() && !args.is_present("force") {
+        bail!("The new CCFG may lock you out of the device, use --force if you want to write it anyway");
+    }
+
+    if !args.is_present("yes")
+        && !confirm("Rewrite the CCFG of the device? [y/N] ")?
+    {
+        bail!("Cancelled");
+    }
+
+    write_ccfg(&mut device, &new)?;
+    check_diagnostics(&device);
+
+    Ok(())
+}
+
+fn write_ccfg(device: &mut Device<SystemPort>, ccfg: &Ccfg) -> Result<()> {
+    log::info!("Writing the CCFG");
+    ti_sbl::util::write_ccfg(device, &ccfg.to_bytes())
+        .context("Couldn't write the CCFG")
+}
+
+/// Apply the `KEY=VALUE` `fields` to a copy of the `ccfg`, refusing the
+/// changes that can lock the user out of the bootloader unless `force`.
+fn patch(ccfg: &Ccfg, fields: &[&str], force: bool) -> Result<Ccfg> {
+    let mut new = ccfg.clone();
+    for field in fields {
+        let mut split = field.splitn(2, '=');
+        let key = split.next().unwrap();
+        let value = split
+            .next()
+            .ok_or_else(|| anyhow!("Expected KEY=VALUE, found `{}`", field))?;
+        set_field(&mut new, key, value)
+            .with_context(|| format!("Invalid CCFG field `{}`", field))?;
+    }
+
+    let config = new.bootloader_config();
+    if config.backdoor_enabled && config.backdoor_pin == 0xFF {
+        bail!("The backdoor needs a pin");
+    }
+
+    for risk in lockout_risks(ccfg, &new) {
+        log::warn!("{}", risk);
+    }
+    // Moving the backdoor only warns, disabling it needs --force.
+    let disabled = !config.bootloader_enabled || !config.backdoor_enabled;
+    if disabled && !force {
+        bail!("The new CCFG may lock you out of the device, use --force if you want to write it anyway");
+    }
+
+    new.validate()?;
+
+    Ok(new)
+}
+
+/// Change the field `key` of the `ccfg` to `value`.
+fn set_field(ccfg: &mut Ccfg, key: &str, value: &str) -> Result<()> {
+    let mut config = ccfg.bootloader_config();
+    let mut taps = ccfg.tap_config();
+
+    match key {
+        "bootloader" => config.bootloader_enabled = parse_bool(value)?,
+        "backdoor" => config.backdoor_enabled = parse_bool(value)?,
+        "backdoor-pin" => config.backdoor_pin = parse_u32(value)? as u8,
+        "backdoor-level" => {
+            config.backdoor_active_high = match value {
+                "high" => true,
+                "low" => false,
+                _ => bail!("Expected `high` or `low`"),
+            }
+        }
+        "cpu-dap" => taps.cpu_dap = parse_bool(value)?,
+        "prcm-tap" => taps.prcm_tap = parse_bool(value)?,
+        "test-tap" => taps.test_tap = parse_bool(value)?,
+        "pbist1-tap" => taps.pbist1_tap = parse_bool(value)?,
+        "pbist2-tap" => taps.pbist2_tap = parse_bool(value)?,
+        "wuc-tap" => taps.wuc_tap = parse_bool(value)?,
+        "jtag" => {
+            let enabled = parse_bool(value)?;
+            taps.cpu_dap = enabled;
+            taps.prcm_tap = enabled;
+            taps.test_tap = enabled;
+            taps.pbist1_tap = enabled;
+            taps.pbist2_tap = enabled;
+            taps.wuc_tap = enabled;
+        }
+        "ti-fa" => ccfg.set_ti_fa_enabled(parse_bool(value)?),
+        "bank-erase" => ccfg.set_bank_erase_enabled(parse_bool(value)?),
+        "chip-erase" => ccfg.set_chip_erase_enabled(parse_bool(value)?),
+        "image-valid" => ccfg.set_image_valid_conf(parse_u32(value)?),
+        "ieee-address" => ccfg.set_ieee_address(parse_addr(value)?),
+        "ble-address" => ccfg.set_ble_address(parse_ble_addr(value)?),
+        "protect" | "unprotect" => {
+            let sector = parse_u32(value)?;
+            if sector >= PROT_SECTORS {
+                bail!("Sector must be lower than {}", PROT_SECTORS);
+            }
+            ccfg.set_sector_protected(sector, key == "protect");
+        }
+        _ => bail!("Unknown key"),
+    }
+
+    ccfg.set_bootloader_config(config);
+    ccfg.set_tap_config(taps);
+
+    Ok(())
+}
+
+/// Describe the changes from `old` to `new` that can leave the device
+/// without a way to enter the bootloader.
+pub fn lockout_risks(old: &Ccfg, new: &Ccfg) -> Vec<String> {
+    let old_config = old.bootloader_config();
+    let new_config = new.bootloader_config();
+    let mut risks = Vec::new();
+
+    if !new_config.bootloader_enabled {
+        risks.push("The bootloader is disabled".to_string());
+    } else if !new_config.backdoor_enabled {
+        risks.push("The bootloader backdoor is disabled".to_string());
+    } else if old_config.backdoor_enabled {
+        if old_config.backdoor_pin != new_config.backdoor_pin {
+            risks.push(format!(
+                "The bootloader backdoor pin changes from DIO{} to DIO{}",
+                old_config.backdoor_pin, new_config.backdoor_pin
+            ));
+        }
+        if old_config.backdoor_active_high != new_config.backdoor_active_high {
+            risks.push(format!(
+                "The bootloader backdoor level changes to active {}",
+                if new_config.backdoor_active_high {
+                    "high"
+                } else {
+                    "low"
+                }
+            ));
+        }
+    }
+
+    risks
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{}", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "on" | "true" | "enabled" | "1" => Ok(true),
+        "off" | "false" | "disabled" | "0" => Ok(false),
+        _ => bail!("Expected `on` or `off`"),
+    }
+}
+
+pub fn parse_u32(value: &str) -> Result<u32> {
+    let result = if value.starts_with("0x") {
+        u32::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse()
+    };
+
+    result.context("Expected a number")
+}
+
+pub fn parse_addr(value: &str) -> Result<[u8; 8]> {
+    let bytes = value
+        .split(':')
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Expected an address, e.g.: 00:12:4B:00:14:B5:B6:01")?;
+    if bytes.len() != 8 {
+        bail!("Expected 8 bytes");
+    }
+
+    let mut addr = [0u8; 8];
+    addr.copy_from_slice(&bytes);
+    Ok(addr)
+}
+
+/// Parse a BLE address, 6 bytes, the unused bytes of the CCFG field are
+/// left erased.
+pub fn parse_ble_addr(value: &str) -> Result<[u8; 8]> {
+    let bytes = value
+        .split(':')
+        .map(|byte| u8::from_str_radix(byte, 16))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Expected an address, e.g.: 80:6F:B0:EE:1D:A2")?;
+    if bytes.len() != 6 {
+        bail!("Expected 6 bytes");
+    }
+
+    let mut addr = [0xFFu8; 8];
+    addr[..6].copy_from_slice(&bytes);
+    Ok(addr)
+}
+
+/// Print the decoded fields of the `ccfg`.
+pub fn print_ccfg(ccfg: &Ccfg) {
+    let config = ccfg.bootloader_config();
+    println!("Bootloader:         {}", enabled(config.bootloader_enabled));
+    println!("Backdoor:           {}", format_backdoor(&config));
+
+    let taps = ccfg.tap_config();
+    println!("CPU DAP:            {}", enabled(taps.cpu_dap));
+    println!("PRCM TAP:           {}", enabled(taps.prcm_tap));
+    println!("TEST TAP:           {}", enabled(taps.test_tap));
+    println!("PBIST2 TAP:         {}", enabled(taps.pbist2_tap));
+    println!("PBIST1 TAP:         {}", enabled(taps.pbist1_tap));
+    println!("WUC TAP:            {}", enabled(taps.wuc_tap));
+    println!("TI FA:              {}", enabled(ccfg.ti_fa_enabled()));
+    println!("Bank erase:         {}", enabled(ccfg.bank_erase_enabled()));
+    println!("Chip erase:         {}", enabled(ccfg.chip_erase_enabled()));
+    println!("Image valid:        {:#010X}", ccfg.image_valid_conf());
+    println!("IEEE address:       {}", format_addr(ccfg.ieee_address()));
+    println!(
+        "BLE address:        {}",
+        format_ble_addr(ccfg.ble_address())
+    );
+
+    let protected = (0..PROT_SECTORS)
+        .filter(|sector| ccfg.sector_protected(*sector))
+        .map(|sector| sector.to_string())
+        .collect::<Vec<_>>();
+    if protected.is_empty() {
+        println!("Protected sectors:  none");
+    } else {
+        println!("Protected sectors:  {}", protected.join(", "));
+    }
+}
+
+/// Describe the backdoor of the bootloader, e.g.: `DIO13 active low`.
+pub fn format_backdoor(config: &BootloaderConfig) -> String {
+    if !config.backdoor_enabled {
+        return String::from("disabled");
+    }
+
+    format!(
+        "DIO{} active {}",
+        config.backdoor_pin,
+        if config.backdoor_active_high {
+            "high"
+        } else {
+            "low"
+        }
+    )
+}
+
+pub fn enabled(enabled: bool) -> &'static str {
+    if enabled {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
@@ -12,176 +12,1059 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{fs::File, io::Read, path::PathBuf};
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
 use serial::SystemPort;
 use ti_sbl::{
-    util::{Transfer, CCFG_SIZE},
-    Device, Family,
+    cca::{Cca, CCA_SIZE},
+    ccfg::{Ccfg, CCFG_SIZE},
+    firmware::{Firmware, Segment, ELF_MAGIC},
+    flasher::{Erase, Flasher, ProgressEvent},
+    mcuboot::{self, FlashMap, ImageHeader, Slot},
+    oad,
+    util::{ReadProgress, VerifyMethod},
+    Device, FlashLayout,
 };
 
 use anyhow::{bail, Context, Result};
 use clap::ArgMatches;
+
 use indicatif::{ProgressBar, ProgressStyle};
 
+use crate::{
+    bundle::{self, Bundle},
+    ccfg::parse_u32,
+    manifest::Manifest,
+    report::Report,
+};
+
 /// Flash subcommand entry point.
 pub fn flash(
+    global: &ArgMatches<'_>,
     args: &ArgMatches<'_>,
     flash_size: u32,
     device: &mut Device<SystemPort>,
+    report: &mut Report,
 ) -> Result<()> {
-    let args = FlashArgs::from_matches(args)?;
+    let args = FlashArgs::from_matches(global, args)?;
 
-    let mut binary_file = File::open(&args.binary_path).with_context(|| {
-        format!(
-            "Couldn't open firmware file: `{}`",
-            args.binary_path.display()
-        )
-    })?;
+    let mut segments = read_firmware(&args)?;
 
-    let mut binary = Vec::new();
-    binary_file
-        .read_to_end(&mut binary)
-        .context("Failed to read firmware file contents")?;
+    let layout = FlashLayout::new(device.family(), flash_size)
+        .with_sector_size(device.sector_size());
+    log::trace!("Flash layout: {:?}", layout);
+    check_layout(&layout, &segments)?;
 
-    if binary.len() > flash_size as usize {
-        bail!("Binary size is too large");
+    // Before erasing anything, e.g.: an ELF file flashed as a raw binary.
+    if let Some(first) = segments
+        .iter()
+        .find(|segment| segment.address == layout.flash().start)
+    {
+        if let Err(e) = ti_sbl::util::check_vector_table(&layout, &first.data) {
+            log::warn!(
+                "The firmware doesn't look like a {:?} image, {}",
+                device.family(),
+                e
+            );
+        }
     }
 
-    log::info!(
-        "Binary file: `{}`",
-        args.binary_path.file_name().unwrap().to_string_lossy()
-    );
-    log::info!("Binary file size: {} bytes", binary.len());
+    for (i, segment) in segments.iter().enumerate() {
+        check_downgrade(device, &args, i == 0, segment)?;
+    }
+
+    if args.set_image_valid {
+        // Only the last segment can reach the CCA.
+        if let Some(last) = segments.last_mut() {
+            set_image_valid(
+                device,
+                &layout,
+                last.address,
+                &mut last.data,
+                args.force,
+            )?;
+        }
+    }
+
+    let mut flasher = Flasher::new(device, layout.clone())
+        .erase(args.erase)
+        .verify(args.verify)
+        .keep_ccfg(args.keep_ccfg)
+        .verify_erase(args.verify_erase)
+        .skip_identical(args.skip_identical);
+
+    let plan = flasher.plan(&segments)?;
+    log::debug!("Flash plan: {}", plan);
+    // The CCFG of the device is written instead with --keep-ccfg.
+    if !args.keep_ccfg {
+        for ccfg in plan.ccfg() {
+            check_ccfg(
+                flasher.device(),
+                ccfg.data,
+                args.bl_invoke_active_high,
+                args.force,
+            )?;
+        }
+    }
+    if !plan.cca().is_empty() {
+        if !args.force && !args.set_image_valid {
+            bail!("Binary may overwrite the CCA, use --force if you want to flash it anyway");
+        }
+
+        for cca in plan.cca() {
+            check_cca(cca.data, args.bl_invoke_active_high);
+        }
+    }
+
+    report.set_images(&segments);
+
+    let backup = match args.backup {
+        Some(ref path) => Some(backup(
+            flasher.device(),
+            &layout,
+            &segments,
+            args.erase,
+            path,
+        )?),
+        None => None,
+    };
+
+    let progress_style = ProgressStyle::default_spinner()
+        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+        .template("{prefix:.bold.dim} {spinner} {wide_msg}");
+
+    let progress_bar = ProgressBar::new(100);
+    progress_bar.set_style(progress_style);
+    progress_bar.set_message("Flashing");
+
+    let percent = |done, total| {
+        if total == 0 {
+            100.0
+        } else {
+            100.0 * done as f64 / total as f64
+        }
+    };
+    let result = flasher.flash(&segments, |event: ProgressEvent| {
+        progress_bar.set_message(&match event {
+            ProgressEvent::EraseStarted { address, len } => {
+                format!("Erasing {} bytes at {:#X}", len, address)
+            }
+            ProgressEvent::SectorErased {
+                address,
+                bytes_done,
+                bytes_total,
+            } => format!(
+                "{:.1} - Erased sector {:#X} ({}/{} bytes)",
+                percent(bytes_done as usize, bytes_total as usize),
+                address,
+                bytes_done,
+                bytes_total
+            ),
+            ProgressEvent::ChunkWritten {
+                address,
+                bytes_done,
+                bytes_total,
+                ..
+            } => format!(
+                "{:.1} Writing flash, chunk {:#X} ({}/{} bytes)",
+                percent(bytes_done, bytes_total),
+                address,
+                bytes_done,
+                bytes_total
+            ),
+            ProgressEvent::VerifyProgress {
+                bytes_done,
+                bytes_total,
+            } => format!(
+                "{:.1} Verifying flash",
+                percent(bytes_done, bytes_total)
+            ),
+            ProgressEvent::Done(_) => "Flashing finished".to_string(),
+        });
+        progress_bar.inc(1);
+    });
+    progress_bar.finish_with_message("Flashing finished");
+
+    let stats = *flasher.stats();
+    if !flasher.segments().is_empty() {
+        // With the CCFG of the device, if it was kept.
+        segments = flasher.segments().to_vec();
+        report.set_images(&segments);
+    }
+    report.skipped = stats.skipped;
+    report.erase = stats.erase;
+    report.write = stats.write.map(|write| write.elapsed);
+    report.verify = stats.verify;
+    report.verified = stats.verified;
+
+    match result {
+        Ok(()) => (),
+        Err(e @ ti_sbl::Error::Verification(_)) if stats.verified.is_some() => {
+            let e = anyhow::Error::new(e).context("Couldn't verify the flash");
+            return match backup {
+                Some((address, ref data)) if args.rollback => {
+                    log::error!("{:#}", e);
+                    rollback(device, &layout, address, data)
+                        .context("Couldn't restore the backup")?;
+                    report.rolled_back = true;
+                    Err(e.context("The backup was restored"))
+                }
+                _ => Err(e),
+            };
+        }
+        Err(e @ ti_sbl::Error::Verification(_)) => {
+            return Err(e).context("The flash isn't blank after erasing it, it may be write protected or worn out");
+        }
+        Err(e @ ti_sbl::Error::ProtectedPages(_)) => {
+            return Err(e).context(
+                "Can't erase or write protected flash pages, unprotect them first",
+            );
+        }
+        Err(e) => return Err(e).context("Couldn't flash binary"),
+    }
+
+    if args.attest {
+        attest(&segments);
+    }
+
+    Ok(())
+}
+
+/// Print the SHA-256 digest of the `segments`, once they're verified, as a
+/// record of what was flashed.
+fn attest(segments: &[Segment]) {
+    for segment in segments {
+        let range = segment.range();
+        println!(
+            "{:#010X}..{:#010X} sha256:{}",
+            range.start,
+            range.end,
+            bundle::sha256_hex(&segment.data)
+        );
+    }
+}
+
+/// Check the firmware files against the flash of the device without writing
+/// anything, e.g.: to audit devices in the field.
+pub fn verify_only(
+    global: &ArgMatches<'_>,
+    args: &ArgMatches<'_>,
+    flash_size: u32,
+    device: &mut Device<SystemPort>,
+) -> Result<()> {
+    let args = FlashArgs::from_matches(global, args)?;
+    let segments = read_firmware(&args)?;
+
+    let layout = FlashLayout::new(device.family(), flash_size)
+        .with_sector_size(device.sector_size());
+    check_layout(&layout, &segments)?;
+
+    verify(
+        device,
+        &segments,
+        args.verify.unwrap_or(VerifyMethod::Crc32),
+    )
+}
+
+/// Print which sectors of the flash differ from the firmware files, without
+/// writing anything.
+pub fn diff(
+    global: &ArgMatches<'_>,
+    args: &ArgMatches<'_>,
+    flash_size: u32,
+    device: &mut Device<SystemPort>,
+) -> Result<()> {
+    let args = FlashArgs::from_matches(global, args)?;
+    let segments = read_firmware(&args)?;
+
+    let layout = FlashLayout::new(device.family(), flash_size)
+        .with_sector_size(device.sector_size());
+    check_layout(&layout, &segments)?;
+
+    let transfers = segments.iter().map(Segment::transfer).collect::<Vec<_>>();
+    let sectors = ti_sbl::util::diff_flash(device, &transfers)
+        .map_err(|e| crate::read_error(device, e))
+        .context("Couldn't compare the flash with the firmware")?;
+    for sector in &sectors {
+        println!(
+            "{:#010X}..{:#010X} {}",
+            sector.range.start,
+            sector.range.end,
+            if sector.changed { "changed" } else { "same" }
+        );
+    }
 
-    let family = device.family();
+    let changed = sectors.iter().filter(|sector| sector.changed).count();
+    if changed != 0 {
+        bail!("{} of {} sectors differ", changed, sectors.len());
+    }
+    log::info!("All {} sectors are the same", sectors.len());
 
-    if args.address < family.flash_base() {
+    Ok(())
+}
+
+/// Check that a range of the flash is erased, the whole flash if no address
+/// or length is given.
+pub fn blank_check(
+    args: &ArgMatches<'_>,
+    flash_size: u32,
+    device: &mut Device<SystemPort>,
+) -> Result<()> {
+    let range = flash_range(args, flash_size, device)?;
+    let method = if args.is_present("readback") {
+        VerifyMethod::Readback
+    } else {
+        VerifyMethod::Crc32
+    };
+    ti_sbl::util::blank_check(device, range.clone(), method)
+        .map_err(|e| match e {
+            ti_sbl::Error::Verification(_) => e.into(),
+            e => crate::read_error(device, e),
+        })
+        .context("The flash isn't blank")?;
+    log::info!("{:#X}..{:#X} is blank", range.start, range.end);
+
+    Ok(())
+}
+
+/// Read a range of the flash to a file, the whole flash if no address or
+/// length is given.
+pub fn read(
+    args: &ArgMatches<'_>,
+    flash_size: u32,
+    device: &mut Device<SystemPort>,
+) -> Result<()> {
+    let range = flash_range(args, flash_size, device)?;
+    let output = args.value_of("output").unwrap();
+
+    let progress_style = ProgressStyle::default_spinner()
+        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+        .template("{prefix:.bold.dim} {spinner} {wide_msg}");
+
+    let progress_bar = ProgressBar::new(100);
+    progress_bar.set_style(progress_style);
+    progress_bar.set_message("Reading flash");
+
+    let mut data = Vec::with_capacity((range.end - range.start) as usize);
+    ti_sbl::util::read_flash(device, range.clone(), &mut data, |progress| {
+        progress_bar.set_message(&format!(
+            "{:.1} - Reading sector {:#X}",
+            progress.percent(),
+            progress.address
+        ));
+        progress_bar.inc(1);
+    })
+    .map_err(|e| crate::read_error(device, e))
+    .context("Couldn't read the flash")?;
+    progress_bar.finish_with_message("Flash read");
+
+    crate::save_memory(output, range.start, &data)?;
+    log::info!("Saved {:#X}..{:#X} to `{}`", range.start, range.end, output);
+
+    Ok(())
+}
+
+/// The flash range given with `--address` and `--length`, from the start
+/// and up to the end of the flash by default.
+fn flash_range(
+    args: &ArgMatches<'_>,
+    flash_size: u32,
+    device: &Device<SystemPort>,
+) -> Result<Range<u32>> {
+    let layout = FlashLayout::new(device.family(), flash_size)
+        .with_sector_size(device.sector_size());
+    let flash = layout.flash();
+    let address = args
+        .value_of("address")
+        .map(parse_u32)
+        .transpose()
+        .context("Invalid --address value")?
+        .unwrap_or(flash.start);
+    let len = args
+        .value_of("length")
+        .map(parse_u32)
+        .transpose()
+        .context("Invalid --length value")?
+        .unwrap_or_else(|| flash.end.saturating_sub(address));
+    let range = address..address.saturating_add(len);
+    if !layout.contains_range(&range) {
         bail!(
-            "Start address out of range (base is: {:#X})",
-            family.flash_base()
+            "{:#X}..{:#X} is out of the flash ({:#X}..{:#X})",
+            range.start,
+            range.end,
+            flash.start,
+            flash.end
         );
     }
 
-    let overwrites_ccfg = may_overwrite_ccfg(flash_size, args.address, &binary);
+    Ok(range)
+}
+
+/// Check that the `segments` are inside of the flash.
+fn check_layout(layout: &FlashLayout, segments: &[Segment]) -> Result<()> {
+    for segment in segments {
+        if !layout.contains(segment.address) {
+            bail!(
+                "Start address {:#X} out of range (flash is: {:#X}..{:#X})",
+                segment.address,
+                layout.flash().start,
+                layout.flash().end
+            );
+        }
+        if !layout.contains_range(&segment.range()) {
+            bail!("Binary file is too large for flash (end address: {:#X}, flash size: {:#X})",
+                  segment.range().end, layout.flash_size());
+        }
+    }
 
-    if matches!(family, Family::CC26X0 | Family::CC26X2)
-        && overwrites_ccfg
-        && !args.force
+    Ok(())
+}
+
+/// Compare the `segments` on the flash with their data, using their CRC32
+/// or reading them back.
+fn verify(
+    device: &mut Device<SystemPort>,
+    segments: &[Segment],
+    method: VerifyMethod,
+) -> Result<()> {
+    log::info!("Verifying");
+    let transfers = segments.iter().map(Segment::transfer).collect::<Vec<_>>();
+    ti_sbl::util::verify_flash(device, &transfers, method)
+        .map_err(|e| match e {
+            ti_sbl::Error::Verification(_) => e.into(),
+            e => crate::read_error(device, e),
+        })
+        .context("Couldn't verify the flash")?;
+    log::info!("Flash verified");
+
+    Ok(())
+}
+
+/// Save the sectors of the flash that are going to be erased to `path`, the
+/// whole flash if it's bank erased, so it can be flashed again to restore the
+/// device. Returns the address and the contents of the backup.
+fn backup(
+    device: &mut Device<SystemPort>,
+    layout: &FlashLayout,
+    segments: &[Segment],
+    erase: Erase,
+    path: &str,
+) -> Result<(u32, Vec<u8>)> {
+    let progress_style = ProgressStyle::default_spinner()
+        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+        .template("{prefix:.bold.dim} {spinner} {wide_msg}");
+
+    let progress_bar = ProgressBar::new(100);
+    progress_bar.set_style(progress_style);
+    progress_bar.set_message("Backing up flash");
+
+    let progress = |progress: ReadProgress| {
+        progress_bar.set_message(&format!(
+            "{:.1} - Backing up sector {:#X}",
+            progress.percent(),
+            progress.address
+        ));
+        progress_bar.inc(1);
+    };
+    let mut data = Vec::new();
+    let range = if erase == Erase::Bank {
+        let range = layout.flash();
+        ti_sbl::util::read_flash(device, range.clone(), &mut data, progress)
+            .map(|()| range)
+    } else {
+        let transfers =
+            segments.iter().map(Segment::transfer).collect::<Vec<_>>();
+        ti_sbl::util::backup_flash(device, &transfers, &mut data, progress)
+    }
+    .map_err(|e| crate::read_error(device, e))
+    .context("Couldn't back up the flash")?;
+    progress_bar.finish_with_message("Flash backed up");
+
+    crate::save_memory(path, range.start, &data)?;
+    log::info!(
+        "Saved a backup of {:#X}..{:#X} to `{}`",
+        range.start,
+        range.end,
+        path
+    );
+
+    Ok((range.start, data))
+}
+
+/// Flash the `data` of a backup at `address` again, after the new firmware
+/// failed verification.
+fn rollback(
+    device: &mut Device<SystemPort>,
+    layout: &FlashLayout,
+    address: u32,
+    data: &[u8],
+) -> Result<()> {
+    let range = address..address + data.len() as u32;
+    log::warn!(
+        "Restoring the backup of {:#X}..{:#X}",
+        range.start,
+        range.end
+    );
+
+    let backup = Segment {
+        address,
+        data: data.to_vec(),
+    };
+    Flasher::new(device, layout.clone())
+        .erase(Erase::Sectors)
+        .verify(Some(VerifyMethod::Crc32))
+        .flash(&[backup], ())?;
+    log::info!("Backup restored");
+
+    Ok(())
+}
+
+/// Read the firmware files, returns the segments to flash.
+///
+/// ELF executables and Intel HEX files (`.hex`) carry the address of their
+/// segments, each one is flashed on its own. Other files are raw binaries
+/// flashed at the address given with `ADDRESS=FILE`, or `--address`. The
+/// segments are padded to 32-bit words.
+fn read_firmware(args: &FlashArgs) -> Result<Vec<Segment>> {
+    if args.images.len() > 1
+        && (args.bin_offset != 0 || args.bin_length.is_some())
     {
-        bail!("Binary may overwrite the CCFG, use --force if you want to flash it anyway");
+        bail!(
+            "--bin-offset and --bin-length can only be used with a single file"
+        );
+    }
+
+    let mut firmware = Firmware::default();
+    for (address, path) in &args.images {
+        let image = read_image(args, *address, path)?;
+        firmware.merge(image).with_context(|| {
+            format!("`{}` overlaps another file", path.display())
+        })?;
     }
 
-    if args.write_erase {
+    // The bootloader writes 32-bit words.
+    if firmware.align(4) {
+        log::info!("Padding the firmware with 0xFF to 32-bit word boundaries");
+    }
+    for segment in firmware.segments() {
         log::info!(
-            "{} bytes will be erased at start address {}",
-            binary.len(),
-            args.address
+            "Segment {:#X}..{:#X}",
+            segment.address,
+            segment.range().end
         );
+    }
+    if firmware.is_empty() {
+        bail!("The firmware file doesn't have any data");
+    }
 
-        let len = if overwrites_ccfg {
-            binary.len() - CCFG_SIZE
-        } else {
-            binary.len()
-        };
+    Ok(firmware.into_segments())
+}
 
-        let progress_style = ProgressStyle::default_spinner()
-            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-            .template("{prefix:.bold.dim} {spinner} {wide_msg}");
-
-        let progress_bar = ProgressBar::new(100);
-        progress_bar.set_style(progress_style);
-        progress_bar.set_message("Erasing sectors");
-
-        ti_sbl::util::erase_flash_range(
-            device,
-            args.address,
-            len as u32,
-            |progress, addr| {
-                progress_bar.set_message(&format!(
-                    "{:.1} - Erasing sector {:#X}",
-                    progress, addr
-                ));
-                progress_bar.inc(1);
-            },
+/// Read a single firmware file, from the bundle if there's one, a raw binary
+/// is loaded at `address` or `--address`. Files ending in `.gz` are
+/// decompressed first.
+fn read_image(
+    args: &FlashArgs,
+    address: Option<u32>,
+    path: &Path,
+) -> Result<Firmware> {
+    let (name, contents) = match args.bundle {
+        Some(ref bundle) => bundle.read_file(path)?,
+        None => bundle::read_file(path, args.sha256.as_deref())?,
+    };
+
+    log::info!(
+        "Binary file: `{}` ({} bytes)",
+        name.file_name().unwrap_or_default().to_string_lossy(),
+        contents.len()
+    );
+
+    // There's no extension on the standard input, Intel HEX records start
+    // with a colon.
+    let is_hex = name
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("hex"))
+        || (bundle::is_stdin(&name) && contents.starts_with(b":"));
+    let is_elf = contents.starts_with(&ELF_MAGIC);
+    if (is_elf || is_hex) && (args.bin_offset != 0 || args.bin_length.is_some())
+    {
+        bail!(
+            "--bin-offset and --bin-length can only be used with raw binaries"
+        );
+    }
+    if (is_elf || is_hex) && (args.address_given || address.is_some()) {
+        log::warn!(
+            "`{}` carries its address, ignoring the given address",
+            path.display()
+        );
+    }
+
+    if is_elf {
+        log::info!("Loading the segments of the ELF file");
+        return Ok(Firmware::from_elf(&contents)?);
+    }
+    if is_hex {
+        let text = String::from_utf8(contents)
+            .context("Intel HEX file isn't valid text")?;
+        return Ok(Firmware::from_hex(&text)?);
+    }
+
+    let end = match args.bin_length {
+        Some(len) => args.bin_offset.saturating_add(len as usize),
+        None => contents.len(),
+    };
+    let slice = contents.get(args.bin_offset..end).with_context(|| {
+        format!(
+            "--bin-offset and --bin-length are outside of the {} bytes of the binary file",
+            contents.len()
         )
-        .context("Couldn't erase flash")?;
+    })?;
+    let mut data = slice.to_vec();
+    let slot = if args.oad {
+        Some(prepare_oad(&mut data)?)
+    } else if let Some(slot) = args.slot {
+        Some(prepare_mcuboot(&data, slot, &args.flash_map)?)
+    } else {
+        if ImageHeader::is_mcuboot(&data) {
+            log::info!(
+                "`{}` is an MCUboot image, use --slot to flash it at its slot",
+                path.display()
+            );
+        }
+        None
+    };
 
-        progress_bar.finish_with_message("Sectors erased");
+    let explicit = match address {
+        Some(address) => Some(address),
+        None if args.address_given => Some(args.address),
+        None => None,
+    };
+    let address = match (explicit, slot) {
+        (Some(address), Some(slot)) if address != slot => {
+            log::warn!(
+                "Flashing the image at {:#X} instead of its slot at {:#X}",
+                address,
+                slot
+            );
+            address
+        }
+        (_, Some(slot)) => slot,
+        (explicit, None) => explicit.unwrap_or(args.address),
+    };
+
+    Ok(Firmware::from_binary(address, data))
+}
+
+/// Version of a firmware image, compared with the one on the device to
+/// refuse downgrades.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+enum ImageVersion {
+    /// Software version of a TI OAD header, e.g.: `0001`.
+    Oad([u8; 4]),
+    Mcuboot(mcuboot::ImageVersion),
+    /// A 32-bit number at `--version-offset`.
+    Number(u32),
+}
+
+impl std::fmt::Display for ImageVersion {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageVersion::Oad(version) => {
+                write!(fmt, "{}", String::from_utf8_lossy(version))
+            }
+            ImageVersion::Mcuboot(version) => write!(fmt, "{}", version),
+            ImageVersion::Number(version) => write!(fmt, "{}", version),
+        }
     }
+}
+
+/// Bytes read from the device to find the version of its image.
+const VERSION_READ_LEN: usize = 44;
 
-    let end_addr = args.address + binary.len() as u32;
-    if end_addr > family.flash_base() + flash_size {
-        bail!("Binary file is too large for flash (end address: {:#X}, flash size: {:#X})",
-              end_addr, flash_size);
+/// The version of the `image`, from its OAD or MCUboot header, or at
+/// `--version-offset` of the first image. `None` if it doesn't have one,
+/// e.g.: on blank flash.
+fn image_version(
+    args: &FlashArgs,
+    first: bool,
+    image: &[u8],
+) -> Option<ImageVersion> {
+    if args.oad {
+        let header = oad::OadHeader::parse(image).ok()?;
+        Some(ImageVersion::Oad(*header.software_version()))
+    } else if ImageHeader::is_mcuboot(image) {
+        let header = ImageHeader::parse(image).ok()?;
+        Some(ImageVersion::Mcuboot(header.version()))
+    } else {
+        let offset = args.version_offset.filter(|_| first)? as usize;
+        let bytes = image.get(offset..offset + 4)?;
+        let version =
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        Some(ImageVersion::Number(version)).filter(|_| version != 0xFFFF_FFFF)
     }
+}
 
-    // CCFG is sent separately, and doesn't
-    // expect an ACK in return, if the device locks itself.
-    let transfers = if matches!(family, Family::CC26X0 | Family::CC26X2)
-        && overwrites_ccfg
+/// Refuse to flash a `segment` with an older version than the image on the
+/// device at the same address, unless `--allow-downgrade` is given.
+fn check_downgrade(
+    device: &mut Device<SystemPort>,
+    args: &FlashArgs,
+    first: bool,
+    segment: &Segment,
+) -> Result<()> {
+    let new = match image_version(args, first, &segment.data) {
+        Some(new) => new,
+        None => return Ok(()),
+    };
+
+    let offset = args.version_offset.filter(|_| first).unwrap_or(0) as usize;
+    let len = (VERSION_READ_LEN.max(offset + 4) + 3) & !3;
+    let mut installed = vec![0u8; len];
+    if let Err(e) =
+        ti_sbl::util::memory_read_32(device, segment.address, &mut installed)
     {
-        debug_assert!(args.force);
+        log::warn!(
+            "Couldn't read the version of the image on the device: {}",
+            e
+        );
+        return Ok(());
+    }
 
-        let mut txs = Vec::with_capacity(2);
+    // Versions of different kinds of images can't be compared.
+    let installed = image_version(args, first, &installed).filter(|old| {
+        std::mem::discriminant(old) == std::mem::discriminant(&new)
+    });
+    match installed {
+        Some(old) if old > new => {
+            if !args.allow_downgrade {
+                bail!("The image at {:#X} would be downgraded from version {} to {}, use --allow-downgrade if you want to flash it anyway", segment.address, old, new);
+            }
+            log::warn!(
+                "Downgrading the image at {:#X} from version {} to {}",
+                segment.address,
+                old,
+                new
+            );
+        }
+        Some(old) => log::info!(
+            "Image at {:#X}: version {} on the device, flashing {}",
+            segment.address,
+            old,
+            new
+        ),
+        None => log::info!(
+            "Image at {:#X}: no version on the device, flashing {}",
+            segment.address,
+            new
+        ),
+    }
 
-        txs.push(Transfer {
-            data: &binary[..binary.len() - CCFG_SIZE],
-            start_address: args.address,
-            expect_ack: true,
-        });
+    Ok(())
+}
 
-        txs.push(Transfer {
-            data: &binary[binary.len() - CCFG_SIZE..],
-            start_address: (args.address + binary.len() as u32)
-                - CCFG_SIZE as u32,
-            expect_ack: false,
-        });
+/// Validate the MCUboot header and trailer of the `image` and return the
+/// address of the `slot` in the flash map.
+fn prepare_mcuboot(
+    image: &[u8],
+    slot: Slot,
+    flash_map: &FlashMap,
+) -> Result<u32> {
+    let (header, len) =
+        mcuboot::validate(image).context("Invalid MCUboot image")?;
+    if len > flash_map.slot_size as usize {
+        bail!(
+            "The MCUboot image ({} bytes) doesn't fit in a slot of {} bytes",
+            len,
+            flash_map.slot_size
+        );
+    }
+    if len < image.len() {
+        log::debug!(
+            "{} bytes after the trailer of the MCUboot image",
+            image.len() - len
+        );
+    }
+
+    let address = flash_map.address(slot);
+    log::info!(
+        "MCUboot image version {}, {} bytes, {:?} slot at {:#X}",
+        header.version(),
+        len,
+        slot,
+        address
+    );
+
+    Ok(address)
+}
+
+/// Validate the TI OAD header of the `image`, fixing its CRC32 if it doesn't
+/// match, and return the address of its slot.
+fn prepare_oad(image: &mut [u8]) -> Result<u32> {
+    let header = match oad::validate(image) {
+        Ok(header) => header,
+        Err(ti_sbl::Error::OadCrcMismatch { .. }) => {
+            log::warn!("The CRC32 of the OAD image doesn't match, fixing it");
+            oad::fix_crc(image)?
+        }
+        Err(e) => return Err(e).context("Invalid OAD image"),
+    };
+
+    log::info!(
+        "OAD image `{}`, version `{}`, {} bytes at {:#X}",
+        String::from_utf8_lossy(header.image_id()),
+        String::from_utf8_lossy(header.software_version()),
+        header.len(),
+        header.image_address()
+    );
+
+    Ok(header.image_address())
+}
+
+/// Compare the CCFG written by the binary with the one of the device,
+/// refusing to disable the bootloader or to change its backdoor unless
+/// `force`.
+///
+/// `bl_invoke_active_high` is the level of the backdoor pin driven by
+/// `--bl-invoke`, if used, a warning is given if the new backdoor can't be
+/// triggered by it anymore.
+///
+/// The CCFG can't be checked if the binary only covers part of it or the
+/// family has a different CCFG layout, `force` is required then.
+fn check_ccfg(
+    device: &mut Device<SystemPort>,
+    ccfg_data: &[u8],
+    bl_invoke_active_high: Option<bool>,
+    force: bool,
+) -> Result<()> {
+    let ccfg_size = device.family().ccfg_size() as usize;
+    if ccfg_data.len() < ccfg_size || ccfg_size != CCFG_SIZE {
+        if !force {
+            bail!("Binary may overwrite the CCFG, use --force if you want to flash it anyway");
+        }
+        return Ok(());
+    }
+
+    let new = Ccfg::parse(&ccfg_data[..CCFG_SIZE])?;
+    let old = ti_sbl::util::read_ccfg(device)
+        .context("Couldn't read the CCFG of the device")?;
 
-        txs
+    let mut risks = crate::ccfg::lockout_risks(&old, &new);
+    if let Err(e) = new.validate() {
+        risks.push(format!("The CCFG of the binary is invalid: {}", e));
+    }
+    for risk in &risks {
+        log::warn!("{}", risk);
+    }
+
+    if let Some(active_high) = bl_invoke_active_high {
+        check_invoke(&old, &new, active_high);
+    }
+
+    if !risks.is_empty() && !force {
+        bail!("The CCFG of the binary may lock you out of the device, use --force if you want to flash it anyway");
+    }
+    if risks.is_empty() {
+        log::info!("The CCFG of the binary keeps the bootloader backdoor");
+    }
+
+    Ok(())
+}
+
+/// Warn when the wiring used by `--bl-invoke`, that worked with the `old`
+/// CCFG, can't trigger the backdoor of the `new` one.
+fn check_invoke(old: &Ccfg, new: &Ccfg, active_high: bool) {
+    let old = old.bootloader_config();
+    let new = new.bootloader_config();
+    if !new.bootloader_enabled || !new.backdoor_enabled {
+        log::warn!("--bl-invoke won't work anymore, the CCFG of the binary disables the backdoor");
+        return;
+    }
+
+    if new.backdoor_active_high != active_high {
+        log::warn!(
+            "--bl-invoke won't work anymore, it drives the backdoor pin active {} but the CCFG of the binary expects it active {}",
+            level(active_high),
+            level(new.backdoor_active_high)
+        );
+    }
+    if old.backdoor_enabled && old.backdoor_pin != new.backdoor_pin {
+        log::warn!(
+            "--bl-invoke won't work anymore unless the board is rewired, the CCFG of the binary moves the backdoor from DIO{} to DIO{}",
+            old.backdoor_pin,
+            new.backdoor_pin
+        );
+    }
+}
+
+fn level(active_high: bool) -> &'static str {
+    if active_high {
+        "high"
     } else {
-        vec![Transfer {
-            data: &binary,
-            start_address: args.address,
-            expect_ack: true,
-        }]
+        "low"
+    }
+}
+
+/// Decode the CCA written by the binary and warn about the settings that
+/// would keep the device from booting or from entering the bootloader.
+fn check_cca(cca_data: &[u8], bl_invoke_active_high: Option<bool>) {
+    if cca_data.len() < CCA_SIZE {
+        log::warn!("Binary partially overwrites the CCA");
+        return;
+    }
+
+    let cca = match Cca::parse(cca_data) {
+        Ok(cca) => cca,
+        Err(e) => {
+            log::warn!("Couldn't decode the CCA: {}", e);
+            return;
+        }
     };
-    let progress_style = ProgressStyle::default_spinner()
-        .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-        .template("{prefix:.bold.dim} {spinner} {wide_msg}");
 
-    let progress_bar = ProgressBar::new(100);
-    progress_bar.set_style(progress_style);
-    progress_bar.set_message("Writing flash");
+    let backdoor = cca.backdoor();
+    log::info!(
+        "CCA: image valid: {}, entry point: {:#X}, backdoor: {}",
+        cca.image_valid(),
+        cca.entry_point(),
+        if backdoor.enabled {
+            format!("PA{} active {}", backdoor.pin, level(backdoor.active_high))
+        } else {
+            "disabled".to_string()
+        }
+    );
 
-    ti_sbl::util::write_flash_range(
-        device,
-        &transfers,
-        |txfer, progress, chunk_index, chunk_addr| {
-            progress_bar.set_message(&format!(
-                "{:.1} Writing flash, transfer #{}, chunk #{} ({:#X})",
-                progress, txfer, chunk_index, chunk_addr
-            ));
-            progress_bar.inc(1);
-        },
-    )
-    .context("Couldn't flash binary")?;
-    progress_bar.finish_with_message("Transfers finished");
+    if !backdoor.enabled {
+        log::warn!("The CCA disables the bootloader backdoor, the device won't be able to enter the bootloader again");
+    }
+    match bl_invoke_active_high {
+        Some(active_high)
+            if backdoor.enabled && backdoor.active_high != active_high =>
+        {
+            log::warn!(
+                "--bl-invoke won't work anymore, it drives the backdoor pin active {} but the CCA of the binary expects it active {}",
+                level(active_high),
+                level(backdoor.active_high)
+            );
+        }
+        _ => (),
+    }
+    if !cca.image_valid() {
+        log::warn!("The CCA marks the image as invalid, the device will stay in the bootloader");
+    }
+}
+
+/// Mark the image as valid on the CCA of the [`Family::CC2538`], so the boot
+/// ROM jumps to it, when the binary writes the last page of the flash.
+///
+/// A binary that ends on the last page without covering the CCA is padded
+/// up to it, and the CCA of the device is appended so it's kept after the
+/// page is erased. The entry point is set to the start of the binary if
+/// it's outside of the flash.
+///
+/// [`Family::CC2538`]: ti_sbl::Family::CC2538
+fn set_image_valid(
+    device: &mut Device<SystemPort>,
+    layout: &FlashLayout,
+    address: u32,
+    binary: &mut Vec<u8>,
+    force: bool,
+) -> Result<()> {
+    let cca = match layout.cca() {
+        Some(cca) => cca,
+        None => bail!("--set-image-valid is only supported on CC2538 devices"),
+    };
+    let end = address + binary.len() as u32;
+    if end <= cca.end - layout.sector_size() {
+        let current = ti_sbl::util::read_cca(device)
+            .context("Couldn't read the CCA of the device")?;
+        if !current.image_valid() {
+            log::warn!("The binary doesn't cover the last page of the flash, the CCA of the device marks the image as invalid");
+        }
+        return Ok(());
+    }
+
+    if end <= cca.start {
+        let current = ti_sbl::util::read_cca(device)
+            .context("Couldn't read the CCA of the device")?;
+        binary.resize((cca.start - address) as usize, 0xFF);
+        binary.extend_from_slice(&current.to_bytes());
+    } else if end != cca.end {
+        bail!("--set-image-valid can't be used with a binary that covers part of the CCA");
+    }
+
+    let offset = (cca.start - address) as usize;
+    let mut new = Cca::parse(&binary[offset..])?;
+    new.set_image_valid(true);
+    if !layout.flash().contains(&new.entry_point()) {
+        new.set_entry_point(address);
+    }
+    if !new.backdoor().enabled && !force {
+        bail!("The CCA disables the bootloader backdoor, use --force if you want to flash it anyway");
+    }
+    binary[offset..].copy_from_slice(&new.to_bytes());
+    log::info!(
+        "Marking the image as valid, entry point: {:#X}",
+        new.entry_point()
+    );
 
     Ok(())
 }
 
 struct FlashArgs {
-    binary_path: PathBuf,
+    /// The files to flash, with the address given with `ADDRESS=FILE`.
+    images: Vec<(Option<u32>, PathBuf)>,
     address: u32,
-    write_erase: bool,
+    /// Whether `--address` was given, instead of using its default value.
+    address_given: bool,
+    /// Offset of the binary file to flash from.
+    bin_offset: usize,
+    /// Number of bytes of the binary file to flash, up to the end if `None`.
+    bin_length: Option<u32>,
+    /// How the flash is erased, from `--write-erase`, `--bank-erase` or
+    /// `--incremental`.
+    erase: Erase,
+    /// Don't erase nor write anything if the CRC32 of the firmware matches
+    /// the flash.
+    skip_identical: bool,
+    /// File to save the flash to before erasing it.
+    backup: Option<String>,
+    /// Flash the backup again if the verification fails.
+    rollback: bool,
+    /// Check that the erased sectors are blank before writing them.
+    verify_erase: bool,
+    /// Print the SHA-256 digest of the flashed ranges once verified.
+    attest: bool,
+    keep_ccfg: bool,
+    set_image_valid: bool,
+    /// How the flash is verified after writing it, if it's verified.
+    verify: Option<VerifyMethod>,
+    /// Whether the raw binaries are TI OAD images.
+    oad: bool,
+    /// Offset of a 32-bit version number in the first image.
+    version_offset: Option<u32>,
+    allow_downgrade: bool,
+    /// The MCUboot slot to flash the raw binaries at.
+    slot: Option<Slot>,
+    flash_map: FlashMap,
     force: bool,
+    /// Level of the backdoor pin driven by `--bl-invoke`, if used.
+    bl_invoke_active_high: Option<bool>,
+    /// Expected SHA-256 digest of the firmware file, in hexadecimal.
+    sha256: Option<String>,
+    /// The `.zip` bundle the images are read from, if one is given.
+    bundle: Option<Bundle>,
 }
 
 impl FlashArgs {
-    pub fn from_matches(args: &ArgMatches<'_>) -> Result<FlashArgs> {
-        Ok(FlashArgs {
-            binary_path: args.value_of("BIN").unwrap().parse().context("Invalid binary file path")?,
+    pub fn from_matches(
+        global: &ArgMatches<'_>,
+        args: &ArgMatches<'_>,
+    ) -> Result<FlashArgs> {
+        let mut flash_args = FlashArgs {
+            images: args.values_of("BIN").map_or_else(Vec::new, |values| values.map(parse_image).collect()),
             address: u32::from_str_radix(&args.value_of("address").map(|a| {
                 let mut a = a.to_string();
                 if a.starts_with("0x") {
@@ -190,21 +1073,174 @@ impl FlashArgs {
                     a
                 }
             }).unwrap(), 16).context("Invalid flash address, must be an hexadecimal number, e.g.: 0x00000000")?,
-            write_erase: args.is_present("write-erase"),
+            address_given: args.occurrences_of("address") > 0,
+            bin_offset: args.value_of("bin-offset").map(parse_u32).transpose().context("Invalid --bin-offset value")?.unwrap_or(0) as usize,
+            bin_length: args.value_of("bin-length").map(parse_u32).transpose().context("Invalid --bin-length value")?,
+            erase: if args.is_present("bank-erase") {
+                Erase::Bank
+            } else if args.is_present("incremental") {
+                Erase::Incremental
+            } else if args.is_present("write-erase") {
+                Erase::Sectors
+            } else {
+                Erase::None
+            },
+            skip_identical: args.is_present("skip-identical"),
+            backup: args.value_of("backup").map(str::to_string),
+            rollback: args.is_present("rollback"),
+            verify_erase: args.is_present("verify-erase"),
+            attest: args.is_present("attest"),
+            keep_ccfg: args.is_present("keep-ccfg"),
+            set_image_valid: args.is_present("set-image-valid"),
+            verify: if args.is_present("readback") {
+                Some(VerifyMethod::Readback)
+            } else if let Some(percent) = args.value_of("verify-sample") {
+                Some(VerifyMethod::Sampled(parse_percent(percent)?))
+            } else if args.is_present("verify") {
+                Some(VerifyMethod::Crc32)
+            } else {
+                None
+            },
+            oad: args.is_present("oad"),
+            version_offset: args.value_of("version-offset").map(parse_u32).transpose().context("Invalid --version-offset value")?,
+            allow_downgrade: args.is_present("allow-downgrade"),
+            slot: args.value_of("slot").map(str::parse).transpose().context("Invalid --slot value")?,
+            flash_map: args.value_of("flash-map").map(parse_flash_map).transpose()?.unwrap_or_default(),
             force: args.is_present("force"),
-        })
+            bl_invoke_active_high: if global.is_present("bl-invoke") {
+                Some(!global.is_present("bl-active-low"))
+            } else {
+                None
+            },
+            sha256: args.value_of("sha256").map(str::to_string),
+            bundle: None,
+        };
+
+        if let Some(ref sha256) = flash_args.sha256 {
+            if sha256.len() != 64
+                || !sha256.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                bail!(
+                    "Invalid --sha256 digest, expected 64 hexadecimal digits"
+                );
+            }
+        }
+
+        if let Some(path) = args.value_of("manifest") {
+            flash_args.apply_manifest(Manifest::read(Path::new(path))?);
+        }
+        if (flash_args.rollback || flash_args.attest)
+            && flash_args.verify.is_none()
+        {
+            flash_args.verify = Some(VerifyMethod::Crc32);
+        }
+
+        match flash_args.images.as_slice() {
+            [(address, path)] if Bundle::is_bundle(path) => {
+                if address.is_some() {
+                    bail!("A bundle can't be flashed at an address, the address of its images is given by its manifest");
+                }
+                let bundle = Bundle::open(path, flash_args.sha256.as_deref())?;
+                match bundle.manifest() {
+                    Some(manifest) => {
+                        let manifest = bundle
+                            .read_file(manifest)
+                            .and_then(|(name, text)| {
+                                Manifest::parse_bytes(
+                                    &text,
+                                    name.parent()
+                                        .unwrap_or_else(|| Path::new("")),
+                                )
+                            })
+                            .with_context(|| {
+                                format!(
+                                    "Invalid flash manifest in `{}`",
+                                    path.display()
+                                )
+                            })?;
+                        flash_args.apply_manifest(manifest);
+                    }
+                    None => {
+                        flash_args.images = bundle
+                            .images()
+                            .into_iter()
+                            .map(|path| (None, path))
+                            .collect();
+                    }
+                }
+                flash_args.bundle = Some(bundle);
+            }
+            images
+                if images.iter().any(|(_, path)| Bundle::is_bundle(path)) =>
+            {
+                bail!("A bundle must be flashed on its own");
+            }
+            images
+                if images
+                    .iter()
+                    .filter(|(_, path)| bundle::is_stdin(path))
+                    .count()
+                    > 1 =>
+            {
+                bail!("The standard input can only be read once");
+            }
+            _ => (),
+        }
+        if flash_args.sha256.is_some()
+            && flash_args.bundle.is_none()
+            && flash_args.images.len() > 1
+        {
+            bail!("--sha256 can only be used with a single file or a bundle");
+        }
+
+        Ok(flash_args)
+    }
+
+    /// Use the images and the policy of the flash `manifest`, the flags given
+    /// on the command line are kept.
+    fn apply_manifest(&mut self, manifest: Manifest) {
+        self.images = manifest.images;
+        if let Some(erase) = manifest.erase {
+            self.erase = erase;
+        }
+        self.keep_ccfg |= manifest.keep_ccfg;
+        self.verify = self.verify.or(manifest.verify);
+        self.force |= manifest.force;
     }
 }
 
-fn may_overwrite_ccfg(
-    flash_size: u32,
-    binary_offset_in_flash: u32,
-    binary: &[u8],
-) -> bool {
-    let ccfg_offset = flash_size - CCFG_SIZE as u32;
-    log::trace!("CCFG offset: {:X}", ccfg_offset);
+/// Parse the percentage of sectors verified by `--verify-sample`.
+fn parse_percent(value: &str) -> Result<u8> {
+    match value.trim_end_matches('%').parse::<u8>() {
+        Ok(percent) if (1..=100).contains(&percent) => Ok(percent),
+        _ => bail!("Invalid --verify-sample value, expected a percentage from 1 to 100"),
+    }
+}
+
+/// Parse a `PRIMARY,SECONDARY,SIZE` MCUboot flash map.
+fn parse_flash_map(value: &str) -> Result<FlashMap> {
+    let values = value
+        .split(',')
+        .map(parse_u32)
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|values| values.len() == 3)
+        .context("Invalid --flash-map, expected PRIMARY,SECONDARY,SIZE, e.g.: 0xC000,0x3E000,0x32000")?;
 
-    let binary_end_addr = binary_offset_in_flash + binary.len() as u32;
+    Ok(FlashMap {
+        primary: values[0],
+        secondary: values[1],
+        slot_size: values[2],
+    })
+}
 
-    binary_end_addr >= ccfg_offset
+/// Parse a `FILE` or `ADDRESS=FILE` argument, a file name containing `=`
+/// is taken as is if the part before it isn't a number.
+fn parse_image(value: &str) -> (Option<u32>, PathBuf) {
+    let mut split = value.splitn(2, '=');
+    let first = split.next().unwrap();
+    match (parse_u32(first), split.next()) {
+        (Ok(address), Some(path)) => (Some(address), PathBuf::from(path)),
+        _ => (None, PathBuf::from(value)),
+    }
 }
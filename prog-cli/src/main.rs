@@ -14,15 +14,27 @@
 
 #[cfg(windows)]
 use std::ffi::OsString;
-use std::{path::PathBuf, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use serial::SerialPort;
 
 use anyhow::{bail, Context, Result};
-use clap::{crate_authors, crate_version, App, AppSettings, Arg, SubCommand};
+use clap::{
+    crate_authors, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand,
+};
+use ti_sbl::{firmware::Firmware, Device};
 
+mod bundle;
+mod cc32xx;
+mod ccfg;
 mod flash;
 mod list;
+mod manifest;
+mod msp430;
+mod report;
 
 #[cfg(target_os = "linux")]
 const DEFAULT_PORT: &str = "/dev/ttyUSB0";
@@ -39,106 +51,420 @@ fn main() -> Result<()> {
 
     match args.subcommand() {
         ("flash", Some(m)) => {
-            // Sanity checks first
-            if args.is_present("bl-inverted") && !args.is_present("bl-invoke") {
-                bail!("--bl-inverted can't be used if --bl-invoke is not specified. See --help for more information");
-            }
+            let start = Instant::now();
+            let mut device = connect(&args)?;
+
+            let info = ti_sbl::util::read_chip_info(&mut device)
+                .context("Couldn't read chip information")?;
+            log::info!("Chip: {}", info);
 
-            if args.is_present("bl-active-low") && !args.is_present("bl-invoke")
-            {
-                bail!("--bl-active-low can't be used if --bl-invoke is not specified. See --help for more information");
+            let (ieee_address, ble_address) = log_ieee_address(&mut device)?;
+
+            let mut report = report::Report::default();
+            report.chip = Some(info);
+            report.ieee_address = ieee_address;
+            report.ble_address = ble_address;
+            if m.is_present("report") {
+                report.chip_id = Some(
+                    device
+                        .get_chip_id()
+                        .context("Couldn't read the chip ID")?,
+                );
             }
 
-            let global_args = GlobalArgs {
-                #[cfg(unix)]
-                port: args.value_of("port").unwrap().parse()?,
-                #[cfg(windows)]
-                port: args.value_of("port").unwrap().map(OsString::from),
-                family: args.value_of("family").unwrap().parse()?,
-                baudrate: args
-                    .value_of("baudrate")
-                    .unwrap()
-                    .parse::<usize>()
-                    .map(|v| match v {
-                    110 => serial::BaudRate::Baud110,
-                    300 => serial::BaudRate::Baud300,
-                    600 => serial::BaudRate::Baud600,
-                    1200 => serial::BaudRate::Baud1200,
-                    2400 => serial::BaudRate::Baud2400,
-                    4800 => serial::BaudRate::Baud4800,
-                    9600 => serial::BaudRate::Baud9600,
-                    19200 => serial::BaudRate::Baud19200,
-                    38400 => serial::BaudRate::Baud38400,
-                    57600 => serial::BaudRate::Baud57600,
-                    115200 => serial::BaudRate::Baud115200,
-                    n => serial::BaudRate::BaudOther(n),
-                })?,
-                enable_xosc: args.is_present("enable-xosc"),
-                bootloader_invoke: args.is_present("bl-invoke"),
-                bootloader_inverted: args.is_present("bl-inverted"),
-                bootloader_active_low: args.is_present("bl-active-low"),
-            };
-
-            if global_args.enable_xosc
-                && !global_args.family.supports_set_xosc()
-            {
-                anyhow::bail!("XOSC can only be enabled on CC2538 family");
+            let result =
+                flash::flash(&args, m, info.flash, &mut device, &mut report);
+            if let Some(path) = m.value_of("report") {
+                report.save(path, &result, start.elapsed())?;
             }
+            result?;
 
-            log::info!(
-                "Opening serial port `{}`",
-                global_args.port_to_string()
+            check_diagnostics(&device);
+        }
+        ("verify", Some(m)) => {
+            let mut device = connect(&args)?;
+            let info = ti_sbl::util::read_chip_info(&mut device)
+                .context("Couldn't read chip information")?;
+            log::info!("Chip: {}", info);
+
+            flash::verify_only(&args, m, info.flash, &mut device)?;
+
+            check_diagnostics(&device);
+        }
+        ("diff", Some(m)) => {
+            let mut device = connect(&args)?;
+            let info = ti_sbl::util::read_chip_info(&mut device)
+                .context("Couldn't read chip information")?;
+            log::info!("Chip: {}", info);
+
+            flash::diff(&args, m, info.flash, &mut device)?;
+
+            check_diagnostics(&device);
+        }
+        ("blank-check", Some(m)) => {
+            let mut device = connect(&args)?;
+            let info = ti_sbl::util::read_chip_info(&mut device)
+                .context("Couldn't read chip information")?;
+
+            flash::blank_check(m, info.flash, &mut device)?;
+
+            check_diagnostics(&device);
+        }
+        ("read", Some(m)) => {
+            let mut device = connect(&args)?;
+            let info = ti_sbl::util::read_chip_info(&mut device)
+                .context("Couldn't read chip information")?;
+
+            flash::read(m, info.flash, &mut device)?;
+
+            check_diagnostics(&device);
+        }
+        ("info", Some(_)) => {
+            let mut device = connect(&args)?;
+            info(&mut device)?;
+            check_diagnostics(&device);
+        }
+        ("ccfg", Some(m)) => ccfg::ccfg(&args, m)?,
+        ("set-mac", Some(m)) => {
+            let address = ccfg::parse_addr(m.value_of("ADDRESS").unwrap())
+                .context("Invalid IEEE 802.15.4 address")?;
+            let mut device = connect(&args)?;
+            log::info!("Writing the secondary IEEE 802.15.4 address");
+            ti_sbl::util::write_secondary_ieee_address(&mut device, address)
+                .context("Couldn't write the IEEE 802.15.4 address")?;
+            check_diagnostics(&device);
+        }
+        ("set-ble-mac", Some(m)) => {
+            let address = ccfg::parse_ble_addr(m.value_of("ADDRESS").unwrap())
+                .context("Invalid BLE address")?;
+            let mut device = connect(&args)?;
+            log::info!("Writing the BLE address");
+            ti_sbl::util::write_ble_address(&mut device, address)
+                .context("Couldn't write the BLE address")?;
+            check_diagnostics(&device);
+        }
+        ("cc32xx", Some(m)) => {
+            cc32xx::cc32xx(args.value_of("port").unwrap(), m)?
+        }
+        ("msp430", Some(m)) => {
+            msp430::msp430(args.value_of("port").unwrap(), m)?
+        }
+        ("list", _) => list::list()?,
+        _ => {
+            println!("Error: Sub-command required");
+            println!("{}", args.usage());
+        }
+    }
+
+    Ok(())
+}
+
+/// Open the serial port and synchronize with the bootloader.
+fn connect(args: &ArgMatches) -> Result<Device<serial::SystemPort>> {
+    // Sanity checks first
+    if args.is_present("bl-inverted") && !args.is_present("bl-invoke") {
+        bail!("--bl-inverted can't be used if --bl-invoke is not specified. See --help for more information");
+    }
+
+    if args.is_present("bl-active-low") && !args.is_present("bl-invoke") {
+        bail!("--bl-active-low can't be used if --bl-invoke is not specified. See --help for more information");
+    }
+
+    let chip = match args.value_of("chip") {
+        Some(name) => Some(
+            ti_sbl::chipdb::find_part(name)
+                .with_context(|| format!("Unknown chip `{}`", name))?,
+        ),
+        None => None,
+    };
+
+    if chip.is_some() && args.value_of("family") != Some("auto") {
+        bail!("--family can't be used together with --chip. See --help for more information");
+    }
+
+    let mut global_args = GlobalArgs {
+        #[cfg(unix)]
+        port: args.value_of("port").unwrap().parse()?,
+        #[cfg(windows)]
+        port: args.value_of("port").unwrap().map(OsString::from),
+        family: match (chip, args.value_of("family").unwrap()) {
+            (Some(part), _) => Some(part.family),
+            (None, "auto") => None,
+            (None, family) => Some(family.parse()?),
+        },
+        chip,
+        baudrate: args.value_of("baudrate").unwrap().parse::<usize>().map(
+            |v| match v {
+                110 => serial::BaudRate::Baud110,
+                300 => serial::BaudRate::Baud300,
+                600 => serial::BaudRate::Baud600,
+                1200 => serial::BaudRate::Baud1200,
+                2400 => serial::BaudRate::Baud2400,
+                4800 => serial::BaudRate::Baud4800,
+                9600 => serial::BaudRate::Baud9600,
+                19200 => serial::BaudRate::Baud19200,
+                38400 => serial::BaudRate::Baud38400,
+                57600 => serial::BaudRate::Baud57600,
+                115200 => serial::BaudRate::Baud115200,
+                n => serial::BaudRate::BaudOther(n),
+            },
+        )?,
+        enable_xosc: args.is_present("enable-xosc"),
+        bootloader_invoke: args.is_present("bl-invoke"),
+        bootloader_inverted: args.is_present("bl-inverted"),
+        bootloader_active_low: args.is_present("bl-active-low"),
+        sync_attempts: args
+            .value_of("sync-attempts")
+            .unwrap()
+            .parse()
+            .context("Invalid --sync-attempts value")?,
+        sector_size: args
+            .value_of("sector-size")
+            .map(str::parse)
+            .transpose()
+            .context("Invalid --sector-size value")?,
+    };
+
+    if let Some(sector_size) = global_args.sector_size {
+        if !sector_size.is_power_of_two() {
+            bail!("--sector-size must be a power of two");
+        }
+    }
+
+    if let Some(family) = global_args.family {
+        let max_baudrate = family.max_baudrate();
+        if baudrate_to_usize(global_args.baudrate) > max_baudrate {
+            log::warn!(
+                "Baudrate {} exceeds the maximum of {} supported by {:?} devices, using {}",
+                baudrate_to_usize(global_args.baudrate),
+                max_baudrate,
+                family,
+                max_baudrate
             );
-            log::info!("Baudrate: {}", baudrate_to_usize(global_args.baudrate));
-            let mut port = serial::SystemPort::open(&global_args.port)
-                .with_context(|| {
-                    format!(
-                        "Couldn't open serial port `{}`",
-                        global_args.port_to_string()
-                    )
-                })?;
+            global_args.baudrate = serial::BaudRate::from_speed(max_baudrate);
+        }
+    }
 
-            let mut settings = ti_sbl::port_settings();
-            settings.baud_rate = global_args.baudrate;
+    if global_args.enable_xosc
+        && global_args.family.map_or(false, |f| !f.supports_set_xosc())
+    {
+        anyhow::bail!("XOSC can only be enabled on CC2538 family");
+    }
 
-            port.set_timeout(Duration::from_millis(200))?;
-            port.configure(&settings)?;
+    log::info!("Opening serial port `{}`", global_args.port_to_string());
+    log::info!("Baudrate: {}", baudrate_to_usize(global_args.baudrate));
+    let mut port =
+        serial::SystemPort::open(&global_args.port).with_context(|| {
+            format!(
+                "Couldn't open serial port `{}`",
+                global_args.port_to_string()
+            )
+        })?;
 
-            if global_args.bootloader_invoke {
-                log::info!("Invoking bootloader");
-                ti_sbl::invoke_bootloader(
-                    &mut port,
-                    global_args.bootloader_inverted,
-                    !global_args.bootloader_active_low,
-                )
-                .context("Failed to invoke bootloader")?;
-            }
+    let mut settings = ti_sbl::port_settings();
+    settings.baud_rate = global_args.baudrate;
+
+    port.set_timeout(Duration::from_millis(200))?;
+    port.configure(&settings)?;
 
-            log::info!("Initializing communications with the device");
-            let mut device = ti_sbl::Device::new(port, global_args.family)
-                .context("Failed to synchronize with the bootloader")?;
+    if global_args.bootloader_invoke {
+        log::info!("Invoking bootloader");
+        ti_sbl::invoke_bootloader(
+            &mut port,
+            global_args.bootloader_inverted,
+            !global_args.bootloader_active_low,
+        )
+        .context("Failed to invoke bootloader")?;
+    }
+
+    log::info!("Initializing communications with the device");
+    let mut builder = ti_sbl::DeviceBuilder::new(
+        global_args.family.unwrap_or(ti_sbl::Family::CC26X2),
+    )
+    .sync_retry(ti_sbl::SyncRetry {
+        attempts: global_args.sync_attempts,
+        ..Default::default()
+    })
+    .detect_family(global_args.family.is_none());
+    if let Some(sector_size) = global_args.sector_size {
+        builder = builder.sector_size(sector_size);
+    }
+    if let Some(path) = args.value_of("trace") {
+        let file = std::fs::File::create(path).with_context(|| {
+            format!("Couldn't create trace file `{}`", path)
+        })?;
+        builder = builder.trace_sink(std::io::BufWriter::new(file));
+    }
+
+    let mut device = builder
+        .build(port)
+        .context("Failed to synchronize with the bootloader")?;
+
+    log::info!("Family: {:?}", device.family());
+
+    log::info!("Pinging device");
+    if !device.ping()? {
+        anyhow::bail!("Ping command wasn't acknowledged");
+    }
+
+    if global_args.enable_xosc {
+        if !device.family().supports_set_xosc() {
+            anyhow::bail!("XOSC can only be enabled on CC2538 family");
+        }
+        device.set_xosc().context("Couldn't switch to XOSC")?;
+
+        // The UART clock changes with the oscillator, the baudrate has to be
+        // detected again by the bootloader.
+        log::info!("Synchronizing again with the bootloader on the XOSC");
+        device
+            .resynchronize()
+            .context("Failed to synchronize with the bootloader on the XOSC")?;
+        if !device.ping()? {
+            anyhow::bail!("Ping command wasn't acknowledged on the XOSC");
+        }
+    }
+
+    if let Some(part) = global_args.chip {
+        let info = ti_sbl::util::read_chip_info(&mut device)
+            .context("Couldn't read chip information")?;
+        if !part.matches(&info) {
+            bail!("Expected a {} but found a {}", part.name, info);
+        }
+    }
 
-            log::info!("Pinging device");
-            if !device.ping()? {
-                anyhow::bail!("Ping command wasn't acknowledged");
+    Ok(device)
+}
+
+/// Print the information about the connected part.
+fn info(device: &mut Device<serial::SystemPort>) -> Result<()> {
+    let device_info = ti_sbl::util::read_device_info(device)
+        .context("Couldn't read chip information")?;
+    let info = device_info.chip;
+
+    println!("Chip:    {}", info.name);
+    println!("Family:  {:?}", info.family);
+    if device_info.memory_readable()
+        && device
+            .family()
+            .supports_command(ti_sbl::CommandId::GetChipId)
+    {
+        let chip_id = ti_sbl::util::read_chip_id(device)
+            .context("Couldn't read chip ID")?;
+        println!("Chip ID: {}", chip_id);
+    }
+    if info.flash != 0 {
+        println!("Flash:   {} KB", info.flash / 1024);
+    } else {
+        println!("Flash:   unknown");
+    }
+    println!("RAM:     {} KB", info.ram / 1024);
+    match device_info.security {
+        ti_sbl::util::SecurityState::Unlocked => (),
+        ti_sbl::util::SecurityState::ReadLocked => {
+            println!("Memory read: disabled, the device is read-out locked");
+            return Ok(());
+        }
+        ti_sbl::util::SecurityState::Unknown => {
+            println!("Memory read: not supported by the bootloader");
+            return Ok(());
+        }
+    }
+
+    match ti_sbl::util::read_ieee_address(device) {
+        Ok((primary, secondary)) => {
+            println!(
+                "IEEE 802.15.4g primary address:   {}",
+                format_addr(primary)
+            );
+            if secondary != ti_sbl::util::INVALID_ADDR {
+                println!(
+                    "IEEE 802.15.4g secondary address: {}",
+                    format_addr(secondary)
+                );
             }
+        }
+        Err(ti_sbl::Error::UnsupportedFamily(_)) => (),
+        Err(e) => return Err(e).context("Couldn't read IEEE 802.15.4 address"),
+    }
 
-            if global_args.enable_xosc {
-                device.set_xosc().context("Couldn't switch to XOSC")?;
-                todo!();
+    match ti_sbl::util::read_ble_address(device) {
+        Ok((primary, secondary)) => {
+            println!(
+                "BLE primary address:              {}",
+                format_ble_addr(primary)
+            );
+            if secondary != ti_sbl::util::INVALID_ADDR {
+                println!(
+                    "BLE secondary address:            {}",
+                    format_ble_addr(secondary)
+                );
             }
+        }
+        Err(ti_sbl::Error::UnsupportedFamily(_)) => (),
+        Err(e) => return Err(e).context("Couldn't read BLE address"),
+    }
 
-            let flash_size = ti_sbl::util::read_flash_size(&mut device)
-                .context("Couldn't read flash size")?;
-            log::info!("Flash size: {} K", flash_size / 1024);
+    match ti_sbl::util::read_ccfg(device) {
+        Ok(ccfg) => {
+            let config = ccfg.bootloader_config();
+            println!(
+                "Bootloader:                       {}",
+                ccfg::enabled(config.bootloader_enabled)
+            );
+            println!(
+                "Bootloader backdoor:              {}",
+                ccfg::format_backdoor(&config)
+            );
+            if !config.bootloader_enabled {
+                log::warn!("The bootloader is disabled, it's only entered while there's no valid image");
+            }
+        }
+        Err(ti_sbl::Error::UnsupportedFamily(_)) => (),
+        Err(e) => return Err(e).context("Couldn't read the CCFG"),
+    }
 
-            let chip_id =
-                device.get_chip_id().context("Couldn't read chip ID")?;
-            log::info!("Chip ID: {:#X}", chip_id);
+    Ok(())
+}
 
-            let (primary, secondary) =
-                ti_sbl::util::read_ieee_address(&mut device)
-                    .context("Couldn't read IEEE 802.15.4 address")?;
+/// Explain why reading the memory of the device failed, if the memory
+/// read commands are disabled.
+pub fn read_error(
+    device: &mut Device<serial::SystemPort>,
+    e: ti_sbl::Error,
+) -> anyhow::Error {
+    match ti_sbl::util::read_security_state(device) {
+        Ok(ti_sbl::util::SecurityState::ReadLocked) => anyhow::Error::new(e)
+            .context("The memory read commands are disabled, the device is read-out locked"),
+        _ => e.into(),
+    }
+}
+
+/// Save the `data` read from the memory of the device at `address` to
+/// `path`, as an Intel HEX file if its extension is `.hex` so the address is
+/// kept, or as a raw binary.
+pub fn save_memory(path: &str, address: u32, data: &[u8]) -> Result<()> {
+    let is_hex = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("hex"));
+    let result = if is_hex {
+        let firmware = Firmware::from_binary(address, data.to_vec());
+        std::fs::write(path, firmware.to_hex())
+    } else {
+        std::fs::write(path, data)
+    };
+
+    result.with_context(|| format!("Couldn't write `{}`", path))
+}
+
+/// Log the IEEE 802.15.4g and BLE addresses of the device, returns the ones
+/// in use, the secondary address if it's set.
+fn log_ieee_address(
+    device: &mut Device<serial::SystemPort>,
+) -> Result<(Option<String>, Option<String>)> {
+    let mut addresses = (None, None);
+    match ti_sbl::util::read_ieee_address(device) {
+        Ok((primary, secondary)) => {
             log::info!(
                 "IEEE 802.15.4g primary address: {}",
                 format_addr(primary)
@@ -148,18 +474,47 @@ fn main() -> Result<()> {
                     "IEEE 802.15.4g secondary address: {}",
                     format_addr(secondary)
                 );
+                addresses.0 = Some(format_addr(secondary));
+            } else {
+                addresses.0 = Some(format_addr(primary));
             }
+        }
+        Err(ti_sbl::Error::UnsupportedFamily(_)) => {
+            log::debug!("IEEE 802.15.4g address not available")
+        }
+        Err(e) => return Err(e).context("Couldn't read IEEE 802.15.4 address"),
+    }
 
-            flash::flash(m, flash_size, &mut device)?
+    match ti_sbl::util::read_ble_address(device) {
+        Ok((primary, secondary)) => {
+            log::info!("BLE primary address: {}", format_ble_addr(primary));
+            if secondary != ti_sbl::util::INVALID_ADDR {
+                log::info!(
+                    "BLE secondary address: {}",
+                    format_ble_addr(secondary)
+                );
+                addresses.1 = Some(format_ble_addr(secondary));
+            } else {
+                addresses.1 = Some(format_ble_addr(primary));
+            }
         }
-        ("list", _) => list::list()?,
-        _ => {
-            println!("Error: Sub-command required");
-            println!("{}", args.usage());
+        Err(ti_sbl::Error::UnsupportedFamily(_)) => {
+            log::debug!("BLE address not available")
         }
+        Err(e) => return Err(e).context("Couldn't read BLE address"),
     }
 
-    Ok(())
+    Ok(addresses)
+}
+
+fn check_diagnostics(device: &Device<serial::SystemPort>) {
+    let diagnostics = device.diagnostics();
+    if diagnostics.is_marginal() {
+        log::warn!(
+            "Communication problems detected, check the wiring or use a lower baudrate: {:?}",
+            diagnostics
+        );
+    }
 }
 
 struct GlobalArgs {
@@ -167,12 +522,18 @@ struct GlobalArgs {
     port: PathBuf,
     #[cfg(windows)]
     port: OsString,
-    family: ti_sbl::Family,
+    /// `None` to detect the family.
+    family: Option<ti_sbl::Family>,
+    /// The part given with `--chip`.
+    chip: Option<&'static ti_sbl::chipdb::Part>,
     baudrate: serial::BaudRate,
     enable_xosc: bool,
     bootloader_invoke: bool,
     bootloader_inverted: bool,
     bootloader_active_low: bool,
+    sync_attempts: usize,
+    /// Sector size given with `--sector-size`.
+    sector_size: Option<u32>,
 }
 
 impl GlobalArgs {
@@ -203,6 +564,15 @@ fn baudrate_to_usize(baudrate: serial::BaudRate) -> usize {
     }
 }
 
+/// Format the 6 bytes used of a BLE address.
+fn format_ble_addr(addr: [u8; 8]) -> String {
+    addr[..6]
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
 fn format_addr(addr: [u8; 8]) -> String {
     format!(
         "{:X}{:X}:{:X}{:X}:{:X}{:X}:{:X}{:X}:{:X}{:X}:{:X}{:X}:{:X}{:X}:{:X}{:X}",
@@ -233,12 +603,17 @@ fn cli() -> App<'static, 'static> {
         .about("Programmer for Texas Instruments Serial Interface Bootloader\nProject homepage: https://github.com/btcven/ti-bootloader")
         .arg(port)
         .arg(
-            opt("family", "Family: cc2538, cc26x0, cc26x2")
+            opt("family", "Family: auto, cc2538, cc26x0, cc26x0r2, cc26x2, cc26x2x7, cc26x4, tm4c. `auto` detects the family of the device")
                 .required(true)
-                .default_value("cc26x2")
+                .default_value("auto")
+        )
+        .arg(
+            opt("chip", "Part name, e.g.: cc1312r, cc2652rb, cc1352p. Sets the family and fails if the connected device is a different part")
+                .takes_value(true)
+                .value_name("NAME")
         )
         .arg(
-            opt("baudrate", "Serial port baudrate")
+            opt("baudrate", "Serial port baudrate, limited to the maximum supported by the family")
                 .short("b")
                 .required(true)
                 .default_value("500000")
@@ -265,6 +640,29 @@ fn cli() -> App<'static, 'static> {
                 "Use an active-low level when using --bl-invoke flag, this sets level of the bootloader pin to active-low"
             )
         )
+        .arg(
+            opt(
+                "sync-attempts",
+                "Number of attempts to synchronize with the bootloader, some boards need several tries right after reset"
+            )
+                .default_value("1")
+        )
+        .arg(
+            opt(
+                "sector-size",
+                "Override the sector size of the family, in bytes, for parts with a different flash geometry"
+            )
+                .takes_value(true)
+                .value_name("BYTES")
+        )
+        .arg(
+            opt(
+                "trace",
+                "Record the raw traffic with the bootloader into a file"
+            )
+                .takes_value(true)
+                .value_name("FILE")
+        )
         .arg(
             opt("verbose", "Use verbose output: -v (debug), -vv (trace)")
                 .short("v")
@@ -278,17 +676,110 @@ fn cli() -> App<'static, 'static> {
                     Arg::with_name("BIN")
                         .required(true)
                         .takes_value(true)
-                        .help("Binary file to flash")
+                        .multiple(true)
+                        .required_unless("manifest")
+                        .help("Binary, Intel HEX (.hex) or ELF files to flash, as FILE or ADDRESS=FILE, e.g.: 0x56000=stack.bin. Use - to read the firmware from the standard input, an http:// or https:// URL to download it. Files ending in .gz are decompressed, a .zip bundle is flashed following its manifest.toml, or all its files if it has none")
                 )
                 .arg(
                     opt(
                         "address",
-                        "Address in memory where the binary contents will be flashed"
+                        "Address in memory where the binary contents will be flashed, ELF and Intel HEX files carry their own addresses"
                     )
                         .short("a")
                         .required(true)
                         .default_value("0x00000000")
                 )
+                .arg(
+                    opt(
+                        "manifest",
+                        "TOML manifest describing the images to flash, their addresses and the erase and verify policy"
+                    )
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .conflicts_with("BIN")
+                )
+                .arg(
+                    opt(
+                        "sha256",
+                        "Expected SHA-256 digest of the firmware file (of the compressed file or bundle), required for a downloaded release"
+                    )
+                        .takes_value(true)
+                        .value_name("DIGEST")
+                )
+                .arg(
+                    opt(
+                        "verify",
+                        "Verify the flash after writing it, comparing the CRC32 computed by the bootloader with the one of the firmware"
+                    )
+                )
+                .arg(
+                    opt(
+                        "readback",
+                        "Verify the flash by reading it back instead of with its CRC32, finds the address of the first difference but the memory reads must be enabled"
+                    )
+                )
+                .arg(
+                    opt(
+                        "verify-sample",
+                        "Verify the CRC32 of this percentage of the sectors, spread over the firmware, for quick smoke tests of large images"
+                    )
+                        .takes_value(true)
+                        .value_name("PERCENT")
+                        .conflicts_with("readback")
+                )
+                .arg(
+                    opt(
+                        "oad",
+                        "The binaries are TI OAD images, their header is validated, the CRC32 fixed and they're flashed at their slot address"
+                    )
+                )
+                .arg(
+                    opt(
+                        "slot",
+                        "Flash the MCUboot images at this slot of the flash map, their header and trailer are validated"
+                    )
+                        .takes_value(true)
+                        .possible_values(&["primary", "secondary"])
+                        .conflicts_with("oad")
+                )
+                .arg(
+                    opt(
+                        "flash-map",
+                        "MCUboot flash map used with --slot, the default is the one of Zephyr for CC13x2/CC26x2: 0xC000,0x3E000,0x32000"
+                    )
+                        .takes_value(true)
+                        .value_name("PRIMARY,SECONDARY,SIZE")
+                )
+                .arg(
+                    opt(
+                        "version-offset",
+                        "Offset in the firmware of a 32-bit little endian version number, compared with the one on the device to refuse downgrades. OAD and MCUboot images carry their version"
+                    )
+                        .takes_value(true)
+                        .value_name("BYTES")
+                )
+                .arg(
+                    opt(
+                        "allow-downgrade",
+                        "Flash an image with an older version than the one on the device"
+                    )
+                )
+                .arg(
+                    opt(
+                        "bin-offset",
+                        "Only flash the binary file from this offset, it's flashed at --address"
+                    )
+                        .takes_value(true)
+                        .value_name("BYTES")
+                )
+                .arg(
+                    opt(
+                        "bin-length",
+                        "Only flash this number of bytes of the binary file"
+                    )
+                        .takes_value(true)
+                        .value_name("BYTES")
+                )
                 .arg(
                     opt(
                         "write-erase",
@@ -296,14 +787,395 @@ fn cli() -> App<'static, 'static> {
                     )
                         .short("e")
                 )
+                .arg(
+                    opt(
+                        "bank-erase",
+                        "Erase the whole flash first, including the CCFG unless --keep-ccfg is used"
+                    )
+                        .conflicts_with("write-erase")
+                )
+                .arg(
+                    opt(
+                        "incremental",
+                        "Compare the CRC32 of each sector of the flash with the binary and only erase and write the sectors that differ, the rest of the partially written sectors is erased"
+                    )
+                        .conflicts_with_all(&["write-erase", "bank-erase"])
+                )
+                .arg(
+                    opt(
+                        "backup",
+                        "Save the sectors that are going to be erased to this file first, the whole flash with --bank-erase, as Intel HEX if its extension is .hex or as a raw binary, to restore them by flashing it"
+                    )
+                        .takes_value(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    opt(
+                        "rollback",
+                        "Flash the --backup again if the verification fails, the flash is verified even without --verify"
+                    )
+                        .requires("backup")
+                )
+                .arg(
+                    opt(
+                        "attest",
+                        "Print the SHA-256 digest of each flashed range once verified, as a record of what was flashed, the flash is verified even without --verify"
+                    )
+                )
+                .arg(
+                    opt(
+                        "report",
+                        "Save a JSON report of the run to this file, with the chip, its addresses, the images written, the durations and the verification result"
+                    )
+                        .takes_value(true)
+                        .value_name("FILE")
+                )
+                .arg(
+                    opt(
+                        "skip-identical",
+                        "Compare the CRC32 of the firmware with the flash first and don't erase nor write anything if it already matches"
+                    )
+                )
+                .arg(
+                    opt(
+                        "verify-erase",
+                        "Check that the erased sectors are blank (using their CRC32) before writing them"
+                    )
+                )
+                .arg(
+                    opt(
+                        "keep-ccfg",
+                        "Keep the CCFG of the device, it's restored after --bank-erase and replaces the CCFG of the binary"
+                    )
+                )
+                .arg(
+                    opt(
+                        "set-image-valid",
+                        "On CC2538, mark the image as valid on the CCA so it boots when the binary writes the last flash page, the CCA of the device is kept if the binary doesn't include one"
+                    )
+                )
                 .arg(
                     opt(
                         "force",
-                        "Force the write of the CCFG. Warning: may lock yourself out of the device."
+                        "Force the write of a CCFG that disables the bootloader or changes its backdoor, or of the CCA. Warning: may lock yourself out of the device."
                     )
                         .short("f")
                 )
             )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Verify that the flash contains a firmware, without writing anything")
+                .setting(AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("BIN")
+                        .required(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .required_unless("manifest")
+                        .help("Binary, Intel HEX (.hex) or ELF files to verify, as FILE or ADDRESS=FILE")
+                )
+                .arg(
+                    opt(
+                        "address",
+                        "Address in memory where the binary contents are expected, ELF and Intel HEX files carry their own addresses"
+                    )
+                        .short("a")
+                        .required(true)
+                        .default_value("0x00000000")
+                )
+                .arg(
+                    opt(
+                        "manifest",
+                        "TOML manifest describing the images to verify and their addresses"
+                    )
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .conflicts_with("BIN")
+                )
+                .arg(
+                    opt(
+                        "readback",
+                        "Read the flash back instead of comparing its CRC32, finds the address of the first difference but the memory reads must be enabled"
+                    )
+                )
+                .arg(
+                    opt(
+                        "verify-sample",
+                        "Only verify the CRC32 of this percentage of the sectors, spread over the firmware, for quick smoke tests of large images"
+                    )
+                        .takes_value(true)
+                        .value_name("PERCENT")
+                        .conflicts_with("readback")
+                )
+                .arg(
+                    opt(
+                        "bin-offset",
+                        "Only verify the binary file from this offset, it's expected at --address"
+                    )
+                        .takes_value(true)
+                        .value_name("BYTES")
+                )
+                .arg(
+                    opt(
+                        "bin-length",
+                        "Only verify this number of bytes of the binary file"
+                    )
+                        .takes_value(true)
+                        .value_name("BYTES")
+                )
+            )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Show which sectors of the flash differ from a firmware, without writing anything")
+                .setting(AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("BIN")
+                        .required(true)
+                        .takes_value(true)
+                        .multiple(true)
+                        .required_unless("manifest")
+                        .help("Binary, Intel HEX (.hex) or ELF files to compare, as FILE or ADDRESS=FILE")
+                )
+                .arg(
+                    opt(
+                        "address",
+                        "Address in memory where the binary contents are expected, ELF and Intel HEX files carry their own addresses"
+                    )
+                        .short("a")
+                        .required(true)
+                        .default_value("0x00000000")
+                )
+                .arg(
+                    opt(
+                        "manifest",
+                        "TOML manifest describing the images to compare and their addresses"
+                    )
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .conflicts_with("BIN")
+                )
+                .arg(
+                    opt(
+                        "bin-offset",
+                        "Only compare the binary file from this offset, it's expected at --address"
+                    )
+                        .takes_value(true)
+                        .value_name("BYTES")
+                )
+                .arg(
+                    opt(
+                        "bin-length",
+                        "Only compare this number of bytes of the binary file"
+                    )
+                        .takes_value(true)
+                        .value_name("BYTES")
+                )
+            )
+        .subcommand(
+            SubCommand::with_name("blank-check")
+                .about("Check that the flash is erased, sector by sector")
+                .setting(AppSettings::ColoredHelp)
+                .arg(
+                    opt("address", "Start of the range to check, by default the start of the flash")
+                        .short("a")
+                        .takes_value(true)
+                        .value_name("ADDRESS")
+                )
+                .arg(
+                    opt("length", "Number of bytes to check, by default up to the end of the flash")
+                        .short("l")
+                        .takes_value(true)
+                        .value_name("BYTES")
+                )
+                .arg(
+                    opt(
+                        "readback",
+                        "Read the flash back instead of comparing its CRC32, finds the address of the first byte that isn't erased but the memory reads must be enabled"
+                    )
+                )
+            )
+        .subcommand(
+            SubCommand::with_name("read")
+                .about("Read the flash to a file, e.g.: to back up a device")
+                .setting(AppSettings::ColoredHelp)
+                .arg(
+                    opt("address", "Start of the range to read, by default the start of the flash")
+                        .short("a")
+                        .takes_value(true)
+                        .value_name("ADDRESS")
+                )
+                .arg(
+                    opt("length", "Number of bytes to read, by default up to the end of the flash")
+                        .short("l")
+                        .takes_value(true)
+                        .value_name("BYTES")
+                )
+                .arg(
+                    opt("output", "File to save the flash to, as Intel HEX if its extension is .hex or as a raw binary")
+                        .short("o")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true)
+                )
+            )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Show information about the connected device")
+                .setting(AppSettings::ColoredHelp)
+        )
+        .subcommand(
+            SubCommand::with_name("ccfg")
+                .about("Inspect or change the CCFG (Customer Configuration) of a CC13xx/CC26xx device")
+                .setting(AppSettings::ColoredHelp)
+                .subcommand(
+                    SubCommand::with_name("dump")
+                        .about("Read the CCFG and print its fields")
+                        .setting(AppSettings::ColoredHelp)
+                        .arg(
+                            opt("output", "Also save the raw CCFG into a file, as Intel HEX at the CCFG address if the file ends in .hex")
+                                .short("o")
+                                .takes_value(true)
+                                .value_name("FILE")
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Change CCFG fields on the device, or on a firmware image with --image")
+                        .setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("FIELDS")
+                                .required(true)
+                                .multiple(true)
+                                .help("KEY=VALUE pairs. Keys: bootloader, backdoor, backdoor-pin, backdoor-level, cpu-dap, prcm-tap, test-tap, pbist1-tap, pbist2-tap, wuc-tap, jtag, ti-fa, bank-erase, chip-erase, image-valid, ieee-address, ble-address, protect, unprotect")
+                        )
+                        .arg(
+                            opt("image", "Patch the CCFG of a firmware image instead, the image must end with the CCFG")
+                                .takes_value(true)
+                                .value_name("FILE")
+                        )
+                        .arg(
+                            opt("output", "Where the patched image is saved, by default the image is modified in place")
+                                .short("o")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .requires("image")
+                        )
+                        .arg(opt("yes", "Don't ask for confirmation before rewriting the CCFG of the device").short("y"))
+                        .arg(
+                            opt("force", "Allow disabling the bootloader or its backdoor. Warning: may lock yourself out of the device.")
+                                .short("f")
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("generate")
+                        .about("Apply a TOML description of the CCFG (with the keys of `ccfg set`) to a raw CCFG or to a firmware image")
+                        .setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("CONFIG")
+                                .required(true)
+                                .help("TOML file, e.g.: `backdoor-pin = 13`")
+                        )
+                        .arg(
+                            opt("base", "Raw CCFG (88 bytes) to start from, e.g.: saved with `ccfg dump --output`")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .required_unless("image")
+                        )
+                        .arg(
+                            opt("image", "Patch the CCFG of a firmware image instead, the image must end with the CCFG")
+                                .takes_value(true)
+                                .value_name("FILE")
+                                .conflicts_with("base")
+                        )
+                        .arg(
+                            opt("output", "Where the result is saved, by default the input is modified in place")
+                                .short("o")
+                                .takes_value(true)
+                                .value_name("FILE")
+                        )
+                        .arg(
+                            opt("force", "Allow disabling the bootloader or its backdoor. Warning: may lock yourself out of the device.")
+                                .short("f")
+                        )
+                )
+                .subcommand(
+                    SubCommand::with_name("write")
+                        .about("Write only the CCFG of the device from a file, e.g.: saved with `ccfg dump --output`")
+                        .setting(AppSettings::ColoredHelp)
+                        .arg(
+                            Arg::with_name("FILE")
+                                .required(true)
+                                .help("File with the raw CCFG (88 bytes)")
+                        )
+                        .arg(opt("yes", "Don't ask for confirmation before rewriting the CCFG of the device").short("y"))
+                        .arg(
+                            opt("force", "Allow disabling the bootloader or its backdoor. Warning: may lock yourself out of the device.")
+                                .short("f")
+                        )
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("set-mac")
+                .about("Write the secondary IEEE 802.15.4 address on the CCFG, it overrides the factory address")
+                .setting(AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .required(true)
+                        .help("The address as shown by `info`, e.g.: 00:12:4B:00:14:B5:B6:01")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("set-ble-mac")
+                .about("Write the BLE address on the CCFG, it overrides the factory address")
+                .setting(AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .required(true)
+                        .help("The address in memory order, e.g.: 80:6F:B0:EE:1D:A2")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("cc32xx")
+                .about("Program a CC3200/CC3220 SimpleLink Wi-Fi device through its UART bootloader, the device must be reset with SOP2 set")
+                .setting(AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("BIN")
+                        .takes_value(true)
+                        .help("File to write to the serial flash")
+                )
+                .arg(
+                    opt("file", "Name of the file on the serial flash")
+                        .default_value("/sys/mcuimg.bin")
+                )
+                .arg(
+                    opt("format", "Format the serial flash first, removing all the files. The size of the serial flash in KB: 512, 1024, 2048, 4096, 8192 or 16384")
+                        .takes_value(true)
+                        .value_name("SIZE")
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("msp430")
+                .about("Flash a binary file on a MSP430/MSP432 device through its UART BSL, the BSL must be invoked first")
+                .setting(AppSettings::ColoredHelp)
+                .arg(
+                    Arg::with_name("BIN")
+                        .required(true)
+                        .takes_value(true)
+                        .help("Binary file to flash")
+                )
+                .arg(
+                    opt("address", "Address in memory where the binary contents will be flashed")
+                        .short("a")
+                        .required(true)
+                        .takes_value(true)
+                )
+                .arg(
+                    opt("password", "File with the BSL password (the interrupt vector table). If not given the device is mass erased first")
+                        .takes_value(true)
+                        .value_name("FILE")
+                )
+                .arg(opt("msp432", "The device is a MSP432P4xx"))
+        )
         .subcommand(
             SubCommand::with_name("list")
                 .about("List available serial ports")
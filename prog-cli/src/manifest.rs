@@ -0,0 +1,148 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ti_sbl::{flasher::Erase, util::VerifyMethod};
+
+/// A flash job described by a TOML manifest, e.g.:
+///
+/// ```toml
+/// erase = "sectors"
+/// keep-ccfg = true
+/// verify = true # or "crc", or "readback"
+///
+/// [[image]]
+/// file = "app.bin"
+/// address = 0x00000000
+///
+/// [[image]]
+/// file = "stack.hex"
+/// ```
+///
+/// The paths of the images are relative to the manifest.
+#[derive(Debug)]
+pub struct Manifest {
+    /// The files to flash, with the address of the raw binaries.
+    pub images: Vec<(Option<u32>, PathBuf)>,
+    pub erase: Option<Erase>,
+    pub keep_ccfg: bool,
+    pub verify: Option<VerifyMethod>,
+    pub force: bool,
+}
+
+impl Manifest {
+    /// Read the manifest at `path`.
+    pub fn read(path: &Path) -> Result<Manifest> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read `{}`", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        Manifest::parse(&text, dir).with_context(|| {
+            format!("Invalid flash manifest `{}`", path.display())
+        })
+    }
+
+    /// Parse the manifest in `text`, the images are relative to `dir`.
+    pub fn parse_bytes(text: &[u8], dir: &Path) -> Result<Manifest> {
+        let text = std::str::from_utf8(text)?;
+        Manifest::parse(text, dir)
+    }
+
+    fn parse(text: &str, dir: &Path) -> Result<Manifest> {
+        let value = text.parse::<toml::Value>()?;
+        let table = value
+            .as_table()
+            .ok_or_else(|| anyhow!("Expected a table"))?;
+
+        let mut manifest = Manifest {
+            images: Vec::new(),
+            erase: None,
+            keep_ccfg: false,
+            verify: None,
+            force: false,
+        };
+        for (key, value) in table {
+            match key.as_str() {
+                "erase" => {
+                    manifest.erase = Some(match value.as_str() {
+                        Some("none") => Erase::None,
+                        Some("sectors") => Erase::Sectors,
+                        Some("bank") => Erase::Bank,
+                        Some("incremental") => Erase::Incremental,
+                        _ => bail!(
+                            "`erase` must be \"none\", \"sectors\", \"bank\" or \"incremental\""
+                        ),
+                    })
+                }
+                "keep-ccfg" => manifest.keep_ccfg = boolean(key, value)?,
+                "verify" => manifest.verify = verify_method(value)?,
+                "force" => manifest.force = boolean(key, value)?,
+                "image" => {
+                    let images = value
+                        .as_array()
+                        .ok_or_else(|| anyhow!("Expected [[image]] tables"))?;
+                    for image in images {
+                        manifest.images.push(parse_image(image, dir)?);
+                    }
+                }
+                _ => bail!("Unknown key `{}`", key),
+            }
+        }
+
+        if manifest.images.is_empty() {
+            bail!("No [[image]] to flash");
+        }
+
+        Ok(manifest)
+    }
+}
+
+fn parse_image(
+    image: &toml::Value,
+    dir: &Path,
+) -> Result<(Option<u32>, PathBuf)> {
+    let file = image
+        .get("file")
+        .and_then(toml::Value::as_str)
+        .ok_or_else(|| anyhow!("Every [[image]] needs a `file`"))?;
+    let address = match image.get("address") {
+        Some(address) => {
+            let address = address
+                .as_integer()
+                .filter(|address| *address >= 0 && *address <= 0xFFFF_FFFF)
+                .ok_or_else(|| anyhow!("Invalid address of `{}`", file))?;
+            Some(address as u32)
+        }
+        None => None,
+    };
+
+    Ok((address, dir.join(file)))
+}
+
+fn verify_method(value: &toml::Value) -> Result<Option<VerifyMethod>> {
+    match (value.as_bool(), value.as_str()) {
+        (Some(true), _) | (_, Some("crc")) => Ok(Some(VerifyMethod::Crc32)),
+        (_, Some("readback")) => Ok(Some(VerifyMethod::Readback)),
+        (Some(false), _) => Ok(None),
+        _ => bail!("`verify` must be true, false, \"crc\" or \"readback\""),
+    }
+}
+
+fn boolean(key: &str, value: &toml::Value) -> Result<bool> {
+    value
+        .as_bool()
+        .ok_or_else(|| anyhow!("`{}` must be true or false", key))
+}
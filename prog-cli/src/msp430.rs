@@ -0,0 +1,79 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use serial::SerialPort;
+use ti_sbl::msp430bsl::{self, Bsl, Variant};
+
+use anyhow::{bail, Context, Result};
+use clap::ArgMatches;
+
+/// MSP430 subcommand entry point.
+pub fn msp430(port_name: &str, args: &ArgMatches<'_>) -> Result<()> {
+    let variant = if args.is_present("msp432") {
+        Variant::Msp432
+    } else {
+        Variant::Msp430
+    };
+    let address = parse_hex(args.value_of("address").unwrap())
+        .context("Invalid address, must be an hexadecimal number")?;
+    let path = args.value_of("BIN").unwrap();
+    let binary = std::fs::read(path)
+        .with_context(|| format!("Couldn't read `{}`", path))?;
+
+    log::info!("Opening serial port `{}`", port_name);
+    let mut port = serial::open(port_name).with_context(|| {
+        format!("Couldn't open serial port `{}`", port_name)
+    })?;
+    port.set_timeout(Duration::from_secs(2))?;
+    port.configure(&msp430bsl::port_settings(variant))?;
+
+    let mut bsl = Bsl::new(port, variant);
+    match args.value_of("password") {
+        Some(password) => {
+            let password = std::fs::read(password).with_context(|| {
+                format!("Couldn't read password file `{}`", password)
+            })?;
+            bsl.unlock(&password).context("Couldn't unlock the BSL")?;
+        }
+        None => {
+            log::info!("Mass erasing");
+            bsl.mass_erase().context("Couldn't mass erase")?;
+            bsl.unlock_erased().context("Couldn't unlock the BSL")?;
+        }
+    }
+
+    log::info!("Writing {} bytes at {:#X}", binary.len(), address);
+    bsl.write(address, &binary)
+        .context("Couldn't write the binary")?;
+
+    log::info!("Verifying");
+    for (i, chunk) in binary.chunks(u16::MAX as usize).enumerate() {
+        let chunk_address = address + (i * u16::MAX as usize) as u32;
+        let crc = bsl
+            .crc(chunk_address, chunk.len() as u16)
+            .context("Couldn't calculate the CRC")?;
+        if crc != msp430bsl::crc16(chunk) {
+            bail!("Verification failed at {:#X}", chunk_address);
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_hex(s: &str) -> Result<u32> {
+    let s = s.trim_start_matches("0x");
+    Ok(u32::from_str_radix(s, 16)?)
+}
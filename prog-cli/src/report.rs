@@ -0,0 +1,114 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use ti_sbl::{chipdb::ChipInfo, firmware::Segment};
+
+use crate::bundle;
+
+/// An image written by the `flash` subcommand.
+#[derive(Debug)]
+struct Image {
+    address: u32,
+    size: usize,
+    crc32: u32,
+    sha256: String,
+}
+
+/// A JSON report of a `flash` run, saved with `--report`, for traceability
+/// in production databases.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub chip: Option<ChipInfo>,
+    /// The value of `CMD_GET_CHIP_ID`.
+    pub chip_id: Option<u32>,
+    pub ieee_address: Option<String>,
+    pub ble_address: Option<String>,
+    images: Vec<Image>,
+    /// Whether nothing was written as the flash already matched.
+    pub skipped: bool,
+    pub erase: Option<Duration>,
+    pub write: Option<Duration>,
+    pub verify: Option<Duration>,
+    /// Result of the verification, if the flash was verified.
+    pub verified: Option<bool>,
+    /// Whether the backup was restored after the verification failed.
+    pub rolled_back: bool,
+}
+
+impl Report {
+    /// Record the `segments` to write.
+    pub fn set_images(&mut self, segments: &[Segment]) {
+        self.images = segments
+            .iter()
+            .map(|segment| Image {
+                address: segment.address,
+                size: segment.data.len(),
+                crc32: ti_sbl::crc32(&segment.data),
+                sha256: bundle::sha256_hex(&segment.data),
+            })
+            .collect();
+    }
+
+    /// Save the report to `path`, with the `result` of the run and its
+    /// `elapsed` time.
+    pub fn save(
+        &self,
+        path: &str,
+        result: &Result<()>,
+        elapsed: Duration,
+    ) -> Result<()> {
+        let secs = |duration: Option<Duration>| {
+            duration.map(|duration| duration.as_secs_f64())
+        };
+        let report = json!({
+            "chip": self.chip.map(|chip| json!({
+                "name": chip.name,
+                "family": format!("{:?}", chip.family),
+                "chip_id": self.chip_id.map(|id| format!("{:#010X}", id)),
+                "flash_size": chip.flash,
+                "ram_size": chip.ram,
+            })),
+            "ieee_address": self.ieee_address,
+            "ble_address": self.ble_address,
+            "images": self.images.iter().map(|image| json!({
+                "address": format!("{:#010X}", image.address),
+                "size": image.size,
+                "crc32": format!("{:#010X}", image.crc32),
+                "sha256": image.sha256,
+            })).collect::<Vec<_>>(),
+            "skipped": self.skipped,
+            "durations": {
+                "erase": secs(self.erase),
+                "write": secs(self.write),
+                "verify": secs(self.verify),
+                "total": elapsed.as_secs_f64(),
+            },
+            "verified": self.verified,
+            "rolled_back": self.rolled_back,
+            "success": result.is_ok(),
+            "error": match *result {
+                Ok(()) => Value::Null,
+                Err(ref e) => Value::from(format!("{:#}", e)),
+            },
+        });
+
+        let text = serde_json::to_string_pretty(&report)?;
+        std::fs::write(path, text + "\n")
+            .with_context(|| format!("Couldn't save the report to `{}`", path))
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{fmt, io};
+
+use serial::SerialPort;
+
+use crate::{
+    trace::TraceSink, util, AckTimeouts, Device, Diagnostics, Family, Result,
+    SyncRetry,
+};
+
+/// Default number of times a response is requested again if its checksum is
+/// invalid.
+const DEFAULT_RESPONSE_ATTEMPTS: usize = 3;
+
+/// Builder of a [`Device`].
+///
+/// # Example
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use ti_sbl::{DeviceBuilder, Family, SyncRetry};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let port = serial::open("/dev/ttyUSB0")?;
+/// let device = DeviceBuilder::new(Family::CC26X2)
+///     .sync_retry(SyncRetry {
+///         attempts: 3,
+///         delay: Duration::from_millis(500),
+///     })
+///     .strict(true)
+///     .build(port)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct DeviceBuilder {
+    family: Family,
+    ack_timeouts: AckTimeouts,
+    sync_retry: SyncRetry,
+    response_attempts: usize,
+    auto_baud: bool,
+    strict: bool,
+    detect_family: bool,
+    trace_sink: Option<TraceSink>,
+    sector_size: Option<u32>,
+}
+
+impl DeviceBuilder {
+    /// Create a new builder for a device of the `family`.
+    pub fn new(family: Family) -> Self {
+        DeviceBuilder {
+            family,
+            ack_timeouts: AckTimeouts::default(),
+            sync_retry: SyncRetry::default(),
+            response_attempts: DEFAULT_RESPONSE_ATTEMPTS,
+            auto_baud: true,
+            strict: false,
+            detect_family: false,
+            trace_sink: None,
+            sector_size: None,
+        }
+    }
+
+    /// Set the ACK timeouts used for each class of command.
+    ///
+    /// See [`Device::set_ack_timeouts`].
+    pub fn ack_timeouts(mut self, ack_timeouts: AckTimeouts) -> Self {
+        self.ack_timeouts = ack_timeouts;
+        self
+    }
+
+    /// Set the synchronization attempts made with the bootloader.
+    ///
+    /// See [`Device::set_sync_retry`].
+    pub fn sync_retry(mut self, sync_retry: SyncRetry) -> Self {
+        self.sync_retry = sync_retry;
+        self
+    }
+
+    /// Number of times a response is requested again if its checksum is
+    /// invalid, by default `3`. At least one attempt is always made.
+    pub fn response_attempts(mut self, attempts: usize) -> Self {
+        self.response_attempts = attempts;
+        self
+    }
+
+    /// Whether the auto baud procedure is performed when the bootloader
+    /// doesn't respond, enabled by default.
+    ///
+    /// Disable it when the bootloader was already synchronized, e.g.: by a
+    /// previous session at the same baudrate.
+    pub fn auto_baud(mut self, auto_baud: bool) -> Self {
+        self.auto_baud = auto_baud;
+        self
+    }
+
+    /// Whether unexpected bytes received before an ACK are treated as an
+    /// error ([`Error::InvalidResponse`](crate::Error::InvalidResponse))
+    /// instead of being discarded, disabled by default.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Whether the family is detected after synchronizing with the
+    /// bootloader, replacing the family given to [`DeviceBuilder::new`].
+    /// Disabled by default.
+    ///
+    /// See [`util::detect_family`].
+    pub fn detect_family(mut self, detect_family: bool) -> Self {
+        self.detect_family = detect_family;
+        self
+    }
+
+    /// Override the sector size of the family, [`DeviceBuilder::build`]
+    /// fails if it's not a power of two.
+    ///
+    /// See [`Device::set_sector_size`].
+    pub fn sector_size(mut self, sector_size: u32) -> Self {
+        self.sector_size = Some(sector_size);
+        self
+    }
+
+    /// Record the traffic with the device, including the synchronization.
+    ///
+    /// See [`Device::set_trace_sink`].
+    pub fn trace_sink<W>(mut self, sink: W) -> Self
+    where
+        W: io::Write + Send + 'static,
+    {
+        self.trace_sink = Some(TraceSink::new(Box::new(sink)));
+        self
+    }
+
+    /// Create the `Device` from an already opened port and synchronize with
+    /// the bootloader.
+    ///
+    /// See [`Device::new`].
+    pub fn build<P>(self, port: P) -> Result<Device<P>>
+    where
+        P: SerialPort,
+    {
+        let mut device = Device {
+            port,
+            family: self.family,
+            ack_timeouts: self.ack_timeouts,
+            sync_retry: self.sync_retry,
+            response_attempts: self.response_attempts.max(1),
+            auto_baud: self.auto_baud,
+            strict: self.strict,
+            deadline: None,
+            trace_sink: self.trace_sink,
+            diagnostics: Diagnostics::default(),
+            sector_size: None,
+        };
+        device.set_sector_size(self.sector_size)?;
+
+        if !self.detect_family {
+            device.check_baudrate();
+        }
+
+        device.init_communications()?;
+        // The timeouts before the auto baud procedure are expected.
+        device.reset_diagnostics();
+
+        if self.detect_family {
+            device.family = util::detect_family(&mut device)?;
+            log::debug!("Detected family: {:?}", device.family);
+            device.check_baudrate();
+        }
+
+        Ok(device)
+    }
+}
+
+impl fmt::Debug for DeviceBuilder {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("DeviceBuilder")
+            .field("family", &self.family)
+            .field("ack_timeouts", &self.ack_timeouts)
+            .field("sync_retry", &self.sync_retry)
+            .field("response_attempts", &self.response_attempts)
+            .field("auto_baud", &self.auto_baud)
+            .field("strict", &self.strict)
+            .field("detect_family", &self.detect_family)
+            .field("trace_sink", &self.trace_sink.is_some())
+            .field("sector_size", &self.sector_size)
+            .finish()
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # CC3200/CC3220 SimpleLink UART bootloader
+//!
+//! The Wi-Fi SimpleLink devices have a different ROM bootloader, reached by
+//! holding the UART RX line low (a break) while the device resets. Instead
+//! of programming the flash directly, it manages the files of the
+//! SimpleLink file system on the external serial flash, the application is
+//! the `/sys/mcuimg.bin` file.
+//!
+//! The framing is similar to the Serial Bootloader Interface (a checksum
+//! over the data and `00 CC` ACKs) but with a 16-bit length, the protocol
+//! follows the one used by TI's Uniflash and the `cc3200tool` project.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use serial::SerialPort;
+//! use ti_sbl::cc32xx::Bootloader;
+//!
+//! let mut port = serial::open("/dev/ttyUSB0")?;
+//! port.configure(&ti_sbl::cc32xx::port_settings())?;
+//!
+//! let mut bootloader = Bootloader::new(port)?;
+//! bootloader.format_flash(1024)?;
+//! bootloader.write_file("/sys/mcuimg.bin", &std::fs::read("app.bin")?)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{io, thread, time::Duration};
+
+use serial::SerialPort;
+
+use crate::{Error, Result};
+
+const OPCODE_START_UPLOAD: u8 = 0x21;
+const OPCODE_FINISH_UPLOAD: u8 = 0x22;
+const OPCODE_GET_LAST_STATUS: u8 = 0x23;
+const OPCODE_FILE_CHUNK: u8 = 0x24;
+const OPCODE_FORMAT_FLASH: u8 = 0x28;
+const OPCODE_ERASE_FILE: u8 = 0x2E;
+const OPCODE_GET_VERSION_INFO: u8 = 0x2F;
+
+/// Status returned by `OPCODE_GET_LAST_STATUS` on success.
+const STATUS_SUCCESS: u8 = 0x40;
+
+/// Size of the chunks of a file upload.
+const FILE_CHUNK_SIZE: usize = 4080;
+
+/// Allocation block sizes of the file system.
+const BLOCK_SIZES: [u32; 5] = [256, 1024, 4096, 16384, 65536];
+
+/// Supported sizes of the serial flash, in KB, see
+/// [`Bootloader::format_flash`].
+pub const FLASH_SIZES: [u32; 6] = [512, 1024, 2048, 4096, 8192, 16384];
+
+/// Serial port settings of the bootloader, it only works at 921600 baud.
+pub fn port_settings() -> serial::PortSettings {
+    serial::PortSettings {
+        baud_rate: serial::BaudRate::BaudOther(921_600),
+        ..crate::port_settings()
+    }
+}
+
+/// Connection with the CC32xx UART bootloader.
+pub struct Bootloader<P> {
+    port: P,
+}
+
+impl<P> Bootloader<P>
+where
+    P: SerialPort,
+{
+    /// Break into the bootloader.
+    ///
+    /// The `port` must be configured with [`port_settings`] and the device
+    /// must be reset right before, or be held in reset, with the SOP2 pin
+    /// set.
+    pub fn new(port: P) -> Result<Self> {
+        let mut bootloader = Bootloader { port };
+        bootloader.send_break()?;
+        bootloader.expect_ack(OPCODE_GET_VERSION_INFO)?;
+
+        Ok(bootloader)
+    }
+
+    /// Consume the `Bootloader` and return the underlying port.
+    pub fn into_port(self) -> P {
+        self.port
+    }
+
+    /// Read the version information of the bootloader (28 bytes).
+    pub fn version(&mut self) -> Result<Vec<u8>> {
+        self.send_packet(&[OPCODE_GET_VERSION_INFO])?;
+        self.read_packet()
+    }
+
+    /// Format the serial flash, `size` is the size of the serial flash in
+    /// KB, one of [`FLASH_SIZES`]. All the files are removed.
+    pub fn format_flash(&mut self, size: u32) -> Result<()> {
+        if !FLASH_SIZES.contains(&size) {
+            return Err(Error::InvalidLength(size as usize));
+        }
+
+        let mut cmd = vec![OPCODE_FORMAT_FLASH];
+        // Blocks of 4 KB.
+        for word in [2, size / 4, 0, 0, 2].iter() {
+            cmd.extend_from_slice(&u32::to_be_bytes(*word));
+        }
+        self.send_packet(&cmd)?;
+        self.check_status()
+    }
+
+    /// Remove the `name` file.
+    pub fn erase_file(&mut self, name: &str) -> Result<()> {
+        let mut cmd = vec![OPCODE_ERASE_FILE];
+        cmd.extend_from_slice(&[0; 4]);
+        cmd.extend_from_slice(name.as_bytes());
+        cmd.push(0);
+        self.send_packet(&cmd)?;
+        self.check_status()
+    }
+
+    /// Write the `data` to the `name` file, replacing it if it exists.
+    pub fn write_file(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        let flags = allocation_flags(data.len() as u32)
+            .ok_or(Error::InvalidLength(data.len()))?;
+
+        let mut cmd = vec![OPCODE_START_UPLOAD];
+        cmd.extend_from_slice(&flags.to_be_bytes());
+        cmd.extend_from_slice(&[0; 4]);
+        cmd.extend_from_slice(name.as_bytes());
+        cmd.extend_from_slice(&[0, 0]);
+        self.send_packet(&cmd)?;
+
+        // The file token, zero if the file could be opened.
+        let mut token = [0u8; 4];
+        self.port.read_exact(&mut token)?;
+        if token != [0; 4] {
+            return Err(Error::InvalidResponse);
+        }
+
+        for (i, chunk) in data.chunks(FILE_CHUNK_SIZE).enumerate() {
+            log::debug!("Writing chunk #{} of `{}`", i, name);
+            let offset = (i * FILE_CHUNK_SIZE) as u32;
+            let mut cmd = Vec::with_capacity(5 + chunk.len());
+            cmd.push(OPCODE_FILE_CHUNK);
+            cmd.extend_from_slice(&offset.to_be_bytes());
+            cmd.extend_from_slice(chunk);
+            self.send_packet(&cmd)?;
+            self.check_status()?;
+        }
+
+        // Empty signature and certificate.
+        let mut cmd = vec![0u8; 1 + 63 + 257];
+        cmd[0] = OPCODE_FINISH_UPLOAD;
+        self.send_packet(&cmd)?;
+        self.check_status()
+    }
+
+    /// Hold the RX line of the device low for longer than a character.
+    ///
+    /// The serial crate can't send a break, so a zero is sent at a fraction
+    /// of the baudrate instead.
+    fn send_break(&mut self) -> Result<()> {
+        self.port
+            .reconfigure(&|settings| {
+                settings.set_baud_rate(serial::BaudRate::Baud9600)
+            })
+            .map_err(io::Error::from)?;
+        self.port.write_all(&[0x00])?;
+        self.port.flush()?;
+        // Let the byte go out before restoring the baudrate.
+        thread::sleep(Duration::from_millis(5));
+        self.port
+            .configure(&port_settings())
+            .map_err(io::Error::from)?;
+
+        Ok(())
+    }
+
+    fn send_packet(&mut self, data: &[u8]) -> Result<()> {
+        let len = (data.len() + 2) as u16;
+        let checksum = checksum(data);
+
+        let mut packet = Vec::with_capacity(data.len() + 3);
+        packet.extend_from_slice(&len.to_be_bytes());
+        packet.push(checksum);
+        packet.extend_from_slice(data);
+        self.port.write_all(&packet)?;
+
+        self.expect_ack(data[0])
+    }
+
+    fn expect_ack(&mut self, opcode: u8) -> Result<()> {
+        let mut ack = [0u8; 2];
+        match self.port.read_exact(&mut ack) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                return Err(Error::Timeout)
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        match ack {
+            [0x00, 0xCC] => Ok(()),
+            [0x00, 0x33] => Err(Error::RawNack(opcode)),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<Vec<u8>> {
+        let mut header = [0u8; 3];
+        self.port.read_exact(&mut header)?;
+        let len = u16::from_be_bytes([header[0], header[1]]) as usize;
+        if len < 2 {
+            return Err(Error::InvalidResponse);
+        }
+
+        let mut data = vec![0u8; len - 2];
+        self.port.read_exact(&mut data)?;
+        if checksum(&data) != header[2] {
+            return Err(Error::InvalidChecksum);
+        }
+
+        self.port.write_all(&[0x00, 0xCC])?;
+
+        Ok(data)
+    }
+
+    fn check_status(&mut self) -> Result<()> {
+        self.send_packet(&[OPCODE_GET_LAST_STATUS])?;
+        let status = self.read_packet()?;
+        match status.first() {
+            Some(&STATUS_SUCCESS) => Ok(()),
+            Some(&status) => Err(Error::UnknownStatus(status)),
+            None => Err(Error::ResponseLength {
+                expected: 1,
+                found: 0,
+            }),
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+}
+
+/// Allocation flags of a file of `len` bytes: the block size index and the
+/// number of blocks, `None` if the file is too large.
+fn allocation_flags(len: u32) -> Option<u32> {
+    let (index, block_size) = BLOCK_SIZES
+        .iter()
+        .enumerate()
+        .find(|(_, block_size)| **block_size * 255 >= len)?;
+    let blocks = (len + block_size - 1) / block_size;
+
+    Some(0x3000 | (index as u32) << 8 | blocks.max(1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allocation_flags() {
+        assert_eq!(allocation_flags(0), Some(0x3001));
+        assert_eq!(allocation_flags(1000), Some(0x3004));
+        assert_eq!(allocation_flags(100_000), Some(0x3162));
+        assert_eq!(allocation_flags(65536 * 255 + 1), None);
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # CC2538 Customer Configuration Area
+//!
+//! The last 44 bytes of the CC2538 flash hold the CCA, it's read by the boot
+//! ROM to decide whether to jump to the application or to stay in the
+//! bootloader:
+//!
+//! | Offset | Field                           |
+//! |--------|---------------------------------|
+//! | `0x00` | Bootloader backdoor config      |
+//! | `0x04` | Image valid, zero if valid      |
+//! | `0x08` | Application entry point         |
+//! | `0x0C` | Page lock bits (32 bytes)       |
+//!
+//! An invalid image or a disabled backdoor can leave the device without a
+//! way to reach the bootloader other than JTAG, so the CCA should be
+//! written with care.
+
+use std::convert::TryFrom;
+
+use crate::{Error, Result};
+
+/// Size of the CCA, in bytes.
+pub const CCA_SIZE: usize = 44;
+
+/// Value of the image valid field for a valid image.
+const IMAGE_VALID: u32 = 0x0000_0000;
+/// Value of the image valid field on an erased flash.
+const IMAGE_INVALID: u32 = 0xFFFF_FFFF;
+
+const BACKDOOR_ENABLE: u32 = 1 << 28;
+const BACKDOOR_ACTIVE_HIGH: u32 = 1 << 27;
+const BACKDOOR_PIN_S: u32 = 24;
+const BACKDOOR_PIN_M: u32 = 0x7 << BACKDOOR_PIN_S;
+
+/// Bootloader backdoor configuration.
+///
+/// When enabled, the boot ROM stays in the bootloader if the pin `PA<pin>`
+/// is at the active level on reset.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Backdoor {
+    /// Whether the backdoor is enabled.
+    pub enabled: bool,
+    /// Whether the pin is active high, otherwise it's active low.
+    pub active_high: bool,
+    /// Port A pin number, `0` to `7`.
+    pub pin: u8,
+}
+
+/// The CC2538 CCA contents.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cca {
+    bootloader_config: u32,
+    image_valid: u32,
+    entry_point: u32,
+    lock_bits: [u8; 32],
+}
+
+impl Cca {
+    /// Parse the CCA from its `CCA_SIZE` bytes.
+    pub fn parse(data: &[u8]) -> Result<Cca> {
+        if data.len() != CCA_SIZE {
+            return Err(Error::InvalidLength(data.len()));
+        }
+
+        let word = |offset: usize| {
+            u32::from_le_bytes(
+                <[u8; 4]>::try_from(&data[offset..offset + 4]).unwrap(),
+            )
+        };
+        let mut lock_bits = [0u8; 32];
+        lock_bits.copy_from_slice(&data[12..]);
+
+        Ok(Cca {
+            bootloader_config: word(0),
+            image_valid: word(4),
+            entry_point: word(8),
+            lock_bits,
+        })
+    }
+
+    /// Serialize the CCA back to bytes.
+    pub fn to_bytes(&self) -> [u8; CCA_SIZE] {
+        let mut data = [0u8; CCA_SIZE];
+        data[0..4].copy_from_slice(&self.bootloader_config.to_le_bytes());
+        data[4..8].copy_from_slice(&self.image_valid.to_le_bytes());
+        data[8..12].copy_from_slice(&self.entry_point.to_le_bytes());
+        data[12..].copy_from_slice(&self.lock_bits);
+        data
+    }
+
+    /// The raw bootloader backdoor configuration word.
+    #[inline]
+    pub fn bootloader_config(&self) -> u32 {
+        self.bootloader_config
+    }
+
+    /// The bootloader backdoor configuration.
+    pub fn backdoor(&self) -> Backdoor {
+        Backdoor {
+            enabled: self.bootloader_config & BACKDOOR_ENABLE != 0,
+            active_high: self.bootloader_config & BACKDOOR_ACTIVE_HIGH != 0,
+            pin: ((self.bootloader_config & BACKDOOR_PIN_M) >> BACKDOOR_PIN_S)
+                as u8,
+        }
+    }
+
+    /// Change the bootloader backdoor configuration, the reserved bits are
+    /// kept.
+    ///
+    /// # Panics
+    ///
+    /// If the backdoor pin is greater than `7`.
+    pub fn set_backdoor(&mut self, backdoor: Backdoor) {
+        assert!(backdoor.pin <= 7, "invalid backdoor pin");
+
+        let mut config = self.bootloader_config
+            & !(BACKDOOR_ENABLE | BACKDOOR_ACTIVE_HIGH | BACKDOOR_PIN_M);
+        if backdoor.enabled {
+            config |= BACKDOOR_ENABLE;
+        }
+        if backdoor.active_high {
+            config |= BACKDOOR_ACTIVE_HIGH;
+        }
+        config |= u32::from(backdoor.pin) << BACKDOOR_PIN_S;
+        self.bootloader_config = config;
+    }
+
+    /// Whether the image is marked as valid, the boot ROM only jumps to the
+    /// application if it is.
+    #[inline]
+    pub fn image_valid(&self) -> bool {
+        self.image_valid == IMAGE_VALID
+    }
+
+    /// Mark the image as valid or invalid.
+    pub fn set_image_valid(&mut self, valid: bool) {
+        self.image_valid = if valid { IMAGE_VALID } else { IMAGE_INVALID };
+    }
+
+    /// Address of the application vector table.
+    #[inline]
+    pub fn entry_point(&self) -> u32 {
+        self.entry_point
+    }
+
+    /// Change the address of the application vector table.
+    #[inline]
+    pub fn set_entry_point(&mut self, address: u32) {
+        self.entry_point = address;
+    }
+
+    /// The page lock bits, a cleared bit locks the page.
+    #[inline]
+    pub fn lock_bits(&self) -> &[u8; 32] {
+        &self.lock_bits
+    }
+
+    /// Whether the flash `page` is locked (write protected).
+    ///
+    /// # Panics
+    ///
+    /// If the `page` is not lower than `256`.
+    pub fn page_locked(&self, page: u32) -> bool {
+        assert!(page < 256, "invalid page");
+        self.lock_bits[(page / 8) as usize] & (1 << (page % 8)) == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cca() {
+        let mut data = [0xFFu8; CCA_SIZE];
+        // Backdoor enabled, active low, on PA3, valid image at 0x200000.
+        data[0..4].copy_from_slice(&0xF3FF_FFFFu32.to_le_bytes());
+        data[4..8].copy_from_slice(&[0; 4]);
+        data[8..12].copy_from_slice(&0x0020_0000u32.to_le_bytes());
+
+        let mut cca = Cca::parse(&data).unwrap();
+        assert_eq!(
+            cca.backdoor(),
+            Backdoor {
+                enabled: true,
+                active_high: false,
+                pin: 3,
+            }
+        );
+        assert!(cca.image_valid());
+        assert_eq!(cca.entry_point(), 0x0020_0000);
+        assert!(!cca.page_locked(0));
+        assert_eq!(cca.to_bytes(), data);
+
+        cca.set_backdoor(Backdoor {
+            enabled: false,
+            active_high: true,
+            pin: 7,
+        });
+        cca.set_image_valid(false);
+        assert_eq!(cca.bootloader_config(), 0xEFFF_FFFF);
+        assert!(!cca.image_valid());
+
+        assert!(Cca::parse(&data[..40]).is_err());
+    }
+}
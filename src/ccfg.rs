@@ -0,0 +1,480 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Based on the previous work of cc2538-bsl and Texas Instruments sblAppEx
+// 1.03.00.00 (swra466c.zip).
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # CC13xx/CC26xx Customer Configuration
+//!
+//! The CCFG are the last 88 bytes of the flash of the CC13xx/CC26xx
+//! devices, read by the boot ROM on reset. Among others, they configure
+//! the bootloader, its backdoor pin, the debug interfaces and the write
+//! protection of the flash sectors.
+//!
+//! [`Ccfg`] parses them into named fields and serializes them back,
+//! keeping the bits it doesn't know about. The 2 KB CCFG of the
+//! [`Family::CC26X4`](crate::Family::CC26X4) has a different layout and
+//! isn't supported.
+//!
+//! # Example
+//!
+//! ```
+//! use ti_sbl::ccfg::{Ccfg, CCFG_SIZE};
+//!
+//! # let data = [0xFFu8; CCFG_SIZE];
+//! let mut ccfg = Ccfg::parse(&data)?;
+//! let mut config = ccfg.bootloader_config();
+//! config.backdoor_pin = 13;
+//! ccfg.set_bootloader_config(config);
+//! let data = ccfg.to_bytes();
+//! # Ok::<(), ti_sbl::Error>(())
+//! ```
+
+use std::convert::TryFrom;
+
+use crate::{Error, Result};
+
+/// Size of the CCFG in bytes.
+pub const CCFG_SIZE: usize = 88;
+
+const SIZE_AND_DIS_FLAGS: usize = 0x08;
+pub(crate) const IEEE_MAC_0: usize = 0x20;
+const IEEE_BLE_0: usize = 0x28;
+const BL_CONFIG: usize = 0x30;
+const ERASE_CONF: usize = 0x34;
+const CCFG_TI_OPTIONS: usize = 0x38;
+const CCFG_TAP_DAP_0: usize = 0x3C;
+const CCFG_TAP_DAP_1: usize = 0x40;
+const IMAGE_VALID_CONF: usize = 0x44;
+const CCFG_PROT_31_0: usize = 0x48;
+
+/// Value of the 8-bit fields that enable a feature, any other value
+/// disables it.
+const ENABLE: u32 = 0xC5;
+/// Value written to the 8-bit fields to disable a feature.
+const DISABLE: u32 = 0x00;
+
+/// Number of sectors covered by the `CCFG_PROT_*` registers.
+pub const PROT_SECTORS: u32 = 128;
+
+/// `BL_CONFIG` register.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BootloaderConfig {
+    /// Whether the bootloader is enabled (`BOOTLOADER_ENABLE`).
+    pub bootloader_enabled: bool,
+    /// Whether the backdoor is enabled (`BL_ENABLE`).
+    pub backdoor_enabled: bool,
+    /// DIO of the backdoor pin (`BL_PIN_NUMBER`).
+    pub backdoor_pin: u8,
+    /// Whether the backdoor pin is active high (`BL_LEVEL`).
+    pub backdoor_active_high: bool,
+}
+
+/// `CCFG_TAP_DAP_0` and `CCFG_TAP_DAP_1` registers, whether each debug
+/// access port is enabled.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TapConfig {
+    /// `CPU_DAP_ENABLE`, debugging of the Cortex-M.
+    pub cpu_dap: bool,
+    /// `PRCM_TAP_ENABLE`.
+    pub prcm_tap: bool,
+    /// `TEST_TAP_ENABLE`.
+    pub test_tap: bool,
+    /// `PBIST2_TAP_ENABLE`.
+    pub pbist2_tap: bool,
+    /// `PBIST1_TAP_ENABLE`.
+    pub pbist1_tap: bool,
+    /// `WUC_TAP_ENABLE`.
+    pub wuc_tap: bool,
+}
+
+/// The CCFG contents of a CC13xx/CC26xx device.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Ccfg {
+    words: [u32; CCFG_SIZE / 4],
+}
+
+impl Ccfg {
+    /// Parse the CCFG from its [`CCFG_SIZE`] bytes.
+    ///
+    /// The reserved bits aren't checked, see [`Ccfg::validate`].
+    pub fn parse(data: &[u8]) -> Result<Ccfg> {
+        if data.len() != CCFG_SIZE {
+            return Err(Error::InvalidLength(data.len()));
+        }
+
+        let mut words = [0u32; CCFG_SIZE / 4];
+        for (word, bytes) in words.iter_mut().zip(data.chunks(4)) {
+            *word = u32::from_le_bytes(<[u8; 4]>::try_from(bytes).unwrap());
+        }
+
+        Ok(Ccfg { words })
+    }
+
+    /// Serialize the CCFG back to bytes.
+    pub fn to_bytes(&self) -> [u8; CCFG_SIZE] {
+        let mut data = [0u8; CCFG_SIZE];
+        for (bytes, word) in data.chunks_mut(4).zip(self.words.iter()) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        data
+    }
+
+    /// Check that the size of the CCFG and the reserved bits have their
+    /// expected values, writing anything else may result in undefined
+    /// behavior of the device.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidCcfg`] with the name of the first invalid
+    /// register.
+    pub fn validate(&self) -> Result<()> {
+        let checks: [(usize, u32, u32, &'static str); 6] = [
+            (
+                SIZE_AND_DIS_FLAGS,
+                0xFFFF_0000,
+                (CCFG_SIZE as u32) << 16,
+                "SIZE_AND_DIS_FLAGS",
+            ),
+            (BL_CONFIG, 0x00FE_0000, 0x00FE_0000, "BL_CONFIG"),
+            (ERASE_CONF, 0xFFFF_FEFE, 0xFFFF_FEFE, "ERASE_CONF"),
+            (CCFG_TI_OPTIONS, 0xFFFF_FF00, 0xFFFF_FF00, "CCFG_TI_OPTIONS"),
+            (CCFG_TAP_DAP_0, 0xFF00_0000, 0xFF00_0000, "CCFG_TAP_DAP_0"),
+            (CCFG_TAP_DAP_1, 0xFF00_0000, 0xFF00_0000, "CCFG_TAP_DAP_1"),
+        ];
+
+        for (offset, mask, expected, register) in checks.iter() {
+            if self.word(*offset) & mask != *expected {
+                return Err(Error::InvalidCcfg(register));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The raw register at `offset` bytes from the start of the CCFG.
+    ///
+    /// # Panics
+    ///
+    /// If the `offset` is out of the CCFG or not aligned to 32-bits.
+    #[inline]
+    pub fn word(&self, offset: usize) -> u32 {
+        assert_eq!(offset % 4, 0, "unaligned CCFG offset");
+        self.words[offset / 4]
+    }
+
+    /// Change the raw register at `offset` bytes from the start of the
+    /// CCFG.
+    ///
+    /// # Panics
+    ///
+    /// If the `offset` is out of the CCFG or not aligned to 32-bits.
+    #[inline]
+    pub fn set_word(&mut self, offset: usize, value: u32) {
+        assert_eq!(offset % 4, 0, "unaligned CCFG offset");
+        self.words[offset / 4] = value;
+    }
+
+    /// The bootloader configuration.
+    pub fn bootloader_config(&self) -> BootloaderConfig {
+        let bl_config = self.word(BL_CONFIG);
+        BootloaderConfig {
+            bootloader_enabled: bl_config >> 24 == ENABLE,
+            backdoor_enabled: bl_config & 0xFF == ENABLE,
+            backdoor_pin: (bl_config >> 8) as u8,
+            backdoor_active_high: bl_config & (1 << 16) != 0,
+        }
+    }
+
+    /// Change the bootloader configuration, the reserved bits are kept.
+    pub fn set_bootloader_config(&mut self, config: BootloaderConfig) {
+        let mut bl_config = self.word(BL_CONFIG) & 0x00FE_0000;
+        bl_config |= enable_field(config.bootloader_enabled) << 24;
+        bl_config |= enable_field(config.backdoor_enabled);
+        bl_config |= u32::from(config.backdoor_pin) << 8;
+        if config.backdoor_active_high {
+            bl_config |= 1 << 16;
+        }
+        self.set_word(BL_CONFIG, bl_config);
+    }
+
+    /// The debug access ports configuration.
+    pub fn tap_config(&self) -> TapConfig {
+        let tap_dap_0 = self.word(CCFG_TAP_DAP_0);
+        let tap_dap_1 = self.word(CCFG_TAP_DAP_1);
+        let enabled = |word: u32, shift: u32| (word >> shift) & 0xFF == ENABLE;
+
+        TapConfig {
+            cpu_dap: enabled(tap_dap_0, 16),
+            prcm_tap: enabled(tap_dap_0, 8),
+            test_tap: enabled(tap_dap_0, 0),
+            pbist2_tap: enabled(tap_dap_1, 16),
+            pbist1_tap: enabled(tap_dap_1, 8),
+            wuc_tap: enabled(tap_dap_1, 0),
+        }
+    }
+
+    /// Change the debug access ports configuration, the reserved bits are
+    /// kept.
+    pub fn set_tap_config(&mut self, config: TapConfig) {
+        let tap_dap_0 = (self.word(CCFG_TAP_DAP_0) & 0xFF00_0000)
+            | enable_field(config.cpu_dap) << 16
+            | enable_field(config.prcm_tap) << 8
+            | enable_field(config.test_tap);
+        let tap_dap_1 = (self.word(CCFG_TAP_DAP_1) & 0xFF00_0000)
+            | enable_field(config.pbist2_tap) << 16
+            | enable_field(config.pbist1_tap) << 8
+            | enable_field(config.wuc_tap);
+        self.set_word(CCFG_TAP_DAP_0, tap_dap_0);
+        self.set_word(CCFG_TAP_DAP_1, tap_dap_1);
+    }
+
+    /// Whether the TI failure analysis is enabled (`TI_FA_ENABLE`).
+    #[inline]
+    pub fn ti_fa_enabled(&self) -> bool {
+        self.word(CCFG_TI_OPTIONS) & 0xFF == ENABLE
+    }
+
+    /// Enable or disable the TI failure analysis.
+    pub fn set_ti_fa_enabled(&mut self, enabled: bool) {
+        let options =
+            (self.word(CCFG_TI_OPTIONS) & !0xFF) | enable_field(enabled);
+        self.set_word(CCFG_TI_OPTIONS, options);
+    }
+
+    /// Whether the bank erase command is enabled (`BANK_ERASE_DIS_N`).
+    #[inline]
+    pub fn bank_erase_enabled(&self) -> bool {
+        self.word(ERASE_CONF) & (1 << 0) != 0
+    }
+
+    /// Enable or disable the bank erase command.
+    pub fn set_bank_erase_enabled(&mut self, enabled: bool) {
+        self.set_bit(ERASE_CONF, 0, enabled);
+    }
+
+    /// Whether the chip erase is enabled (`CHIP_ERASE_DIS_N`).
+    #[inline]
+    pub fn chip_erase_enabled(&self) -> bool {
+        self.word(ERASE_CONF) & (1 << 8) != 0
+    }
+
+    /// Enable or disable the chip erase.
+    pub fn set_chip_erase_enabled(&mut self, enabled: bool) {
+        self.set_bit(ERASE_CONF, 8, enabled);
+    }
+
+    /// The `IMAGE_VALID_CONF` register, the boot ROM only starts the
+    /// application if it's zero (or the address of the vector table on
+    /// the newer devices).
+    #[inline]
+    pub fn image_valid_conf(&self) -> u32 {
+        self.word(IMAGE_VALID_CONF)
+    }
+
+    /// Change the `IMAGE_VALID_CONF` register.
+    #[inline]
+    pub fn set_image_valid_conf(&mut self, value: u32) {
+        self.set_word(IMAGE_VALID_CONF, value);
+    }
+
+    /// The secondary IEEE 802.15.4 address (`IEEE_MAC_0`/`IEEE_MAC_1`),
+    /// all `0xFF` if unset.
+    #[inline]
+    pub fn ieee_address(&self) -> [u8; 8] {
+        self.address(IEEE_MAC_0)
+    }
+
+    /// Change the secondary IEEE 802.15.4 address.
+    #[inline]
+    pub fn set_ieee_address(&mut self, address: [u8; 8]) {
+        self.set_address(IEEE_MAC_0, address);
+    }
+
+    /// The secondary BLE address (`IEEE_BLE_0`/`IEEE_BLE_1`), all `0xFF` if
+    /// unset. Only the first 6 bytes are used.
+    #[inline]
+    pub fn ble_address(&self) -> [u8; 8] {
+        self.address(IEEE_BLE_0)
+    }
+
+    /// Change the secondary BLE address.
+    #[inline]
+    pub fn set_ble_address(&mut self, address: [u8; 8]) {
+        self.set_address(IEEE_BLE_0, address);
+    }
+
+    /// Whether the flash `sector` is write protected (`CCFG_PROT_*`).
+    ///
+    /// # Panics
+    ///
+    /// If the `sector` is not lower than [`PROT_SECTORS`].
+    pub fn sector_protected(&self, sector: u32) -> bool {
+        assert!(sector < PROT_SECTORS, "invalid sector");
+        let offset = CCFG_PROT_31_0 + (sector / 32) as usize * 4;
+        // A cleared bit protects the sector.
+        self.word(offset) & (1 << (sector % 32)) == 0
+    }
+
+    /// Write protect or unprotect the flash `sector`.
+    ///
+    /// # Panics
+    ///
+    /// If the `sector` is not lower than [`PROT_SECTORS`].
+    pub fn set_sector_protected(&mut self, sector: u32, protected: bool) {
+        assert!(sector < PROT_SECTORS, "invalid sector");
+        let offset = CCFG_PROT_31_0 + (sector / 32) as usize * 4;
+        self.set_bit(offset, sector % 32, !protected);
+    }
+
+    fn set_bit(&mut self, offset: usize, bit: u32, value: bool) {
+        let word = if value {
+            self.word(offset) | (1 << bit)
+        } else {
+            self.word(offset) & !(1 << bit)
+        };
+        self.set_word(offset, word);
+    }
+
+    fn address(&self, offset: usize) -> [u8; 8] {
+        let mut address = [0u8; 8];
+        address[..4].copy_from_slice(&self.word(offset).to_le_bytes());
+        address[4..].copy_from_slice(&self.word(offset + 4).to_le_bytes());
+        address
+    }
+
+    fn set_address(&mut self, offset: usize, address: [u8; 8]) {
+        let word = |bytes: &[u8]| {
+            u32::from_le_bytes(<[u8; 4]>::try_from(bytes).unwrap())
+        };
+        self.set_word(offset, word(&address[..4]));
+        self.set_word(offset + 4, word(&address[4..]));
+    }
+}
+
+/// Value of an 8-bit enable field.
+fn enable_field(enabled: bool) -> u32 {
+    if enabled {
+        ENABLE
+    } else {
+        DISABLE
+    }
+}
+
+/// CCFG fields that can be written with `COMMAND_SET_CCFG`.
+///
+/// See [`Device::set_ccfg`](crate::Device::set_ccfg).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CcfgField {
+    /// Write protect a flash sector. The value is the sector number.
+    SectorProt,
+    /// Image valid.
+    ImageValid,
+    /// Test TAP lock.
+    TestTapLck,
+    /// PRCM TAP lock.
+    PrcmTapLck,
+    /// CPU DAP lock.
+    CpuDapLck,
+    /// WUC TAP lock.
+    WucTapLck,
+    /// PBIST1 TAP lock.
+    Pbist1TapLck,
+    /// PBIST2 TAP lock.
+    Pbist2TapLck,
+    /// Bank erase disable.
+    BankEraseDis,
+    /// Chip erase disable.
+    ChipEraseDis,
+    /// TI failure analysis enable.
+    TiFaEnable,
+    /// Bootloader backdoor enable.
+    BlBackdoorEn,
+    /// Bootloader backdoor pin (DIO number).
+    BlBackdoorPin,
+    /// Bootloader backdoor level, `0` is active low, `1` is active high.
+    BlBackdoorLevel,
+    /// Bootloader enable.
+    BlEnable,
+}
+
+impl CcfgField {
+    /// The field ID sent to the bootloader.
+    #[inline]
+    pub fn id(&self) -> u32 {
+        match *self {
+            CcfgField::SectorProt => 0x00,
+            CcfgField::ImageValid => 0x01,
+            CcfgField::TestTapLck => 0x02,
+            CcfgField::PrcmTapLck => 0x03,
+            CcfgField::CpuDapLck => 0x04,
+            CcfgField::WucTapLck => 0x05,
+            CcfgField::Pbist1TapLck => 0x06,
+            CcfgField::Pbist2TapLck => 0x07,
+            CcfgField::BankEraseDis => 0x08,
+            CcfgField::ChipEraseDis => 0x09,
+            CcfgField::TiFaEnable => 0x0A,
+            CcfgField::BlBackdoorEn => 0x0B,
+            CcfgField::BlBackdoorPin => 0x0C,
+            CcfgField::BlBackdoorLevel => 0x0D,
+            CcfgField::BlEnable => 0x0E,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ccfg() {
+        let mut data = [0xFFu8; CCFG_SIZE];
+        data[0x08..0x0C].copy_from_slice(&0x0058_FFFFu32.to_le_bytes());
+        // Bootloader and backdoor enabled on DIO13, active low.
+        data[0x30..0x34].copy_from_slice(&0xC5FE_0DC5u32.to_le_bytes());
+        data[0x44..0x48].copy_from_slice(&[0; 4]);
+
+        let mut ccfg = Ccfg::parse(&data).unwrap();
+        ccfg.validate().unwrap();
+        assert_eq!(
+            ccfg.bootloader_config(),
+            BootloaderConfig {
+                bootloader_enabled: true,
+                backdoor_enabled: true,
+                backdoor_pin: 13,
+                backdoor_active_high: false,
+            }
+        );
+        assert!(!ccfg.tap_config().cpu_dap);
+        assert!(ccfg.bank_erase_enabled());
+        assert_eq!(ccfg.image_valid_conf(), 0);
+        assert!(!ccfg.sector_protected(3));
+        assert_eq!(ccfg.to_bytes()[..], data[..]);
+
+        ccfg.set_sector_protected(33, true);
+        assert!(ccfg.sector_protected(33));
+        assert_eq!(ccfg.word(0x4C), 0xFFFF_FFFD);
+
+        let mut config = ccfg.bootloader_config();
+        config.backdoor_enabled = false;
+        config.backdoor_active_high = true;
+        ccfg.set_bootloader_config(config);
+        assert_eq!(ccfg.word(0x30), 0xC5FF_0D00);
+        ccfg.validate().unwrap();
+
+        ccfg.set_word(0x30, 0xC500_0DC5);
+        assert!(ccfg.validate().is_err());
+        assert!(Ccfg::parse(&data[..84]).is_err());
+    }
+}
@@ -0,0 +1,390 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Chip database
+//!
+//! Maps the chip ID returned by
+//! [`Device::get_chip_id`](crate::Device::get_chip_id) to the name of the
+//! part.
+//!
+//! On CC13xx/CC26xx devices the chip ID is the `FCFG1.USER_ID` register, the
+//! part is derived from the family and the supported protocols. Variants
+//! that only differ on the package or the power amplifier (e.g.: CC1352R and
+//! CC1352P) can't be told apart, the common name is used in those cases.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::Family;
+
+/// Information about a part.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ChipInfo {
+    /// Name of the part, e.g.: `"CC2652R"`.
+    pub name: &'static str,
+    /// Family of the part.
+    pub family: Family,
+    /// Flash size in bytes.
+    pub flash: u32,
+    /// RAM size in bytes.
+    pub ram: u32,
+}
+
+impl Display for ChipInfo {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "{} ({} KB flash, {} KB RAM)",
+            self.name,
+            self.flash / 1024,
+            self.ram / 1024
+        )
+    }
+}
+
+/// Package of a CC13xx/CC26xx part, from the `USER_ID`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Package {
+    /// 4x4 mm QFN (RSM).
+    Qfn4x4,
+    /// 5x5 mm QFN (RHB).
+    Qfn5x5,
+    /// 7x7 mm QFN (RGZ).
+    Qfn7x7,
+    /// Wafer sale.
+    Wafer,
+    /// 2.7x2.7 mm WCSP (YFV).
+    Wcsp,
+    /// 7x7 mm QFN, automotive (Q1).
+    Qfn7x7Q1,
+    /// Unknown package code.
+    Unknown(u8),
+}
+
+impl From<u8> for Package {
+    fn from(value: u8) -> Package {
+        match value {
+            0 => Package::Qfn4x4,
+            1 => Package::Qfn5x5,
+            2 => Package::Qfn7x7,
+            3 => Package::Wafer,
+            4 => Package::Wcsp,
+            5 => Package::Qfn7x7Q1,
+            v => Package::Unknown(v),
+        }
+    }
+}
+
+impl Display for Package {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Package::Qfn4x4 => write!(fmt, "4x4 mm QFN"),
+            Package::Qfn5x5 => write!(fmt, "5x5 mm QFN"),
+            Package::Qfn7x7 => write!(fmt, "7x7 mm QFN"),
+            Package::Wafer => write!(fmt, "wafer"),
+            Package::Wcsp => write!(fmt, "2.7x2.7 mm WCSP"),
+            Package::Qfn7x7Q1 => write!(fmt, "7x7 mm QFN (Q1)"),
+            Package::Unknown(v) => write!(fmt, "unknown ({})", v),
+        }
+    }
+}
+
+/// Decoded chip ID, see [`util::read_chip_id`](crate::util::read_chip_id).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ChipId {
+    /// The value returned by
+    /// [`Device::get_chip_id`](crate::Device::get_chip_id).
+    pub raw: u32,
+    /// Part number, the wafer ID of the die, e.g.: `0xB964` for the CC2538
+    /// or `0xBB41` for the CC13x2/CC26x2. `None` if it's unknown.
+    pub part_number: Option<u16>,
+    /// Silicon revision (PG level), `None` if it's unknown.
+    pub revision: Option<u8>,
+    /// Package, `None` if it's unknown.
+    pub package: Option<Package>,
+}
+
+impl ChipId {
+    /// Decode the `chip_id` of a `family` device.
+    ///
+    /// On CC13xx/CC26xx devices the chip ID is the `FCFG1.USER_ID` register,
+    /// it has the revision and the package but not the part number, see
+    /// [`ChipId::set_device_id`].
+    pub fn new(family: Family, chip_id: u32) -> ChipId {
+        match family {
+            Family::CC2538 => ChipId {
+                raw: chip_id,
+                part_number: Some(chip_id as u16),
+                revision: None,
+                package: None,
+            },
+            Family::TM4C => ChipId {
+                raw: chip_id,
+                part_number: None,
+                revision: None,
+                package: None,
+            },
+            _ => ChipId {
+                raw: chip_id,
+                part_number: None,
+                revision: Some((chip_id >> 28) as u8),
+                package: Some(Package::from(((chip_id >> 16) & 0x07) as u8)),
+            },
+        }
+    }
+
+    /// Complete the chip ID of a CC13xx/CC26xx device with its
+    /// `FCFG1.ICEPICK_DEVICE_ID` register, which has the part number and
+    /// the silicon revision.
+    pub fn set_device_id(&mut self, device_id: u32) {
+        self.part_number = Some((device_id >> 12) as u16);
+        self.revision = Some((device_id >> 28) as u8);
+    }
+}
+
+impl Display for ChipId {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{:#X}", self.raw)?;
+        if let Some(part_number) = self.part_number {
+            write!(fmt, ", part {:#X}", part_number)?;
+        }
+        if let Some(revision) = self.revision {
+            write!(fmt, ", PG revision {}", revision)?;
+        }
+        if let Some(package) = self.package {
+            write!(fmt, ", {}", package)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Bluetooth Low Energy.
+const PROTO_BLE: u32 = 0x01;
+/// IEEE 802.15.4 (ZigBee, 6LoWPAN, Thread).
+const PROTO_IEEE: u32 = 0x04;
+/// Proprietary Sub-1 GHz.
+const PROTO_PROPRIETARY: u32 = 0x08;
+
+/// The protocols supported by the part, from the `USER_ID`.
+fn protocols(chip_id: u32) -> u32 {
+    (chip_id >> 12) & 0x0F
+}
+
+/// Identify the part from its `family`, `chip_id` and `flash_size` (in
+/// bytes, see [`util::read_flash_size`](crate::util::read_flash_size)).
+pub fn identify(family: Family, chip_id: u32, flash_size: u32) -> ChipInfo {
+    let protocols = protocols(chip_id);
+    let sub_1ghz = protocols & PROTO_PROPRIETARY != 0;
+    let ble = protocols & PROTO_BLE != 0;
+    let ieee = protocols & PROTO_IEEE != 0;
+
+    let (name, ram) = match family {
+        Family::CC2538 => ("CC2538", 32 * 1024),
+        Family::CC26X0 => {
+            let name = match (sub_1ghz, ble, ieee) {
+                (true, true, _) => "CC1350",
+                (true, false, _) => "CC1310",
+                (false, true, true) => "CC2650",
+                (false, true, false) => "CC2640",
+                (false, false, true) => "CC2630",
+                (false, false, false) => "CC2620",
+            };
+            (name, 20 * 1024)
+        }
+        Family::CC26X0R2 => ("CC2640R2F", 20 * 1024),
+        Family::CC26X2 => {
+            let name = match (sub_1ghz, ble || ieee) {
+                (true, true) => "CC1352",
+                (true, false) => "CC1312R",
+                (false, _) if ieee => "CC2652",
+                (false, _) => "CC2642R",
+            };
+            (name, 80 * 1024)
+        }
+        Family::CC26X2X7 => {
+            let name = match (sub_1ghz, ble || ieee) {
+                (true, true) => "CC1352x7",
+                (true, false) => "CC1312R7",
+                (false, _) => "CC2652x7",
+            };
+            (name, 144 * 1024)
+        }
+        Family::CC26X4 => {
+            let name = match (sub_1ghz, ble || ieee) {
+                (true, true) => "CC1354",
+                (true, false) => "CC1314",
+                (false, _) => "CC2674",
+            };
+            (name, 296 * 1024)
+        }
+        // There's no GET_CHIP_ID command.
+        Family::TM4C => ("TM4C123", 32 * 1024),
+    };
+
+    ChipInfo {
+        name,
+        family,
+        flash: flash_size,
+        ram,
+    }
+}
+
+/// A known part, see [`find_part`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Part {
+    /// Name of the part, e.g.: `"CC2652RB"`.
+    pub name: &'static str,
+    /// Family of the part.
+    pub family: Family,
+    /// Flash size in bytes, `None` if the part comes in several sizes.
+    pub flash: Option<u32>,
+}
+
+impl Part {
+    /// Whether the identified chip can be this part.
+    ///
+    /// Variants [`identify`] can't tell apart are accepted, e.g.: a
+    /// `"CC1352"` matches both `"CC1352R"` and `"CC1352P"`.
+    pub fn matches(&self, info: &ChipInfo) -> bool {
+        self.family == info.family
+            && self.flash.map_or(true, |flash| flash == info.flash)
+            && name_matches(info.name, self.name)
+    }
+}
+
+/// Match the `name` of a part against the `pattern` returned by
+/// [`identify`], where `x` matches any character and a shorter pattern
+/// matches as a prefix.
+fn name_matches(pattern: &str, name: &str) -> bool {
+    pattern.len() <= name.len()
+        && pattern
+            .chars()
+            .zip(name.chars())
+            .all(|(p, n)| p == 'x' || p == n)
+}
+
+const K: u32 = 1024;
+
+/// Known parts.
+#[rustfmt::skip]
+pub const PARTS: &[Part] = &[
+    Part { name: "CC2538", family: Family::CC2538, flash: None },
+    Part { name: "CC1310", family: Family::CC26X0, flash: None },
+    Part { name: "CC1350", family: Family::CC26X0, flash: Some(128 * K) },
+    Part { name: "CC2620", family: Family::CC26X0, flash: Some(128 * K) },
+    Part { name: "CC2630", family: Family::CC26X0, flash: Some(128 * K) },
+    Part { name: "CC2640", family: Family::CC26X0, flash: Some(128 * K) },
+    Part { name: "CC2650", family: Family::CC26X0, flash: Some(128 * K) },
+    Part { name: "CC2640R2F", family: Family::CC26X0R2, flash: Some(128 * K) },
+    Part { name: "CC1312R", family: Family::CC26X2, flash: Some(352 * K) },
+    Part { name: "CC1352R", family: Family::CC26X2, flash: Some(352 * K) },
+    Part { name: "CC1352P", family: Family::CC26X2, flash: Some(352 * K) },
+    Part { name: "CC2642R", family: Family::CC26X2, flash: Some(352 * K) },
+    Part { name: "CC2652R", family: Family::CC26X2, flash: Some(352 * K) },
+    Part { name: "CC2652RB", family: Family::CC26X2, flash: Some(352 * K) },
+    Part { name: "CC2652P", family: Family::CC26X2, flash: Some(352 * K) },
+    Part { name: "CC1312R7", family: Family::CC26X2X7, flash: Some(704 * K) },
+    Part { name: "CC1352P7", family: Family::CC26X2X7, flash: Some(704 * K) },
+    Part { name: "CC2652R7", family: Family::CC26X2X7, flash: Some(704 * K) },
+    Part { name: "CC2652P7", family: Family::CC26X2X7, flash: Some(704 * K) },
+    Part { name: "CC1314R10", family: Family::CC26X4, flash: Some(1024 * K) },
+    Part { name: "CC1354R10", family: Family::CC26X4, flash: Some(1024 * K) },
+    Part { name: "CC1354P10", family: Family::CC26X4, flash: Some(1024 * K) },
+    Part { name: "CC2674R10", family: Family::CC26X4, flash: Some(1024 * K) },
+    Part { name: "CC2674P10", family: Family::CC26X4, flash: Some(1024 * K) },
+    Part { name: "TM4C123", family: Family::TM4C, flash: Some(256 * K) },
+];
+
+/// Find a part by its name, case-insensitively.
+pub fn find_part(name: &str) -> Option<&'static Part> {
+    PARTS
+        .iter()
+        .find(|part| part.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_identify() {
+        assert_eq!(identify(Family::CC2538, 0xB964, 0x80000).name, "CC2538");
+        assert_eq!(
+            identify(Family::CC26X0, 0x0000_8000, 0x20000).name,
+            "CC1310"
+        );
+        assert_eq!(
+            identify(Family::CC26X0, 0x0000_9000, 0x20000).name,
+            "CC1350"
+        );
+        assert_eq!(
+            identify(Family::CC26X0, 0x0000_5000, 0x20000).name,
+            "CC2650"
+        );
+        assert_eq!(
+            identify(Family::CC26X2, 0x0000_8000, 0x58000).name,
+            "CC1312R"
+        );
+        assert_eq!(
+            identify(Family::CC26X2, 0x0000_D000, 0x58000).name,
+            "CC1352"
+        );
+        assert_eq!(
+            identify(Family::CC26X2, 0x0000_5000, 0x58000).name,
+            "CC2652"
+        );
+        assert_eq!(
+            identify(Family::CC26X2, 0x0000_1000, 0x58000).name,
+            "CC2642R"
+        );
+
+        let info = identify(Family::CC26X2X7, 0x0000_D000, 0xB0000);
+        assert_eq!(info.name, "CC1352x7");
+        assert_eq!(info.flash, 704 * 1024);
+    }
+
+    #[test]
+    fn test_chip_id() {
+        let mut chip_id = ChipId::new(Family::CC26X2, 0x2082_D000);
+        assert_eq!(chip_id.revision, Some(2));
+        assert_eq!(chip_id.package, Some(Package::Qfn7x7));
+        assert_eq!(chip_id.part_number, None);
+
+        chip_id.set_device_id(0x3BB4_102F);
+        assert_eq!(chip_id.part_number, Some(0xBB41));
+        assert_eq!(chip_id.revision, Some(3));
+        assert_eq!(
+            chip_id.to_string(),
+            "0x2082D000, part 0xBB41, PG revision 3, 7x7 mm QFN"
+        );
+
+        let chip_id = ChipId::new(Family::CC2538, 0xB964);
+        assert_eq!(chip_id.part_number, Some(0xB964));
+        assert_eq!(chip_id.package, None);
+    }
+
+    #[test]
+    fn test_part_matches() {
+        let cc2652rb = find_part("cc2652rb").unwrap();
+        assert_eq!(cc2652rb.family, Family::CC26X2);
+        assert!(cc2652rb.matches(&identify(Family::CC26X2, 0x5000, 0x58000)));
+        assert!(!cc2652rb.matches(&identify(Family::CC26X2, 0xD000, 0x58000)));
+
+        let cc1352p7 = find_part("CC1352P7").unwrap();
+        assert!(cc1352p7.matches(&identify(Family::CC26X2X7, 0xD000, 0xB0000)));
+        assert!(!cc1352p7.matches(&identify(Family::CC26X2, 0xD000, 0x58000)));
+
+        assert!(find_part("cc2652").is_none());
+    }
+}
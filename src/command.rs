@@ -0,0 +1,217 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Based on the previous work of cc2538-bsl and Texas Instruments sblAppEx
+// 1.03.00.00 (swra466c.zip).
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::Family;
+
+/// Bootloader commands.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CommandId {
+    /// `COMMAND_PING`.
+    Ping,
+    /// `COMMAND_DOWNLOAD`.
+    Download,
+    /// `COMMAND_RUN`, only on [`Family::CC2538`].
+    Run,
+    /// `COMMAND_GET_STATUS`.
+    GetStatus,
+    /// `COMMAND_SEND_DATA`.
+    SendData,
+    /// `COMMAND_RESET`.
+    Reset,
+    /// `COMMAND_ERASE`, only on [`Family::CC2538`].
+    Erase,
+    /// `COMMAND_SECTOR_ERASE`, not on [`Family::CC2538`].
+    SectorErase,
+    /// `COMMAND_CRC32`.
+    Crc32,
+    /// `COMMAND_GET_CHIP_ID`.
+    GetChipId,
+    /// `COMMAND_SET_XOSC`, only on [`Family::CC2538`].
+    SetXosc,
+    /// `COMMAND_MEMORY_READ`.
+    MemoryRead,
+    /// `COMMAND_MEMORY_WRITE`.
+    MemoryWrite,
+    /// `COMMAND_BANK_ERASE`, not on [`Family::CC2538`].
+    BankErase,
+    /// `COMMAND_SET_CCFG`, not on [`Family::CC2538`] and
+    /// [`Family::CC26X4`].
+    SetCcfg,
+    /// `COMMAND_DOWNLOAD_CRC`, only on [`Family::CC26X2`],
+    /// [`Family::CC26X2X7`] and [`Family::CC26X4`].
+    DownloadCrc,
+}
+
+impl CommandId {
+    /// The command byte sent to the bootloader.
+    #[inline]
+    pub fn value(&self) -> u8 {
+        match *self {
+            CommandId::Ping => 0x20,
+            CommandId::Download => 0x21,
+            CommandId::Run => 0x22,
+            CommandId::GetStatus => 0x23,
+            CommandId::SendData => 0x24,
+            CommandId::Reset => 0x25,
+            CommandId::Erase => 0x26,
+            CommandId::SectorErase => 0x26,
+            CommandId::Crc32 => 0x27,
+            CommandId::GetChipId => 0x28,
+            CommandId::SetXosc => 0x29,
+            CommandId::MemoryRead => 0x2A,
+            CommandId::MemoryWrite => 0x2B,
+            CommandId::BankErase => 0x2C,
+            CommandId::SetCcfg => 0x2D,
+            CommandId::DownloadCrc => 0x2F,
+        }
+    }
+
+    /// The name of the command.
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            CommandId::Ping => "CMD_PING",
+            CommandId::Download => "CMD_DOWNLOAD",
+            CommandId::Run => "CMD_RUN",
+            CommandId::GetStatus => "CMD_GET_STATUS",
+            CommandId::SendData => "CMD_SEND_DATA",
+            CommandId::Reset => "CMD_RESET",
+            CommandId::Erase => "CMD_ERASE",
+            CommandId::SectorErase => "CMD_SECTOR_ERASE",
+            CommandId::Crc32 => "CMD_CRC32",
+            CommandId::GetChipId => "CMD_GET_CHIP_ID",
+            CommandId::SetXosc => "CMD_SET_XOSC",
+            CommandId::MemoryRead => "CMD_MEMORY_READ",
+            CommandId::MemoryWrite => "CMD_MEMORY_WRITE",
+            CommandId::BankErase => "CMD_BANK_ERASE",
+            CommandId::SetCcfg => "CMD_SET_CCFG",
+            CommandId::DownloadCrc => "CMD_DOWNLOAD_CRC",
+        }
+    }
+
+    /// Whether the command is supported by the `family`.
+    #[inline]
+    pub fn is_supported_by(&self, family: Family) -> bool {
+        family.supports_command(*self)
+    }
+
+    /// Convert a command byte to the command of the `family`.
+    ///
+    /// Unlike the [`TryFrom<u8>`] implementation, this resolves `0x26` to
+    /// [`CommandId::Erase`] or [`CommandId::SectorErase`] depending on the
+    /// family, and rejects commands the family doesn't support.
+    pub fn from_family(
+        family: Family,
+        value: u8,
+    ) -> Result<CommandId, UnknownCommandId> {
+        let cmd = match CommandId::try_from(value)? {
+            CommandId::SectorErase if family.supports_erase() => {
+                CommandId::Erase
+            }
+            cmd => cmd,
+        };
+
+        if cmd.is_supported_by(family) {
+            Ok(cmd)
+        } else {
+            Err(UnknownCommandId(value))
+        }
+    }
+}
+
+impl Display for CommandId {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.as_str())
+    }
+}
+
+/// The value is not a known command.
+#[derive(Debug)]
+pub struct UnknownCommandId(pub u8);
+
+impl Display for UnknownCommandId {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "unknown command {:#X}", self.0)
+    }
+}
+
+impl Error for UnknownCommandId {}
+
+impl TryFrom<u8> for CommandId {
+    type Error = UnknownCommandId;
+
+    /// Convert a command byte to a `CommandId`.
+    ///
+    /// `0x26` is shared by `COMMAND_ERASE` and `COMMAND_SECTOR_ERASE`, it's
+    /// converted to [`CommandId::SectorErase`], use
+    /// [`CommandId::from_family`] to take into account the device family.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x20 => Ok(CommandId::Ping),
+            0x21 => Ok(CommandId::Download),
+            0x22 => Ok(CommandId::Run),
+            0x23 => Ok(CommandId::GetStatus),
+            0x24 => Ok(CommandId::SendData),
+            0x25 => Ok(CommandId::Reset),
+            0x26 => Ok(CommandId::SectorErase),
+            0x27 => Ok(CommandId::Crc32),
+            0x28 => Ok(CommandId::GetChipId),
+            0x29 => Ok(CommandId::SetXosc),
+            0x2A => Ok(CommandId::MemoryRead),
+            0x2B => Ok(CommandId::MemoryWrite),
+            0x2C => Ok(CommandId::BankErase),
+            0x2D => Ok(CommandId::SetCcfg),
+            0x2F => Ok(CommandId::DownloadCrc),
+            v => Err(UnknownCommandId(v)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_command_id_roundtrip() {
+        for value in 0..=u8::MAX {
+            if let Ok(cmd) = CommandId::try_from(value) {
+                assert_eq!(cmd.value(), value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_command_id_from_family() {
+        assert_eq!(
+            CommandId::from_family(Family::CC2538, 0x26).unwrap(),
+            CommandId::Erase
+        );
+        assert_eq!(
+            CommandId::from_family(Family::CC26X2, 0x26).unwrap(),
+            CommandId::SectorErase
+        );
+        assert!(CommandId::from_family(Family::CC26X0, 0x2F).is_err());
+        assert!(CommandId::from_family(Family::CC2538, 0x2C).is_err());
+        assert!(CommandId::from_family(Family::CC2538, 0x2B).is_err());
+    }
+}
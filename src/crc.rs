@@ -0,0 +1,54 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// CRC32 (IEEE 802.3) of the `data`, as computed by the bootloader for
+/// [`Device::crc32`](crate::Device::crc32): reflected polynomial
+/// `0xEDB88320`, initial value `0xFFFFFFFF` and inverted result.
+///
+/// # Example
+///
+/// ```
+/// assert_eq!(ti_sbl::crc32(b"123456789"), 0xCBF4_3926);
+/// ```
+pub fn crc32(data: &[u8]) -> u32 {
+    !update(0xFFFF_FFFF, data)
+}
+
+/// Update a running CRC32 with the `data`, without the initial value nor
+/// the final inversion of [`crc32`], to compute it over several slices.
+pub(crate) fn update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+        assert_eq!(!update(update(!0, b"1234"), b"56789"), 0xCBF4_3926);
+    }
+}
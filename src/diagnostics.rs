@@ -0,0 +1,44 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Counters of the anomalies found while talking with the bootloader.
+///
+/// A healthy link has all counters at zero (except for NACKs of commands the
+/// bootloader rejects on purpose), growing counters usually mean a marginal
+/// wiring or a baudrate the board can't sustain. See
+/// [`Device::diagnostics`](crate::Device::diagnostics).
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Diagnostics {
+    /// Bytes received while waiting for an ACK that weren't an ACK/NACK.
+    pub garbage_bytes: usize,
+    /// NACKs received.
+    pub nacks: usize,
+    /// Times no ACK/NACK was received before the timeout.
+    pub ack_timeouts: usize,
+    /// Responses requested again because of an invalid checksum.
+    pub checksum_errors: usize,
+    /// Times the communication was synchronized again, see
+    /// [`Device::resynchronize`](crate::Device::resynchronize).
+    pub resyncs: usize,
+}
+
+impl Diagnostics {
+    /// Whether any anomaly (other than NACKs) was found.
+    pub fn is_marginal(&self) -> bool {
+        self.garbage_bytes != 0
+            || self.ack_timeouts != 0
+            || self.checksum_errors != 0
+            || self.resyncs != 0
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    error,
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+use crate::{CommandId, Family, Page, StatusCode, VerificationError};
+
+/// Result type used by this library.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors of this library: errors talking with the bootloader, errors
+/// reported by the bootloader, invalid parameters and invalid firmware
+/// images or files (Intel HEX, ELF, OAD and MCUboot images).
+///
+/// New variants may be added in minor releases, matches must have a
+/// wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The command wasn't acknowledged by the bootloader.
+    Nack(CommandId),
+    /// The raw command wasn't acknowledged by the bootloader, see
+    /// [`Device::send_raw_command`](crate::Device::send_raw_command).
+    RawNack(u8),
+    /// Timed out waiting for the bootloader.
+    Timeout,
+    /// The deadline of the operation passed, see
+    /// [`Device::with_deadline`](crate::Device::with_deadline).
+    DeadlineExceeded,
+    /// The bootloader returned an error status for the last command.
+    Status(StatusCode),
+    /// The bootloader returned an unknown status code.
+    UnknownStatus(u8),
+    /// The command is not supported by the device family.
+    UnsupportedCommand(CommandId),
+    /// The operation is not supported by the device family.
+    UnsupportedFamily(Family),
+    /// The family of the device couldn't be detected, contains the chip ID.
+    UnknownChip(u32),
+    /// The address range is outside of the memory map of the family.
+    AddressOutOfRange {
+        /// Start address.
+        address: u32,
+        /// Length in bytes.
+        len: u32,
+    },
+    /// The address isn't aligned as required by the command.
+    UnalignedAddress(u32),
+    /// The length of the data isn't valid for the command, e.g.: it's not
+    /// a multiple of the access size.
+    InvalidLength(usize),
+    /// The sector size isn't a power of two, see
+    /// [`Device::set_sector_size`](crate::Device::set_sector_size).
+    InvalidSectorSize(u32),
+    /// The percentage isn't in the `1..=100` range, see
+    /// [`util::verify_flash_range_sampled`](crate::util::verify_flash_range_sampled).
+    InvalidPercentage(u8),
+    /// The data is too large to be sent in a single command.
+    DataTooLarge {
+        /// Maximum length in bytes.
+        max: usize,
+        /// Length of the data in bytes.
+        found: usize,
+    },
+    /// The baudrate couldn't be synchronized with the bootloader.
+    NotSynchronized,
+    /// The length of the response doesn't match the expected one.
+    ResponseLength {
+        /// Expected length in bytes.
+        expected: usize,
+        /// Received length in bytes.
+        found: usize,
+    },
+    /// The response header is invalid.
+    InvalidResponse,
+    /// The response checksum was invalid after all the retransmissions.
+    InvalidChecksum,
+    /// A CCFG register has an invalid value, contains the name of the
+    /// register, see [`Ccfg::validate`](crate::ccfg::Ccfg::validate).
+    InvalidCcfg(&'static str),
+    /// The flash pages are write protected, see
+    /// [`util::check_write_protection`](crate::util::check_write_protection).
+    ProtectedPages(Vec<Page>),
+    /// An Intel HEX file is malformed, see
+    /// [`Firmware::from_hex`](crate::firmware::Firmware::from_hex).
+    InvalidHex {
+        /// Line number, starting at 1.
+        line: usize,
+        /// What's wrong with the record.
+        reason: &'static str,
+    },
+    /// The data at this address overlaps another segment, see
+    /// [`Firmware::merge`](crate::firmware::Firmware::merge).
+    OverlappingData(u32),
+    /// A TI OAD image is invalid, contains the reason, see
+    /// [`oad::validate`](crate::oad::validate).
+    InvalidOad(&'static str),
+    /// The CRC32 of a TI OAD image doesn't match the one of its header, see
+    /// [`oad::validate`](crate::oad::validate).
+    OadCrcMismatch {
+        /// CRC32 stored in the header.
+        expected: u32,
+        /// CRC32 of the image.
+        found: u32,
+    },
+    /// An MCUboot image is invalid, contains the reason, see
+    /// [`mcuboot::validate`](crate::mcuboot::validate).
+    InvalidMcuboot(&'static str),
+    /// The vector table of a firmware image doesn't look valid, contains the
+    /// reason, see
+    /// [`util::check_vector_table`](crate::util::check_vector_table).
+    InvalidVectorTable(&'static str),
+    /// The flash contents don't match the image after writing it.
+    Verification(VerificationError),
+    /// An ELF file can't be loaded, contains the reason, see
+    /// [`Firmware::from_elf`](crate::firmware::Firmware::from_elf).
+    InvalidElf(&'static str),
+    /// The operation was cancelled, see
+    /// [`util::CancelToken`](crate::util::CancelToken).
+    Cancelled,
+    /// I/O error on the serial port.
+    Io(io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::Nack(cmd) => write!(fmt, "{} not acknowledged", cmd),
+            Error::RawNack(cmd) => {
+                write!(fmt, "command {:#X} not acknowledged", cmd)
+            }
+            Error::Timeout => write!(fmt, "ACK bytes not found, timed out"),
+            Error::DeadlineExceeded => {
+                write!(fmt, "operation deadline exceeded")
+            }
+            Error::Status(status) => write!(fmt, "command failed: {}", status),
+            Error::UnknownStatus(status) => {
+                write!(fmt, "unknown status code {:#X}", status)
+            }
+            Error::UnsupportedCommand(cmd) => {
+                write!(fmt, "{} is not supported", cmd)
+            }
+            Error::UnsupportedFamily(family) => {
+                write!(fmt, "operation not supported on {:?} devices", family)
+            }
+            Error::UnknownChip(chip_id) => {
+                write!(fmt, "unknown device, chip ID {:#X}", chip_id)
+            }
+            Error::AddressOutOfRange { address, len } => write!(
+                fmt,
+                "address range {:#X}..{:#X} is outside of the device memory",
+                address,
+                u64::from(address) + u64::from(len)
+            ),
+            Error::UnalignedAddress(address) => {
+                write!(fmt, "unaligned address {:#X}", address)
+            }
+            Error::InvalidLength(len) => {
+                write!(fmt, "invalid data length {}", len)
+            }
+            Error::InvalidSectorSize(size) => {
+                write!(fmt, "invalid sector size {}, not a power of two", size)
+            }
+            Error::InvalidPercentage(percent) => write!(
+                fmt,
+                "invalid percentage {}, expected 1 to 100",
+                percent
+            ),
+            Error::DataTooLarge { max, found } => write!(
+                fmt,
+                "data too large, maximum is {} bytes, found {}",
+                max, found
+            ),
+            Error::NotSynchronized => {
+                write!(fmt, "couldn't synchronize bootloader baudrate")
+            }
+            Error::ResponseLength { expected, found } => write!(
+                fmt,
+                "invalid response length, expected {}, found {}",
+                expected, found
+            ),
+            Error::InvalidResponse => write!(fmt, "invalid response header"),
+            Error::InvalidChecksum => write!(fmt, "invalid response checksum"),
+            Error::InvalidCcfg(register) => {
+                write!(fmt, "invalid CCFG register {}", register)
+            }
+            Error::ProtectedPages(ref pages) => {
+                write!(fmt, "write protected flash pages:")?;
+                for page in pages {
+                    write!(fmt, " {} ({:#X})", page.index, page.address)?;
+                }
+                Ok(())
+            }
+            Error::InvalidHex { line, reason } => {
+                write!(fmt, "invalid Intel HEX file, line {}: {}", line, reason)
+            }
+            Error::OverlappingData(address) => {
+                write!(fmt, "data at {:#X} overlaps another segment", address)
+            }
+            Error::InvalidOad(reason) => {
+                write!(fmt, "invalid OAD image: {}", reason)
+            }
+            Error::OadCrcMismatch { expected, found } => write!(
+                fmt,
+                "invalid OAD image: CRC32 mismatch, expected {:#010X}, found {:#010X}",
+                expected, found
+            ),
+            Error::InvalidMcuboot(reason) => {
+                write!(fmt, "invalid MCUboot image: {}", reason)
+            }
+            Error::InvalidVectorTable(reason) => {
+                write!(fmt, "invalid vector table: {}", reason)
+            }
+            Error::Verification(ref e) => {
+                write!(fmt, "verification failed: {}", e)
+            }
+            Error::InvalidElf(reason) => {
+                write!(fmt, "invalid ELF file: {}", reason)
+            }
+            Error::Cancelled => write!(fmt, "operation cancelled"),
+            Error::Io(ref e) => write!(fmt, "I/O error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Verification(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::Timeout | Error::DeadlineExceeded => {
+                io::Error::new(io::ErrorKind::TimedOut, e)
+            }
+            Error::Cancelled => io::Error::new(io::ErrorKind::Interrupted, e),
+            Error::AddressOutOfRange { .. }
+            | Error::UnalignedAddress(_)
+            | Error::InvalidLength(_)
+            | Error::InvalidSectorSize(_)
+            | Error::InvalidPercentage(_)
+            | Error::DataTooLarge { .. } => {
+                io::Error::new(io::ErrorKind::InvalidInput, e)
+            }
+            Error::NotSynchronized => {
+                io::Error::new(io::ErrorKind::NotConnected, e)
+            }
+            Error::InvalidCcfg(_)
+            | Error::InvalidHex { .. }
+            | Error::InvalidElf(_)
+            | Error::OverlappingData(_)
+            | Error::InvalidOad(_)
+            | Error::OadCrcMismatch { .. }
+            | Error::InvalidMcuboot(_)
+            | Error::InvalidVectorTable(_)
+            | Error::Verification(_) => {
+                io::Error::new(io::ErrorKind::InvalidData, e)
+            }
+            Error::ProtectedPages(_) => {
+                io::Error::new(io::ErrorKind::PermissionDenied, e)
+            }
+            e => io::Error::new(io::ErrorKind::Other, e),
+        }
+    }
+}
@@ -21,6 +21,11 @@ use std::{
     str::FromStr,
 };
 
+use crate::{
+    ccfg::{CCFG_SIZE, IEEE_MAC_0},
+    CommandId,
+};
+
 /// The type of the bootloader.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Family {
@@ -28,32 +33,387 @@ pub enum Family {
     CC2538,
     /// CC26x0 and CC13x0 microcontrollers.
     CC26X0,
+    /// CC26x0R2 microcontrollers (e.g.: CC2640R2F), a CC26x0 with a larger
+    /// ROM and 128 KB of flash.
+    CC26X0R2,
     /// CC26x2 and CC13x2 microcontrollers.
     CC26X2,
+    /// CC26x2x7 and CC13x2x7 microcontrollers (e.g.: CC1352P7), with 704 KB
+    /// of flash.
+    CC26X2X7,
+    /// CC26x4 and CC13x4 microcontrollers (e.g.: CC1354P10, CC2674R10),
+    /// the CCFG is on its own flash sector outside of the main flash.
+    CC26X4,
+    /// Tiva C TM4C123x and Stellaris LM4F microcontrollers, with 256 KB of
+    /// flash. The ROM bootloader only supports the basic commands and
+    /// erases the flash itself when downloading.
+    TM4C,
+}
+
+/// Where the size of the flash is read from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FlashSizeSource {
+    /// All the parts of the family have the same flash size, in bytes.
+    Fixed(u32),
+    /// `FLASH.FLASH_SIZE` register address, the lower byte contains the
+    /// number of sectors.
+    Sectors(u32),
+    /// `FLASH_CTRL.DIECFG0` register address of the CC2538.
+    Diecfg0(u32),
 }
 
+/// Location and size of the CCFG (Customer Configuration) area.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CcfgGeometry {
+    /// Size in bytes.
+    pub size: u32,
+    /// Fixed address of the CCFG, `None` if it's placed at the end of the
+    /// flash.
+    pub address: Option<u32>,
+}
+
+/// Kind of a [`MemoryRegion`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MemoryKind {
+    /// Flash that can be erased and programmed through the bootloader.
+    Flash,
+    /// Read-only memory, including factory configuration pages.
+    Rom,
+    /// SRAM, including the GPRAM of the CC13xx/CC26xx.
+    Sram,
+    /// Peripheral and system registers.
+    Peripheral,
+}
+
+/// A range of the address space of a family.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MemoryRegion {
+    /// What the region contains.
+    pub kind: MemoryKind,
+    /// First address of the region.
+    pub start: u32,
+    /// Size in bytes.
+    pub len: u32,
+}
+
+impl MemoryRegion {
+    /// Whether the `len` bytes starting at `address` are inside of the
+    /// region.
+    #[inline]
+    pub fn contains(&self, address: u32, len: u32) -> bool {
+        address >= self.start
+            && u64::from(address) + u64::from(len)
+                <= u64::from(self.start) + u64::from(self.len)
+    }
+}
+
+/// Shorthand to build a [`MemoryRegion`].
+const fn region(kind: MemoryKind, start: u32, len: u32) -> MemoryRegion {
+    MemoryRegion { kind, start, len }
+}
+
+/// Peripherals, FCFG1 and CCFG of the CC13xx/CC26xx and the System
+/// Control Space of the Cortex-M.
+const CC26XX_PERIPHERALS: [MemoryRegion; 2] = [
+    region(MemoryKind::Peripheral, 0x4000_0000, 0x2000_0000),
+    region(MemoryKind::Peripheral, 0xE000_0000, 0x0010_0000),
+];
+
+/// Description of a family, see [`Family::descriptor`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FamilyDescriptor {
+    /// The family being described.
+    pub family: Family,
+    /// Name of the family, as accepted by [`Family::from_str`].
+    pub name: &'static str,
+    /// Other names accepted by [`Family::from_str`].
+    pub aliases: &'static [&'static str],
+    /// Commands supported by the bootloader.
+    pub commands: &'static [CommandId],
+    /// Address of the first byte of the flash.
+    pub flash_base: u32,
+    /// Sector erase size, in bytes.
+    pub sector_size: u32,
+    /// Highest baudrate the auto baud of the bootloader can lock onto.
+    pub max_baudrate: usize,
+    /// Whether [`CommandId::Download`] erases the flash being downloaded,
+    /// the family has no erase commands.
+    pub download_erases: bool,
+    /// Where the flash size is read from.
+    pub flash_size: FlashSizeSource,
+    /// The address space, flash regions cover the largest flash of the
+    /// family.
+    pub memory_map: &'static [MemoryRegion],
+    /// The CCFG area, `None` if the family doesn't have a CCFG.
+    pub ccfg: Option<CcfgGeometry>,
+    /// Address of the primary IEEE 802.15.4 address, `None` if it's
+    /// unknown.
+    pub ieee_primary: Option<u32>,
+    /// Offset of the secondary IEEE 802.15.4 address from the end of the
+    /// flash, `None` if it's stored on the CCFG (`CCFG.IEEE_MAC_0`).
+    pub ieee_secondary: Option<u32>,
+}
+
+/// Commands supported by the CC2538 and the CC13xx/CC26xx families,
+/// followed by the `extra` commands of the family.
+macro_rules! commands {
+    ($($extra:ident),* $(,)?) => {
+        &[
+            CommandId::Ping,
+            CommandId::Download,
+            CommandId::GetStatus,
+            CommandId::SendData,
+            CommandId::Reset,
+            CommandId::Crc32,
+            CommandId::GetChipId,
+            CommandId::MemoryRead,
+            $(CommandId::$extra,)*
+        ]
+    };
+}
+
+/// `FLASH.FLASH_SIZE` of CC13xx/CC26xx devices.
+pub(crate) const CC26XX_FLASH_O_FLASH_SIZE: u32 = 0x4003_002C;
+/// `FCFG1.MAC_15_4_0` of CC13xx/CC26xx devices.
+const CC26XX_FCFG1_O_MAC_15_4_0: u32 = 0x5000_12F0;
+
+/// The descriptors of all the families.
+pub const FAMILIES: &[FamilyDescriptor] = &[
+    FamilyDescriptor {
+        family: Family::CC2538,
+        name: "cc2538",
+        aliases: &[],
+        commands: commands![Run, Erase, SetXosc],
+        flash_base: 0x0020_0000,
+        sector_size: 2048,
+        max_baudrate: 460_800,
+        download_erases: false,
+        flash_size: FlashSizeSource::Diecfg0(0x400D_3014),
+        memory_map: &[
+            region(MemoryKind::Rom, 0x0000_0000, 0x0002_0000),
+            region(MemoryKind::Flash, 0x0020_0000, 0x0008_0000),
+            // Flash information page, with the IEEE address.
+            region(MemoryKind::Rom, 0x0028_0000, 0x0000_0800),
+            region(MemoryKind::Sram, 0x2000_0000, 0x0000_8000),
+            region(MemoryKind::Peripheral, 0x4000_0000, 0x0420_0000),
+            region(MemoryKind::Peripheral, 0xE000_0000, 0x0010_0000),
+        ],
+        ccfg: None,
+        ieee_primary: Some(0x0028_0028),
+        ieee_secondary: Some(0x34),
+    },
+    FamilyDescriptor {
+        family: Family::CC26X0,
+        name: "cc26x0",
+        aliases: &[
+            "cc13x0", "cc1310", "cc1350", "cc2620", "cc2630", "cc2640",
+            "cc2650",
+        ],
+        commands: commands![MemoryWrite, SectorErase, BankErase, SetCcfg],
+        flash_base: 0x0000_0000,
+        sector_size: 4096,
+        max_baudrate: 1_500_000,
+        download_erases: false,
+        flash_size: FlashSizeSource::Sectors(CC26XX_FLASH_O_FLASH_SIZE),
+        memory_map: &[
+            region(MemoryKind::Flash, 0x0000_0000, 0x0002_0000),
+            region(MemoryKind::Rom, 0x1000_0000, 0x0002_0000),
+            region(MemoryKind::Sram, 0x1100_0000, 0x0000_2000),
+            region(MemoryKind::Sram, 0x2000_0000, 0x0000_5000),
+            CC26XX_PERIPHERALS[0],
+            CC26XX_PERIPHERALS[1],
+        ],
+        ccfg: Some(CcfgGeometry {
+            size: CCFG_SIZE as u32,
+            address: None,
+        }),
+        ieee_primary: Some(CC26XX_FCFG1_O_MAC_15_4_0),
+        ieee_secondary: None,
+    },
+    FamilyDescriptor {
+        family: Family::CC26X0R2,
+        name: "cc26x0r2",
+        aliases: &["cc2640r2", "cc2640r2f"],
+        commands: commands![MemoryWrite, SectorErase, BankErase, SetCcfg],
+        flash_base: 0x0000_0000,
+        sector_size: 4096,
+        max_baudrate: 1_500_000,
+        download_erases: false,
+        flash_size: FlashSizeSource::Sectors(CC26XX_FLASH_O_FLASH_SIZE),
+        memory_map: &[
+            region(MemoryKind::Flash, 0x0000_0000, 0x0002_0000),
+            region(MemoryKind::Rom, 0x1000_0000, 0x0004_0000),
+            region(MemoryKind::Sram, 0x1100_0000, 0x0000_2000),
+            region(MemoryKind::Sram, 0x2000_0000, 0x0000_5000),
+            CC26XX_PERIPHERALS[0],
+            CC26XX_PERIPHERALS[1],
+        ],
+        ccfg: Some(CcfgGeometry {
+            size: CCFG_SIZE as u32,
+            address: None,
+        }),
+        ieee_primary: Some(CC26XX_FCFG1_O_MAC_15_4_0),
+        ieee_secondary: None,
+    },
+    FamilyDescriptor {
+        family: Family::CC26X2,
+        name: "cc26x2",
+        aliases: &["cc13x2", "cc1312", "cc1352", "cc2642", "cc2652"],
+        commands: commands![
+            MemoryWrite,
+            SectorErase,
+            BankErase,
+            SetCcfg,
+            DownloadCrc
+        ],
+        flash_base: 0x0000_0000,
+        sector_size: 8192,
+        max_baudrate: 1_500_000,
+        download_erases: false,
+        flash_size: FlashSizeSource::Sectors(CC26XX_FLASH_O_FLASH_SIZE),
+        memory_map: &[
+            region(MemoryKind::Flash, 0x0000_0000, 0x0005_8000),
+            region(MemoryKind::Rom, 0x1000_0000, 0x0004_0000),
+            region(MemoryKind::Sram, 0x1100_0000, 0x0000_2000),
+            region(MemoryKind::Sram, 0x2000_0000, 0x0001_4000),
+            CC26XX_PERIPHERALS[0],
+            CC26XX_PERIPHERALS[1],
+        ],
+        ccfg: Some(CcfgGeometry {
+            size: CCFG_SIZE as u32,
+            address: None,
+        }),
+        ieee_primary: Some(CC26XX_FCFG1_O_MAC_15_4_0),
+        ieee_secondary: None,
+    },
+    FamilyDescriptor {
+        family: Family::CC26X2X7,
+        name: "cc26x2x7",
+        aliases: &["cc13x2x7"],
+        commands: commands![
+            MemoryWrite,
+            SectorErase,
+            BankErase,
+            SetCcfg,
+            DownloadCrc
+        ],
+        flash_base: 0x0000_0000,
+        sector_size: 8192,
+        max_baudrate: 1_500_000,
+        download_erases: false,
+        flash_size: FlashSizeSource::Sectors(CC26XX_FLASH_O_FLASH_SIZE),
+        memory_map: &[
+            region(MemoryKind::Flash, 0x0000_0000, 0x000B_0000),
+            region(MemoryKind::Rom, 0x1000_0000, 0x0004_0000),
+            region(MemoryKind::Sram, 0x1100_0000, 0x0000_2000),
+            region(MemoryKind::Sram, 0x2000_0000, 0x0002_4000),
+            CC26XX_PERIPHERALS[0],
+            CC26XX_PERIPHERALS[1],
+        ],
+        ccfg: Some(CcfgGeometry {
+            size: CCFG_SIZE as u32,
+            address: None,
+        }),
+        ieee_primary: Some(CC26XX_FCFG1_O_MAC_15_4_0),
+        ieee_secondary: None,
+    },
+    // The CCFG has its own flash sector and all the parts have 1 MB of main
+    // flash. The location of the IEEE address isn't documented yet.
+    FamilyDescriptor {
+        family: Family::CC26X4,
+        name: "cc26x4",
+        aliases: &["cc13x4", "cc1314", "cc1354", "cc2674"],
+        commands: commands![MemoryWrite, SectorErase, BankErase, DownloadCrc],
+        flash_base: 0x0000_0000,
+        sector_size: 2048,
+        max_baudrate: 1_500_000,
+        download_erases: false,
+        flash_size: FlashSizeSource::Fixed(0x10_0000),
+        memory_map: &[
+            region(MemoryKind::Flash, 0x0000_0000, 0x0010_0000),
+            region(MemoryKind::Flash, 0x4E02_0000, 0x0000_0800),
+            region(MemoryKind::Rom, 0x1000_0000, 0x0004_0000),
+            region(MemoryKind::Sram, 0x2000_0000, 0x0004_A000),
+            CC26XX_PERIPHERALS[0],
+            CC26XX_PERIPHERALS[1],
+        ],
+        ccfg: Some(CcfgGeometry {
+            size: 0x800,
+            address: Some(0x4E02_0000),
+        }),
+        ieee_primary: None,
+        ieee_secondary: None,
+    },
+    // The bootloader is the same found on older Stellaris parts, with a
+    // reduced command set.
+    FamilyDescriptor {
+        family: Family::TM4C,
+        name: "tm4c",
+        aliases: &["tm4c123", "lm4f", "tiva"],
+        commands: &[
+            CommandId::Ping,
+            CommandId::Download,
+            CommandId::Run,
+            CommandId::GetStatus,
+            CommandId::SendData,
+            CommandId::Reset,
+        ],
+        flash_base: 0x0000_0000,
+        sector_size: 1024,
+        max_baudrate: 115_200,
+        download_erases: true,
+        flash_size: FlashSizeSource::Fixed(0x4_0000),
+        memory_map: &[
+            region(MemoryKind::Flash, 0x0000_0000, 0x0004_0000),
+            region(MemoryKind::Rom, 0x0100_0000, 0x0001_0000),
+            region(MemoryKind::Sram, 0x2000_0000, 0x0000_8000),
+            region(MemoryKind::Peripheral, 0x4000_0000, 0x0010_0000),
+            region(MemoryKind::Peripheral, 0xE000_0000, 0x0010_0000),
+        ],
+        ccfg: None,
+        ieee_primary: None,
+        ieee_secondary: None,
+    },
+];
+
 impl Family {
+    /// The descriptor of the family, from [`FAMILIES`].
+    #[inline]
+    pub fn descriptor(&self) -> &'static FamilyDescriptor {
+        FAMILIES
+            .iter()
+            .find(|descriptor| descriptor.family == *self)
+            .expect("all families have a descriptor")
+    }
+
+    /// Whether the bootloader supports the `cmd`.
+    #[inline]
+    pub fn supports_command(&self, cmd: CommandId) -> bool {
+        self.descriptor().commands.contains(&cmd)
+    }
+
     /// Whether the device supports `COMMAND_RUN`.
     ///
-    /// - **Note:** supported only on [`Family::CC2538`].
+    /// - **Note:** supported only on [`Family::CC2538`] and
+    ///   [`Family::TM4C`].
     #[inline]
     pub fn supports_run(&self) -> bool {
-        matches!(*self, Family::CC2538)
+        self.supports_command(CommandId::Run)
     }
     /// Whether the device supports `COMMAND_ERASE`.
     ///
     /// - **Note:** supported only on [`Family::CC2538`].
     #[inline]
     pub fn supports_erase(&self) -> bool {
-        matches!(*self, Family::CC2538)
+        self.supports_command(CommandId::Erase)
     }
 
     /// Whether the device supports `COMMAND_SECTOR_ERASE`.
     ///
-    /// - **Note:** supported only on [`Family::CC26X0`] and [`Family::CC26X2`].
+    /// - **Note:** not supported on [`Family::CC2538`] and [`Family::TM4C`].
     #[inline]
     pub fn supports_sector_erase(&self) -> bool {
-        matches!(*self, Family::CC26X0 | Family::CC26X2)
+        self.supports_command(CommandId::SectorErase)
     }
 
     /// Whether the device supports `COMMAND_SET_XOSC`.
@@ -61,65 +421,300 @@ impl Family {
     /// - **Note:** supported only on [`Family::CC2538`].
     #[inline]
     pub fn supports_set_xosc(&self) -> bool {
-        matches!(*self, Family::CC2538)
+        self.supports_command(CommandId::SetXosc)
     }
 
     /// Whether the device supports `COMMAND_BANK_ERASE`.
     ///
-    /// - **Note:** supported only on [`Family::CC26X0`] and [`Family::CC26X2`].
+    /// - **Note:** not supported on [`Family::CC2538`] and [`Family::TM4C`].
     #[inline]
     pub fn supports_bank_erase(&self) -> bool {
-        matches!(*self, Family::CC26X0 | Family::CC26X2)
+        self.supports_command(CommandId::BankErase)
     }
 
     /// Whether the device supports `COMMAND_SET_CCFG`.
     ///
-    /// - **Note:** supported only on [`Family::CC26X0`] and [`Family::CC26X2`].
+    /// - **Note:** not supported on [`Family::CC2538`], [`Family::CC26X4`]
+    ///   and [`Family::TM4C`].
     #[inline]
     pub fn supports_set_ccfg(&self) -> bool {
-        matches!(*self, Family::CC26X0 | Family::CC26X2)
+        self.supports_command(CommandId::SetCcfg)
     }
 
     /// Whether the device supports `COMMAND_DOWNLOAD_CRC`.
     ///
-    /// - **Note:** supported only on [`Family::CC26X2`].
+    /// - **Note:** supported only on [`Family::CC26X2`],
+    ///   [`Family::CC26X2X7`] and [`Family::CC26X4`].
     #[inline]
     pub fn supports_download_crc(&self) -> bool {
-        matches!(*self, Family::CC26X2)
+        self.supports_command(CommandId::DownloadCrc)
+    }
+
+    /// Whether [`CommandId::Download`] erases the flash pages being
+    /// downloaded, instead of having erase commands.
+    ///
+    /// - **Note:** only on [`Family::TM4C`].
+    #[inline]
+    pub fn download_erases(&self) -> bool {
+        self.descriptor().download_erases
+    }
+
+    /// Whether the device has a CCFG (Customer Configuration) area.
+    ///
+    /// - **Note:** all families except [`Family::CC2538`] and
+    ///   [`Family::TM4C`].
+    #[inline]
+    pub fn has_ccfg(&self) -> bool {
+        self.descriptor().ccfg.is_some()
+    }
+
+    /// Size of the CCFG, in bytes. Zero if the family doesn't have a CCFG.
+    #[inline]
+    pub fn ccfg_size(&self) -> u32 {
+        self.descriptor().ccfg.map_or(0, |ccfg| ccfg.size)
     }
 
     /// Sector erase size, in bytes.
     #[inline]
     pub fn sector_size(&self) -> u32 {
-        match *self {
-            Family::CC2538 => 2048,
-            Family::CC26X0 => 4092,
-            Family::CC26X2 => 8192,
-        }
+        self.descriptor().sector_size
+    }
+
+    /// Highest baudrate the auto baud of the bootloader can lock onto,
+    /// faster baudrates may fail to synchronize or corrupt data.
+    ///
+    /// - **Note:** 460800 on [`Family::CC2538`], 1.5 Mbaud otherwise.
+    #[inline]
+    pub fn max_baudrate(&self) -> usize {
+        self.descriptor().max_baudrate
     }
 
     /// Flash base size.
     #[inline]
     pub fn flash_base(&self) -> u32 {
-        match *self {
-            Family::CC2538 => 0x00200000,
-            Family::CC26X0 | Family::CC26X2 => 0x00000000,
-        }
+        self.descriptor().flash_base
+    }
+
+    /// Address of the CCFG for a flash of `flash_size` bytes, `None` if the
+    /// family doesn't have a CCFG.
+    ///
+    /// The CCFG is placed at the end of the flash, except on
+    /// [`Family::CC26X4`] where it has its own sector.
+    #[inline]
+    pub fn ccfg_address(&self, flash_size: u32) -> Option<u32> {
+        let ccfg = self.descriptor().ccfg?;
+
+        Some(
+            ccfg.address
+                .unwrap_or(self.flash_base() + flash_size - ccfg.size),
+        )
+    }
+
+    /// Addresses of the primary and secondary IEEE 802.15.4 addresses for
+    /// a flash of `flash_size` bytes, `None` if they're unknown.
+    ///
+    /// The secondary address is on the last flash page (on the CCFG of the
+    /// CC13xx/CC26xx), so it moves with the flash size.
+    pub fn ieee_addresses(&self, flash_size: u32) -> Option<(u32, u32)> {
+        let descriptor = self.descriptor();
+        let primary = descriptor.ieee_primary?;
+        let secondary = match descriptor.ieee_secondary {
+            Some(offset) => self.flash_base() + flash_size - offset,
+            None => self.ccfg_address(flash_size)? + IEEE_MAC_0 as u32,
+        };
+
+        Some((primary, secondary))
+    }
+
+    /// The address space of the family, see
+    /// [`FamilyDescriptor::memory_map`].
+    #[inline]
+    pub fn memory_map(&self) -> &'static [MemoryRegion] {
+        self.descriptor().memory_map
+    }
+
+    /// The region containing the `len` bytes starting at `address`, `None`
+    /// if they aren't inside a single region.
+    pub fn memory_region(
+        &self,
+        address: u32,
+        len: u32,
+    ) -> Option<&'static MemoryRegion> {
+        self.memory_map()
+            .iter()
+            .find(|region| region.contains(address, len))
     }
 
     /// Convert a flash address to the flash page.
+    ///
+    /// # Panics
+    ///
+    /// If the `address` is below the flash, see
+    /// [`Family::checked_address_to_page`].
     #[inline]
     pub fn address_to_page(&self, address: u32) -> u32 {
-        (address - self.flash_base()) / self.sector_size()
+        self.checked_address_to_page(address)
+            .expect("address below the flash")
+    }
+
+    /// Convert a flash address to the flash page.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressOutOfRange`](crate::Error::AddressOutOfRange)
+    /// if the `address` is below the flash.
+    #[inline]
+    pub fn checked_address_to_page(&self, address: u32) -> crate::Result<u32> {
+        match address.checked_sub(self.flash_base()) {
+            Some(offset) => Ok(offset / self.sector_size()),
+            None => Err(crate::Error::AddressOutOfRange { address, len: 1 }),
+        }
+    }
+
+    /// Address of the first byte of the flash `page`.
+    #[inline]
+    pub fn page_to_address(&self, page: u32) -> u32 {
+        self.flash_base() + page * self.sector_size()
+    }
+
+    /// The pages of a flash of `flash_size` bytes.
+    #[inline]
+    pub fn pages(&self, flash_size: u32) -> Pages {
+        Pages::flash(self.flash_base(), self.sector_size(), flash_size)
+    }
+
+    /// The pages containing any of the `len` bytes starting at `address`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressOutOfRange`](crate::Error::AddressOutOfRange)
+    /// if the range starts below the flash or overflows the address space.
+    pub fn pages_in_range(
+        &self,
+        address: u32,
+        len: u32,
+    ) -> crate::Result<Pages> {
+        Pages::new(self.flash_base(), self.sector_size(), address, len)
+    }
+}
+
+/// A flash page (sector), see [`Family::pages`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Page {
+    /// Index of the page, from the start of the flash.
+    pub index: u32,
+    /// Address of the first byte.
+    pub address: u32,
+    /// Size in bytes.
+    pub len: u32,
+}
+
+/// Iterator over flash pages, see [`Family::pages`].
+#[derive(Debug, Clone)]
+pub struct Pages {
+    flash_base: u32,
+    sector_size: u32,
+    next: u32,
+    end: u32,
+}
+
+impl Pages {
+    /// The pages of `sector_size` bytes containing any of the `len` bytes
+    /// starting at `address`, for a flash starting at `flash_base`.
+    pub(crate) fn new(
+        flash_base: u32,
+        sector_size: u32,
+        address: u32,
+        len: u32,
+    ) -> crate::Result<Pages> {
+        let (next, end) = if len == 0 {
+            (0, 0)
+        } else {
+            let first = address.checked_sub(flash_base);
+            let last = address
+                .checked_add(len - 1)
+                .and_then(|last| last.checked_sub(flash_base));
+            match (first, last) {
+                (Some(first), Some(last)) => {
+                    (first / sector_size, last / sector_size + 1)
+                }
+                _ => {
+                    return Err(crate::Error::AddressOutOfRange {
+                        address,
+                        len,
+                    })
+                }
+            }
+        };
+
+        Ok(Pages {
+            flash_base,
+            sector_size,
+            next,
+            end,
+        })
+    }
+
+    /// All the pages of a flash of `flash_size` bytes starting at
+    /// `flash_base`.
+    pub(crate) fn flash(
+        flash_base: u32,
+        sector_size: u32,
+        flash_size: u32,
+    ) -> Pages {
+        let partial = flash_size % sector_size != 0;
+
+        Pages {
+            flash_base,
+            sector_size,
+            next: 0,
+            end: flash_size / sector_size + partial as u32,
+        }
     }
 }
 
+impl Iterator for Pages {
+    type Item = Page;
+
+    fn next(&mut self) -> Option<Page> {
+        if self.next >= self.end {
+            return None;
+        }
+
+        let index = self.next;
+        self.next += 1;
+
+        Some(Page {
+            index,
+            address: self.flash_base + index * self.sector_size,
+            len: self.sector_size,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end.saturating_sub(self.next) as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Pages {}
+
 #[derive(Debug)]
 pub struct ParseFamilyError;
 
 impl Display for ParseFamilyError {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
-        write!(fmt, "invalid value, family must be one of: `cc2538`, `cc26x0` or `cc26x2`")
+        write!(fmt, "invalid value, family must be one of: ")?;
+        for (i, descriptor) in FAMILIES.iter().enumerate() {
+            match i {
+                0 => (),
+                i if i == FAMILIES.len() - 1 => write!(fmt, " or ")?,
+                _ => write!(fmt, ", ")?,
+            }
+            write!(fmt, "`{}`", descriptor.name)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -128,12 +723,170 @@ impl Error for ParseFamilyError {}
 impl FromStr for Family {
     type Err = ParseFamilyError;
 
+    /// Parse a family name, case-insensitively.
+    ///
+    /// Besides the family names, the aliases of
+    /// [`FamilyDescriptor::aliases`] are accepted, these are the `cc13xx`
+    /// family names and the part names used by cc2538-bsl, e.g.: `cc13x2`
+    /// and `cc2652` map to [`Family::CC26X2`].
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "cc2538" | "CC2538" => Ok(Family::CC2538),
-            "cc26x0" | "CC26X0" => Ok(Family::CC26X0),
-            "cc26x2" | "CC26X2" => Ok(Family::CC26X2),
-            _ => Err(ParseFamilyError),
+        FAMILIES
+            .iter()
+            .find(|descriptor| {
+                descriptor.name.eq_ignore_ascii_case(s)
+                    || descriptor
+                        .aliases
+                        .iter()
+                        .any(|alias| alias.eq_ignore_ascii_case(s))
+            })
+            .map(|descriptor| descriptor.family)
+            .ok_or(ParseFamilyError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_descriptors() {
+        let families = [
+            Family::CC2538,
+            Family::CC26X0,
+            Family::CC26X0R2,
+            Family::CC26X2,
+            Family::CC26X2X7,
+            Family::CC26X4,
+            Family::TM4C,
+        ];
+        assert_eq!(FAMILIES.len(), families.len());
+
+        for family in families.iter() {
+            let descriptor = family.descriptor();
+            assert_eq!(descriptor.family, *family);
+            assert_eq!(descriptor.name.parse::<Family>().unwrap(), *family);
+            for alias in descriptor.aliases {
+                assert_eq!(alias.parse::<Family>().unwrap(), *family);
+            }
+
+            assert!(descriptor.sector_size.is_power_of_two());
+            assert!(descriptor.max_baudrate >= 115_200);
+            assert_eq!(
+                family
+                    .memory_region(descriptor.flash_base, 1)
+                    .map(|region| region.kind),
+                Some(MemoryKind::Flash)
+            );
+            if let Some(ccfg) = descriptor.ccfg {
+                let address = family.ccfg_address(0x20000).unwrap();
+                assert!(family.memory_region(address, ccfg.size).is_some());
+            }
+            assert_eq!(descriptor.flash_base % descriptor.sector_size, 0);
+            // Either COMMAND_ERASE, COMMAND_SECTOR_ERASE or an implicit erase
+            // on download, the commands share the command byte.
+            let erase_methods = [
+                family.supports_erase(),
+                family.supports_sector_erase(),
+                family.download_erases(),
+            ];
+            assert_eq!(erase_methods.iter().filter(|m| **m).count(), 1);
+            if descriptor.ieee_primary.is_some() {
+                assert_eq!(
+                    descriptor.ieee_secondary.is_none(),
+                    family.has_ccfg()
+                );
+            }
+            for cmd in descriptor.commands {
+                assert_eq!(
+                    CommandId::from_family(*family, cmd.value()).unwrap(),
+                    *cmd
+                );
+            }
         }
     }
+
+    #[test]
+    fn test_family_from_str() {
+        assert_eq!("cc26x2".parse::<Family>().unwrap(), Family::CC26X2);
+        assert_eq!("CC2652".parse::<Family>().unwrap(), Family::CC26X2);
+        assert_eq!("Cc13X0".parse::<Family>().unwrap(), Family::CC26X0);
+        assert_eq!("cc1310".parse::<Family>().unwrap(), Family::CC26X0);
+        assert_eq!("cc13x2x7".parse::<Family>().unwrap(), Family::CC26X2X7);
+        assert!("cc2652r".parse::<Family>().is_err());
+    }
+
+    #[test]
+    fn test_ccfg_address() {
+        assert_eq!(Family::CC2538.ccfg_address(0x80000), None);
+        assert_eq!(Family::CC26X0R2.ccfg_address(0x20000), Some(0x1FFA8));
+        assert_eq!(Family::CC26X2.ccfg_address(0x58000), Some(0x57FA8));
+        assert_eq!(Family::CC26X2X7.ccfg_address(0xB0000), Some(0xAFFA8));
+        assert_eq!(Family::CC26X4.ccfg_address(0x100000), Some(0x4E020000));
+    }
+
+    #[test]
+    fn test_ieee_addresses() {
+        assert_eq!(
+            Family::CC2538.ieee_addresses(0x80000),
+            Some((0x0028_0028, 0x0027_FFCC))
+        );
+        assert_eq!(
+            Family::CC2538.ieee_addresses(0x20000),
+            Some((0x0028_0028, 0x0021_FFCC))
+        );
+        assert_eq!(
+            Family::CC26X2.ieee_addresses(0x58000),
+            Some((0x5000_12F0, 0x57FC8))
+        );
+        assert_eq!(Family::CC26X4.ieee_addresses(0x100000), None);
+    }
+
+    #[test]
+    fn test_address_to_page() {
+        assert_eq!(Family::CC26X0R2.address_to_page(0x1F000), 31);
+        assert_eq!(Family::CC2538.address_to_page(0x00200800), 1);
+        assert_eq!(
+            Family::CC2538.checked_address_to_page(0x00200800).unwrap(),
+            1
+        );
+        assert!(matches!(
+            Family::CC2538.checked_address_to_page(0x0),
+            Err(crate::Error::AddressOutOfRange { address: 0x0, .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "address below the flash")]
+    fn test_address_to_page_below_flash() {
+        Family::CC2538.address_to_page(0x0);
+    }
+
+    #[test]
+    fn test_pages() {
+        let pages = Family::CC26X2.pages(0x58000);
+        assert_eq!(pages.len(), 44);
+        assert_eq!(
+            pages.last(),
+            Some(Page {
+                index: 43,
+                address: 0x56000,
+                len: 0x2000,
+            })
+        );
+
+        let pages = Family::CC2538
+            .pages_in_range(0x00200800, 0x801)
+            .unwrap()
+            .map(|page| page.address)
+            .collect::<Vec<_>>();
+        assert_eq!(pages, [0x00200800, 0x00201000]);
+        assert_eq!(Family::CC2538.page_to_address(2), 0x00201000);
+        assert_eq!(Family::CC26X0.pages_in_range(0x1000, 0).unwrap().len(), 0);
+        // Below the flash base.
+        assert!(matches!(
+            Family::CC2538.pages_in_range(0x0, 0x800),
+            Err(crate::Error::AddressOutOfRange { address: 0x0, .. })
+        ));
+        assert!(Family::CC26X2.pages_in_range(0xFFFF_F000, 0x2000).is_err());
+    }
 }
@@ -0,0 +1,566 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Firmware images
+//!
+//! A [`Firmware`] is a list of [`Segment`]s, the data to write at each
+//! address, parsed from the file formats produced by the toolchains:
+//!
+//! - Raw binaries, loaded at a given address.
+//! - Intel HEX files, see [`Firmware::from_hex`] and [`Firmware::to_hex`].
+//! - ELF executables, see [`Firmware::from_elf`].
+
+use std::{convert::TryFrom, ops::Range};
+
+use crate::{util::Transfer, Error, Result};
+
+const RECORD_DATA: u8 = 0x00;
+const RECORD_EOF: u8 = 0x01;
+const RECORD_EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const RECORD_START_SEGMENT_ADDRESS: u8 = 0x03;
+const RECORD_EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const RECORD_START_LINEAR_ADDRESS: u8 = 0x05;
+/// Data bytes per record written by [`Firmware::to_hex`].
+const HEX_RECORD_LEN: usize = 16;
+
+/// Magic number at the start of the ELF files.
+pub const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_LSB: u8 = 1;
+const ELF_HEADER_SIZE: usize = 52;
+const ELF_PHDR_SIZE: usize = 32;
+const PT_LOAD: u32 = 1;
+
+/// Data to write at an address.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Segment {
+    /// Address of the first byte.
+    pub address: u32,
+    /// Contents of the segment.
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    /// The address range covered by the segment.
+    #[inline]
+    pub fn range(&self) -> Range<u32> {
+        self.address..self.address + self.data.len() as u32
+    }
+
+    /// A transfer writing the segment, e.g.: to compare it with the flash.
+    #[inline]
+    pub fn transfer(&self) -> Transfer<'_> {
+        Transfer {
+            data: &self.data,
+            start_address: self.address,
+            expect_ack: true,
+        }
+    }
+}
+
+/// A firmware image, its segments are sorted by address and don't overlap,
+/// adjacent segments are merged.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Firmware {
+    segments: Vec<Segment>,
+}
+
+impl Firmware {
+    /// A raw binary image loaded at `address`.
+    pub fn from_binary(address: u32, data: Vec<u8>) -> Firmware {
+        let mut firmware = Firmware::default();
+        if !data.is_empty() {
+            firmware.segments.push(Segment { address, data });
+        }
+        firmware
+    }
+
+    /// Parse an Intel HEX file.
+    ///
+    /// The data records are placed using the extended segment and extended
+    /// linear address records, the start address records are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidHex`] if a record is malformed or its data
+    /// overlaps a previous one.
+    pub fn from_hex(text: &str) -> Result<Firmware> {
+        let mut firmware = Firmware::default();
+        let mut base = 0u32;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = |reason| Error::InvalidHex {
+                line: i + 1,
+                reason,
+            };
+            let record = parse_record(line).map_err(invalid)?;
+            let len = record[0] as usize;
+            let offset = u16::from_be_bytes([record[1], record[2]]);
+            let data = &record[4..record.len() - 1];
+            if data.len() != len {
+                return Err(invalid("record length mismatch"));
+            }
+
+            match record[3] {
+                RECORD_DATA => {
+                    let address = base.wrapping_add(u32::from(offset));
+                    if !firmware.add_segment(address, data) {
+                        return Err(invalid("overlapping data"));
+                    }
+                }
+                RECORD_EOF => break,
+                RECORD_EXTENDED_SEGMENT_ADDRESS if len == 2 => {
+                    base =
+                        u32::from(u16::from_be_bytes([data[0], data[1]])) << 4;
+                }
+                RECORD_EXTENDED_LINEAR_ADDRESS if len == 2 => {
+                    base =
+                        u32::from(u16::from_be_bytes([data[0], data[1]])) << 16;
+                }
+                RECORD_START_SEGMENT_ADDRESS | RECORD_START_LINEAR_ADDRESS
+                    if len == 4 => {}
+                _ => return Err(invalid("invalid record")),
+            }
+        }
+
+        Ok(firmware)
+    }
+
+    /// Write the image as an Intel HEX file, with extended linear address
+    /// records so the segments keep their 32-bit address.
+    pub fn to_hex(&self) -> String {
+        let mut text = String::new();
+        let mut base = None;
+        for segment in &self.segments {
+            let mut address = segment.address;
+            let mut data = segment.data.as_slice();
+            while !data.is_empty() {
+                let upper = (address >> 16) as u16;
+                if base != Some(upper) {
+                    write_record(
+                        &mut text,
+                        RECORD_EXTENDED_LINEAR_ADDRESS,
+                        0,
+                        &upper.to_be_bytes(),
+                    );
+                    base = Some(upper);
+                }
+
+                // Records don't cross a 64 KiB boundary.
+                let offset = address as u16;
+                let len = data
+                    .len()
+                    .min(HEX_RECORD_LEN)
+                    .min(0x1_0000 - usize::from(offset));
+                write_record(&mut text, RECORD_DATA, offset, &data[..len]);
+                address = address.wrapping_add(len as u32);
+                data = &data[len..];
+            }
+        }
+        write_record(&mut text, RECORD_EOF, 0, &[]);
+
+        text
+    }
+
+    /// Extract the loadable segments (`PT_LOAD`) of a 32-bit little endian
+    /// ELF executable, e.g.: built with `arm-none-eabi-gcc`.
+    ///
+    /// The segments are placed at their physical (load) address, so the
+    /// initialized data copied to RAM on startup is kept on flash. Segments
+    /// without data in the file, like `.bss`, are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidElf`] if the file isn't a 32-bit little
+    /// endian ELF, it's truncated or its segments overlap.
+    pub fn from_elf(data: &[u8]) -> Result<Firmware> {
+        if data.len() < ELF_HEADER_SIZE || data[..4] != ELF_MAGIC {
+            return Err(Error::InvalidElf("not an ELF file"));
+        }
+        if data[4] != ELF_CLASS_32 || data[5] != ELF_DATA_LSB {
+            return Err(Error::InvalidElf("not a 32-bit little endian ELF"));
+        }
+
+        let half = |offset: usize| {
+            u16::from_le_bytes([data[offset], data[offset + 1]]) as usize
+        };
+        let word = |offset: usize| {
+            u32::from_le_bytes(
+                <[u8; 4]>::try_from(&data[offset..offset + 4]).unwrap(),
+            )
+        };
+        let phoff = word(28) as usize;
+        let phentsize = half(42);
+        let phnum = half(44);
+        if phnum != 0 && phentsize < ELF_PHDR_SIZE {
+            return Err(Error::InvalidElf("invalid program header size"));
+        }
+
+        let mut firmware = Firmware::default();
+        for i in 0..phnum {
+            let phdr = phoff + i * phentsize;
+            if phdr + ELF_PHDR_SIZE > data.len() {
+                return Err(Error::InvalidElf("truncated program headers"));
+            }
+
+            let kind = word(phdr);
+            let offset = word(phdr + 4) as usize;
+            let paddr = word(phdr + 12);
+            let filesz = word(phdr + 16) as usize;
+            if kind != PT_LOAD || filesz == 0 {
+                continue;
+            }
+
+            let contents = data
+                .get(offset..offset + filesz)
+                .ok_or(Error::InvalidElf("truncated segment"))?;
+            if !firmware.add_segment(paddr, contents) {
+                return Err(Error::InvalidElf("overlapping segments"));
+            }
+        }
+
+        Ok(firmware)
+    }
+
+    /// The segments of the image, sorted by address.
+    #[inline]
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Consume the image and return its segments, sorted by address.
+    #[inline]
+    pub fn into_segments(self) -> Vec<Segment> {
+        self.segments
+    }
+
+    /// Whether the image doesn't have any data.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// The address range from the first to the last byte of the image,
+    /// `None` if it's empty.
+    pub fn range(&self) -> Option<Range<u32>> {
+        let first = self.segments.first()?;
+        let last = self.segments.last()?;
+        Some(first.address..last.range().end)
+    }
+
+    /// A single segment covering the whole image, the gaps between the
+    /// segments are filled with `fill`. `None` if the image is empty.
+    pub fn to_contiguous(&self, fill: u8) -> Option<Segment> {
+        let range = self.range()?;
+        let mut data = vec![fill; (range.end - range.start) as usize];
+        for segment in &self.segments {
+            let offset = (segment.address - range.start) as usize;
+            data[offset..offset + segment.data.len()]
+                .copy_from_slice(&segment.data);
+        }
+
+        Some(Segment {
+            address: range.start,
+            data,
+        })
+    }
+
+    /// Add the segments of `other` to the image, e.g.: to flash several
+    /// images in a single session.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::OverlappingData`] if a segment of `other` overlaps
+    /// one of the image, the image is left unchanged then.
+    pub fn merge(&mut self, other: Firmware) -> Result<()> {
+        let mut merged = self.clone();
+        for segment in other.segments {
+            if !merged.add_segment(segment.address, &segment.data) {
+                return Err(Error::OverlappingData(segment.address));
+            }
+        }
+        *self = merged;
+
+        Ok(())
+    }
+
+    /// Pad the segments with `0xFF` so they start and end on a multiple of
+    /// `alignment` bytes, the bootloader writes the flash in 32-bit words.
+    /// Segments that end up overlapping are merged.
+    ///
+    /// Returns whether any segment was padded.
+    ///
+    /// # Panics
+    ///
+    /// If the `alignment` is not a power of two.
+    pub fn align(&mut self, alignment: u32) -> bool {
+        assert!(alignment.is_power_of_two(), "invalid alignment");
+        let mask = alignment - 1;
+        let aligned = self.segments.iter().all(|segment| {
+            segment.address & mask == 0 && segment.data.len() as u32 & mask == 0
+        });
+        if aligned {
+            return false;
+        }
+
+        let mut segments: Vec<Segment> =
+            Vec::with_capacity(self.segments.len());
+        for segment in self.segments.drain(..) {
+            let start = segment.address & !mask;
+            let end = (segment.range().end + mask) & !mask;
+            let merged = match segments.last_mut() {
+                Some(last) if last.range().end >= start => last,
+                _ => {
+                    segments.push(Segment {
+                        address: start,
+                        data: Vec::new(),
+                    });
+                    segments.last_mut().unwrap()
+                }
+            };
+
+            let len = (end - merged.address) as usize;
+            if merged.data.len() < len {
+                merged.data.resize(len, 0xFF);
+            }
+            let offset = (segment.address - merged.address) as usize;
+            merged.data[offset..offset + segment.data.len()]
+                .copy_from_slice(&segment.data);
+        }
+        self.segments = segments;
+
+        true
+    }
+
+    /// Add the `data` at `address`, merging it with the adjacent segments.
+    ///
+    /// Returns `false` if it overlaps an existing segment.
+    fn add_segment(&mut self, address: u32, data: &[u8]) -> bool {
+        if data.is_empty() {
+            return true;
+        }
+
+        let end = match address.checked_add(data.len() as u32) {
+            Some(end) => end,
+            None => return false,
+        };
+        let index = self
+            .segments
+            .iter()
+            .position(|segment| segment.address >= end)
+            .unwrap_or(self.segments.len());
+
+        if index > 0 {
+            let previous = &mut self.segments[index - 1];
+            let previous_end = previous.range().end;
+            if previous_end > address {
+                return false;
+            }
+            if previous_end == address {
+                previous.data.extend_from_slice(data);
+                self.merge_next(index - 1);
+                return true;
+            }
+        }
+
+        self.segments.insert(
+            index,
+            Segment {
+                address,
+                data: data.to_vec(),
+            },
+        );
+        self.merge_next(index);
+
+        true
+    }
+
+    /// Merge the segment at `index` with the next one if they're adjacent.
+    fn merge_next(&mut self, index: usize) {
+        if index + 1 < self.segments.len()
+            && self.segments[index].range().end
+                == self.segments[index + 1].address
+        {
+            let next = self.segments.remove(index + 1);
+            self.segments[index].data.extend_from_slice(&next.data);
+        }
+    }
+}
+
+/// Append a record to an Intel HEX file.
+fn write_record(text: &mut String, kind: u8, offset: u16, data: &[u8]) {
+    let offset = offset.to_be_bytes();
+    let header = [data.len() as u8, offset[0], offset[1], kind];
+    let sum = header
+        .iter()
+        .chain(data)
+        .fold(0u8, |acc, b| acc.wrapping_add(*b));
+
+    text.push(':');
+    for byte in header.iter().chain(data).chain(&[sum.wrapping_neg()]) {
+        text.push_str(&format!("{:02X}", byte));
+    }
+    text.push('\n');
+}
+
+/// Decode and check a `:`-prefixed hexadecimal record, including its
+/// checksum.
+fn parse_record(line: &str) -> std::result::Result<Vec<u8>, &'static str> {
+    if !line.starts_with(':') {
+        return Err("missing start code");
+    }
+    let hex = &line[1..];
+    if !hex.is_ascii() || hex.len() % 2 != 0 || hex.len() < 10 {
+        return Err("invalid record length");
+    }
+
+    let record = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| "invalid hexadecimal digit")?;
+
+    let sum = record.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    if sum != 0 {
+        return Err("invalid checksum");
+    }
+
+    Ok(record)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_hex() {
+        let hex = "\
+:020000040000FA
+:0400000001020304F2
+:0400040005060708DE
+:02000004000AF0
+:02000000AABB99
+:04000005000000CD2A
+:00000001FF
+";
+        let firmware = Firmware::from_hex(hex).unwrap();
+        assert_eq!(
+            firmware.segments(),
+            &[
+                Segment {
+                    address: 0,
+                    data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                },
+                Segment {
+                    address: 0x000A_0000,
+                    data: vec![0xAA, 0xBB],
+                },
+            ]
+        );
+        assert_eq!(firmware.range(), Some(0..0x000A_0002));
+
+        let contiguous = Firmware::from_binary(0x10, vec![1, 2]);
+        assert_eq!(contiguous.to_contiguous(0xFF).unwrap().range(), 0x10..0x12);
+
+        // Bad checksum and overlapping data.
+        assert!(Firmware::from_hex(":0400000001020304F3\n").is_err());
+        assert!(Firmware::from_hex(":0400000001020304F2\n:0200020001FFFC\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_to_hex() {
+        let firmware = Firmware::from_binary(0x0001_FFF8, (0..24).collect());
+        let hex = firmware.to_hex();
+        assert!(hex.starts_with(":020000040001F9\n"));
+        assert!(hex.ends_with(":00000001FF\n"));
+        assert_eq!(Firmware::from_hex(&hex).unwrap(), firmware);
+    }
+
+    #[test]
+    fn test_align() {
+        let mut firmware = Firmware::from_binary(0x100, vec![1, 2, 3, 4]);
+        assert!(!firmware.align(4));
+
+        let mut firmware = Firmware::default();
+        firmware.add_segment(0x101, &[1, 2, 3]);
+        firmware.add_segment(0x106, &[4]);
+        firmware.add_segment(0x200, &[5]);
+        assert!(firmware.align(4));
+        assert!(firmware
+            .merge(Firmware::from_binary(0x1FE, vec![0, 0, 0]))
+            .is_err());
+        assert_eq!(
+            firmware.segments(),
+            &[
+                Segment {
+                    address: 0x100,
+                    data: vec![0xFF, 1, 2, 3, 0xFF, 0xFF, 4, 0xFF],
+                },
+                Segment {
+                    address: 0x200,
+                    data: vec![5, 0xFF, 0xFF, 0xFF],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_elf() {
+        let mut elf = vec![0u8; ELF_HEADER_SIZE + 3 * ELF_PHDR_SIZE];
+        elf[..4].copy_from_slice(&ELF_MAGIC);
+        elf[4] = ELF_CLASS_32;
+        elf[5] = ELF_DATA_LSB;
+        elf[28..32].copy_from_slice(&(ELF_HEADER_SIZE as u32).to_le_bytes());
+        elf[42..44].copy_from_slice(&(ELF_PHDR_SIZE as u16).to_le_bytes());
+        elf[44..46].copy_from_slice(&3u16.to_le_bytes());
+
+        let data_offset = elf.len() as u32;
+        elf.extend_from_slice(&[1, 2, 3, 4, 5, 6]);
+        // .text at 0x0, .data loaded after it but running from RAM, .bss.
+        let phdrs: [(u32, u32, u32, u32, u32); 3] = [
+            (PT_LOAD, data_offset, 0x0000_0000, 0x0000_0000, 4),
+            (PT_LOAD, data_offset + 4, 0x2000_0000, 0x0000_0004, 2),
+            (PT_LOAD, 0, 0x2000_0002, 0x2000_0002, 0),
+        ];
+        for (i, (kind, offset, vaddr, paddr, filesz)) in
+            phdrs.iter().enumerate()
+        {
+            let phdr = ELF_HEADER_SIZE + i * ELF_PHDR_SIZE;
+            elf[phdr..phdr + 4].copy_from_slice(&kind.to_le_bytes());
+            elf[phdr + 4..phdr + 8].copy_from_slice(&offset.to_le_bytes());
+            elf[phdr + 8..phdr + 12].copy_from_slice(&vaddr.to_le_bytes());
+            elf[phdr + 12..phdr + 16].copy_from_slice(&paddr.to_le_bytes());
+            elf[phdr + 16..phdr + 20].copy_from_slice(&filesz.to_le_bytes());
+        }
+
+        let firmware = Firmware::from_elf(&elf).unwrap();
+        assert_eq!(
+            firmware.segments(),
+            &[Segment {
+                address: 0,
+                data: vec![1, 2, 3, 4, 5, 6],
+            }]
+        );
+
+        assert!(Firmware::from_elf(&elf[..40]).is_err());
+        elf[5] = 2;
+        assert!(Firmware::from_elf(&elf).is_err());
+    }
+}
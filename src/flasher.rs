@@ -0,0 +1,775 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Flashing a firmware
+//!
+//! [`Flasher`] runs the whole flow to flash the segments of a
+//! [`Firmware`](crate::firmware::Firmware): the write protection is checked,
+//! the flash is erased following the [`Erase`] policy, the segments are
+//! written (the CCFG last, as the device may not answer once it's written)
+//! and the flash is verified.
+//!
+//! ```no_run
+//! use ti_sbl::{
+//!     firmware::Firmware,
+//!     flasher::{Erase, Flasher},
+//!     util::VerifyMethod,
+//! };
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let port = serial::open("/dev/ttyUSB0")?;
+//! let mut device = ti_sbl::DeviceBuilder::new(ti_sbl::Family::CC26X2)
+//!     .detect_family(true)
+//!     .build(port)?;
+//! let layout = ti_sbl::util::read_flash_layout(&mut device)?;
+//!
+//! let text = std::fs::read_to_string("app.hex")?;
+//! let firmware = Firmware::from_hex(&text)?;
+//! Flasher::new(&mut device, layout)
+//!     .erase(Erase::Sectors)
+//!     .verify(Some(VerifyMethod::Crc32))
+//!     .keep_ccfg(true)
+//!     .flash(firmware.segments(), ())?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The progress is reported as [`ProgressEvent`]s to a [`ProgressHandler`],
+//! a closure or an [`mpsc::Sender`] to consume them from another thread,
+//! e.g.: the event loop of a GUI:
+//!
+//! ```no_run
+//! # use ti_sbl::{firmware::Segment, flasher::{Flasher, ProgressEvent}};
+//! # fn flash(
+//! #     mut device: ti_sbl::Device<serial::SystemPort>,
+//! #     layout: ti_sbl::FlashLayout,
+//! #     segments: Vec<Segment>,
+//! # ) {
+//! use std::{sync::mpsc, thread};
+//!
+//! let (sender, receiver) = mpsc::channel();
+//! let flashing = thread::spawn(move || {
+//!     Flasher::new(&mut device, layout).flash(&segments, sender)
+//! });
+//! for event in receiver {
+//!     if let ProgressEvent::ChunkWritten {
+//!         bytes_done,
+//!         bytes_total,
+//!         ..
+//!     } = event
+//!     {
+//!         println!("{}/{} bytes written", bytes_done, bytes_total);
+//!     }
+//! }
+//! let result = flashing.join().unwrap();
+//! # }
+//! ```
+
+use std::{
+    ops::Range,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    firmware::Segment,
+    plan::{self, FlashPlan},
+    util::{
+        self, CancelToken, Recovery, Transfer, VerifyMethod, WriteProgress,
+        WriteStats,
+    },
+    Device, Error, FlashLayout, Result, VerificationError,
+};
+
+/// How the flash is erased before writing it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Erase {
+    /// Don't erase, the flash must be blank.
+    None,
+    /// Erase the sectors of each segment.
+    Sectors,
+    /// Erase the whole flash, including the CCFG unless it's kept, see
+    /// [`Flasher::keep_ccfg`].
+    Bank,
+    /// Only erase and write the sectors that differ from the flash, see
+    /// [`util::changed_sectors`].
+    Incremental,
+}
+
+/// Progress of [`Flasher::flash`], see [`ProgressHandler`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressEvent {
+    /// Started erasing `len` bytes at `address`.
+    EraseStarted {
+        /// Address of the range to erase.
+        address: u32,
+        /// Length of the range to erase.
+        len: u32,
+    },
+    /// The sector at `address` was erased. Families erasing a whole range
+    /// with a single command report it as one sector.
+    SectorErased {
+        /// Address of the erased sector.
+        address: u32,
+        /// Number of bytes of the range erased so far.
+        bytes_done: u32,
+        /// Length of the range to erase.
+        bytes_total: u32,
+    },
+    /// The chunk of `len` bytes at `address` was written.
+    ChunkWritten {
+        /// Address of the chunk.
+        address: u32,
+        /// Length of the chunk.
+        len: usize,
+        /// Number of bytes written so far.
+        bytes_done: usize,
+        /// Number of bytes to write.
+        bytes_total: usize,
+    },
+    /// Verifying the flash, reported before verifying each segment and once
+    /// all of them are verified.
+    VerifyProgress {
+        /// Number of bytes verified so far.
+        bytes_done: usize,
+        /// Number of bytes to verify.
+        bytes_total: usize,
+    },
+    /// The flashing finished successfully, with its statistics.
+    Done(FlashStats),
+}
+
+/// Receives the [`ProgressEvent`]s of [`Flasher::flash`].
+///
+/// It's implemented for closures, for [`mpsc::Sender`] to consume the events
+/// from another thread, and for `()` to ignore them.
+pub trait ProgressHandler {
+    /// Handle an `event`. It's called while flashing, so it shouldn't block.
+    fn on_event(&mut self, event: ProgressEvent);
+}
+
+impl<F> ProgressHandler for F
+where
+    F: FnMut(ProgressEvent),
+{
+    fn on_event(&mut self, event: ProgressEvent) {
+        self(event)
+    }
+}
+
+impl ProgressHandler for mpsc::Sender<ProgressEvent> {
+    fn on_event(&mut self, event: ProgressEvent) {
+        // The flashing goes on if the receiver is gone.
+        self.send(event).ok();
+    }
+}
+
+impl ProgressHandler for () {
+    fn on_event(&mut self, _: ProgressEvent) {}
+}
+
+/// Statistics of [`Flasher::flash`], see [`Flasher::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FlashStats {
+    /// Whether nothing was erased nor written as the flash already matched,
+    /// see [`Flasher::skip_identical`].
+    pub skipped: bool,
+    /// Time spent erasing the flash, including the comparison of the
+    /// sectors with [`Erase::Incremental`].
+    pub erase: Option<Duration>,
+    /// Statistics of the write.
+    pub write: Option<WriteStats>,
+    /// Time spent verifying the flash.
+    pub verify: Option<Duration>,
+    /// Result of the verification, if the flash was verified.
+    pub verified: Option<bool>,
+}
+
+/// Flash a firmware on a device, see the [module documentation](self).
+pub struct Flasher<'a, P> {
+    device: &'a mut Device<P>,
+    layout: FlashLayout,
+    erase: Erase,
+    verify: Option<VerifyMethod>,
+    keep_ccfg: bool,
+    verify_erase: bool,
+    skip_identical: bool,
+    recovery: Recovery,
+    cancel: CancelToken,
+    stats: FlashStats,
+    segments: Vec<Segment>,
+}
+
+impl<'a, P> Flasher<'a, P>
+where
+    P: serial::SerialPort,
+{
+    /// Flash the `device`, with the flash `layout` read from it, see
+    /// [`util::read_flash_layout`].
+    ///
+    /// By default the sectors of the segments are erased, the flash isn't
+    /// verified and the CCFG of the firmware is written.
+    pub fn new(device: &'a mut Device<P>, layout: FlashLayout) -> Self {
+        Flasher {
+            device,
+            layout,
+            erase: Erase::Sectors,
+            verify: None,
+            keep_ccfg: false,
+            verify_erase: false,
+            skip_identical: false,
+            recovery: Recovery::default(),
+            cancel: CancelToken::new(),
+            stats: FlashStats::default(),
+            segments: Vec::new(),
+        }
+    }
+
+    /// How the flash is erased before writing it.
+    pub fn erase(mut self, erase: Erase) -> Self {
+        self.erase = erase;
+        self
+    }
+
+    /// Verify the flash after writing it with the given method, `None`
+    /// doesn't verify it.
+    pub fn verify(mut self, method: Option<VerifyMethod>) -> Self {
+        self.verify = method;
+        self
+    }
+
+    /// Keep the CCFG of the device, it replaces the CCFG of the firmware and
+    /// it's restored after [`Erase::Bank`].
+    pub fn keep_ccfg(mut self, keep_ccfg: bool) -> Self {
+        self.keep_ccfg = keep_ccfg;
+        self
+    }
+
+    /// Check that the erased sectors are blank before writing them, see
+    /// [`util::blank_check`].
+    pub fn verify_erase(mut self, verify_erase: bool) -> Self {
+        self.verify_erase = verify_erase;
+        self
+    }
+
+    /// Don't erase nor write anything if the CRC32 of the segments already
+    /// matches the flash.
+    pub fn skip_identical(mut self, skip_identical: bool) -> Self {
+        self.skip_identical = skip_identical;
+        self
+    }
+
+    /// Recover from communication errors while writing, see
+    /// [`util::write_flash_range_with_recovery`].
+    pub fn recovery(mut self, recovery: Recovery) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Token to cancel the flashing from another thread.
+    pub fn cancel_token(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// The device being flashed.
+    #[inline]
+    pub fn device(&mut self) -> &mut Device<P> {
+        self.device
+    }
+
+    /// Statistics of the last [`Flasher::flash`], also after it failed.
+    #[inline]
+    pub fn stats(&self) -> &FlashStats {
+        &self.stats
+    }
+
+    /// The segments flashed by the last [`Flasher::flash`], with the CCFG of
+    /// the device if it was kept. Empty if it failed before reading it.
+    #[inline]
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// What flashing the `segments` does, to review it before
+    /// [`Flasher::flash`], e.g.: the writes on the CCFG and the CCA. The
+    /// CCFG of the device is written instead of the one of the `segments`
+    /// if it's kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressOutOfRange`] if a segment is outside of the
+    /// flash.
+    pub fn plan<'s>(&self, segments: &'s [Segment]) -> Result<FlashPlan<'s>> {
+        FlashPlan::from_segments(&self.layout, segments)
+    }
+
+    /// Erase, write and verify the `segments`, e.g.: the ones of a
+    /// [`Firmware`](crate::firmware::Firmware), reporting the `progress`
+    /// along the way.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::AddressOutOfRange`] if a segment is outside of the
+    ///   flash.
+    /// - Returns [`Error::ProtectedPages`] if the flash to erase or write is
+    ///   write protected.
+    /// - Returns [`Error::Verification`] if the flash doesn't match the
+    ///   segments after writing them, or if it isn't blank after erasing
+    ///   it.
+    pub fn flash<H>(
+        &mut self,
+        segments: &[Segment],
+        mut progress: H,
+    ) -> Result<()>
+    where
+        H: ProgressHandler,
+    {
+        self.stats = FlashStats::default();
+        self.segments.clear();
+
+        // Fail before reading the device if a segment is outside of the flash.
+        self.plan(segments)?;
+
+        let mut segments = segments.to_vec();
+        if self.keep_ccfg {
+            self.replace_ccfg(&mut segments)?;
+        }
+        self.segments = segments.clone();
+
+        if self.skip_identical && self.matches(&segments)? {
+            log::info!(
+                "The flash already contains the firmware, nothing to erase or write"
+            );
+            self.stats.skipped = true;
+            progress.on_event(ProgressEvent::Done(self.stats));
+            return Ok(());
+        }
+
+        // Fail before erasing anything instead of on the first protected page.
+        if self.erase == Erase::Bank {
+            self.check_protection(self.layout.flash())?;
+        } else {
+            for segment in &segments {
+                self.check_protection(segment.range())?;
+            }
+        }
+
+        let plan = FlashPlan::from_segments(&self.layout, &segments)?;
+        log::debug!("Flash plan: {}", plan);
+
+        let erase_start = Instant::now();
+        match self.erase {
+            Erase::Bank => {
+                log::info!("Erasing the whole flash");
+                let flash = self.layout.flash();
+                let len = flash.end - flash.start;
+                progress.on_event(ProgressEvent::EraseStarted {
+                    address: flash.start,
+                    len,
+                });
+                // The CCFG of a firmware covering it was already replaced.
+                let writes_ccfg = !plan.ccfg().is_empty();
+                util::bank_erase(self.device, self.keep_ccfg && !writes_ccfg)?;
+                progress.on_event(ProgressEvent::SectorErased {
+                    address: flash.start,
+                    bytes_done: len,
+                    bytes_total: len,
+                });
+                if self.verify_erase {
+                    // The CCFG may have been restored.
+                    let flash = self.layout.flash();
+                    let end =
+                        self.layout.ccfg().map_or(flash.end, |ccfg| ccfg.start);
+                    self.check_erased(flash.start..end)?;
+                }
+            }
+            Erase::Sectors => {
+                for range in plan.erased_unwritten() {
+                    log::warn!(
+                        "{:#X}..{:#X} shares a sector with the firmware, it will be erased",
+                        range.start,
+                        range.end
+                    );
+                }
+                for range in plan.erase_ranges() {
+                    self.erase_range(range.clone(), &mut progress)?;
+                }
+            }
+            Erase::None | Erase::Incremental => (),
+        }
+
+        // CCFG is sent separately, and doesn't
+        // expect an ACK in return, if the device locks itself.
+        let mut transfers = plan.transfers().to_vec();
+        if self.erase == Erase::Incremental {
+            transfers = self.incremental(&transfers, &mut progress)?;
+        }
+        if self.erase != Erase::None {
+            self.stats.erase = Some(erase_start.elapsed());
+        }
+
+        // The erased chunks don't need to be written again.
+        if self.erase != Erase::None && !self.device.family().download_erases()
+        {
+            transfers = util::skip_blank_chunks(&transfers);
+        }
+
+        // The write progress is reported before writing each chunk, and once
+        // all of them are written.
+        let mut last: Option<WriteProgress> = None;
+        let stats = util::write_flash_range_with_recovery(
+            self.device,
+            &transfers,
+            &self.cancel,
+            &self.recovery,
+            |write| {
+                if let Some(last) = last {
+                    // A chunk sent again after a recovery isn't reported
+                    // twice.
+                    if write.bytes_done > last.bytes_done {
+                        progress.on_event(ProgressEvent::ChunkWritten {
+                            address: last.address,
+                            len: write.bytes_done - last.bytes_done,
+                            bytes_done: write.bytes_done,
+                            bytes_total: write.bytes_total,
+                        });
+                    }
+                }
+                last = Some(write);
+            },
+        )?;
+        self.stats.write = Some(stats);
+        log::info!(
+            "Written {} bytes in {:.1} s ({:.1} KiB/s)",
+            stats.bytes_written,
+            stats.elapsed.as_secs_f64(),
+            stats.throughput() / 1024.0
+        );
+
+        if let Some(method) = self.verify {
+            log::info!("Verifying");
+            let verify_start = Instant::now();
+            let result = self.verify_segments(&segments, method, &mut progress);
+            self.stats.verify = Some(verify_start.elapsed());
+            self.stats.verified = Some(result.is_ok());
+            result?;
+            log::info!("Flash verified");
+        }
+
+        progress.on_event(ProgressEvent::Done(self.stats));
+
+        Ok(())
+    }
+
+    /// Verify the `segments` one by one to report the progress, the
+    /// mismatches of all of them are returned.
+    fn verify_segments<H>(
+        &mut self,
+        segments: &[Segment],
+        method: VerifyMethod,
+        progress: &mut H,
+    ) -> Result<()>
+    where
+        H: ProgressHandler,
+    {
+        let bytes_total = segments.iter().map(|s| s.data.len()).sum();
+        let mut bytes_done = 0;
+        let mut mismatches = Vec::new();
+        for transfer in segments.iter().map(Segment::transfer) {
+            progress.on_event(ProgressEvent::VerifyProgress {
+                bytes_done,
+                bytes_total,
+            });
+            match util::verify_flash(self.device, &[transfer], method) {
+                Ok(()) => (),
+                Err(Error::Verification(e)) => mismatches.extend(e.mismatches),
+                Err(e) => return Err(e),
+            }
+            bytes_done += transfer.data.len();
+        }
+        progress.on_event(ProgressEvent::VerifyProgress {
+            bytes_done,
+            bytes_total,
+        });
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            mismatches.sort_by_key(|mismatch| mismatch.address);
+            Err(Error::Verification(VerificationError { mismatches }))
+        }
+    }
+
+    /// Replace the CCFG of the `segments` with the one of the device.
+    fn replace_ccfg(&mut self, segments: &mut [Segment]) -> Result<()> {
+        if !segments
+            .iter()
+            .any(|segment| self.layout.overlaps_ccfg(&segment.range()))
+        {
+            return Ok(());
+        }
+
+        let current = util::read_ccfg(self.device)?.to_bytes();
+        plan::replace_ccfg(&self.layout, segments, &current);
+        log::info!("Keeping the CCFG of the device");
+
+        Ok(())
+    }
+
+    /// Whether the CRC32 of the `segments` on the flash matches their data.
+    fn matches(&mut self, segments: &[Segment]) -> Result<bool> {
+        let transfers =
+            segments.iter().map(Segment::transfer).collect::<Vec<_>>();
+        match util::verify_flash_range(self.device, &transfers) {
+            Ok(()) => Ok(true),
+            Err(Error::Verification(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fail if any of the flash pages of the `range` is write protected, the
+    /// flashing goes on if the protection can't be read.
+    fn check_protection(&mut self, range: Range<u32>) -> Result<()> {
+        match util::check_write_protection(
+            self.device,
+            range.start,
+            range.end - range.start,
+        ) {
+            Err(e @ Error::ProtectedPages(_)) => Err(e),
+            Err(e) => {
+                log::warn!("Couldn't check the flash protection: {}", e);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Erase the sectors of the `range`.
+    fn erase_range<H>(
+        &mut self,
+        range: Range<u32>,
+        progress: &mut H,
+    ) -> Result<()>
+    where
+        H: ProgressHandler,
+    {
+        log::info!(
+            "{} bytes will be erased at start address {:#X}",
+            range.end - range.start,
+            range.start
+        );
+        let mut last_address = range.start;
+        util::erase_flash_range(
+            self.device,
+            range.start,
+            range.end - range.start,
+            &self.cancel,
+            |erase| {
+                // The erase progress is reported before erasing each sector,
+                // and once all of them are erased.
+                if erase.sector == 0 {
+                    progress.on_event(ProgressEvent::EraseStarted {
+                        address: erase.address,
+                        len: erase.bytes_total,
+                    });
+                } else {
+                    progress.on_event(ProgressEvent::SectorErased {
+                        address: last_address,
+                        bytes_done: erase.bytes_done,
+                        bytes_total: erase.bytes_total,
+                    });
+                }
+                last_address = erase.address;
+            },
+        )?;
+
+        if self.verify_erase {
+            self.check_erased(range)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that the `range` is blank after erasing it.
+    fn check_erased(&mut self, range: Range<u32>) -> Result<()> {
+        if self.device.family().download_erases() {
+            // Nothing was erased, the flash is erased while writing.
+            return Ok(());
+        }
+
+        util::blank_check(self.device, range, VerifyMethod::Crc32)
+    }
+
+    /// Keep the sectors of the `transfers` that differ from the flash and
+    /// erase them.
+    fn incremental<'t, H>(
+        &mut self,
+        transfers: &[Transfer<'t>],
+        progress: &mut H,
+    ) -> Result<Vec<Transfer<'t>>>
+    where
+        H: ProgressHandler,
+    {
+        let changed = util::changed_sectors(self.device, transfers)?;
+        if changed.is_empty() {
+            log::info!("The flash already contains the firmware");
+        }
+
+        // Erase consecutive sectors at once.
+        let plan = FlashPlan::new(&self.layout, &changed)?;
+        for range in plan.erase_ranges() {
+            self.erase_range(range.clone(), progress)?;
+        }
+
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ccfg::CCFG_SIZE,
+        trace::{Capture, ReplayPort},
+        CommandId, Family,
+    };
+
+    const DATA: [u8; 8] = [0x11; 8];
+    const BLANK_CCFG: [u8; CCFG_SIZE] = [0xFF; CCFG_SIZE];
+
+    fn device(capture: Capture) -> Device<ReplayPort> {
+        Device::new(capture.port(), Family::CC26X2).unwrap()
+    }
+
+    fn layout() -> FlashLayout {
+        FlashLayout::new(Family::CC26X2, 0x58000)
+    }
+
+    fn segment(address: u32, data: &[u8]) -> Segment {
+        Segment {
+            address,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_flash_sectors() {
+        let capture = Capture::new()
+            .read_ccfg(&BLANK_CCFG)
+            .sector_erase(0x0)
+            .write(0x0, &DATA);
+        let mut device = device(capture);
+
+        let mut events = Vec::new();
+        let mut flasher = Flasher::new(&mut device, layout());
+        flasher
+            .flash(&[segment(0x0, &DATA)], |event| events.push(event))
+            .unwrap();
+        assert_eq!(flasher.stats().write.unwrap().bytes_written, DATA.len());
+        assert!(device.port().is_finished());
+
+        assert_eq!(
+            events[0],
+            ProgressEvent::EraseStarted {
+                address: 0x0,
+                len: 0x2000
+            }
+        );
+        assert!(matches!(events.last(), Some(ProgressEvent::Done(_))));
+    }
+
+    #[test]
+    fn test_flash_bank_keep_ccfg() {
+        let mut ccfg = BLANK_CCFG;
+        ccfg[..4].copy_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        let capture = Capture::new()
+            .read_ccfg(&ccfg)
+            .read_ccfg(&ccfg)
+            .command(CommandId::BankErase, &[])
+            .success()
+            .write(0x0, &DATA)
+            .download(0x57FA8, CCFG_SIZE)
+            .command(CommandId::SendData, &ccfg);
+        let mut device = device(capture);
+
+        // The CCFG of the firmware is replaced with the one of the device.
+        let segments = [segment(0x0, &DATA), segment(0x57FA8, &[0; CCFG_SIZE])];
+        let mut flasher = Flasher::new(&mut device, layout())
+            .erase(Erase::Bank)
+            .keep_ccfg(true);
+        flasher.flash(&segments, ()).unwrap();
+        assert_eq!(flasher.segments()[1].data, &ccfg[..]);
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_flash_incremental() {
+        let capture = Capture::new()
+            .read_ccfg(&BLANK_CCFG)
+            .read_ccfg(&BLANK_CCFG)
+            .crc32(0x0, 8, crate::crc32(&DATA))
+            .crc32(0x2000, 8, 0)
+            .sector_erase(0x2000)
+            .write(0x2000, &DATA);
+        let mut device = device(capture);
+
+        // Only the second sector differs.
+        let segments = [segment(0x0, &DATA), segment(0x2000, &DATA)];
+        let mut flasher =
+            Flasher::new(&mut device, layout()).erase(Erase::Incremental);
+        flasher.flash(&segments, ()).unwrap();
+        assert_eq!(flasher.stats().write.unwrap().bytes_written, DATA.len());
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_flash_skip_identical() {
+        let capture = Capture::new().crc32(0x0, 8, crate::crc32(&DATA));
+        let mut device = device(capture);
+
+        let mut events = Vec::new();
+        let mut flasher =
+            Flasher::new(&mut device, layout()).skip_identical(true);
+        flasher
+            .flash(&[segment(0x0, &DATA)], |event| events.push(event))
+            .unwrap();
+        assert!(flasher.stats().skipped);
+        assert_eq!(events, [ProgressEvent::Done(*flasher.stats())]);
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_flash_verify_failure() {
+        let capture = Capture::new()
+            .read_ccfg(&BLANK_CCFG)
+            .sector_erase(0x0)
+            .write(0x0, &DATA)
+            .crc32(0x0, 8, 0);
+        let mut device = device(capture);
+
+        let mut flasher = Flasher::new(&mut device, layout())
+            .verify(Some(VerifyMethod::Crc32));
+        match flasher.flash(&[segment(0x0, &DATA)], ()) {
+            Err(Error::Verification(e)) => {
+                assert_eq!(e.mismatches.len(), 1);
+                assert_eq!(e.mismatches[0].address, 0x0);
+                assert_eq!(e.mismatches[0].actual, 0);
+            }
+            ret => panic!("unexpected result: {:?}", ret),
+        }
+        assert_eq!(flasher.stats().verified, Some(false));
+        assert!(device.port().is_finished());
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::Range;
+
+use crate::{cca::CCA_SIZE, Family, Pages};
+
+/// The flash memory map of a device.
+///
+/// See [`util::read_flash_layout`](crate::util::read_flash_layout) to build
+/// it from a connected device.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FlashLayout {
+    family: Family,
+    flash: Range<u32>,
+    ccfg: Option<Range<u32>>,
+    cca: Option<Range<u32>>,
+    sector_size: u32,
+}
+
+impl FlashLayout {
+    /// Layout of a `family` device with `flash_size` bytes of main flash.
+    pub fn new(family: Family, flash_size: u32) -> FlashLayout {
+        let flash = family.flash_base()..family.flash_base() + flash_size;
+        let ccfg = family
+            .ccfg_address(flash_size)
+            .map(|address| address..address + family.ccfg_size());
+        let cca = match family {
+            Family::CC2538 => Some(flash.end - CCA_SIZE as u32..flash.end),
+            _ => None,
+        };
+
+        FlashLayout {
+            family,
+            flash,
+            ccfg,
+            cca,
+            sector_size: family.sector_size(),
+        }
+    }
+
+    /// Use a sector size different from the one of the family, see
+    /// [`Device::set_sector_size`](crate::Device::set_sector_size).
+    pub fn with_sector_size(mut self, sector_size: u32) -> FlashLayout {
+        self.sector_size = sector_size;
+        self
+    }
+
+    /// Sector erase size, in bytes.
+    #[inline]
+    pub fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
+
+    /// The family of the device.
+    #[inline]
+    pub fn family(&self) -> Family {
+        self.family
+    }
+
+    /// The main flash address range.
+    #[inline]
+    pub fn flash(&self) -> Range<u32> {
+        self.flash.clone()
+    }
+
+    /// Size of the main flash, in bytes.
+    #[inline]
+    pub fn flash_size(&self) -> u32 {
+        self.flash.end - self.flash.start
+    }
+
+    /// The CCFG (Customer Configuration) address range, `None` if the family
+    /// doesn't have a CCFG.
+    ///
+    /// The CCFG is inside the main flash, except on [`Family::CC26X4`].
+    #[inline]
+    pub fn ccfg(&self) -> Option<Range<u32>> {
+        self.ccfg.clone()
+    }
+
+    /// The CCA (Customer Configuration Area) address range of the
+    /// [`Family::CC2538`], `None` on other families.
+    #[inline]
+    pub fn cca(&self) -> Option<Range<u32>> {
+        self.cca.clone()
+    }
+
+    /// Whether the `address` is on the main flash or on the CCFG.
+    pub fn contains(&self, address: u32) -> bool {
+        self.flash.contains(&address)
+            || self
+                .ccfg
+                .as_ref()
+                .map_or(false, |ccfg| ccfg.contains(&address))
+    }
+
+    /// Whether the whole `range` is on the main flash or on the CCFG.
+    pub fn contains_range(&self, range: &Range<u32>) -> bool {
+        let within = |area: &Range<u32>| {
+            range.start >= area.start && range.end <= area.end
+        };
+
+        range.start >= range.end
+            || within(&self.flash)
+            || self.ccfg.as_ref().map_or(false, within)
+    }
+
+    /// Whether any byte of the `range` is on the CCFG.
+    pub fn overlaps_ccfg(&self, range: &Range<u32>) -> bool {
+        self.ccfg.as_ref().map_or(false, |ccfg| {
+            range.start < ccfg.end && range.end > ccfg.start
+        })
+    }
+
+    /// Whether any byte of the `range` is on the CCA.
+    pub fn overlaps_cca(&self, range: &Range<u32>) -> bool {
+        self.cca
+            .as_ref()
+            .map_or(false, |cca| range.start < cca.end && range.end > cca.start)
+    }
+
+    /// Offset from the start of the `range` where the CCFG starts, `None` if
+    /// the `range` doesn't overlap the CCFG.
+    ///
+    /// The offset is zero if the `range` starts inside of the CCFG.
+    pub fn ccfg_offset(&self, range: &Range<u32>) -> Option<u32> {
+        if !self.overlaps_ccfg(range) {
+            return None;
+        }
+
+        let ccfg = self.ccfg.as_ref()?;
+        Some(ccfg.start.saturating_sub(range.start))
+    }
+
+    /// The pages of the main flash.
+    #[inline]
+    pub fn pages(&self) -> Pages {
+        Pages::flash(self.flash.start, self.sector_size, self.flash_size())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flash_layout() {
+        let layout = FlashLayout::new(Family::CC26X2, 0x58000);
+        assert_eq!(layout.ccfg(), Some(0x57FA8..0x58000));
+        assert!(layout.contains(0x57FFF));
+        assert!(!layout.contains(0x58000));
+        assert!(layout.contains_range(&(0x0..0x58000)));
+        assert!(!layout.contains_range(&(0x57000..0x58001)));
+        assert_eq!(layout.ccfg_offset(&(0x0..0x57FA8)), None);
+        assert_eq!(layout.ccfg_offset(&(0x50000..0x58000)), Some(0x7FA8));
+        assert_eq!(layout.ccfg_offset(&(0x57FB0..0x57FC0)), Some(0));
+
+        let layout = FlashLayout::new(Family::CC26X4, 0x100000);
+        assert!(layout.contains_range(&(0x4E02_0000..0x4E02_0800)));
+        assert!(!layout.overlaps_ccfg(&(0x0..0x100000)));
+
+        let layout = FlashLayout::new(Family::CC2538, 0x80000);
+        assert_eq!(layout.ccfg(), None);
+        assert_eq!(layout.cca(), Some(0x0027FFD4..0x00280000));
+        assert!(layout.overlaps_cca(&(0x0027F800..0x00280000)));
+        assert!(!layout.overlaps_cca(&(0x00200000..0x0027FFD4)));
+        assert_eq!(layout.pages().len(), 256);
+        assert_eq!(layout.with_sector_size(4096).pages().len(), 128);
+    }
+}
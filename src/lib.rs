@@ -25,6 +25,17 @@
 //! - [CC1312R](https://www.ti.com/product/CC1312R)
 //! - [CC1352P](https://www.ti.com/product/CC1352P)
 //!
+//! # Features
+//!
+//! - `tracing`: emit a [`tracing`](https://docs.rs/tracing) span for each
+//!   bootloader command, with the command, payload length, duration and
+//!   result, in addition to the `log` records.
+//!
+//! # Other bootloaders
+//!
+//! - [`cc32xx`]: the UART bootloader of the CC3200/CC3220 Wi-Fi devices.
+//! - [`msp430bsl`]: the UART BSL of the MSP430 and MSP432 devices.
+//!
 //! # See also
 //!
 //! - [CC2538/CC26x0/CC26x2 Serial Bootloader Interface](https://www.ti.com/lit/an/swra466c/swra466c.pdf).
@@ -33,26 +44,74 @@
 #![doc(html_favicon_url = "https://locha.io/i/128.png")]
 
 use std::{
-    cmp::Ordering,
-    fmt, io,
+    cell::Cell,
+    convert::TryFrom,
+    fmt, io, thread,
     time::{Duration, Instant},
 };
 
 use serial::SerialPort;
 
+use self::trace::{Direction, TraceSink};
+
+pub mod cc32xx;
+pub mod cca;
+pub mod ccfg;
+pub mod chipdb;
 #[rustfmt::skip]
 pub mod constants;
+pub mod firmware;
+pub mod flasher;
+pub mod mcuboot;
+pub mod msp430bsl;
+pub mod oad;
+pub mod plan;
 pub mod ports;
+pub mod protocol;
+pub mod trace;
 pub mod util;
 
+mod builder;
+mod command;
+mod crc;
+mod diagnostics;
+mod error;
 mod family;
-pub use self::family::Family;
+mod layout;
+mod session;
+mod status;
+mod timeouts;
+mod verify;
+pub use self::builder::DeviceBuilder;
+pub use self::ccfg::CcfgField;
+pub use self::command::{CommandId, UnknownCommandId};
+pub use self::crc::crc32;
+pub use self::diagnostics::Diagnostics;
+pub use self::error::{Error, Result};
+pub use self::family::{
+    CcfgGeometry, Family, FamilyDescriptor, FlashSizeSource, MemoryKind,
+    MemoryRegion, Page, Pages, FAMILIES,
+};
+pub use self::layout::FlashLayout;
+pub use self::session::DownloadSession;
+pub use self::status::{StatusCode, UnknownStatusCode};
+pub use self::timeouts::{AckTimeouts, SyncRetry};
+pub use self::verify::{Mismatch, VerificationError};
 
 /// A TI connected device supporting the Serial Bootloader Interface
 /// (SBL).
 pub struct Device<P> {
     family: Family,
     port: P,
+    ack_timeouts: AckTimeouts,
+    sync_retry: SyncRetry,
+    response_attempts: usize,
+    auto_baud: bool,
+    strict: bool,
+    deadline: Option<Instant>,
+    trace_sink: Option<TraceSink>,
+    diagnostics: Diagnostics,
+    sector_size: Option<u32>,
 }
 
 impl<P> Device<P>
@@ -61,7 +120,8 @@ where
 {
     /// Create a new `Device` from an already opened port.
     ///
-    /// This will synchronize the baudrate with the device.
+    /// This will synchronize the baudrate with the device. Use
+    /// [`DeviceBuilder`] to configure the device before synchronizing.
     ///
     /// # Note
     ///
@@ -69,12 +129,34 @@ where
     /// to enter bootloader please reset the device into this mode or use
     /// [`invoke_bootloader`] function to enter the bootloader on the device
     /// (on supported boards).
-    pub fn new(port: P, family: Family) -> io::Result<Self> {
-        let mut device = Device { port, family };
+    pub fn new(port: P, family: Family) -> Result<Self> {
+        DeviceBuilder::new(family).build(port)
+    }
 
-        device.init_communications()?;
+    /// Create a new `Device` from an already opened port, detecting its
+    /// family.
+    ///
+    /// See [`Device::new`] and [`util::detect_family`].
+    pub fn detect(port: P) -> Result<Self> {
+        // The family is only used to synchronize, the same is done with all
+        // the families.
+        DeviceBuilder::new(Family::CC26X2)
+            .detect_family(true)
+            .build(port)
+    }
 
-        Ok(device)
+    /// Create a new `Device` from an already opened port, retrying the
+    /// synchronization as specified by `sync_retry`.
+    ///
+    /// See [`Device::new`].
+    pub fn new_with_sync_retry(
+        port: P,
+        family: Family,
+        sync_retry: SyncRetry,
+    ) -> Result<Self> {
+        DeviceBuilder::new(family)
+            .sync_retry(sync_retry)
+            .build(port)
     }
 
     /// Returns the `Family` of the device.
@@ -82,155 +164,498 @@ where
         self.family
     }
 
-    fn write_cmd<D>(&mut self, cmd: u8, data: &D) -> io::Result<()>
+    /// Sector erase size, in bytes.
+    ///
+    /// This is [`Family::sector_size`] unless it was overridden with
+    /// [`Device::set_sector_size`].
+    pub fn sector_size(&self) -> u32 {
+        self.sector_size
+            .unwrap_or_else(|| self.family.sector_size())
+    }
+
+    /// Override the sector size of the family, for parts whose geometry
+    /// differs from the family. `None` restores [`Family::sector_size`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidSectorSize`] if the `sector_size` is not a
+    /// power of two, the sector size is left unchanged.
+    pub fn set_sector_size(&mut self, sector_size: Option<u32>) -> Result<()> {
+        if let Some(sector_size) = sector_size {
+            if !sector_size.is_power_of_two() {
+                return Err(Error::InvalidSectorSize(sector_size));
+            }
+        }
+
+        self.sector_size = sector_size;
+
+        Ok(())
+    }
+
+    /// The flash pages containing any of the `len` bytes starting at
+    /// `address`, using the sector size of the device.
+    ///
+    /// See [`Family::pages_in_range`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressOutOfRange`] if the range starts below the
+    /// flash or overflows the address space.
+    pub fn pages_in_range(&self, address: u32, len: u32) -> Result<Pages> {
+        Pages::new(self.family.flash_base(), self.sector_size(), address, len)
+    }
+
+    /// Returns a reference to the underlying port.
+    pub fn port(&self) -> &P {
+        &self.port
+    }
+
+    /// Consume the `Device` and return the underlying port.
+    pub fn into_port(self) -> P {
+        self.port
+    }
+
+    /// Returns the counters of the anomalies found while talking with the
+    /// bootloader.
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.diagnostics
+    }
+
+    /// Reset the counters returned by [`Device::diagnostics`].
+    pub fn reset_diagnostics(&mut self) {
+        self.diagnostics = Diagnostics::default();
+    }
+
+    /// Returns the ACK timeouts used for each class of command.
+    pub fn ack_timeouts(&self) -> AckTimeouts {
+        self.ack_timeouts
+    }
+
+    /// Set the ACK timeouts used for each class of command.
+    ///
+    /// Longer timeouts may be needed for erase commands at low baudrates.
+    pub fn set_ack_timeouts(&mut self, ack_timeouts: AckTimeouts) {
+        self.ack_timeouts = ack_timeouts;
+    }
+
+    /// Returns the synchronization attempts made with the bootloader.
+    pub fn sync_retry(&self) -> SyncRetry {
+        self.sync_retry
+    }
+
+    /// Set the synchronization attempts made with the bootloader, used by
+    /// [`Device::resynchronize`].
+    pub fn set_sync_retry(&mut self, sync_retry: SyncRetry) {
+        self.sync_retry = sync_retry;
+    }
+
+    /// Record every byte sent to and received from the device into `sink`.
+    ///
+    /// See the [`trace`] module for the format of the capture. Replaces the
+    /// previous sink, if any.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    ///
+    /// use ti_sbl::{Device, Family};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let port = serial::open("/dev/ttyUSB0")?;
+    /// let mut device = Device::new(port, Family::CC26X2)?;
+    /// device.set_trace_sink(File::create("session.trace")?);
+    /// device.ping()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_trace_sink<W>(&mut self, sink: W)
+    where
+        W: io::Write + Send + 'static,
+    {
+        self.trace_sink = Some(TraceSink::new(Box::new(sink)));
+    }
+
+    /// Stop recording the traffic, returns the flushed sink.
+    pub fn clear_trace_sink(&mut self) -> Option<Box<dyn io::Write + Send>> {
+        self.trace_sink.take().map(TraceSink::into_inner)
+    }
+
+    /// Run `f` with a deadline for the whole operation.
+    ///
+    /// Once `duration` has elapsed no more commands are sent to the device
+    /// and the operation in progress fails with [`Error::DeadlineExceeded`], so a
+    /// sequence of commands can't hang indefinitely if the board stops
+    /// responding.
+    ///
+    /// Nested deadlines are allowed, the earliest deadline is used.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn example<P: serial::SerialPort>(device: &mut ti_sbl::Device<P>) -> ti_sbl::Result<()> {
+    /// use std::time::Duration;
+    ///
+    /// let chip_id = device.with_deadline(Duration::from_secs(5), |device| {
+    ///     device.ping()?;
+    ///     device.get_chip_id()
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_deadline<T, F>(&mut self, duration: Duration, f: F) -> Result<T>
     where
-        D: AsRef<[u8]>,
+        F: FnOnce(&mut Self) -> Result<T>,
     {
-        // [len | checksum | cmd]
-        const HDR_LEN: usize = 3;
+        let previous = self.deadline;
+        let deadline = Instant::now() + duration;
+        self.deadline = Some(match previous {
+            Some(previous) => previous.min(deadline),
+            None => deadline,
+        });
+
+        let ret = f(self);
+        self.deadline = previous;
 
-        let data = data.as_ref();
+        ret
+    }
 
-        let pkt_len = HDR_LEN + data.len();
-        if pkt_len > usize::from(std::u8::MAX) {
-            // Logic error, just panic.
-            panic!("packet too big");
+    /// Returns [`Error::DeadlineExceeded`] if the deadline has passed.
+    fn check_deadline(&self) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                log::debug!("Operation deadline exceeded");
+                Err(Error::DeadlineExceeded)
+            }
+            _ => Ok(()),
         }
+    }
 
-        let mut pkt = Vec::with_capacity(pkt_len);
+    /// Returns an error if the command is not supported by the family.
+    fn check_supported(&self, cmd: CommandId) -> Result<()> {
+        if cmd.is_supported_by(self.family) {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedCommand(cmd))
+        }
+    }
 
-        pkt.push(pkt_len as u8);
-        pkt.push(command_checksum(cmd, data));
-        pkt.push(cmd);
-        pkt.extend_from_slice(data);
+    /// Check that the `len` bytes at `address` are inside of a region of the
+    /// memory map of the family of one of the `kinds`.
+    fn check_address_range(
+        &self,
+        address: u32,
+        len: u32,
+        kinds: &[MemoryKind],
+    ) -> Result<()> {
+        match self.family.memory_region(address, len) {
+            Some(region) if kinds.contains(&region.kind) => Ok(()),
+            _ => Err(Error::AddressOutOfRange { address, len }),
+        }
+    }
+
+    /// Memory that can be programmed with [`Device::download_session`], the
+    /// SRAM only on families supporting [`Device::run`].
+    fn download_kinds(&self) -> &'static [MemoryKind] {
+        if self.family.supports_run() {
+            EXECUTABLE_MEMORY
+        } else {
+            FLASH_MEMORY
+        }
+    }
+
+    fn write_cmd<D>(&mut self, cmd: CommandId, data: &D) -> Result<()>
+    where
+        D: AsRef<[u8]>,
+    {
+        log::trace!("sending {}", cmd);
+
+        self.write_raw_cmd(cmd.value(), data)
+    }
+
+    fn write_raw_cmd<D>(&mut self, cmd: u8, data: &D) -> Result<()>
+    where
+        D: AsRef<[u8]>,
+    {
+        self.check_deadline()?;
+
+        let pkt = protocol::command_packet(cmd, data.as_ref())?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("payload_len", data.as_ref().len());
 
         log::trace!("sending cmd {:#X}, pkt = {:?}", cmd, pkt);
 
-        self.port.write_all(pkt.as_slice())?;
-        self.port.flush()?;
+        self.port_write(&pkt)?;
 
         Ok(())
     }
 
-    fn read_ack(&mut self) -> io::Result<bool> {
+    /// Run the command `f`.
+    ///
+    /// With the `tracing` feature a span is emitted for the command, see
+    /// [`Device::instrument_raw`].
+    fn instrument<T, F>(&mut self, cmd: CommandId, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        self.instrument_raw(cmd.as_str(), cmd.value(), f)
+    }
+
+    /// Run the command `f`, emitting a `command` span with its name, id,
+    /// payload length, duration and result.
+    #[cfg(feature = "tracing")]
+    fn instrument_raw<T, F>(
+        &mut self,
+        name: &'static str,
+        id: u8,
+        f: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        use tracing::field;
+
+        let span = tracing::debug_span!(
+            "command",
+            command = name,
+            id = id,
+            payload_len = field::Empty,
+            duration_us = field::Empty,
+            result = field::Empty,
+        );
+        let _enter = span.enter();
+
+        let start = Instant::now();
+        let ret = f(self);
+        span.record("duration_us", start.elapsed().as_micros() as u64);
+        match ret {
+            Ok(_) => span.record("result", "ok"),
+            Err(ref e) => span.record("result", field::display(e)),
+        };
+
+        ret
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    fn instrument_raw<T, F>(
+        &mut self,
+        _name: &'static str,
+        _id: u8,
+        f: F,
+    ) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        f(self)
+    }
+
+    /// Write all of `data` to the port and flush it.
+    fn port_write(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some(ref mut sink) = self.trace_sink {
+            sink.record(Direction::Tx, data);
+        }
+
+        self.port.write_all(data)?;
+        self.port.flush()
+    }
+
+    fn port_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.port.read(buf)?;
+        if let Some(ref mut sink) = self.trace_sink {
+            sink.record(Direction::Rx, &buf[..n]);
+        }
+
+        Ok(n)
+    }
+
+    fn port_read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let ret = self.port.read_exact(buf);
+        if let (Some(sink), Ok(())) = (self.trace_sink.as_mut(), &ret) {
+            sink.record(Direction::Rx, buf);
+        }
+
+        ret
+    }
+
+    fn read_ack(&mut self, cmd: CommandId) -> Result<AckResponse> {
+        self.read_ack_timeout(self.ack_timeouts.for_command(cmd))
+    }
+
+    /// Read the ACK of `cmd`, anything other than an ACK is an error.
+    fn expect_ack(&mut self, cmd: CommandId) -> Result<()> {
+        match self.read_ack(cmd)? {
+            AckResponse::Ack => Ok(()),
+            AckResponse::Nack => Err(Error::Nack(cmd)),
+            AckResponse::Timeout { .. } => Err(Error::Timeout),
+        }
+    }
+
+    fn read_ack_timeout(&mut self, timeout: Duration) -> Result<AckResponse> {
         log::trace!("waiting for ACK");
 
         let start_time = Instant::now();
-        let timeout = Duration::from_secs(1);
-        let mut ack = vec![0xFF, 0xFF];
-        loop {
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                timeout.min(deadline.saturating_duration_since(start_time))
+            }
+            None => timeout,
+        };
+        let mut scanner = protocol::AckScanner::new();
+        let response = loop {
             let mut byte = [0u8; 1];
-            match self.port.read(&mut byte) {
-                Ok(n) if n == 0 => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "unexpected EOF",
-                    ));
+            match self.port_read(&mut byte) {
+                Ok(0) => {
+                    return Err(
+                        io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                    );
                 }
                 Ok(_) => {
-                    ack.push(byte[0]);
+                    if let Some(response) = scanner.push(byte[0]) {
+                        break response;
+                    }
                 }
                 Err(e) if e.kind() == io::ErrorKind::TimedOut => {
                     log::trace!("read timed out");
                 }
-                Err(e) => return Err(e),
+                Err(e) => return Err(e.into()),
             }
 
-            if ack[ack.len() - 2] == 0x00
-                && (ack[ack.len() - 1] == constants::ACK
-                    || ack[ack.len() - 1] == constants::NACK)
-            {
-                log::trace!("ACK bytes found {:?}", &ack[2..]);
-                break;
-            } else if Instant::now().duration_since(start_time) >= timeout {
+            if Instant::now().duration_since(start_time) >= timeout {
+                // The ACK timeout was shortened to end with the deadline.
+                self.check_deadline()?;
+
                 log::trace!("ACK bytes not found, timed out");
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "ACK bytes not found, timed out",
-                ));
+                let garbage_bytes = scanner.garbage_bytes(false);
+                self.diagnostics.garbage_bytes += garbage_bytes;
+                self.diagnostics.ack_timeouts += 1;
+                return Ok(AckResponse::Timeout { garbage_bytes });
             }
+        };
+
+        let garbage_bytes = scanner.garbage_bytes(true);
+        log::trace!("found {:?} after {} bytes", response, garbage_bytes);
+        self.diagnostics.garbage_bytes += garbage_bytes;
+        if response == AckResponse::Nack {
+            self.diagnostics.nacks += 1;
+        }
+        if self.strict && garbage_bytes > 0 {
+            return Err(Error::InvalidResponse);
         }
 
-        log::trace!("found ACK bytes after {} bytes", ack.len() - 2);
+        Ok(response)
+    }
 
-        match (ack[ack.len() - 2], ack[ack.len() - 1]) {
-            (0x00, constants::ACK) => Ok(true),
-            (0x00, constants::NACK) => Ok(false),
-            _ => unreachable!(),
-        }
+    fn write_ack(&mut self, ack: bool) -> Result<()> {
+        self.port_write(&protocol::ack_packet(ack))?;
+
+        Ok(())
     }
 
-    fn write_ack(&mut self, ack: bool) -> io::Result<()> {
-        let data: [u8; 2] =
-            [0x00, if ack { constants::ACK } else { constants::NACK }];
+    /// Read a response packet into `response`.
+    ///
+    /// The length of the received payload must match exactly the length of
+    /// `response`, see [`Device::read_response_vec`] for responses of
+    /// variable length.
+    ///
+    /// The caller is responsible of acknowledging the packet on success.
+    fn read_response(&mut self, response: &mut [u8]) -> Result<()> {
+        let payload = self.read_response_vec()?;
+        if response.len() != payload.len() {
+            return Err(Error::ResponseLength {
+                expected: response.len(),
+                found: payload.len(),
+            });
+        }
 
-        self.port.write_all(&data)?;
-        self.port.flush()?;
+        response.copy_from_slice(&payload);
 
         Ok(())
     }
 
-    fn read_response(&mut self, response: &mut [u8]) -> io::Result<()> {
-        const HDR_LEN: usize = 2;
+    /// Read a response packet of any length, returns the received payload.
+    ///
+    /// If the checksum of the received packet doesn't match, a NACK is sent
+    /// to the device in order to receive the packet again, up to
+    /// [`DeviceBuilder::response_attempts`] times.
+    ///
+    /// The caller is responsible of acknowledging the packet on success.
+    fn read_response_vec(&mut self) -> Result<Vec<u8>> {
+        self.read_response_vec_with_len(None)
+    }
+
+    /// Same as [`Device::read_response_vec`], but the length byte of the
+    /// first packet may have been already received.
+    fn read_response_vec_with_len(
+        &mut self,
+        mut len: Option<u8>,
+    ) -> Result<Vec<u8>> {
+        for attempt in 1..=self.response_attempts {
+            let (hdr, payload) = self.read_response_packet(len.take())?;
+            if hdr.verify(&payload) {
+                return Ok(payload);
+            }
+
+            log::debug!(
+                "response checksum mismatch (attempt {} of {}), expected \
+                 {:#X}, found {:#X}",
+                attempt,
+                self.response_attempts,
+                hdr.checksum,
+                protocol::response_checksum(&payload),
+            );
+            self.diagnostics.checksum_errors += 1;
+            self.write_ack(false)?;
+        }
+
+        Err(Error::InvalidChecksum)
+    }
 
+    /// Read a single response packet, returns the received header and the
+    /// payload.
+    ///
+    /// If `len` is `Some` the length byte of the header was already received.
+    fn read_response_packet(
+        &mut self,
+        len: Option<u8>,
+    ) -> Result<(protocol::ResponseHeader, Vec<u8>)> {
         log::trace!("waiting for response header");
-        let mut hdr = [0u8; HDR_LEN];
-        self.port.read_exact(&mut hdr)?;
+        let mut hdr = [0u8; protocol::RESPONSE_HEADER_LEN];
+        match len {
+            Some(len) => {
+                hdr[0] = len;
+                self.port_read_exact(&mut hdr[1..])?;
+            }
+            None => self.port_read_exact(&mut hdr)?,
+        }
         log::trace!(
             "response header received, len = {}, cksum = {:#X}",
             hdr[0],
             hdr[1]
         );
 
-        let payload_len = hdr[0] as usize - HDR_LEN;
-        match response.len().cmp(&payload_len) {
-            Ordering::Greater => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "received response is too small, expected {}, found {}",
-                        response.len(),
-                        payload_len,
-                    ),
-                ))
-            }
-            Ordering::Less => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "received response is too big, expected {}, found {}",
-                        response.len(),
-                        payload_len,
-                    ),
-                ))
-            }
-            _ => (),
-        }
+        let hdr = protocol::ResponseHeader::parse(hdr)?;
 
+        let mut payload = vec![0u8; hdr.payload_len];
         log::trace!(
             "waiting for rest of response, expecting {} bytes",
-            response.len()
+            payload.len()
         );
-        self.port.read_exact(response)?;
+        self.port_read_exact(&mut payload)?;
 
-        Ok(())
+        Ok((hdr, payload))
     }
 
     /// # Errors
     ///
-    /// This function will return an error with the
-    /// [`std::io::ErrorKind::NotConnected`] if the auto baud procedure didn't
-    /// finish.
-    fn perform_auto_baud(&mut self) -> io::Result<()> {
+    /// This function will return [`Error::NotSynchronized`] if the auto baud
+    /// procedure didn't finish.
+    fn perform_auto_baud(&mut self) -> Result<()> {
         // To synchronize with the host (us) send two bytes containing 0x55. If
         // synchronization succeeds, the bootloader will return an acknowledge.
         let data = [0x55u8, 0x55u8];
-        self.port.write_all(&data)?;
-        if !self.read_ack()? {
-            return Err(io::Error::new(
-                io::ErrorKind::NotConnected,
-                "couldn't synchronize bootloader baudrate",
-            ));
+        self.port_write(&data)?;
+        if self.read_ack_timeout(self.ack_timeouts.misc)? != AckResponse::Ack {
+            return Err(Error::NotSynchronized);
         }
 
         log::debug!("Auto baud finished correctly");
@@ -238,10 +663,38 @@ where
         Ok(())
     }
 
-    fn init_communications(&mut self) -> io::Result<()> {
+    fn init_communications(&mut self) -> Result<()> {
+        let attempts = self.sync_retry.attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            match self.try_init_communications() {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < attempts => {
+                    log::debug!(
+                        "Synchronization attempt {} of {} failed: {}",
+                        attempt,
+                        attempts,
+                        e
+                    );
+                    attempt += 1;
+                    thread::sleep(self.sync_retry.delay);
+                    self.drain_input()?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn try_init_communications(&mut self) -> Result<()> {
         log::debug!("Sending dummy test command to check communication");
-        self.write_cmd(0, &[])?;
-        if self.read_ack().is_err() {
+        self.write_raw_cmd(0, &[])?;
+        if let Ok(AckResponse::Timeout { .. }) | Err(_) =
+            self.read_ack_timeout(self.ack_timeouts.misc)
+        {
+            if !self.auto_baud {
+                return Err(Error::NotSynchronized);
+            }
+
             log::debug!("No response received, performing auto baud procedure");
             // No successful response received, try auto baud.
             self.perform_auto_baud()?;
@@ -250,10 +703,144 @@ where
         Ok(())
     }
 
+    /// Warn if the baudrate of the port exceeds
+    /// [`Family::max_baudrate`].
+    pub(crate) fn check_baudrate(&mut self) {
+        let baudrate = Cell::new(None);
+        let result = self.port.reconfigure(&|settings| {
+            baudrate.set(settings.baud_rate());
+            Ok(())
+        });
+
+        let max_baudrate = self.family.max_baudrate();
+        if let (Ok(()), Some(baudrate)) = (result, baudrate.get()) {
+            if baudrate.speed() > max_baudrate {
+                log::warn!(
+                    "Baudrate {} exceeds the maximum of {} supported by {:?} devices",
+                    baudrate.speed(),
+                    max_baudrate,
+                    self.family
+                );
+            }
+        }
+    }
+
+    /// Discard any pending bytes received from the device.
+    ///
+    /// Reads until the port times out.
+    fn drain_input(&mut self) -> Result<()> {
+        let mut buf = [0u8; 64];
+        let mut discarded = 0;
+        loop {
+            match self.port_read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => discarded += n,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        log::trace!("discarded {} bytes", discarded);
+
+        Ok(())
+    }
+
+    /// Invoke the bootloader using the DTR and RTS lines of the port.
+    ///
+    /// See [`invoke_bootloader`] for the meaning of the parameters. After
+    /// invoking the bootloader [`Device::resynchronize`] must be used.
+    pub fn invoke_bootloader(
+        &mut self,
+        inverted: bool,
+        bootloader_active_high: bool,
+    ) -> Result<()> {
+        invoke_bootloader(&mut self.port, inverted, bootloader_active_high)
+            .map_err(io::Error::from)?;
+
+        Ok(())
+    }
+
+    /// Synchronize again with the bootloader.
+    ///
+    /// Useful to recover the communication after a failed command or a
+    /// glitch on the serial line, this flushes the pending output, discards
+    /// any received bytes and performs the same synchronization done by
+    /// [`Device::new`].
+    pub fn resynchronize(&mut self) -> Result<()> {
+        log::debug!("Resynchronizing with the bootloader");
+        self.diagnostics.resyncs += 1;
+        self.port.flush()?;
+        self.drain_input()?;
+        self.init_communications()
+    }
+
     /// Ping the bootloader.
-    pub fn ping(&mut self) -> io::Result<bool> {
-        self.write_cmd(constants::CMD_PING, &[])?;
-        self.read_ack()
+    pub fn ping(&mut self) -> Result<bool> {
+        self.instrument(CommandId::Ping, |device| {
+            device.write_cmd(CommandId::Ping, &[])?;
+            match device.read_ack(CommandId::Ping)? {
+                AckResponse::Ack => Ok(true),
+                AckResponse::Nack => Ok(false),
+                AckResponse::Timeout { .. } => Err(Error::Timeout),
+            }
+        })
+    }
+
+    /// Send a raw command to the bootloader.
+    ///
+    /// This handles the framing of the packet, the checksum, the ACK and
+    /// the response (if any) of the command, it's intended to be used with
+    /// commands not covered by this library.
+    ///
+    /// # Parameters
+    ///
+    /// - `cmd`: the command byte.
+    /// - `payload`: the data sent after the command byte. Can't be higher
+    ///   than `252` bytes.
+    ///
+    /// # Return value
+    ///
+    /// The payload of the response packet, or `None` if the device didn't
+    /// send a response after acknowledging the command before the port
+    /// timeout expired.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::DataTooLarge`] if the `payload` length is higher than
+    /// `252` bytes.
+    pub fn send_raw_command(
+        &mut self,
+        cmd: u8,
+        payload: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        self.instrument_raw("CMD_RAW", cmd, |device| {
+            device.write_raw_cmd(cmd, &payload)?;
+            match device.read_ack_timeout(device.ack_timeouts.misc)? {
+                AckResponse::Ack => (),
+                AckResponse::Nack => return Err(Error::RawNack(cmd)),
+                AckResponse::Timeout { .. } => return Err(Error::Timeout),
+            }
+
+            let mut len = [0u8; 1];
+            match device.port_read(&mut len) {
+                Ok(0) => {
+                    return Err(
+                        io::Error::from(io::ErrorKind::UnexpectedEof).into()
+                    );
+                }
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    log::trace!("no response received for command {:#X}", cmd);
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            let response = device.read_response_vec_with_len(Some(len[0]))?;
+            device.write_ack(true)?;
+
+            Ok(Some(response))
+        })
     }
 
     /// Prepares flash programming.
@@ -262,45 +849,139 @@ where
     ///
     /// This command must be followed by a [`Device::get_status`] command
     /// to verify it worked.
-    pub fn download(
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressOutOfRange`] if the range isn't on the flash
+    /// of the family, see [`Family::memory_map`]. Memory accesses and erase
+    /// commands are checked the same way.
+    pub(crate) fn download(
         &mut self,
         program_address: u32,
         program_size: u32,
-    ) -> io::Result<()> {
-        const CMD_DOWNLOAD_LEN: usize = 8;
-
-        let mut data = [0u8; CMD_DOWNLOAD_LEN];
-        (&mut data[..4]).copy_from_slice(&program_address.to_be_bytes());
-        (&mut data[4..]).copy_from_slice(&program_size.to_be_bytes());
-
-        self.write_cmd(constants::CMD_DOWNLOAD, &data)?;
-        let ack = self.read_ack()?;
-        if !ack {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "COMMAND_DOWNLOAD not acknowledged",
-            ));
-        }
+    ) -> Result<()> {
+        self.instrument(CommandId::Download, |device| {
+            const CMD_DOWNLOAD_LEN: usize = 8;
 
-        Ok(())
+            device.check_address_range(
+                program_address,
+                program_size,
+                device.download_kinds(),
+            )?;
+
+            let mut data = [0u8; CMD_DOWNLOAD_LEN];
+            data[..4].copy_from_slice(&program_address.to_be_bytes());
+            data[4..].copy_from_slice(&program_size.to_be_bytes());
+
+            device.write_cmd(CommandId::Download, &data)?;
+            device.expect_ack(CommandId::Download)?;
+
+            Ok(())
+        })
     }
 
-    /// Get the status of the last issued command.
-    pub fn get_status(&mut self) -> io::Result<u8> {
-        self.write_cmd(constants::CMD_GET_STATUS, &[])?;
-        let ack = self.read_ack()?;
-        if !ack {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "COMMAND_GET_STATUS not acknowledged",
-            ));
-        }
+    /// Start a flash download of `program_size` bytes at
+    /// `program_address`.
+    ///
+    /// The status of the command is verified and the data can only be sent
+    /// through the returned [`DownloadSession`].
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::AddressOutOfRange`] if the range isn't on the flash
+    ///   of the family, see [`Family::memory_map`].
+    /// - Returns [`Error::Status`] if the bootloader rejected the download.
+    pub fn download_session(
+        &mut self,
+        program_address: u32,
+        program_size: u32,
+    ) -> Result<DownloadSession<'_, P>> {
+        DownloadSession::start(self, program_address, program_size)
+    }
 
-        let mut response = [0u8; 1];
-        self.read_response(&mut response)?;
-        self.write_ack(true)?;
+    /// Start a flash download of `program_size` bytes at `program_address`
+    /// whose data is verified against `crc32` by the bootloader, the status
+    /// checked by [`DownloadSession::finish`] reports whether it matched.
+    /// Only supported on [`Family::CC26X2`], [`Family::CC26X2X7`] and
+    /// [`Family::CC26X4`].
+    ///
+    /// See [`Device::download_session`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    /// [`CommandId::DownloadCrc`].
+    pub fn download_crc32_session(
+        &mut self,
+        program_address: u32,
+        program_size: u32,
+        crc32: u32,
+    ) -> Result<DownloadSession<'_, P>> {
+        DownloadSession::start_crc32(self, program_address, program_size, crc32)
+    }
 
-        Ok(response[0])
+    /// Prepares flash programming, the data sent after this command is
+    /// verified against `crc32` by the bootloader. Only supported on
+    /// [`Family::CC26X2`], [`Family::CC26X2X7`] and [`Family::CC26X4`].
+    ///
+    /// - See [`Family::supports_download_crc`].
+    ///
+    /// # Notes
+    ///
+    /// This command must be followed by a [`Device::get_status`] command
+    /// to verify it worked. After the last [`Device::send_data`] command
+    /// the status reports whether the CRC32 of the received data matched.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    /// this command.
+    pub(crate) fn download_crc32(
+        &mut self,
+        program_address: u32,
+        program_size: u32,
+        crc32: u32,
+    ) -> Result<()> {
+        self.instrument(CommandId::DownloadCrc, |device| {
+            const CMD_DOWNLOAD_CRC_LEN: usize = 12;
+
+            device.check_supported(CommandId::DownloadCrc)?;
+            device.check_address_range(
+                program_address,
+                program_size,
+                FLASH_MEMORY,
+            )?;
+
+            let mut data = [0u8; CMD_DOWNLOAD_CRC_LEN];
+            data[..4].copy_from_slice(&program_address.to_be_bytes());
+            data[4..8].copy_from_slice(&program_size.to_be_bytes());
+            data[8..].copy_from_slice(&crc32.to_be_bytes());
+
+            device.write_cmd(CommandId::DownloadCrc, &data)?;
+            device.expect_ack(CommandId::DownloadCrc)?;
+
+            Ok(())
+        })
+    }
+
+    /// Get the status of the last issued command.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnknownStatus`] if the device returned an unknown
+    /// status code.
+    pub fn get_status(&mut self) -> Result<StatusCode> {
+        self.instrument(CommandId::GetStatus, |device| {
+            device.write_cmd(CommandId::GetStatus, &[])?;
+            device.expect_ack(CommandId::GetStatus)?;
+
+            let mut response = [0u8; 1];
+            device.read_response(&mut response)?;
+            device.write_ack(true)?;
+
+            StatusCode::try_from(response[0])
+                .map_err(|e| Error::UnknownStatus(e.0))
+        })
     }
 
     /// Send data to be written into the flash memory.
@@ -308,79 +989,179 @@ where
     /// The return value represents if the command was
     /// acknowledged or not, if not acknowledged the write address
     /// is not incrmeneted by the device which allows for retransmissions
-    /// of the previous data.
+    /// of the previous data. A [`AckResponse::Timeout`] usually means the
+    /// communication was lost and [`Device::resynchronize`] is needed.
     ///
     /// # Note
     ///
     /// This only does work after a [`Device::download`] command
-    /// has been issued.
+    /// has been issued, [`Device::download_session`] is the public
+    /// interface.
     ///
     /// After issuing this command, [`Device::get_status`] command
     /// _should_ be used to check for errors.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// This function will panic if the `data` length in bytes is
+    /// Returns [`Error::DataTooLarge`] if the `data` length in bytes is
     /// higher than [`constants::MAX_BYTES_PER_TRANSFER`].
-    pub fn send_data<D>(&mut self, data: &D) -> io::Result<bool>
+    pub(crate) fn send_data<D>(&mut self, data: &D) -> Result<AckResponse>
     where
         D: AsRef<[u8]>,
     {
-        assert!(data.as_ref().len() <= constants::MAX_BYTES_PER_TRANSFER);
+        self.instrument(CommandId::SendData, |device| {
+            if data.as_ref().len() > constants::MAX_BYTES_PER_TRANSFER {
+                return Err(Error::DataTooLarge {
+                    max: constants::MAX_BYTES_PER_TRANSFER,
+                    found: data.as_ref().len(),
+                });
+            }
 
-        self.write_cmd(constants::CMD_SEND_DATA, data)?;
-        self.read_ack()
+            device.write_cmd(CommandId::SendData, data)?;
+            device.read_ack(CommandId::SendData)
+        })
     }
 
     /// Read chip ID.
-    pub fn get_chip_id(&mut self) -> io::Result<u32> {
-        const CHIP_ID_RESPONSE_LEN: usize = 4;
-
-        self.write_cmd(constants::CMD_GET_CHIP_ID, &[])?;
-        let ack = self.read_ack()?;
-        if !ack {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "bootloader didn't acknowledge the command",
-            ));
-        }
+    pub fn get_chip_id(&mut self) -> Result<u32> {
+        self.instrument(CommandId::GetChipId, |device| {
+            const CHIP_ID_RESPONSE_LEN: usize = 4;
+
+            device.check_supported(CommandId::GetChipId)?;
+
+            device.write_cmd(CommandId::GetChipId, &[])?;
+            device.expect_ack(CommandId::GetChipId)?;
+
+            let mut response = [0u8; CHIP_ID_RESPONSE_LEN];
+            device.read_response(&mut response)?;
+            device.write_ack(true)?;
+
+            Ok(u32::from_be_bytes(response))
+        })
+    }
+
+    /// Reset the device.
+    ///
+    /// # Note
+    ///
+    /// The device leaves the bootloader mode after acknowledging this
+    /// command and starts executing the flashed firmware (if the flash image
+    /// is valid), so no further commands can be sent afterwards. To talk
+    /// again with the bootloader a new [`Device`] has to be created once the
+    /// device is back in bootloader mode.
+    pub fn reset(&mut self) -> Result<()> {
+        self.instrument(CommandId::Reset, |device| {
+            device.write_cmd(CommandId::Reset, &[])?;
+            device.expect_ack(CommandId::Reset)?;
+
+            Ok(())
+        })
+    }
 
-        let mut response = [0u8; CHIP_ID_RESPONSE_LEN];
-        self.read_response(&mut response)?;
-        self.write_ack(true)?;
+    /// Start executing the code at `address`. Only supported on
+    /// [`Family::CC2538`].
+    ///
+    /// - See [`Family::supports_run`].
+    ///
+    /// Used to run programs loaded into the SRAM with
+    /// [`Device::download_session`], see [`util::run_from_ram`]. The bootloader
+    /// stops after acknowledging this command, no further commands can be
+    /// sent.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    ///   this command.
+    /// - Returns [`Error::AddressOutOfRange`] if the `address` isn't on the
+    ///   flash or the SRAM.
+    pub fn run(&mut self, address: u32) -> Result<()> {
+        self.instrument(CommandId::Run, |device| {
+            device.check_supported(CommandId::Run)?;
+            device.check_address_range(address, 4, EXECUTABLE_MEMORY)?;
+
+            device.write_cmd(CommandId::Run, &address.to_be_bytes())?;
+            device.expect_ack(CommandId::Run)?;
+
+            Ok(())
+        })
+    }
 
-        Ok(u32::from_be_bytes(response))
+    /// Calculate the CRC32 of a memory region.
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: the start address of the memory region.
+    /// - `size`: the number of bytes used to calculate the CRC32.
+    /// - `read_repeat`: the number of times the data is read from memory to
+    ///   perform the calculation. Ignored on [`Family::CC2538`].
+    pub fn crc32(
+        &mut self,
+        address: u32,
+        size: u32,
+        read_repeat: u32,
+    ) -> Result<u32> {
+        self.instrument(CommandId::Crc32, |device| {
+            const CC2538_CMD_CRC32_LEN: usize = 8;
+            const CC26XX_CMD_CRC32_LEN: usize = 12;
+            const CRC32_RESPONSE_LEN: usize = 4;
+
+            device.check_supported(CommandId::Crc32)?;
+            device.check_address_range(address, size, ANY_MEMORY)?;
+
+            let mut data = [0u8; CC26XX_CMD_CRC32_LEN];
+            data[..4].copy_from_slice(&address.to_be_bytes());
+            data[4..8].copy_from_slice(&size.to_be_bytes());
+            data[8..].copy_from_slice(&read_repeat.to_be_bytes());
+
+            let data = match device.family {
+                Family::CC2538 | Family::TM4C => &data[..CC2538_CMD_CRC32_LEN],
+                Family::CC26X0
+                | Family::CC26X0R2
+                | Family::CC26X2
+                | Family::CC26X2X7
+                | Family::CC26X4 => &data[..],
+            };
+
+            device.write_cmd(CommandId::Crc32, &data)?;
+            device.expect_ack(CommandId::Crc32)?;
+
+            let mut response = [0u8; CRC32_RESPONSE_LEN];
+            device.read_response(&mut response)?;
+            device.write_ack(true)?;
+
+            Ok(u32::from_be_bytes(response))
+        })
     }
 
     /// Erase. Only supported on [`Family::CC2538`].
     ///
     /// - See [`Family::supports_erase`].
-    pub fn erase(&mut self, address: u32, byte_count: u32) -> io::Result<()> {
-        const CMD_ERASE_LEN: usize = 8;
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    /// this command.
+    pub fn erase(&mut self, address: u32, byte_count: u32) -> Result<()> {
+        self.instrument(CommandId::Erase, |device| {
+            const CMD_ERASE_LEN: usize = 8;
 
-        if !self.family.supports_erase() {
-            panic!("`COMMAND_ERASE` is not supported");
-        }
+            device.check_supported(CommandId::Erase)?;
+            device.check_address_range(address, byte_count, FLASH_MEMORY)?;
 
-        let mut data = [0u8; CMD_ERASE_LEN];
-        (&mut data[..4]).copy_from_slice(&address.to_be_bytes());
-        (&mut data[4..]).copy_from_slice(&byte_count.to_be_bytes());
-
-        self.write_cmd(constants::CC2538_CMD_ERASE, &data)?;
-        let ack = self.read_ack()?;
-        if !ack {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "failed to erase",
-            ));
-        }
+            let mut data = [0u8; CMD_ERASE_LEN];
+            data[..4].copy_from_slice(&address.to_be_bytes());
+            data[4..].copy_from_slice(&byte_count.to_be_bytes());
 
-        Ok(())
+            device.write_cmd(CommandId::Erase, &data)?;
+            device.expect_ack(CommandId::Erase)?;
+
+            Ok(())
+        })
     }
 
-    /// Sector erase. Only supported on [`Family::CC26X0`] and [`Family::CC26X2`].
+    /// Sector erase. Not supported on [`Family::CC2538`].
     ///
-    /// The size of each sector is specified at [`Family::sector_size`].
+    /// The size of each sector is specified at [`Device::sector_size`].
     ///
     /// - See [`Family::supports_sector_erase`].
     ///
@@ -388,62 +1169,118 @@ where
     ///
     /// - `address`: The start address of the sector.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// - This function panics if the family doesn't support this command.
-    /// - This function panics if the `address` isn't the start of a sector.
+    /// - Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    ///   this command.
+    /// - Returns [`Error::UnalignedAddress`] if the `address` isn't the start
+    ///   of a sector.
     ///
     /// See [`util::erase_flash_range`] for an easier to use wrapper of this
     /// function.
-    pub fn sector_erase(&mut self, address: u32) -> io::Result<()> {
-        const CMD_SECTOR_ERASE_LEN: usize = 4;
+    pub fn sector_erase(&mut self, address: u32) -> Result<()> {
+        self.instrument(CommandId::SectorErase, |device| {
+            const CMD_SECTOR_ERASE_LEN: usize = 4;
 
-        if !self.family.supports_sector_erase() {
-            panic!("`COMMAND_SECTOR_ERASE` is not supported");
-        }
+            device.check_supported(CommandId::SectorErase)?;
 
-        assert!(
-            address % self.family.sector_size() == 0,
-            "invalid sector address"
-        );
+            if address % device.sector_size() != 0 {
+                return Err(Error::UnalignedAddress(address));
+            }
+            device.check_address_range(
+                address,
+                device.sector_size(),
+                FLASH_MEMORY,
+            )?;
 
-        let mut data = [0u8; CMD_SECTOR_ERASE_LEN];
-        data.copy_from_slice(&address.to_be_bytes());
+            let mut data = [0u8; CMD_SECTOR_ERASE_LEN];
+            data.copy_from_slice(&address.to_be_bytes());
 
-        self.write_cmd(constants::CC26X0_CMD_SECTOR_ERASE, &data)?;
-        let ack = self.read_ack()?;
-        if !ack {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "failed to erase sector",
-            ));
-        }
+            device.write_cmd(CommandId::SectorErase, &data)?;
+            device.expect_ack(CommandId::SectorErase)?;
 
-        Ok(())
+            Ok(())
+        })
+    }
+
+    /// Bank erase. Not supported on [`Family::CC2538`].
+    ///
+    /// Erases the whole flash, including the CCFG.
+    ///
+    /// - See [`Family::supports_bank_erase`].
+    ///
+    /// # Notes
+    ///
+    /// This command must be followed by a [`Device::get_status`] command
+    /// to verify it worked.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    /// this command.
+    pub fn bank_erase(&mut self) -> Result<()> {
+        self.instrument(CommandId::BankErase, |device| {
+            device.check_supported(CommandId::BankErase)?;
+
+            device.write_cmd(CommandId::BankErase, &[])?;
+            device.expect_ack(CommandId::BankErase)?;
+
+            Ok(())
+        })
+    }
+
+    /// Set a CCFG field. Not supported on [`Family::CC2538`].
+    ///
+    /// - See [`Family::supports_set_ccfg`].
+    ///
+    /// # Parameters
+    ///
+    /// - `field`: the CCFG field to write.
+    /// - `value`: the new value of the field.
+    ///
+    /// # Notes
+    ///
+    /// This command must be followed by a [`Device::get_status`] command
+    /// to verify it worked.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    /// this command.
+    pub fn set_ccfg(&mut self, field: CcfgField, value: u32) -> Result<()> {
+        self.instrument(CommandId::SetCcfg, |device| {
+            const CMD_SET_CCFG_LEN: usize = 8;
+
+            device.check_supported(CommandId::SetCcfg)?;
+
+            let mut data = [0u8; CMD_SET_CCFG_LEN];
+            data[..4].copy_from_slice(&field.id().to_be_bytes());
+            data[4..].copy_from_slice(&value.to_be_bytes());
+
+            device.write_cmd(CommandId::SetCcfg, &data)?;
+            device.expect_ack(CommandId::SetCcfg)?;
+
+            Ok(())
+        })
     }
 
     /// Switch to XOSC. Only supported on [`Family::CC2538`].
     ///
     /// - See [`Family::supports_set_xosc`].
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// This function panics if the family doesn't support this command.
-    pub fn set_xosc(&mut self) -> io::Result<()> {
-        if !self.family.supports_set_xosc() {
-            panic!("XOSC switch is not supported");
-        }
+    /// Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    /// this command.
+    pub fn set_xosc(&mut self) -> Result<()> {
+        self.instrument(CommandId::SetXosc, |device| {
+            device.check_supported(CommandId::SetXosc)?;
 
-        self.write_cmd(constants::CC2538_CMD_SET_XOSC, &[])?;
-        let ack = self.read_ack()?;
-        if !ack {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "failed to switch to xosc",
-            ));
-        }
+            device.write_cmd(CommandId::SetXosc, &[])?;
+            device.expect_ack(CommandId::SetXosc)?;
 
-        Ok(())
+            Ok(())
+        })
     }
 
     /// Read memory using 32-bit access type.
@@ -452,63 +1289,216 @@ where
     ///
     /// - `address`: the memory address to read. Must be aligned to 32-bits.
     /// - `data`: where the data will be stored. Can't be higher than `63 * 4`
-    /// bytes. The number of bytes MUST be exactly divisible by 4.
+    ///   bytes. The number of bytes MUST be exactly divisible by 4.
+    ///
+    /// On [`Family::CC2538`] the bootloader reads a single word per command,
+    /// so a command is sent for each word of `data`.
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// - This function will panic if the length of the `data` slice
-    /// is higher than `63 * 4` bytes, this is the maximum number of accesses
-    /// that can be done using this mode.
-    /// - This function will panic if the `address` is not aligned to 32-bits.
+    /// - Returns [`Error::DataTooLarge`] if the length of the `data` slice
+    ///   is higher than `63 * 4` bytes, this is the maximum number of accesses
+    ///   that can be done using this mode.
+    /// - Returns [`Error::InvalidLength`] if the length of the `data` slice
+    ///   is not divisible by 4.
+    /// - Returns [`Error::UnalignedAddress`] if the `address` is not aligned
+    ///   to 32-bits.
     pub fn memory_read_32(
         &mut self,
         address: u32,
         data: &mut [u8],
-    ) -> io::Result<()> {
-        const MEMORY_READ_LEN: usize = 6;
-        if let Family::CC2538 = self.family {
-            panic!("32-bit memory accesses are only allowed on CC26xx");
-        }
+    ) -> Result<()> {
+        self.instrument(CommandId::MemoryRead, |device| {
+            const MEMORY_READ_LEN: usize = 6;
+
+            device.check_supported(CommandId::MemoryRead)?;
+            check_word_access(
+                address,
+                data.len(),
+                constants::MAX_MEMORY_READ_32_BYTES,
+            )?;
+            device.check_address_range(
+                address,
+                data.len() as u32,
+                ANY_MEMORY,
+            )?;
+
+            if let Family::CC2538 = device.family {
+                return device.memory_read_cc2538(address, data);
+            }
 
-        assert!(
-            data.len() <= (63 * 4),
-            "only a maximum of 63 accesses can be done on word mode"
-        );
-        assert!(
-            data.len() % 4 == 0,
-            "number of bytes is not divisible from 4"
-        );
-        assert!(
-            (address & 0x03) == 0,
-            "memory address must be 32-bits aligned"
-        );
+            log::trace!(
+                "memory_read_32 `{}` elements at start address `{:#X}`",
+                data.len() / 4,
+                address
+            );
 
-        log::trace!(
-            "memory_read_32 `{}` elements at start address `{:#X}`",
-            data.len() / 4,
-            address
-        );
+            let mut cmd = [0u8; MEMORY_READ_LEN];
+            cmd[..4].copy_from_slice(&address.to_be_bytes()); /* address */
+            cmd[4] = 1; /* access type */
+            cmd[5] = (data.len() / 4) as u8; /* number of accesses */
+            device.write_cmd(CommandId::MemoryRead, &cmd)?;
+            device.expect_ack(CommandId::MemoryRead)?;
+
+            device.read_response(data)?;
+            device.write_ack(true)?;
 
-        let mut cmd = [0u8; MEMORY_READ_LEN];
-        (&mut cmd[..4]).copy_from_slice(&address.to_be_bytes()); /* address */
-        cmd[4] = 1; /* access type */
-        cmd[5] = (data.len() / 4) as u8; /* number of accesses */
-        self.write_cmd(constants::CMD_MEMORY_READ, &cmd)?;
-        let ack = self.read_ack()?;
-        if !ack {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "failed to read memory",
-            ));
+            Ok(())
+        })
+    }
+
+    /// `COMMAND_MEMORY_READ` of the CC2538, one 32-bit access per command.
+    fn memory_read_cc2538(
+        &mut self,
+        address: u32,
+        data: &mut [u8],
+    ) -> Result<()> {
+        for (i, word) in data.chunks_mut(4).enumerate() {
+            let mut cmd = [0u8; 5];
+            cmd[..4].copy_from_slice(&(address + i as u32 * 4).to_be_bytes());
+            cmd[4] = 4; /* access width */
+            self.write_cmd(CommandId::MemoryRead, &cmd)?;
+            self.expect_ack(CommandId::MemoryRead)?;
+
+            // The word is sent most significant byte first.
+            self.read_response(word)?;
+            self.write_ack(true)?;
+            word.reverse();
         }
 
-        self.read_response(data)?;
-        self.write_ack(true)?;
+        Ok(())
+    }
+
+    /// Write memory using 32-bit access type.
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: the memory address to write. Must be aligned to 32-bits.
+    /// - `data`: the data to write. Can't be higher than `61 * 4` bytes. The
+    ///   number of bytes MUST be exactly divisible by 4.
+    ///
+    /// # Notes
+    ///
+    /// This command must be followed by a [`Device::get_status`] command
+    /// to verify it worked.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    ///   this command, e.g.: [`Family::CC2538`].
+    /// - Returns [`Error::DataTooLarge`] if the length of the `data` slice
+    ///   is higher than `61 * 4` bytes, this is the maximum number of accesses
+    ///   that can be done using this mode.
+    /// - Returns [`Error::InvalidLength`] if the length of the `data` slice
+    ///   is not divisible by 4.
+    /// - Returns [`Error::UnalignedAddress`] if the `address` is not aligned
+    ///   to 32-bits.
+    pub fn memory_write_32(&mut self, address: u32, data: &[u8]) -> Result<()> {
+        self.instrument(CommandId::MemoryWrite, |device| {
+            device.check_supported(CommandId::MemoryWrite)?;
+            check_word_access(
+                address,
+                data.len(),
+                constants::MAX_MEMORY_WRITE_32_BYTES,
+            )?;
+
+            log::trace!(
+                "memory_write_32 `{}` elements at start address `{:#X}`",
+                data.len() / 4,
+                address
+            );
+
+            device.memory_write(address, 1, data)
+        })
+    }
+
+    /// Write memory using 8-bit access type.
+    ///
+    /// # Parameters
+    ///
+    /// - `address`: the memory address to write.
+    /// - `data`: the data to write. Can't be higher than `247` bytes.
+    ///
+    /// # Notes
+    ///
+    /// This command must be followed by a [`Device::get_status`] command
+    /// to verify it worked.
+    ///
+    /// # Errors
+    ///
+    /// - Returns [`Error::UnsupportedCommand`] if the family doesn't support
+    ///   this command, e.g.: [`Family::CC2538`].
+    /// - Returns [`Error::DataTooLarge`] if the length of the `data` slice is
+    ///   higher than `247` bytes, this is the maximum number of accesses that
+    ///   can be done using this mode.
+    pub fn memory_write_8(&mut self, address: u32, data: &[u8]) -> Result<()> {
+        self.instrument(CommandId::MemoryWrite, |device| {
+            device.check_supported(CommandId::MemoryWrite)?;
+
+            if data.len() > constants::MAX_MEMORY_WRITE_8_BYTES {
+                return Err(Error::DataTooLarge {
+                    max: constants::MAX_MEMORY_WRITE_8_BYTES,
+                    found: data.len(),
+                });
+            }
+
+            log::trace!(
+                "memory_write_8 `{}` elements at start address `{:#X}`",
+                data.len(),
+                address
+            );
+
+            device.memory_write(address, 0, data)
+        })
+    }
+
+    fn memory_write(
+        &mut self,
+        address: u32,
+        access_type: u8,
+        data: &[u8],
+    ) -> Result<()> {
+        const MEMORY_WRITE_HDR_LEN: usize = 5;
+
+        self.check_address_range(address, data.len() as u32, ANY_MEMORY)?;
+
+        let mut cmd = Vec::with_capacity(MEMORY_WRITE_HDR_LEN + data.len());
+        cmd.extend_from_slice(&address.to_be_bytes()); /* address */
+        cmd.push(access_type); /* access type */
+        cmd.extend_from_slice(data); /* data */
+        self.write_cmd(CommandId::MemoryWrite, &cmd)?;
+        self.expect_ack(CommandId::MemoryWrite)?;
 
         Ok(())
     }
 }
 
+/// Memory that can be erased and programmed.
+const FLASH_MEMORY: &[MemoryKind] = &[MemoryKind::Flash];
+/// Memory code can be executed from.
+const EXECUTABLE_MEMORY: &[MemoryKind] = &[MemoryKind::Flash, MemoryKind::Sram];
+/// All the memory.
+const ANY_MEMORY: &[MemoryKind] = &[
+    MemoryKind::Flash,
+    MemoryKind::Rom,
+    MemoryKind::Sram,
+    MemoryKind::Peripheral,
+];
+
+/// Response of the bootloader to a command.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AckResponse {
+    /// The command was acknowledged.
+    Ack,
+    /// The command was not acknowledged.
+    Nack,
+    /// No ACK/NACK was received before the timeout.
+    Timeout {
+        /// Number of bytes received that weren't an ACK/NACK.
+        garbage_bytes: usize,
+    },
+}
+
 impl<P> fmt::Debug for Device<P>
 where
     P: SerialPort,
@@ -517,17 +1507,34 @@ where
         fmt.debug_struct("Device")
             .field("family", &self.family)
             .field("port", &())
+            .field("ack_timeouts", &self.ack_timeouts)
+            .field("sync_retry", &self.sync_retry)
+            .field("response_attempts", &self.response_attempts)
+            .field("auto_baud", &self.auto_baud)
+            .field("strict", &self.strict)
+            .field("deadline", &self.deadline)
+            .field("trace_sink", &self.trace_sink.is_some())
+            .field("diagnostics", &self.diagnostics)
+            .field("sector_size", &self.sector_size)
             .finish()
     }
 }
 
-fn command_checksum(cmd: u8, data: &[u8]) -> u8 {
-    let mut checksum: u8 = cmd;
-    for byte in data {
-        checksum = checksum.overflowing_add(*byte).0;
+/// Check the parameters of a 32-bit memory access.
+fn check_word_access(address: u32, len: usize, max: usize) -> Result<()> {
+    if len > max {
+        return Err(Error::DataTooLarge { max, found: len });
+    }
+
+    if len % 4 != 0 {
+        return Err(Error::InvalidLength(len));
     }
 
-    checksum
+    if (address & 0x03) != 0 {
+        return Err(Error::UnalignedAddress(address));
+    }
+
+    Ok(())
 }
 
 /// Default serial port settings.
@@ -551,9 +1558,9 @@ pub fn port_settings() -> serial::PortSettings {
 /// # Parameters:
 ///
 /// - `inverted`: if it's `false` (default) DTR is connected to the bootloader pin,
-/// RTS connnected to !RESET. If it's `true` it's the other way around
+///   RTS connnected to !RESET. If it's `true` it's the other way around
 /// - `bootloader_active_high`: whether the bootloader pin used is active low or
-/// active high.
+///   active high.
 #[allow(clippy::needless_bool)]
 pub fn invoke_bootloader<P>(
     port: &mut P,
@@ -610,12 +1617,182 @@ where
 #[cfg(test)]
 pub mod test {
     use super::*;
+    use crate::trace::Capture;
+
+    #[test]
+    fn test_memory_write_unsupported() {
+        let mut device =
+            Device::new(Capture::new().port(), Family::CC2538).unwrap();
+        assert!(matches!(
+            device.memory_write_32(0x2000_0000, &[0; 4]),
+            Err(Error::UnsupportedCommand(CommandId::MemoryWrite))
+        ));
+        assert!(matches!(
+            device.memory_write_8(0x2000_0000, &[0]),
+            Err(Error::UnsupportedCommand(CommandId::MemoryWrite))
+        ));
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_sector_size() {
+        assert!(matches!(
+            DeviceBuilder::new(Family::CC26X2)
+                .sector_size(3000)
+                .build(Capture::default().port()),
+            Err(Error::InvalidSectorSize(3000))
+        ));
+
+        let mut device =
+            Device::new(Capture::new().port(), Family::CC26X2).unwrap();
+        device.set_sector_size(Some(0x1000)).unwrap();
+        assert_eq!(device.sector_size(), 0x1000);
+        assert!(matches!(
+            device.set_sector_size(Some(0)),
+            Err(Error::InvalidSectorSize(0))
+        ));
+        assert_eq!(device.sector_size(), 0x1000);
+        device.set_sector_size(None).unwrap();
+        assert_eq!(device.sector_size(), Family::CC26X2.sector_size());
+    }
+
+    #[test]
+    fn test_reset() {
+        let capture = Capture::new().command(CommandId::Reset, &[]);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+        device.reset().unwrap();
+        assert!(device.port().is_finished());
+
+        let pkt = protocol::command_packet(CommandId::Reset.value(), &[]);
+        let capture = Capture::new().tx(&pkt.unwrap()).rx(&[0x00, 0x33]);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+        assert!(matches!(device.reset(), Err(Error::Nack(CommandId::Reset))));
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_deadline_exceeded() {
+        // The device doesn't answer the ping.
+        let capture = Capture::new().tx(&[0x03, 0x20, 0x20]);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+        assert!(matches!(
+            device.with_deadline(Duration::from_millis(20), Device::ping),
+            Err(Error::DeadlineExceeded)
+        ));
+        assert!(device.port().is_finished());
+
+        // Nothing is sent once the deadline has passed.
+        assert!(matches!(
+            device.with_deadline(Duration::from_millis(0), Device::ping),
+            Err(Error::DeadlineExceeded)
+        ));
+    }
+
+    #[test]
+    fn test_detect() {
+        let capture = Capture::new()
+            .command(CommandId::GetChipId, &[])
+            .response(&[0x00, 0x00, 0xB9, 0x64]);
+        let device = Device::detect(capture.port()).unwrap();
+        assert_eq!(device.family(), Family::CC2538);
+        assert_eq!(device.sector_size(), Family::CC2538.sector_size());
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_read_ack() {
+        let timeout = Duration::from_millis(10);
+        let capture = Capture::new()
+            .rx(&[0x00, 0xCC])
+            .rx(&[0x00, 0x33])
+            // Bytes before the ACK are discarded.
+            .rx(&[0x12, 0x34, 0x00, 0xCC])
+            .rx(&[0x00, 0x00, 0x33])
+            // Nothing but garbage before the timeout.
+            .rx(&[0x12, 0x00, 0x34]);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+        assert_eq!(device.read_ack_timeout(timeout).unwrap(), AckResponse::Ack);
+        assert_eq!(
+            device.read_ack_timeout(timeout).unwrap(),
+            AckResponse::Nack
+        );
+        assert_eq!(device.read_ack_timeout(timeout).unwrap(), AckResponse::Ack);
+        assert_eq!(device.diagnostics().garbage_bytes, 2);
+        assert_eq!(
+            device.read_ack_timeout(timeout).unwrap(),
+            AckResponse::Nack
+        );
+        assert_eq!(device.diagnostics().garbage_bytes, 3);
+        assert_eq!(
+            device.read_ack_timeout(timeout).unwrap(),
+            AckResponse::Timeout { garbage_bytes: 3 }
+        );
+        assert_eq!(
+            device.read_ack_timeout(timeout).unwrap(),
+            AckResponse::Timeout { garbage_bytes: 0 }
+        );
+        assert_eq!(device.diagnostics().garbage_bytes, 6);
+        assert_eq!(device.diagnostics().nacks, 2);
+        assert_eq!(device.diagnostics().ack_timeouts, 2);
+        assert!(device.port().is_finished());
+
+        // Garbage is an error in strict mode.
+        let capture = Capture::new().rx(&[0x12, 0x00, 0xCC]);
+        let mut device = DeviceBuilder::new(Family::CC26X2)
+            .strict(true)
+            .build(capture.port())
+            .unwrap();
+        assert!(matches!(
+            device.read_ack_timeout(timeout),
+            Err(Error::InvalidResponse)
+        ));
+    }
 
     #[test]
-    fn test_command_checksum() {
-        // nonsensical data, just to make sure it works.
-        const DATA: &[u8] = &[0xde, 0xad, 0xbe, 0xef];
-        assert_eq!(command_checksum(0xCA, DATA), 0x02);
+    fn test_read_response() {
+        let payload = [0xDE, 0xAD, 0xBE, 0xEF, 0x01];
+        let checksum = protocol::response_checksum(&payload);
+        let mut packet = vec![payload.len() as u8 + 2, checksum];
+        packet.extend_from_slice(&payload);
+        let mut corrupted = packet.clone();
+        corrupted[1] ^= 0xFF;
+
+        let capture = Capture::new()
+            .rx(&packet)
+            // A bad checksum is NACKed and the response sent again.
+            .rx(&corrupted)
+            .tx(&[0x00, 0x33])
+            .rx(&packet)
+            // The response is shorter than expected.
+            .rx(&packet);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+        assert_eq!(device.read_response_vec().unwrap(), payload);
+        assert_eq!(device.read_response_vec().unwrap(), payload);
+        assert_eq!(device.diagnostics().checksum_errors, 1);
+        assert!(matches!(
+            device.read_response(&mut [0; 8]),
+            Err(Error::ResponseLength {
+                expected: 8,
+                found: 5
+            })
+        ));
+        assert!(device.port().is_finished());
+
+        // The checksum is still wrong after all the attempts.
+        let capture = Capture::new()
+            .rx(&corrupted)
+            .tx(&[0x00, 0x33])
+            .rx(&corrupted)
+            .tx(&[0x00, 0x33]);
+        let mut device = DeviceBuilder::new(Family::CC26X2)
+            .response_attempts(2)
+            .build(capture.port())
+            .unwrap();
+        assert!(matches!(
+            device.read_response_vec(),
+            Err(Error::InvalidChecksum)
+        ));
+        assert!(device.port().is_finished());
     }
 
     #[test]
@@ -695,8 +1872,8 @@ pub mod test {
 
         // Test that the invoke functionality leaves the pins on their normal level.
         invoke_bootloader(&mut port, false, false).unwrap();
-        assert_eq!(port.rts_state, false);
-        assert_eq!(port.dtr_state, false);
+        assert!(!port.rts_state);
+        assert!(!port.dtr_state);
 
         // Reset values.
         port.rts_state = false;
@@ -704,8 +1881,8 @@ pub mod test {
 
         // Test that the invoke functionality leaves the pins on their normal level.
         invoke_bootloader(&mut port, true, false).unwrap();
-        assert_eq!(port.rts_state, false);
-        assert_eq!(port.dtr_state, false);
+        assert!(!port.rts_state);
+        assert!(!port.dtr_state);
 
         // Reset values, now for active-high.
         port.rts_state = false;
@@ -713,8 +1890,8 @@ pub mod test {
 
         // Test that the invoke functionality leaves the pins on their normal level.
         invoke_bootloader(&mut port, false, true).unwrap();
-        assert_eq!(port.rts_state, false);
-        assert_eq!(port.dtr_state, true);
+        assert!(!port.rts_state);
+        assert!(port.dtr_state);
 
         // Reset values, now for active-high and inverted.
         port.rts_state = true;
@@ -722,7 +1899,7 @@ pub mod test {
 
         // Test that the invoke functionality leaves the pins on their normal level.
         invoke_bootloader(&mut port, true, true).unwrap();
-        assert_eq!(port.rts_state, true);
-        assert_eq!(port.dtr_state, false);
+        assert!(port.rts_state);
+        assert!(!port.dtr_state);
     }
 }
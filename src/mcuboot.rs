@@ -0,0 +1,287 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # MCUboot images
+//!
+//! Images signed for MCUboot (e.g.: Zephyr applications built for CC13x2 and
+//! CC26x2 with `CONFIG_BOOTLOADER_MCUBOOT`) start with a 32-byte header and
+//! are followed by a TLV (Type-Length-Value) trailer with the hash and
+//! signature of the image.
+//!
+//! MCUboot boots the image in the primary slot, an image is flashed in the
+//! secondary slot to be swapped in by MCUboot on the next boot. The address
+//! of the slots comes from the flash map of the board, see [`FlashMap`].
+
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+use crate::{Error, Result};
+
+/// Size of the MCUboot image header, in bytes.
+pub const IMAGE_HEADER_SIZE: usize = 32;
+
+/// Magic of the current image header format.
+const IMAGE_MAGIC: u32 = 0x96F3_B83D;
+/// Magic of the legacy (v1) image header format.
+const IMAGE_MAGIC_V1: u32 = 0x96F3_B83C;
+
+/// Magic of the TLV trailer.
+const TLV_INFO_MAGIC: u16 = 0x6907;
+/// Magic of the protected TLV trailer, covered by the signature.
+const TLV_PROT_INFO_MAGIC: u16 = 0x6908;
+
+/// Version of an image, `major.minor.revision+build`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct ImageVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u16,
+    pub build: u32,
+}
+
+impl fmt::Display for ImageVersion {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "{}.{}.{}+{}",
+            self.major, self.minor, self.revision, self.build
+        )
+    }
+}
+
+/// The header of an MCUboot image.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ImageHeader {
+    load_address: u32,
+    header_size: u16,
+    protected_tlv_size: u16,
+    image_size: u32,
+    flags: u32,
+    version: ImageVersion,
+}
+
+impl ImageHeader {
+    /// Parse the header at the start of the `image`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidMcuboot`] if the image is too short, the magic
+    /// doesn't match or the header uses the legacy format.
+    pub fn parse(image: &[u8]) -> Result<ImageHeader> {
+        if image.len() < IMAGE_HEADER_SIZE {
+            return Err(Error::InvalidMcuboot("image too short"));
+        }
+
+        let word = |offset: usize| {
+            u32::from_le_bytes(
+                <[u8; 4]>::try_from(&image[offset..offset + 4]).unwrap(),
+            )
+        };
+        let half = |offset: usize| {
+            u16::from_le_bytes([image[offset], image[offset + 1]])
+        };
+
+        match word(0) {
+            IMAGE_MAGIC => (),
+            IMAGE_MAGIC_V1 => {
+                return Err(Error::InvalidMcuboot(
+                    "unsupported legacy image header",
+                ))
+            }
+            _ => return Err(Error::InvalidMcuboot("invalid magic")),
+        }
+
+        let header = ImageHeader {
+            load_address: word(4),
+            header_size: half(8),
+            protected_tlv_size: half(10),
+            image_size: word(12),
+            flags: word(16),
+            version: ImageVersion {
+                major: image[20],
+                minor: image[21],
+                revision: half(22),
+                build: word(24),
+            },
+        };
+
+        if usize::from(header.header_size) < IMAGE_HEADER_SIZE {
+            return Err(Error::InvalidMcuboot("invalid header size"));
+        }
+
+        Ok(header)
+    }
+
+    /// Whether the `image` starts with an MCUboot header.
+    pub fn is_mcuboot(image: &[u8]) -> bool {
+        image.len() >= 4 && image[..4] == IMAGE_MAGIC.to_le_bytes()
+    }
+
+    /// Load address of the image, only used by images loaded to RAM.
+    #[inline]
+    pub fn load_address(&self) -> u32 {
+        self.load_address
+    }
+
+    /// Size of the header, the image follows it.
+    #[inline]
+    pub fn header_size(&self) -> u16 {
+        self.header_size
+    }
+
+    /// Size of the protected TLV trailer, `0` if there's none.
+    #[inline]
+    pub fn protected_tlv_size(&self) -> u16 {
+        self.protected_tlv_size
+    }
+
+    /// Size of the image, without the header nor the trailer.
+    #[inline]
+    pub fn image_size(&self) -> u32 {
+        self.image_size
+    }
+
+    /// The `IMAGE_F_*` flags of the image.
+    #[inline]
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Version of the image.
+    #[inline]
+    pub fn version(&self) -> ImageVersion {
+        self.version
+    }
+}
+
+/// Parse the header of the `image` and check its TLV trailer, returns the
+/// header and the size of the whole image, trailer included.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidMcuboot`] if the header is invalid, the image is
+/// shorter than its size or the trailer is missing.
+pub fn validate(image: &[u8]) -> Result<(ImageHeader, usize)> {
+    let header = ImageHeader::parse(image)?;
+
+    let mut offset = usize::from(header.header_size)
+        .checked_add(header.image_size as usize)
+        .ok_or(Error::InvalidMcuboot("invalid image size"))?;
+    let mut tlv_info = |magic: u16| {
+        let info = image
+            .get(offset..offset + 4)
+            .ok_or(Error::InvalidMcuboot("image shorter than its size"))?;
+        if u16::from_le_bytes([info[0], info[1]]) != magic {
+            return Err(Error::InvalidMcuboot("missing TLV trailer"));
+        }
+        let len = usize::from(u16::from_le_bytes([info[2], info[3]]));
+        offset += len;
+        Ok(len)
+    };
+
+    if header.protected_tlv_size != 0
+        && tlv_info(TLV_PROT_INFO_MAGIC)?
+            != usize::from(header.protected_tlv_size)
+    {
+        return Err(Error::InvalidMcuboot("invalid protected TLV size"));
+    }
+    tlv_info(TLV_INFO_MAGIC)?;
+
+    if offset > image.len() {
+        return Err(Error::InvalidMcuboot("image shorter than its trailer"));
+    }
+
+    Ok((header, offset))
+}
+
+/// An MCUboot slot.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Slot {
+    /// The slot MCUboot boots from.
+    Primary,
+    /// The slot of the upgrade image.
+    Secondary,
+}
+
+impl FromStr for Slot {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Slot> {
+        match s {
+            "primary" => Ok(Slot::Primary),
+            "secondary" => Ok(Slot::Secondary),
+            _ => Err(Error::InvalidMcuboot("unknown slot")),
+        }
+    }
+}
+
+/// Location of the MCUboot slots in the flash.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FlashMap {
+    /// Address of the primary slot.
+    pub primary: u32,
+    /// Address of the secondary slot.
+    pub secondary: u32,
+    /// Size of each slot in bytes.
+    pub slot_size: u32,
+}
+
+impl FlashMap {
+    /// Flash map of the Zephyr CC13x2/CC26x2 LaunchPads: MCUboot in the
+    /// first 48 KiB and two slots of 200 KiB.
+    pub const CC13X2_CC26X2: FlashMap = FlashMap {
+        primary: 0x0000_C000,
+        secondary: 0x0003_E000,
+        slot_size: 0x0003_2000,
+    };
+
+    /// Address of the `slot`.
+    pub fn address(&self, slot: Slot) -> u32 {
+        match slot {
+            Slot::Primary => self.primary,
+            Slot::Secondary => self.secondary,
+        }
+    }
+}
+
+impl Default for FlashMap {
+    fn default() -> FlashMap {
+        FlashMap::CC13X2_CC26X2
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let mut image = vec![0u8; 0x200 + 0x100];
+        image[..4].copy_from_slice(&IMAGE_MAGIC.to_le_bytes());
+        image[8..10].copy_from_slice(&0x200u16.to_le_bytes());
+        image[12..16].copy_from_slice(&0xF0u32.to_le_bytes());
+        image[20..28].copy_from_slice(&[1, 2, 3, 0, 4, 0, 0, 0]);
+        image[0x2F0..0x2F4].copy_from_slice(&[0x07, 0x69, 0x10, 0x00]);
+
+        let (header, len) = validate(&image).unwrap();
+        assert_eq!(header.version().to_string(), "1.2.3+4");
+        assert_eq!(len, 0x300);
+
+        image[0x2F0] = 0;
+        assert!(validate(&image).is_err());
+        assert!(validate(&image[..0x200]).is_err());
+        image[0] = 0x3C;
+        assert!(ImageHeader::parse(&image).is_err());
+        assert!(!ImageHeader::is_mcuboot(&image));
+    }
+}
@@ -0,0 +1,412 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # MSP430/MSP432 UART BSL
+//!
+//! The bootloader (BSL) of the MSP430 5xx/6xx/FRxx and MSP432P4xx devices.
+//! Each core command is wrapped in a `80 NL NH <command> CKL CKH` packet,
+//! with a CRC16-CCITT checksum, and acknowledged with a single byte.
+//!
+//! Most commands require unlocking the BSL first with the password, the
+//! interrupt vector table of the device. After a mass erase the password
+//! is all `0xFF`.
+//!
+//! The BSL has to be invoked by the application or with the TEST/RST entry
+//! sequence before creating a [`Bsl`].
+//!
+//! # See also
+//!
+//! - [MSP430 Flash Device Bootloader (BSL)](https://www.ti.com/lit/ug/slau550/slau550.pdf).
+//! - [MSP432P4xx Bootloader (BSL)](https://www.ti.com/lit/ug/slau622/slau622.pdf).
+
+use std::{
+    error,
+    fmt::{self, Display, Formatter},
+    io,
+};
+
+use serial::SerialPort;
+
+use crate::{Error, Result};
+
+/// Header of the packets.
+const HEADER: u8 = 0x80;
+/// Response with data.
+const RESPONSE_DATA: u8 = 0x3A;
+/// Response with a status message.
+const RESPONSE_MESSAGE: u8 = 0x3B;
+
+const CMD_MASS_ERASE: u8 = 0x15;
+const CMD_TX_BSL_VERSION: u8 = 0x19;
+
+/// Maximum size of a core command, on the BSL buffer.
+const MAX_CORE_COMMAND_LEN: usize = 260;
+
+/// The BSL flavor.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Variant {
+    /// MSP430 5xx/6xx/FRxx, 20-bit addresses and a 32 byte password.
+    Msp430,
+    /// MSP432P4xx, 32-bit addresses and a 256 byte password.
+    Msp432,
+}
+
+impl Variant {
+    /// Length of the addresses of the commands, in bytes.
+    fn address_len(&self) -> usize {
+        match *self {
+            Variant::Msp430 => 3,
+            Variant::Msp432 => 4,
+        }
+    }
+
+    /// Length of the password, in bytes.
+    pub fn password_len(&self) -> usize {
+        match *self {
+            Variant::Msp430 => 32,
+            Variant::Msp432 => 256,
+        }
+    }
+
+    fn cmd_rx_data_block(&self) -> u8 {
+        match *self {
+            Variant::Msp430 => 0x10,
+            Variant::Msp432 => 0x20,
+        }
+    }
+
+    fn cmd_rx_password(&self) -> u8 {
+        match *self {
+            Variant::Msp430 => 0x11,
+            Variant::Msp432 => 0x21,
+        }
+    }
+
+    fn cmd_erase_segment(&self) -> u8 {
+        match *self {
+            Variant::Msp430 => 0x12,
+            Variant::Msp432 => 0x22,
+        }
+    }
+
+    fn cmd_crc_check(&self) -> u8 {
+        match *self {
+            Variant::Msp430 => 0x16,
+            Variant::Msp432 => 0x26,
+        }
+    }
+
+    fn cmd_load_pc(&self) -> u8 {
+        match *self {
+            Variant::Msp430 => 0x17,
+            Variant::Msp432 => 0x27,
+        }
+    }
+
+    fn cmd_tx_data_block(&self) -> u8 {
+        match *self {
+            Variant::Msp430 => 0x18,
+            Variant::Msp432 => 0x28,
+        }
+    }
+}
+
+/// Serial port settings of the BSL, 9600 baud, with even parity on the
+/// MSP430.
+pub fn port_settings(variant: Variant) -> serial::PortSettings {
+    serial::PortSettings {
+        baud_rate: serial::BaudRate::Baud9600,
+        parity: match variant {
+            Variant::Msp430 => serial::Parity::ParityEven,
+            Variant::Msp432 => serial::Parity::ParityNone,
+        },
+        ..crate::port_settings()
+    }
+}
+
+/// Error status returned by the BSL.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct BslError(pub u8);
+
+impl Display for BslError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        let description = match self.0 {
+            0x01 => "flash write check failed",
+            0x02 => "flash fail bit set",
+            0x03 => "voltage change during program",
+            0x04 => "BSL locked",
+            0x05 => "BSL password error",
+            0x06 => "byte write forbidden",
+            0x07 => "unknown command",
+            0x08 => "packet length exceeds buffer size",
+            0x51 => "header incorrect",
+            0x52 => "checksum incorrect",
+            0x53 => "packet size zero",
+            0x54 => "packet size exceeds buffer",
+            0x56 => "unknown baud rate",
+            _ => "unknown error",
+        };
+
+        write!(fmt, "BSL error {:#04X}: {}", self.0, description)
+    }
+}
+
+impl error::Error for BslError {}
+
+impl From<BslError> for Error {
+    fn from(e: BslError) -> Error {
+        Error::UnknownStatus(e.0)
+    }
+}
+
+/// Connection with the MSP430/MSP432 BSL.
+pub struct Bsl<P> {
+    port: P,
+    variant: Variant,
+}
+
+impl<P> Bsl<P>
+where
+    P: SerialPort,
+{
+    /// Talk with the BSL through the `port`, configured with
+    /// [`port_settings`].
+    pub fn new(port: P, variant: Variant) -> Self {
+        Bsl { port, variant }
+    }
+
+    /// The BSL flavor.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Consume the `Bsl` and return the underlying port.
+    pub fn into_port(self) -> P {
+        self.port
+    }
+
+    /// Unlock the BSL with the `password`, see [`Variant::password_len`].
+    pub fn unlock(&mut self, password: &[u8]) -> Result<()> {
+        if password.len() != self.variant.password_len() {
+            return Err(Error::InvalidLength(password.len()));
+        }
+
+        let mut cmd = vec![self.variant.cmd_rx_password()];
+        cmd.extend_from_slice(password);
+        self.command_message(&cmd)
+    }
+
+    /// Unlock the BSL with the password of an erased device.
+    pub fn unlock_erased(&mut self) -> Result<()> {
+        let password = vec![0xFF; self.variant.password_len()];
+        self.unlock(&password)
+    }
+
+    /// Erase the whole main memory. The BSL is locked again afterwards.
+    pub fn mass_erase(&mut self) -> Result<()> {
+        self.command_message(&[CMD_MASS_ERASE])
+    }
+
+    /// Erase the segment containing `address`.
+    pub fn erase_segment(&mut self, address: u32) -> Result<()> {
+        let mut cmd = vec![self.variant.cmd_erase_segment()];
+        self.push_address(&mut cmd, address);
+        self.command_message(&cmd)
+    }
+
+    /// Write the `data` at `address`, split in as many blocks as needed.
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<()> {
+        let max_len = self.max_data_len();
+        for (i, chunk) in data.chunks(max_len).enumerate() {
+            let chunk_address = address + (i * max_len) as u32;
+            log::trace!(
+                "writing {} bytes at {:#X}",
+                chunk.len(),
+                chunk_address
+            );
+
+            let mut cmd = vec![self.variant.cmd_rx_data_block()];
+            self.push_address(&mut cmd, chunk_address);
+            cmd.extend_from_slice(chunk);
+            self.command_message(&cmd)?;
+        }
+
+        Ok(())
+    }
+
+    /// Read `data.len()` bytes from `address`.
+    pub fn read(&mut self, address: u32, data: &mut [u8]) -> Result<()> {
+        let max_len = self.max_data_len();
+        for (i, chunk) in data.chunks_mut(max_len).enumerate() {
+            let chunk_address = address + (i * max_len) as u32;
+
+            let mut cmd = vec![self.variant.cmd_tx_data_block()];
+            self.push_address(&mut cmd, chunk_address);
+            cmd.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            let response = self.command_data(&cmd)?;
+            if response.len() != chunk.len() {
+                return Err(Error::ResponseLength {
+                    expected: chunk.len(),
+                    found: response.len(),
+                });
+            }
+
+            chunk.copy_from_slice(&response);
+        }
+
+        Ok(())
+    }
+
+    /// CRC16-CCITT of the `len` bytes at `address`, as calculated by the
+    /// BSL. Compare with [`crc16`].
+    pub fn crc(&mut self, address: u32, len: u16) -> Result<u16> {
+        let mut cmd = vec![self.variant.cmd_crc_check()];
+        self.push_address(&mut cmd, address);
+        cmd.extend_from_slice(&len.to_le_bytes());
+        let response = self.command_data(&cmd)?;
+        if response.len() != 2 {
+            return Err(Error::ResponseLength {
+                expected: 2,
+                found: response.len(),
+            });
+        }
+
+        Ok(u16::from_le_bytes([response[0], response[1]]))
+    }
+
+    /// Start executing the code at `address`. The BSL doesn't reply.
+    pub fn load_pc(&mut self, address: u32) -> Result<()> {
+        let mut cmd = vec![self.variant.cmd_load_pc()];
+        self.push_address(&mut cmd, address);
+        self.send(&cmd)
+    }
+
+    /// The version of the BSL.
+    pub fn version(&mut self) -> Result<Vec<u8>> {
+        self.command_data(&[CMD_TX_BSL_VERSION])
+    }
+
+    /// Maximum length of the data of a command with an address.
+    fn max_data_len(&self) -> usize {
+        MAX_CORE_COMMAND_LEN - 1 - self.variant.address_len()
+    }
+
+    fn push_address(&self, cmd: &mut Vec<u8>, address: u32) {
+        let bytes = address.to_le_bytes();
+        cmd.extend_from_slice(&bytes[..self.variant.address_len()]);
+    }
+
+    /// Send the `core_command` and wait for the ACK.
+    fn send(&mut self, core_command: &[u8]) -> Result<()> {
+        let mut packet = Vec::with_capacity(core_command.len() + 5);
+        packet.push(HEADER);
+        packet.extend_from_slice(&(core_command.len() as u16).to_le_bytes());
+        packet.extend_from_slice(core_command);
+        packet.extend_from_slice(&crc16(core_command).to_le_bytes());
+        self.port.write_all(&packet)?;
+
+        let mut ack = [0u8];
+        self.read_exact(&mut ack)?;
+        match ack[0] {
+            0x00 => Ok(()),
+            status => Err(BslError(status).into()),
+        }
+    }
+
+    /// Send a command replying with a status message.
+    fn command_message(&mut self, core_command: &[u8]) -> Result<()> {
+        self.send(core_command)?;
+        let (kind, response) = self.read_response()?;
+        match (kind, response.first()) {
+            (RESPONSE_MESSAGE, Some(0x00)) => Ok(()),
+            (RESPONSE_MESSAGE, Some(&status)) => Err(BslError(status).into()),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+
+    /// Send a command replying with data.
+    fn command_data(&mut self, core_command: &[u8]) -> Result<Vec<u8>> {
+        self.send(core_command)?;
+        let (kind, response) = self.read_response()?;
+        match (kind, response.first()) {
+            (RESPONSE_DATA, _) => Ok(response),
+            (RESPONSE_MESSAGE, Some(&status)) => Err(BslError(status).into()),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+
+    /// Read a response packet, returns the kind of response and the data.
+    fn read_response(&mut self) -> Result<(u8, Vec<u8>)> {
+        let mut header = [0u8; 3];
+        self.read_exact(&mut header)?;
+        if header[0] != HEADER {
+            return Err(Error::InvalidResponse);
+        }
+
+        let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+        if len == 0 {
+            return Err(Error::InvalidResponse);
+        }
+
+        let mut core_response = vec![0u8; len + 2];
+        self.read_exact(&mut core_response)?;
+        let checksum =
+            u16::from_le_bytes([core_response[len], core_response[len + 1]]);
+        core_response.truncate(len);
+        if crc16(&core_response) != checksum {
+            return Err(Error::InvalidChecksum);
+        }
+
+        let kind = core_response.remove(0);
+        Ok((kind, core_response))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        match self.port.read_exact(buf) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                Err(Error::Timeout)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// CRC16-CCITT (polynomial `0x1021`, initial value `0xFFFF`) used by the
+/// BSL for the packets and [`Bsl::crc`].
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, byte| {
+        let mut crc = crc ^ (u16::from(*byte) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        // TX BSL version packet from the BSL user's guide.
+        assert_eq!(crc16(&[CMD_TX_BSL_VERSION]).to_le_bytes(), [0xE8, 0x62]);
+    }
+}
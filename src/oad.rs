@@ -0,0 +1,305 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # TI OAD image header
+//!
+//! Images built for the Over the Air Download of the SimpleLink SDK start
+//! with a 44-byte header, checked by the Boot Image Manager (BIM) before
+//! jumping to the image:
+//!
+//! | Offset | Field                                          |
+//! |--------|------------------------------------------------|
+//! | `0x00` | Image identification, e.g.: `CC26x2R1`         |
+//! | `0x08` | CRC32 of the image from offset `0x0C`          |
+//! | `0x0C` | BIM and metadata versions, technology type     |
+//! | `0x10` | Copy and CRC status, image type and number     |
+//! | `0x14` | Image validation                               |
+//! | `0x18` | Image length, including the header             |
+//! | `0x1C` | Program entry address                          |
+//! | `0x20` | Software version                               |
+//! | `0x24` | Image end address                              |
+//! | `0x28` | Header length                                  |
+//!
+//! The image is placed so it ends at the image end address, the start of
+//! its slot is [`OadHeader::image_address`].
+
+use std::convert::TryFrom;
+
+use crate::{crc, Error, Result};
+
+/// Size of the fixed OAD image header, in bytes.
+pub const OAD_HEADER_SIZE: usize = 44;
+
+/// Offset of the CRC32, the CRC covers the image from the next word.
+const CRC_OFFSET: usize = 8;
+const CRC_START: usize = 12;
+const COPY_STATUS_OFFSET: usize = 16;
+const CRC_STATUS_OFFSET: usize = 17;
+
+/// Value of the copy and CRC status fields in an image that hasn't been
+/// checked by the BIM yet.
+const STATUS_PRISTINE: u8 = 0xFF;
+
+/// The fixed OAD image header.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OadHeader {
+    image_id: [u8; 8],
+    crc32: u32,
+    bim_version: u8,
+    meta_version: u8,
+    tech_type: u16,
+    image_type: u8,
+    image_number: u8,
+    len: u32,
+    entry_point: u32,
+    software_version: [u8; 4],
+    end_address: u32,
+    header_len: u16,
+}
+
+impl OadHeader {
+    /// Parse the header at the start of the `image`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidOad`] if the image is too short, the image
+    /// identification isn't printable or the lengths are inconsistent.
+    pub fn parse(image: &[u8]) -> Result<OadHeader> {
+        if image.len() < OAD_HEADER_SIZE {
+            return Err(Error::InvalidOad("image too short"));
+        }
+
+        let word = |offset: usize| {
+            u32::from_le_bytes(
+                <[u8; 4]>::try_from(&image[offset..offset + 4]).unwrap(),
+            )
+        };
+        let half = |offset: usize| {
+            u16::from_le_bytes([image[offset], image[offset + 1]])
+        };
+
+        let mut image_id = [0u8; 8];
+        image_id.copy_from_slice(&image[..8]);
+        if !image_id.iter().all(|b| (0x20..0x7F).contains(b)) {
+            return Err(Error::InvalidOad("invalid image identification"));
+        }
+
+        let mut software_version = [0u8; 4];
+        software_version.copy_from_slice(&image[32..36]);
+
+        let header = OadHeader {
+            image_id,
+            crc32: word(CRC_OFFSET),
+            bim_version: image[12],
+            meta_version: image[13],
+            tech_type: half(14),
+            image_type: image[18],
+            image_number: image[19],
+            len: word(24),
+            entry_point: word(28),
+            software_version,
+            end_address: word(36),
+            header_len: half(40),
+        };
+
+        if (header.len as usize) < OAD_HEADER_SIZE
+            || usize::from(header.header_len) < OAD_HEADER_SIZE
+            || header
+                .end_address
+                .checked_add(1)
+                .and_then(|end| end.checked_sub(header.len))
+                .is_none()
+        {
+            return Err(Error::InvalidOad("invalid image length"));
+        }
+
+        Ok(header)
+    }
+
+    /// The image identification, e.g.: `CC26x2R1`.
+    #[inline]
+    pub fn image_id(&self) -> &[u8; 8] {
+        &self.image_id
+    }
+
+    /// The CRC32 stored in the header.
+    #[inline]
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// Version of the BIM the image was built for.
+    #[inline]
+    pub fn bim_version(&self) -> u8 {
+        self.bim_version
+    }
+
+    /// Version of the header format.
+    #[inline]
+    pub fn meta_version(&self) -> u8 {
+        self.meta_version
+    }
+
+    /// The wireless technologies supported by the image.
+    #[inline]
+    pub fn tech_type(&self) -> u16 {
+        self.tech_type
+    }
+
+    /// Type of image, e.g.: `1` for an application.
+    #[inline]
+    pub fn image_type(&self) -> u8 {
+        self.image_type
+    }
+
+    /// Image number, for images split in several parts.
+    #[inline]
+    pub fn image_number(&self) -> u8 {
+        self.image_number
+    }
+
+    /// Length of the image in bytes, including the header.
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Whether the image only has the header.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len as usize == OAD_HEADER_SIZE
+    }
+
+    /// Address of the program entry.
+    #[inline]
+    pub fn entry_point(&self) -> u32 {
+        self.entry_point
+    }
+
+    /// The software version, four ASCII characters, e.g.: `0001`.
+    #[inline]
+    pub fn software_version(&self) -> &[u8; 4] {
+        &self.software_version
+    }
+
+    /// Address of the last byte of the image.
+    #[inline]
+    pub fn end_address(&self) -> u32 {
+        self.end_address
+    }
+
+    /// Address of the first byte of the image (the header), where the image
+    /// has to be flashed.
+    #[inline]
+    pub fn image_address(&self) -> u32 {
+        self.end_address + 1 - self.len
+    }
+}
+
+/// Parse the header of the `image` and check it has the length and CRC32
+/// of the header.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidOad`] if the header is invalid or the image is
+/// shorter than its length, [`Error::OadCrcMismatch`] if the CRC32 doesn't
+/// match.
+pub fn validate(image: &[u8]) -> Result<OadHeader> {
+    let header = OadHeader::parse(image)?;
+    if image.len() < header.len as usize {
+        return Err(Error::InvalidOad("image shorter than its header length"));
+    }
+    let found = crc32(&image[..header.len as usize]);
+    if found != header.crc32 {
+        return Err(Error::OadCrcMismatch {
+            expected: header.crc32,
+            found,
+        });
+    }
+
+    Ok(header)
+}
+
+/// Compute the CRC32 of the `image` and store it in the header, the copy
+/// and CRC status fields are reset so the BIM checks the image again.
+///
+/// Returns the new header.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidOad`] if the header is invalid or the image is
+/// shorter than its length.
+pub fn fix_crc(image: &mut [u8]) -> Result<OadHeader> {
+    let mut header = OadHeader::parse(image)?;
+    let len = header.len as usize;
+    if image.len() < len {
+        return Err(Error::InvalidOad("image shorter than its header length"));
+    }
+
+    image[COPY_STATUS_OFFSET] = STATUS_PRISTINE;
+    image[CRC_STATUS_OFFSET] = STATUS_PRISTINE;
+    header.crc32 = crc32(&image[..len]);
+    image[CRC_OFFSET..CRC_START].copy_from_slice(&header.crc32.to_le_bytes());
+
+    Ok(header)
+}
+
+/// CRC32 (IEEE 802.3) of the image, from [`CRC_START`], with the copy and
+/// CRC status fields as they're before the BIM checks the image.
+fn crc32(image: &[u8]) -> u32 {
+    let crc = crc::update(!0, &image[CRC_START..COPY_STATUS_OFFSET]);
+    let crc = crc::update(crc, &[STATUS_PRISTINE; 2]);
+    !crc::update(crc, &image[CRC_STATUS_OFFSET + 1..])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_oad_header() {
+        let mut image = vec![0xFFu8; 0x100];
+        image[..8].copy_from_slice(b"CC26x2R1");
+        image[12..20].copy_from_slice(&[3, 1, 0xFE, 0xFF, 0xFE, 0xFE, 1, 0]);
+        image[24..28].copy_from_slice(&0x100u32.to_le_bytes());
+        image[28..32].copy_from_slice(&0x0000_6000u32.to_le_bytes());
+        image[32..36].copy_from_slice(b"0001");
+        image[36..40].copy_from_slice(&0x0000_60FFu32.to_le_bytes());
+        image[40..42].copy_from_slice(&(OAD_HEADER_SIZE as u16).to_le_bytes());
+
+        let header = OadHeader::parse(&image).unwrap();
+        assert_eq!(header.image_id(), b"CC26x2R1");
+        assert_eq!(header.software_version(), b"0001");
+        assert_eq!(header.image_address(), 0x0000_6000);
+        assert!(matches!(
+            validate(&image),
+            Err(Error::OadCrcMismatch {
+                expected: 0xFFFF_FFFF,
+                ..
+            })
+        ));
+
+        let fixed = fix_crc(&mut image).unwrap();
+        assert_eq!(image[16..18], [0xFF, 0xFF]);
+        assert_eq!(validate(&image).unwrap(), fixed);
+
+        image[0x80] = 0;
+        assert!(matches!(
+            validate(&image),
+            Err(Error::OadCrcMismatch { .. })
+        ));
+        assert!(validate(&image[..0x80]).is_err());
+        assert!(OadHeader::parse(&image[..40]).is_err());
+    }
+}
@@ -0,0 +1,371 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Flash planning
+//!
+//! A [`FlashPlan`] computes, before touching the device, what flashing a
+//! list of [`Transfer`]s does on a [`FlashLayout`]: the sectors to erase,
+//! the transfers to write (the CCFG split out and written last, as the
+//! device may not answer once it's written) and the writes on the CCFG and
+//! the CCA, so they can be reviewed.
+//!
+//! ```
+//! use ti_sbl::{plan::FlashPlan, util::Transfer, Family, FlashLayout};
+//!
+//! let layout = FlashLayout::new(Family::CC26X2, 0x58000);
+//! let data = [0u8; 0x100];
+//! let transfer = Transfer {
+//!     data: &data,
+//!     start_address: 0x57F00,
+//!     expect_ack: true,
+//! };
+//! let plan = FlashPlan::new(&layout, &[transfer])?;
+//! assert_eq!(plan.erase_ranges()[0], 0x56000..0x58000);
+//! assert_eq!(plan.ccfg()[0].start_address, 0x57FA8);
+//! # Ok::<(), ti_sbl::Error>(())
+//! ```
+
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::Range,
+};
+
+use crate::{firmware::Segment, util::Transfer, Error, FlashLayout, Result};
+
+/// What flashing a list of transfers does, see the
+/// [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct FlashPlan<'a> {
+    erase: Vec<Range<u32>>,
+    transfers: Vec<Transfer<'a>>,
+    ccfg: Vec<Transfer<'a>>,
+    cca: Vec<Transfer<'a>>,
+}
+
+impl<'a> FlashPlan<'a> {
+    /// Plan the write of the `transfers` on a device with the flash
+    /// `layout`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::AddressOutOfRange`] if a transfer is outside of the
+    /// flash.
+    pub fn new(
+        layout: &FlashLayout,
+        transfers: &[Transfer<'a>],
+    ) -> Result<FlashPlan<'a>> {
+        let mut plan = FlashPlan {
+            erase: Vec::new(),
+            transfers: Vec::with_capacity(transfers.len() + 1),
+            ccfg: Vec::new(),
+            cca: Vec::new(),
+        };
+
+        for transfer in transfers.iter().filter(|t| !t.data.is_empty()) {
+            let range = transfer_range(transfer);
+            if !layout.contains_range(&range) {
+                return Err(Error::AddressOutOfRange {
+                    address: transfer.start_address,
+                    len: transfer.data.len() as u32,
+                });
+            }
+
+            if let Some(cca) = layout.cca() {
+                if let Some(piece) = piece(transfer, &cca) {
+                    plan.cca.push(piece);
+                }
+            }
+
+            let ccfg_offset = layout
+                .ccfg_offset(&range)
+                .map_or(transfer.data.len(), |offset| offset as usize);
+            if ccfg_offset != 0 {
+                let transfer = Transfer {
+                    data: &transfer.data[..ccfg_offset],
+                    ..*transfer
+                };
+                plan.erase.push(sectors(layout, &transfer_range(&transfer)));
+                plan.transfers.push(transfer);
+            }
+            // Flash can only be programmed from 1s to 0s, the sector of the
+            // CCFG is erased as well or the new CCFG would be ANDed with the
+            // old one.
+            if ccfg_offset != transfer.data.len() {
+                let ccfg = Transfer {
+                    data: &transfer.data[ccfg_offset..],
+                    start_address: range.start + ccfg_offset as u32,
+                    expect_ack: false,
+                };
+                plan.erase.push(sectors(layout, &transfer_range(&ccfg)));
+                plan.ccfg.push(ccfg);
+            }
+        }
+
+        plan.erase = merge(plan.erase);
+        plan.transfers.extend_from_slice(&plan.ccfg);
+
+        Ok(plan)
+    }
+
+    /// Plan the write of the `segments`, e.g.: the ones of a
+    /// [`Firmware`](crate::firmware::Firmware).
+    pub fn from_segments(
+        layout: &FlashLayout,
+        segments: &'a [Segment],
+    ) -> Result<FlashPlan<'a>> {
+        let transfers =
+            segments.iter().map(Segment::transfer).collect::<Vec<_>>();
+        FlashPlan::new(layout, &transfers)
+    }
+
+    /// The sector aligned ranges to erase before writing the transfers,
+    /// sorted by address.
+    pub fn erase_ranges(&self) -> &[Range<u32>] {
+        &self.erase
+    }
+
+    /// The transfers to write, the writes on the CCFG are last and don't
+    /// expect an ACK.
+    pub fn transfers(&self) -> &[Transfer<'a>] {
+        &self.transfers
+    }
+
+    /// The writes on the CCFG, empty if the transfers don't overlap it.
+    pub fn ccfg(&self) -> &[Transfer<'a>] {
+        &self.ccfg
+    }
+
+    /// The writes on the CCA of the [`Family::CC2538`](crate::Family), empty
+    /// if the transfers don't overlap it.
+    pub fn cca(&self) -> &[Transfer<'a>] {
+        &self.cca
+    }
+
+    /// The ranges erased but not written, their current contents are lost,
+    /// e.g.: the start of a sector when a transfer doesn't start at its
+    /// beginning.
+    pub fn erased_unwritten(&self) -> Vec<Range<u32>> {
+        let mut written = self.transfers.iter().map(transfer_range).collect();
+        written = merge(written);
+
+        let mut unwritten = Vec::new();
+        for erase in &self.erase {
+            let mut start = erase.start;
+            for write in &written {
+                if write.end <= start || write.start >= erase.end {
+                    continue;
+                }
+                if write.start > start {
+                    unwritten.push(start..write.start);
+                }
+                start = write.end;
+            }
+            if start < erase.end {
+                unwritten.push(start..erase.end);
+            }
+        }
+
+        unwritten
+    }
+}
+
+impl Display for FlashPlan<'_> {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "erase")?;
+        if self.erase.is_empty() {
+            write!(fmt, " nothing")?;
+        }
+        for (i, range) in self.erase.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(fmt, "{}{:#X}..{:#X}", separator, range.start, range.end)?;
+        }
+
+        write!(fmt, "; write")?;
+        if self.transfers.is_empty() {
+            write!(fmt, " nothing")?;
+        }
+        for (i, transfer) in self.transfers.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            let range = transfer_range(transfer);
+            write!(fmt, "{}{:#X}..{:#X}", separator, range.start, range.end)?;
+            if !transfer.expect_ack {
+                write!(fmt, " (CCFG)")?;
+            }
+        }
+        if !self.cca.is_empty() {
+            write!(fmt, "; overwrites the CCA")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Overwrite the bytes of the `segments` on the CCFG with the `ccfg`, e.g.:
+/// the one read from the device to keep it. Returns whether any byte was
+/// overwritten.
+pub fn replace_ccfg(
+    layout: &FlashLayout,
+    segments: &mut [Segment],
+    ccfg: &[u8],
+) -> bool {
+    let area = match layout.ccfg() {
+        Some(area) => area,
+        None => return false,
+    };
+
+    let mut replaced = false;
+    for segment in segments {
+        let range = segment.range();
+        let start = range.start.max(area.start);
+        let end = range.end.min(area.end).min(area.start + ccfg.len() as u32);
+        if start < end {
+            let offset = (start - range.start) as usize;
+            let len = (end - start) as usize;
+            let from = (start - area.start) as usize;
+            segment.data[offset..offset + len]
+                .copy_from_slice(&ccfg[from..from + len]);
+            replaced = true;
+        }
+    }
+
+    replaced
+}
+
+fn transfer_range(transfer: &Transfer<'_>) -> Range<u32> {
+    transfer.start_address..transfer.start_address + transfer.data.len() as u32
+}
+
+/// The part of the `transfer` written on the `area`.
+fn piece<'a>(
+    transfer: &Transfer<'a>,
+    area: &Range<u32>,
+) -> Option<Transfer<'a>> {
+    let range = transfer_range(transfer);
+    let start = range.start.max(area.start);
+    let end = range.end.min(area.end);
+    if start >= end {
+        return None;
+    }
+
+    let offset = (start - range.start) as usize;
+    Some(Transfer {
+        data: &transfer.data[offset..offset + (end - start) as usize],
+        start_address: start,
+        expect_ack: transfer.expect_ack,
+    })
+}
+
+/// The sectors of the `range`, it's on the main flash or on the CCFG.
+fn sectors(layout: &FlashLayout, range: &Range<u32>) -> Range<u32> {
+    let flash = layout.flash();
+    let area = match layout.ccfg() {
+        Some(ccfg) if range.end > flash.end => ccfg,
+        _ => flash.clone(),
+    };
+    let sector_size = layout.sector_size();
+    let start = range.start - (range.start - flash.start) % sector_size;
+    let end = match (range.end - flash.start) % sector_size {
+        0 => range.end,
+        rem => range.end + (sector_size - rem),
+    };
+
+    start..end.min(area.end)
+}
+
+/// Sort the `ranges` and merge the ones overlapping or next to each other.
+fn merge(mut ranges: Vec<Range<u32>>) -> Vec<Range<u32>> {
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<u32>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => {
+                last.end = last.end.max(range.end)
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Family;
+
+    fn transfer(start_address: u32, data: &[u8]) -> Transfer<'_> {
+        Transfer {
+            data,
+            start_address,
+            expect_ack: true,
+        }
+    }
+
+    #[test]
+    fn test_flash_plan() {
+        let layout = FlashLayout::new(Family::CC26X2, 0x58000);
+        let data = [0u8; 0x3000];
+
+        let plan = FlashPlan::new(
+            &layout,
+            &[transfer(0x100, &data[..0x100]), transfer(0x1F00, &data)],
+        )
+        .unwrap();
+        assert_eq!(plan.erase_ranges().len(), 1);
+        assert_eq!(plan.erase_ranges()[0], 0x0..0x6000);
+        assert_eq!(plan.transfers().len(), 2);
+        assert!(plan.ccfg().is_empty());
+        assert_eq!(
+            plan.erased_unwritten(),
+            [0x0..0x100, 0x200..0x1F00, 0x4F00..0x6000]
+        );
+
+        let plan =
+            FlashPlan::new(&layout, &[transfer(0x56000, &data[..0x2000])])
+                .unwrap();
+        assert_eq!(plan.erase_ranges()[0], 0x56000..0x58000);
+        let transfers = plan.transfers();
+        assert_eq!(transfers[0].data.len(), 0x1FA8);
+        assert_eq!(transfers[1].start_address, 0x57FA8);
+        assert!(!transfers[1].expect_ack);
+        assert_eq!(plan.ccfg().len(), 1);
+        assert!(plan.erased_unwritten().is_empty());
+
+        let plan = FlashPlan::new(&layout, &[transfer(0x57FA8, &data[..0x58])])
+            .unwrap();
+        assert_eq!(plan.erase_ranges()[0], 0x56000..0x58000);
+        assert_eq!(plan.transfers().len(), 1);
+        assert_eq!(plan.ccfg()[0].start_address, 0x57FA8);
+        assert_eq!(plan.erased_unwritten()[0], 0x56000..0x57FA8);
+
+        assert!(matches!(
+            FlashPlan::new(&layout, &[transfer(0x57F00, &data[..0x200])]),
+            Err(Error::AddressOutOfRange { .. })
+        ));
+
+        let layout = FlashLayout::new(Family::CC2538, 0x80000);
+        let plan =
+            FlashPlan::new(&layout, &[transfer(0x0027F800, &data[..0x800])])
+                .unwrap();
+        assert_eq!(plan.cca()[0].start_address, 0x0027FFD4);
+        assert_eq!(plan.cca()[0].data.len(), 0x2C);
+
+        let layout = FlashLayout::new(Family::CC26X4, 0x100000);
+        let plan =
+            FlashPlan::new(&layout, &[transfer(0x4E020000, &data[..0x800])])
+                .unwrap();
+        assert_eq!(plan.erase_ranges()[0], 0x4E020000..0x4E020800);
+    }
+}
@@ -117,11 +117,9 @@ pub fn list_all() -> Vec<PortInfo> {
     ports.extend(glob("/dev/ttyAP*")); // Advantech multi-port serial controllers
 
     let mut available = Vec::new();
-    for port in ports {
-        if let Ok(ref port) = port {
-            if let Ok(Some(info)) = port_info(port) {
-                available.push(info);
-            }
+    for port in ports.into_iter().flatten() {
+        if let Ok(Some(info)) = port_info(port) {
+            available.push(info);
         }
     }
 
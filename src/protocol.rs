@@ -0,0 +1,219 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Based on the previous work of cc2538-bsl and Texas Instruments sblAppEx
+// 1.03.00.00 (swra466c.zip).
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Framing of the Serial Bootloader Interface packets.
+//!
+//! This module doesn't perform any I/O, [`Device`](crate::Device) uses it to
+//! build the packets sent to the bootloader and to parse the received ones.
+//! Alternative transports can use it to speak the exact same protocol.
+
+use crate::{constants, AckResponse, Error, Result};
+
+/// Length of the header of a command packet: `[len | checksum | cmd]`.
+pub const COMMAND_HEADER_LEN: usize = 3;
+
+/// Length of the header of a response packet: `[len | checksum]`.
+pub const RESPONSE_HEADER_LEN: usize = 2;
+
+/// Maximum length of the data of a command packet.
+pub const MAX_COMMAND_DATA_LEN: usize = u8::MAX as usize - COMMAND_HEADER_LEN;
+
+/// Checksum of a command packet.
+pub fn command_checksum(cmd: u8, data: &[u8]) -> u8 {
+    cmd.wrapping_add(response_checksum(data))
+}
+
+/// Checksum of a response packet.
+pub fn response_checksum(data: &[u8]) -> u8 {
+    let mut checksum: u8 = 0;
+    for byte in data {
+        checksum = checksum.overflowing_add(*byte).0;
+    }
+
+    checksum
+}
+
+/// Build the packet of the command `cmd` with `data`.
+///
+/// # Errors
+///
+/// Returns [`Error::DataTooLarge`] if the `data` length is higher than
+/// [`MAX_COMMAND_DATA_LEN`].
+pub fn command_packet(cmd: u8, data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() > MAX_COMMAND_DATA_LEN {
+        return Err(Error::DataTooLarge {
+            max: MAX_COMMAND_DATA_LEN,
+            found: data.len(),
+        });
+    }
+
+    let pkt_len = COMMAND_HEADER_LEN + data.len();
+    let mut pkt = Vec::with_capacity(pkt_len);
+
+    pkt.push(pkt_len as u8);
+    pkt.push(command_checksum(cmd, data));
+    pkt.push(cmd);
+    pkt.extend_from_slice(data);
+
+    Ok(pkt)
+}
+
+/// The bytes sent to acknowledge (or not) a response packet.
+pub fn ack_packet(ack: bool) -> [u8; 2] {
+    [0x00, if ack { constants::ACK } else { constants::NACK }]
+}
+
+/// Header of a response packet.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ResponseHeader {
+    /// Length of the payload following the header.
+    pub payload_len: usize,
+    /// Checksum of the payload.
+    pub checksum: u8,
+}
+
+impl ResponseHeader {
+    /// Parse the `[len | checksum]` header of a response packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidResponse`] if the length doesn't include the
+    /// header itself.
+    pub fn parse(hdr: [u8; RESPONSE_HEADER_LEN]) -> Result<Self> {
+        let len = usize::from(hdr[0]);
+        if len < RESPONSE_HEADER_LEN {
+            return Err(Error::InvalidResponse);
+        }
+
+        Ok(ResponseHeader {
+            payload_len: len - RESPONSE_HEADER_LEN,
+            checksum: hdr[1],
+        })
+    }
+
+    /// Whether the `payload` matches the checksum of the header.
+    pub fn verify(&self, payload: &[u8]) -> bool {
+        self.checksum == response_checksum(payload)
+    }
+}
+
+/// Looks for the ACK/NACK bytes in the received bytes.
+///
+/// The bootloader may send other bytes (e.g.: zeros) before the ACK, these
+/// are counted as garbage.
+#[derive(Debug, Clone, Default)]
+pub struct AckScanner {
+    last: Option<u8>,
+    received: usize,
+}
+
+impl AckScanner {
+    /// Create a new scanner.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a received byte, returns [`AckResponse::Ack`] or
+    /// [`AckResponse::Nack`] once found.
+    pub fn push(&mut self, byte: u8) -> Option<AckResponse> {
+        let prev = self.last.replace(byte);
+        self.received += 1;
+
+        match (prev, byte) {
+            (Some(0x00), constants::ACK) => Some(AckResponse::Ack),
+            (Some(0x00), constants::NACK) => Some(AckResponse::Nack),
+            _ => None,
+        }
+    }
+
+    /// Number of bytes received that aren't part of the ACK.
+    ///
+    /// If the ACK was found, the two ACK bytes are excluded.
+    pub fn garbage_bytes(&self, found: bool) -> usize {
+        if found {
+            self.received - 2
+        } else {
+            self.received
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_command_checksum() {
+        // nonsensical data, just to make sure it works.
+        const DATA: &[u8] = &[0xde, 0xad, 0xbe, 0xef];
+        assert_eq!(command_checksum(0xCA, DATA), 0x02);
+    }
+
+    #[test]
+    fn test_response_checksum() {
+        const DATA: &[u8] = &[0xde, 0xad, 0xbe, 0xef];
+        assert_eq!(response_checksum(DATA), 0x38);
+        assert_eq!(response_checksum(&[]), 0x00);
+    }
+
+    #[test]
+    fn test_command_packet() {
+        assert_eq!(command_packet(0x20, &[]).unwrap(), [0x03, 0x20, 0x20]);
+        assert_eq!(
+            command_packet(0x24, &[0x01, 0x02]).unwrap(),
+            [0x05, 0x27, 0x24, 0x01, 0x02]
+        );
+
+        for len in 0..=MAX_COMMAND_DATA_LEN {
+            let data = vec![0xA5; len];
+            let pkt = command_packet(0x24, &data).unwrap();
+            assert_eq!(usize::from(pkt[0]), pkt.len());
+            assert_eq!(pkt[1], command_checksum(pkt[2], &pkt[3..]));
+        }
+        assert!(command_packet(0x24, &[0; MAX_COMMAND_DATA_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn test_response_header() {
+        let hdr = ResponseHeader::parse([0x06, 0x38]).unwrap();
+        assert_eq!(hdr.payload_len, 4);
+        assert!(hdr.verify(&[0xde, 0xad, 0xbe, 0xef]));
+        assert!(!hdr.verify(&[0xde, 0xad, 0xbe, 0xee]));
+
+        assert!(ResponseHeader::parse([0x02, 0x00]).is_ok());
+        assert!(ResponseHeader::parse([0x01, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_ack_scanner() {
+        let mut scanner = AckScanner::new();
+        assert_eq!(scanner.push(0xCC), None);
+        assert_eq!(scanner.push(0x00), None);
+        assert_eq!(scanner.push(0x00), None);
+        assert_eq!(scanner.push(0xCC), Some(AckResponse::Ack));
+        assert_eq!(scanner.garbage_bytes(true), 2);
+
+        let mut scanner = AckScanner::new();
+        assert_eq!(scanner.push(0x00), None);
+        assert_eq!(scanner.push(0x33), Some(AckResponse::Nack));
+        assert_eq!(scanner.garbage_bytes(true), 0);
+
+        let mut scanner = AckScanner::new();
+        assert_eq!(scanner.push(0x55), None);
+        assert_eq!(scanner.garbage_bytes(false), 1);
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::convert::TryFrom;
+
+use serial::SerialPort;
+
+use crate::{
+    constants::MAX_BYTES_PER_TRANSFER, AckResponse, CommandId, Device, Error,
+    Result, StatusCode,
+};
+
+/// A flash download started by [`Device::download_session`] or
+/// [`Device::download_crc32_session`].
+///
+/// [`DownloadSession::send_data`] is only available while the session is
+/// alive, so data can't be sent without a previous `download` command. The
+/// session borrows the device mutably, no other commands can be interleaved.
+///
+/// # Example
+///
+/// ```no_run
+/// use ti_sbl::{Device, Family};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let port = serial::open("/dev/ttyUSB0")?;
+/// let mut device = Device::new(port, Family::CC26X2)?;
+///
+/// let image = [0u8; 1024];
+/// let mut session = device.download_session(0x0000_0000, 1024)?;
+/// for chunk in image.chunks(ti_sbl::constants::MAX_BYTES_PER_TRANSFER) {
+///     session.send_data(chunk)?;
+/// }
+/// session.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+#[must_use = "the download must be finished to check its status"]
+pub struct DownloadSession<'a, P>
+where
+    P: SerialPort,
+{
+    device: &'a mut Device<P>,
+    remaining: usize,
+}
+
+impl<'a, P> DownloadSession<'a, P>
+where
+    P: SerialPort,
+{
+    /// Issue the `download` command and verify its status.
+    pub(crate) fn start(
+        device: &'a mut Device<P>,
+        program_address: u32,
+        program_size: u32,
+    ) -> Result<Self> {
+        device.download(program_address, program_size)?;
+        DownloadSession::started(device, program_size)
+    }
+
+    /// Issue the `download_crc32` command and verify its status.
+    pub(crate) fn start_crc32(
+        device: &'a mut Device<P>,
+        program_address: u32,
+        program_size: u32,
+        crc32: u32,
+    ) -> Result<Self> {
+        device.download_crc32(program_address, program_size, crc32)?;
+        DownloadSession::started(device, program_size)
+    }
+
+    fn started(device: &'a mut Device<P>, program_size: u32) -> Result<Self> {
+        check_status(device)?;
+
+        Ok(DownloadSession {
+            device,
+            remaining: usize::try_from(program_size).unwrap(),
+        })
+    }
+
+    /// Number of bytes left to complete the download.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Send the next chunk of data.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::DataTooLarge`] if the `data` length is higher than
+    ///   [`MAX_BYTES_PER_TRANSFER`] or than the remaining bytes of the
+    ///   download.
+    /// - [`Error::Nack`] if the chunk wasn't acknowledged, the chunk can be
+    ///   sent again.
+    /// - [`Error::Timeout`] if the device didn't respond.
+    pub fn send_data<D>(&mut self, data: &D) -> Result<()>
+    where
+        D: AsRef<[u8]> + ?Sized,
+    {
+        let data = data.as_ref();
+        let max = MAX_BYTES_PER_TRANSFER.min(self.remaining);
+        if data.len() > max {
+            return Err(Error::DataTooLarge {
+                max,
+                found: data.len(),
+            });
+        }
+
+        match self.device.send_data(&data)? {
+            AckResponse::Ack => {
+                self.remaining -= data.len();
+                Ok(())
+            }
+            AckResponse::Nack => Err(Error::Nack(CommandId::SendData)),
+            AckResponse::Timeout { .. } => Err(Error::Timeout),
+        }
+    }
+
+    /// Finish the download, checking the status of the last command.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::InvalidLength`] with the number of remaining bytes if the
+    ///   download isn't complete.
+    /// - [`Error::Status`] if the bootloader reported an error.
+    pub fn finish(self) -> Result<()> {
+        if self.remaining != 0 {
+            return Err(Error::InvalidLength(self.remaining));
+        }
+
+        check_status(self.device)
+    }
+}
+
+fn check_status<P>(device: &mut Device<P>) -> Result<()>
+where
+    P: SerialPort,
+{
+    match device.get_status()? {
+        StatusCode::Success => Ok(()),
+        status => Err(Error::Status(status)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{trace::Capture, Family};
+
+    /// A `COMMAND_GET_STATUS` answered with `status`.
+    fn status(capture: Capture, status: StatusCode) -> Capture {
+        capture
+            .command(CommandId::GetStatus, &[])
+            .response(&[status.value()])
+    }
+
+    #[test]
+    fn test_download_session() {
+        let capture = Capture::new()
+            .download(0x1000, 8)
+            .command(CommandId::SendData, &[1, 2, 3, 4])
+            .command(CommandId::SendData, &[5, 6, 7, 8]);
+        let capture = status(capture, StatusCode::FlashFail);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+
+        let mut session = device.download_session(0x1000, 8).unwrap();
+        assert!(matches!(
+            session.send_data(&[0; 9]),
+            Err(Error::DataTooLarge { max: 8, found: 9 })
+        ));
+        session.send_data(&[1, 2, 3, 4]).unwrap();
+        assert!(matches!(
+            session.send_data(&[0; 5]),
+            Err(Error::DataTooLarge { max: 4, found: 5 })
+        ));
+        session.send_data(&[5, 6, 7, 8]).unwrap();
+        assert_eq!(session.remaining(), 0);
+        assert!(matches!(
+            session.finish(),
+            Err(Error::Status(StatusCode::FlashFail))
+        ));
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_download_session_incomplete() {
+        let capture = Capture::new()
+            .download(0x1000, 8)
+            .command(CommandId::SendData, &[1, 2, 3, 4]);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+
+        let mut session = device.download_session(0x1000, 8).unwrap();
+        session.send_data(&[1, 2, 3, 4]).unwrap();
+        assert!(matches!(session.finish(), Err(Error::InvalidLength(4))));
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_download_session_rejected() {
+        let mut cmd = 0x1000u32.to_be_bytes().to_vec();
+        cmd.extend_from_slice(&8u32.to_be_bytes());
+        let capture = Capture::new().command(CommandId::Download, &cmd);
+        let capture = status(capture, StatusCode::InvalidAdr);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+
+        assert!(matches!(
+            device.download_session(0x1000, 8),
+            Err(Error::Status(StatusCode::InvalidAdr))
+        ));
+        assert!(device.port().is_finished());
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Based on the previous work of cc2538-bsl and Texas Instruments sblAppEx
+// 1.03.00.00 (swra466c.zip).
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::constants::{
+    COMMAND_RET_FLASH_FAIL, COMMAND_RET_INVALID_ADR, COMMAND_RET_INVALID_CMD,
+    COMMAND_RET_SUCCESS, COMMAND_RET_UNKNOWN_CMD,
+};
+
+/// Status of the last command, as returned by
+/// [`Device::get_status`](crate::Device::get_status).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StatusCode {
+    /// The command was executed successfully.
+    Success,
+    /// The command is unknown.
+    UnknownCmd,
+    /// The command has invalid parameters.
+    InvalidCmd,
+    /// An invalid address was used.
+    InvalidAdr,
+    /// The flash operation failed.
+    FlashFail,
+}
+
+impl StatusCode {
+    /// The raw value of the status code.
+    #[inline]
+    pub fn value(&self) -> u8 {
+        match *self {
+            StatusCode::Success => COMMAND_RET_SUCCESS,
+            StatusCode::UnknownCmd => COMMAND_RET_UNKNOWN_CMD,
+            StatusCode::InvalidCmd => COMMAND_RET_INVALID_CMD,
+            StatusCode::InvalidAdr => COMMAND_RET_INVALID_ADR,
+            StatusCode::FlashFail => COMMAND_RET_FLASH_FAIL,
+        }
+    }
+
+    /// The name of the status code, as used on the TI documentation.
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            StatusCode::Success => "COMMAND_RET_SUCCESS",
+            StatusCode::UnknownCmd => "COMMAND_RET_UNKNOWN_CMD",
+            StatusCode::InvalidCmd => "COMMAND_RET_INVALID_CMD",
+            StatusCode::InvalidAdr => "COMMAND_RET_INVALID_ADR",
+            StatusCode::FlashFail => "COMMAND_RET_FLASH_FAIL",
+        }
+    }
+}
+
+impl Display for StatusCode {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{} ({:#X})", self.as_str(), self.value())
+    }
+}
+
+/// The value is not a known status code.
+#[derive(Debug)]
+pub struct UnknownStatusCode(pub u8);
+
+impl Display for UnknownStatusCode {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "unknown status code {:#X}", self.0)
+    }
+}
+
+impl Error for UnknownStatusCode {}
+
+impl TryFrom<u8> for StatusCode {
+    type Error = UnknownStatusCode;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            COMMAND_RET_SUCCESS => Ok(StatusCode::Success),
+            COMMAND_RET_UNKNOWN_CMD => Ok(StatusCode::UnknownCmd),
+            COMMAND_RET_INVALID_CMD => Ok(StatusCode::InvalidCmd),
+            COMMAND_RET_INVALID_ADR => Ok(StatusCode::InvalidAdr),
+            COMMAND_RET_FLASH_FAIL => Ok(StatusCode::FlashFail),
+            v => Err(UnknownStatusCode(v)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_code_roundtrip() {
+        for value in 0..=u8::MAX {
+            if let Ok(status) = StatusCode::try_from(value) {
+                assert_eq!(status.value(), value);
+            }
+        }
+
+        assert_eq!(StatusCode::try_from(0x44).unwrap(), StatusCode::FlashFail);
+        assert!(StatusCode::try_from(0x00).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use crate::CommandId;
+
+/// Time to wait for an ACK from the bootloader, per class of command.
+///
+/// See [`Device::set_ack_timeouts`](crate::Device::set_ack_timeouts).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AckTimeouts {
+    /// Erase commands: [`CommandId::Erase`], [`CommandId::SectorErase`] and
+    /// [`CommandId::BankErase`].
+    pub erase: Duration,
+    /// Commands writing to the device: [`CommandId::Download`],
+    /// [`CommandId::DownloadCrc`], [`CommandId::SendData`],
+    /// [`CommandId::MemoryWrite`] and [`CommandId::SetCcfg`].
+    pub write: Duration,
+    /// Commands reading from the device: [`CommandId::MemoryRead`],
+    /// [`CommandId::Crc32`], [`CommandId::GetChipId`] and
+    /// [`CommandId::GetStatus`].
+    pub read: Duration,
+    /// All other commands, including the synchronization with the
+    /// bootloader.
+    pub misc: Duration,
+}
+
+impl AckTimeouts {
+    /// The timeout used for the `cmd`.
+    pub fn for_command(&self, cmd: CommandId) -> Duration {
+        match cmd {
+            CommandId::Erase
+            | CommandId::SectorErase
+            | CommandId::BankErase => self.erase,
+            CommandId::Download
+            | CommandId::DownloadCrc
+            | CommandId::SendData
+            | CommandId::MemoryWrite
+            | CommandId::SetCcfg => self.write,
+            CommandId::MemoryRead
+            | CommandId::Crc32
+            | CommandId::GetChipId
+            | CommandId::GetStatus => self.read,
+            CommandId::Ping
+            | CommandId::Run
+            | CommandId::Reset
+            | CommandId::SetXosc => self.misc,
+        }
+    }
+}
+
+impl Default for AckTimeouts {
+    /// One second for all commands except erase commands, erasing the
+    /// whole flash takes noticeably longer than other commands.
+    fn default() -> Self {
+        AckTimeouts {
+            erase: Duration::from_secs(10),
+            write: Duration::from_secs(1),
+            read: Duration::from_secs(1),
+            misc: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Synchronization attempts with the bootloader.
+///
+/// Some boards need several tries right after being reset into the
+/// bootloader. See [`Device::set_sync_retry`](crate::Device::set_sync_retry).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SyncRetry {
+    /// Number of synchronization attempts, at least one attempt is always
+    /// made.
+    pub attempts: usize,
+    /// Time to wait between attempts.
+    pub delay: Duration,
+}
+
+impl Default for SyncRetry {
+    /// A single attempt.
+    fn default() -> Self {
+        SyncRetry {
+            attempts: 1,
+            delay: Duration::from_millis(100),
+        }
+    }
+}
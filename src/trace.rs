@@ -0,0 +1,566 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capture of the raw traffic with the bootloader.
+//!
+//! See [`Device::set_trace_sink`](crate::Device::set_trace_sink). Every
+//! transfer is recorded as a line with the format:
+//!
+//! ```text
+//! <seconds> <direction> <bytes>
+//! ```
+//!
+//! - `seconds`: time elapsed since the sink was set, with microsecond
+//!   resolution.
+//! - `direction`: `TX` for the bytes sent to the device, `RX` for the bytes
+//!   received from the device.
+//! - `bytes`: the bytes in hexadecimal, separated by spaces.
+//!
+//! For example, a ping and its ACK:
+//!
+//! ```text
+//! 0.000012 TX 03 20 20
+//! 0.000873 RX 00
+//! 0.000901 RX CC
+//! ```
+//!
+//! A capture can be fed back into a [`Device`](crate::Device) with a
+//! [`ReplayPort`], to reproduce a session deterministically.
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    io::{self, BufRead, Read, Write},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+#[cfg(test)]
+use crate::{protocol, CommandId, StatusCode};
+
+/// Direction of the traffic.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Direction {
+    /// Bytes sent to the device.
+    Tx,
+    /// Bytes received from the device.
+    Rx,
+}
+
+impl Direction {
+    /// The name of the direction, as used on the capture.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Direction::Tx => "TX",
+            Direction::Rx => "RX",
+        }
+    }
+}
+
+impl Display for Direction {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Direction {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "TX" => Ok(Direction::Tx),
+            "RX" => Ok(Direction::Rx),
+            _ => Err(invalid_data(format!("invalid direction `{}`", s))),
+        }
+    }
+}
+
+/// A line of a capture.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Record {
+    /// Time elapsed since the start of the capture.
+    pub time: Duration,
+    /// Direction of the traffic.
+    pub direction: Direction,
+    /// The transferred bytes.
+    pub bytes: Vec<u8>,
+}
+
+impl FromStr for Record {
+    type Err = io::Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut fields = line.split_whitespace();
+
+        let time = fields
+            .next()
+            .ok_or_else(|| invalid_data("missing time".into()))?;
+        let time = parse_time(time)?;
+
+        let direction = fields
+            .next()
+            .ok_or_else(|| invalid_data("missing direction".into()))?
+            .parse()?;
+
+        let bytes = fields
+            .map(|b| {
+                u8::from_str_radix(b, 16)
+                    .map_err(|_| invalid_data(format!("invalid byte `{}`", b)))
+            })
+            .collect::<io::Result<Vec<u8>>>()?;
+
+        Ok(Record {
+            time,
+            direction,
+            bytes,
+        })
+    }
+}
+
+fn parse_time(s: &str) -> io::Result<Duration> {
+    let invalid = || invalid_data(format!("invalid time `{}`", s));
+
+    let mut parts = s.splitn(2, '.');
+    let secs = parts
+        .next()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .ok_or_else(invalid)?;
+    let micros = match parts.next() {
+        Some(micros) if micros.len() == 6 => {
+            micros.parse::<u32>().map_err(|_| invalid())?
+        }
+        Some(_) => return Err(invalid()),
+        None => 0,
+    };
+
+    Ok(Duration::new(secs, micros * 1000))
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Read a capture, empty lines are ignored.
+pub fn read_capture<R>(reader: R) -> io::Result<Vec<Record>>
+where
+    R: BufRead,
+{
+    let mut records = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = line
+            .parse::<Record>()
+            .map_err(|e| invalid_data(format!("line {}: {}", i + 1, e)))?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// A port replaying a capture.
+///
+/// The bytes written to the port must match the `TX` records of the capture,
+/// otherwise the write fails with [`io::ErrorKind::InvalidData`]. The bytes
+/// of the `RX` records are only available for reading once all the
+/// preceding `TX` records were written, reading at any other moment times
+/// out as a real port would do.
+///
+/// The timestamps of the capture are ignored.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::{fs::File, io::BufReader};
+///
+/// use ti_sbl::{trace, Device, Family};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let file = BufReader::new(File::open("session.trace")?);
+/// let port = trace::ReplayPort::new(trace::read_capture(file)?);
+///
+/// let mut device = Device::new(port, Family::CC26X2)?;
+/// assert!(device.ping()?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ReplayPort {
+    records: VecDeque<(Direction, Vec<u8>)>,
+    timeout: Duration,
+}
+
+impl ReplayPort {
+    /// Create a port replaying the `records`.
+    pub fn new<I>(records: I) -> Self
+    where
+        I: IntoIterator<Item = Record>,
+    {
+        ReplayPort {
+            records: records
+                .into_iter()
+                .filter(|r| !r.bytes.is_empty())
+                .map(|r| (r.direction, r.bytes))
+                .collect(),
+            timeout: Duration::from_millis(0),
+        }
+    }
+
+    /// Whether the whole capture was replayed.
+    pub fn is_finished(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl Read for ReplayPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (direction, bytes) = match self.records.front_mut() {
+            Some(record) => record,
+            None => return Err(io::ErrorKind::TimedOut.into()),
+        };
+        if *direction != Direction::Rx {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        let n = buf.len().min(bytes.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+        bytes.drain(..n);
+        if bytes.is_empty() {
+            self.records.pop_front();
+        }
+
+        Ok(n)
+    }
+}
+
+impl Write for ReplayPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (direction, bytes) = match self.records.front_mut() {
+            Some(record) => record,
+            None => {
+                return Err(invalid_data(format!(
+                    "unexpected write {:02X?}, end of capture",
+                    buf
+                )))
+            }
+        };
+        if *direction != Direction::Tx {
+            return Err(invalid_data(format!(
+                "unexpected write {:02X?}, expecting to receive {:02X?}",
+                buf, bytes
+            )));
+        }
+
+        let n = buf.len().min(bytes.len());
+        if buf[..n] != bytes[..n] {
+            return Err(invalid_data(format!(
+                "unexpected write {:02X?}, expecting {:02X?}",
+                &buf[..n],
+                &bytes[..n]
+            )));
+        }
+
+        bytes.drain(..n);
+        if bytes.is_empty() {
+            self.records.pop_front();
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl serial::SerialPort for ReplayPort {
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> serial::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn configure(
+        &mut self,
+        _settings: &serial::PortSettings,
+    ) -> serial::Result<()> {
+        Ok(())
+    }
+
+    fn reconfigure(
+        &mut self,
+        _setup: &dyn Fn(
+            &mut dyn serial::SerialPortSettings,
+        ) -> serial::Result<()>,
+    ) -> serial::Result<()> {
+        Ok(())
+    }
+
+    fn set_rts(&mut self, _level: bool) -> serial::Result<()> {
+        Ok(())
+    }
+
+    fn set_dtr(&mut self, _level: bool) -> serial::Result<()> {
+        Ok(())
+    }
+
+    fn read_cts(&mut self) -> serial::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_dsr(&mut self) -> serial::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_ri(&mut self) -> serial::Result<bool> {
+        Ok(false)
+    }
+
+    fn read_cd(&mut self) -> serial::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Writes the traffic to the user provided writer.
+pub(crate) struct TraceSink {
+    writer: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+impl TraceSink {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        TraceSink {
+            writer,
+            start: Instant::now(),
+        }
+    }
+
+    /// Record the `bytes` transferred in `direction`.
+    ///
+    /// Failing to write the capture isn't fatal for the communication with
+    /// the device, errors are only logged.
+    pub(crate) fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        if let Err(e) = self.write_line(direction, bytes) {
+            log::warn!("couldn't write trace: {}", e);
+        }
+    }
+
+    fn write_line(
+        &mut self,
+        direction: Direction,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        let elapsed = self.start.elapsed();
+        write!(
+            self.writer,
+            "{}.{:06} {}",
+            elapsed.as_secs(),
+            elapsed.subsec_micros(),
+            direction
+        )?;
+        for byte in bytes {
+            write!(self.writer, " {:02X}", byte)?;
+        }
+        writeln!(self.writer)
+    }
+
+    /// Flush and return the writer.
+    pub(crate) fn into_inner(mut self) -> Box<dyn Write + Send> {
+        if let Err(e) = self.writer.flush() {
+            log::warn!("couldn't flush trace: {}", e);
+        }
+
+        self.writer
+    }
+}
+
+/// Builds the capture of a session with the device, to test the library
+/// with a [`ReplayPort`].
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub(crate) struct Capture {
+    records: Vec<Record>,
+}
+
+#[cfg(test)]
+impl Capture {
+    /// A session starting with the synchronization of
+    /// [`Device::new`](crate::Device::new).
+    pub(crate) fn new() -> Capture {
+        Capture::default().tx(&[0x03, 0x00, 0x00]).rx(&[0x00, 0x33])
+    }
+
+    pub(crate) fn tx(self, bytes: &[u8]) -> Capture {
+        self.record(Direction::Tx, bytes)
+    }
+
+    pub(crate) fn rx(self, bytes: &[u8]) -> Capture {
+        self.record(Direction::Rx, bytes)
+    }
+
+    fn record(mut self, direction: Direction, bytes: &[u8]) -> Capture {
+        self.records.push(Record {
+            time: Duration::default(),
+            direction,
+            bytes: bytes.to_vec(),
+        });
+        self
+    }
+
+    /// The `cmd` with its `data`, acknowledged by the device.
+    pub(crate) fn command(self, cmd: CommandId, data: &[u8]) -> Capture {
+        let pkt = protocol::command_packet(cmd.value(), data).unwrap();
+        self.tx(&pkt).ack()
+    }
+
+    pub(crate) fn ack(self) -> Capture {
+        self.rx(&protocol::ack_packet(true))
+    }
+
+    /// A response with the `payload`, acknowledged by the host.
+    pub(crate) fn response(self, payload: &[u8]) -> Capture {
+        let mut pkt = vec![
+            payload.len() as u8 + 2,
+            protocol::response_checksum(payload),
+        ];
+        pkt.extend_from_slice(payload);
+        self.rx(&pkt).tx(&protocol::ack_packet(true))
+    }
+
+    /// `COMMAND_GET_STATUS` answered with `COMMAND_RET_SUCCESS`.
+    pub(crate) fn success(self) -> Capture {
+        self.command(CommandId::GetStatus, &[])
+            .response(&[StatusCode::Success.value()])
+    }
+
+    /// A `COMMAND_MEMORY_READ` of the `data` at `address` on CC13xx/CC26xx.
+    pub(crate) fn memory_read(self, address: u32, data: &[u8]) -> Capture {
+        let mut cmd = address.to_be_bytes().to_vec();
+        cmd.extend_from_slice(&[1, (data.len() / 4) as u8]);
+        self.command(CommandId::MemoryRead, &cmd).response(data)
+    }
+
+    /// Reading the `ccfg` of a CC26x2 with 352 KB of flash, see
+    /// [`util::read_ccfg`](crate::util::read_ccfg).
+    pub(crate) fn read_ccfg(self, ccfg: &[u8]) -> Capture {
+        self.memory_read(0x4003_002C, &[0x2C, 0x00, 0x00, 0x00])
+            .memory_read(0x0005_7FA8, ccfg)
+    }
+
+    /// A `COMMAND_CRC32` of `len` bytes at `address` on CC13xx/CC26xx.
+    pub(crate) fn crc32(self, address: u32, len: u32, crc: u32) -> Capture {
+        let mut cmd = address.to_be_bytes().to_vec();
+        cmd.extend_from_slice(&len.to_be_bytes());
+        cmd.extend_from_slice(&[0; 4]);
+        self.command(CommandId::Crc32, &cmd)
+            .response(&crc.to_be_bytes())
+    }
+
+    /// A successful `COMMAND_SECTOR_ERASE` of the sector at `address`.
+    pub(crate) fn sector_erase(self, address: u32) -> Capture {
+        self.command(CommandId::SectorErase, &address.to_be_bytes())
+            .success()
+    }
+
+    /// A successful `COMMAND_DOWNLOAD` of `len` bytes at `address`.
+    pub(crate) fn download(self, address: u32, len: usize) -> Capture {
+        let mut cmd = address.to_be_bytes().to_vec();
+        cmd.extend_from_slice(&(len as u32).to_be_bytes());
+        self.command(CommandId::Download, &cmd).success()
+    }
+
+    /// A successful write of the `data` at `address`, a chunk small enough
+    /// for a single `COMMAND_SEND_DATA`.
+    pub(crate) fn write(self, address: u32, data: &[u8]) -> Capture {
+        self.download(address, data.len())
+            .command(CommandId::SendData, data)
+            .success()
+    }
+
+    pub(crate) fn port(self) -> ReplayPort {
+        ReplayPort::new(self.records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{Device, Family};
+
+    const PING_SESSION: &str = "\
+0.000010 TX 03 00 00
+0.000900 RX 00 33
+0.001000 TX 03 20 20
+0.001800 RX 00
+0.001850 RX CC
+";
+
+    #[test]
+    fn test_read_capture() {
+        let records = read_capture(PING_SESSION.as_bytes()).unwrap();
+        assert_eq!(records.len(), 5);
+        assert_eq!(
+            records[0],
+            Record {
+                time: Duration::from_micros(10),
+                direction: Direction::Tx,
+                bytes: vec![0x03, 0x00, 0x00],
+            }
+        );
+        assert_eq!(records[4].time, Duration::from_micros(1850));
+
+        assert!("0.000010 XX 00".parse::<Record>().is_err());
+        assert!("0.000010 TX 0G".parse::<Record>().is_err());
+        assert!("0.10 TX 00".parse::<Record>().is_err());
+    }
+
+    #[test]
+    fn test_replay_ping() {
+        let records = read_capture(PING_SESSION.as_bytes()).unwrap();
+        let mut device =
+            Device::new(ReplayPort::new(records), Family::CC26X2).unwrap();
+        assert!(device.ping().unwrap());
+        assert!(device.port().is_finished());
+        assert_eq!(device.diagnostics(), Default::default());
+    }
+
+    #[test]
+    fn test_replay_unexpected_write() {
+        let records = read_capture(PING_SESSION.as_bytes()).unwrap();
+        let mut port = ReplayPort::new(records);
+        assert!(port.write(&[0x03, 0x20]).is_err());
+        assert_eq!(port.write(&[0x03, 0x00]).unwrap(), 2);
+        assert_eq!(port.write(&[0x00]).unwrap(), 1);
+
+        let mut buf = [0u8; 4];
+        assert_eq!(port.read(&mut buf).unwrap(), 2);
+        assert_eq!(buf[..2], [0x00, 0x33]);
+        assert_eq!(
+            port.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+        assert!(!port.is_finished());
+    }
+}
@@ -21,83 +21,237 @@
 //! such as the IEEE 802.15.5g address, BLE MAC address, the flash size in
 //! bytes, etc.
 
-use std::{convert::TryInto, io};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryInto,
+    io,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
+    cca::{Cca, CCA_SIZE},
+    ccfg::{Ccfg, PROT_SECTORS},
+    chipdb::{self, ChipId, ChipInfo},
     constants::{
-        COMMAND_RET_FLASH_FAIL, COMMAND_RET_INVALID_ADR,
-        COMMAND_RET_INVALID_CMD, COMMAND_RET_SUCCESS, COMMAND_RET_UNKNOWN_CMD,
-        MAX_BYTES_PER_TRANSFER,
+        MAX_BYTES_PER_TRANSFER, MAX_MEMORY_READ_32_BYTES,
+        MAX_MEMORY_WRITE_32_BYTES,
     },
-    Device, Family,
+    crc::crc32,
+    family::{FlashSizeSource, CC26XX_FLASH_O_FLASH_SIZE},
+    AckResponse, CommandId, Device, Error, Family, FlashLayout, MemoryKind,
+    Mismatch, Page, Result, StatusCode, VerificationError,
 };
 
-/// CC26xx/CC13xx CCFG size in bytes.
-pub const CCFG_SIZE: usize = 88;
+pub use crate::ccfg::CCFG_SIZE;
 /// The value of an invalid IEEE/BLE address in the CCFG.
 pub const INVALID_ADDR: [u8; 8] =
     [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
 
 const REG32_SIZE: usize = 4;
-/// FLASH.FLASH_SIZE register on CC13xx/CC26xx
-const CC26XX_FLASH_O_FLASH_SIZE: u32 = 0x4003002C;
-const CC26XX_FCFG1_O_MAC_15_4_0: u32 = 0x000002F0;
-/// FLASH_CTRL.DIECFG0 register on CC2538
-const CC2538_FLASH_CTRL_O_DIECFG0: u32 = 0x400D3014;
+/// FCFG1.ICEPICK_DEVICE_ID register on CC13xx/CC26xx
+const CC26XX_FCFG1_O_ICEPICK_DEVICE_ID: u32 = 0x50001318;
+/// FCFG1.MAC_BLE_0 register on CC13xx/CC26xx
+const CC26XX_FCFG1_O_MAC_BLE_0: u32 = 0x500012E8;
+
+/// Token used to cancel long-running operations from another thread.
+///
+/// Operations accepting a `CancelToken` check it between commands, when
+/// cancelled they return [`Error::Cancelled`] leaving the device in
+/// bootloader mode, ready to accept new commands. The memory range being
+/// processed may be partially erased or written.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a new `CancelToken`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operations using this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            log::info!("Operation cancelled");
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Progress of [`erase_flash_range`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EraseProgress {
+    /// Index of the sector being erased. Families erasing a whole range with
+    /// a single command report it as one sector.
+    pub sector: usize,
+    /// Number of sectors to erase.
+    pub sectors: usize,
+    /// Address of the sector being erased.
+    pub address: u32,
+    /// Number of bytes erased so far.
+    pub bytes_done: u32,
+    /// Number of bytes to erase.
+    pub bytes_total: u32,
+}
+
+impl EraseProgress {
+    /// Percentage done.
+    pub fn percent(&self) -> f32 {
+        percent(self.bytes_done as usize, self.bytes_total as usize)
+    }
+}
 
 /// Erase a flash range.
+///
+/// Nothing is done on families erasing the flash on download, see
+/// [`Family::download_erases`].
+///
+/// The `progress` is reported before erasing each sector and once the range
+/// is erased, pass `|_| ()` to ignore it.
+///
+/// The operation can be cancelled using `cancel`.
 pub fn erase_flash_range<P, F>(
     device: &mut Device<P>,
     start_address: u32,
     byte_count: u32,
+    cancel: &CancelToken,
     mut progress: F,
-) -> io::Result<()>
+) -> Result<()>
 where
     P: serial::SerialPort,
-    F: FnMut(f32, u32),
+    F: FnMut(EraseProgress),
 {
     let family = device.family();
-    if family.supports_erase() {
-        progress(0.0, 0);
+    let sector_size = device.sector_size();
+    let end_address = start_address.checked_add(byte_count).ok_or(
+        Error::AddressOutOfRange {
+            address: start_address,
+            len: byte_count,
+        },
+    )?;
+    if family.download_erases() {
+        log::debug!("The flash is erased on download, skipping erase");
+    } else if family.supports_erase() {
+        cancel.check()?;
+        progress(EraseProgress {
+            sector: 0,
+            sectors: 1,
+            address: start_address,
+            bytes_done: 0,
+            bytes_total: byte_count,
+        });
         device.erase(start_address, byte_count)?;
-        progress(100.0, start_address + byte_count);
+        progress(EraseProgress {
+            sector: 1,
+            sectors: 1,
+            address: end_address,
+            bytes_done: byte_count,
+            bytes_total: byte_count,
+        });
     } else if family.supports_sector_erase() {
-        let sector_size = family.sector_size();
-        let sector_count = if (byte_count % sector_size) != 0 {
-            (byte_count / sector_size) + 1
-        } else {
-            byte_count / sector_size
-        };
+        if start_address % sector_size != 0 {
+            return Err(Error::UnalignedAddress(start_address));
+        }
 
-        for i in 0..sector_count {
-            let sector_address = start_address + (i * sector_size);
-            log::info!("Erasing sector #{}, address: {:#X}", i, sector_address);
+        let pages = device.pages_in_range(start_address, byte_count)?;
+        let sector_count = pages.len();
+        for (i, page) in pages.enumerate() {
+            cancel.check()?;
 
-            progress((100.0 * i as f32) / sector_count as f32, sector_address);
+            log::info!(
+                "Erasing sector #{}, address: {:#X}",
+                page.index,
+                page.address
+            );
 
-            device.sector_erase(sector_address)?;
+            progress(EraseProgress {
+                sector: i,
+                sectors: sector_count,
+                address: page.address,
+                bytes_done: page.address - start_address,
+                bytes_total: byte_count,
+            });
+
+            device.sector_erase(page.address)?;
 
             let ret = device.get_status()?;
-            if ret != COMMAND_RET_SUCCESS {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "CMD_SECTOR_ERASE failed: `{}` ({:#X})",
-                        status_code_to_str(ret),
-                        ret
-                    ),
-                ));
+            if ret != StatusCode::Success {
+                return Err(Error::Status(ret));
             }
         }
+        progress(EraseProgress {
+            sector: sector_count,
+            sectors: sector_count,
+            address: end_address,
+            bytes_done: byte_count,
+            bytes_total: byte_count,
+        });
     } else {
-        unreachable!();
+        return Err(Error::UnsupportedCommand(CommandId::SectorErase));
     }
 
     Ok(())
 }
 
+/// Load a `program` into the SRAM at `address` and start executing it from
+/// `entry`. Only supported on [`Family::CC2538`].
+///
+/// Useful to run small helpers, the bootloader stops once the program is
+/// started.
+///
+/// # Errors
+///
+/// - Returns [`Error::UnsupportedCommand`] if the family doesn't support
+///   [`CommandId::Run`].
+/// - Returns [`Error::AddressOutOfRange`] if the `program` doesn't fit in
+///   the SRAM.
+pub fn run_from_ram<P>(
+    device: &mut Device<P>,
+    address: u32,
+    program: &[u8],
+    entry: u32,
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let family = device.family();
+    if !family.supports_run() {
+        return Err(Error::UnsupportedCommand(CommandId::Run));
+    }
+
+    let len = program.len() as u32;
+    match family.memory_region(address, len) {
+        Some(region) if region.kind == MemoryKind::Sram => (),
+        _ => return Err(Error::AddressOutOfRange { address, len }),
+    }
+
+    let mut session = device.download_session(address, len)?;
+    for chunk in program.chunks(MAX_BYTES_PER_TRANSFER) {
+        session.send_data(chunk)?;
+    }
+    session.finish()?;
+
+    log::debug!("Running program from {:#X}", entry);
+    device.run(entry)
+}
+
 /// A binary data transfer
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Transfer<'a> {
     /// The data to write on the device's flash.
     pub data: &'a [u8],
@@ -107,119 +261,1172 @@ pub struct Transfer<'a> {
     pub expect_ack: bool,
 }
 
+/// Split the `transfers` around their chunks that only contain `0xFF`, so
+/// they aren't sent, and a new download starts at the next chunk with data.
+///
+/// This is only correct if the flash was erased before, and not on families
+/// where the download itself erases the flash, see
+/// [`Family::download_erases`]. Transfers that don't expect an ACK, like the
+/// CCFG, are kept whole.
+pub fn skip_blank_chunks<'a>(transfers: &[Transfer<'a>]) -> Vec<Transfer<'a>> {
+    let mut split = Vec::with_capacity(transfers.len());
+    for transfer in transfers {
+        if !transfer.expect_ack {
+            split.push(*transfer);
+            continue;
+        }
+
+        // Offset where the current run of chunks with data starts.
+        let mut start = None;
+        for (i, chunk) in
+            transfer.data.chunks(MAX_BYTES_PER_TRANSFER).enumerate()
+        {
+            let offset = i * MAX_BYTES_PER_TRANSFER;
+            let blank = chunk.iter().all(|b| *b == 0xFF);
+            match (start, blank) {
+                (None, false) => start = Some(offset),
+                (Some(run), true) => {
+                    split.push(Transfer {
+                        data: &transfer.data[run..offset],
+                        start_address: transfer.start_address + run as u32,
+                        expect_ack: true,
+                    });
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+        if let Some(run) = start {
+            split.push(Transfer {
+                data: &transfer.data[run..],
+                start_address: transfer.start_address + run as u32,
+                expect_ack: true,
+            });
+        }
+    }
+
+    let skipped = transfers.iter().map(|t| t.data.len()).sum::<usize>()
+        - split.iter().map(|t| t.data.len()).sum::<usize>();
+    if skipped != 0 {
+        log::info!("Skipping {} bytes of erased (0xFF) chunks", skipped);
+    }
+
+    split
+}
+
+/// A sector of the flash compared by [`diff_flash`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SectorDiff {
+    /// The part of the sector covered by the data.
+    pub range: Range<u32>,
+    /// Whether the flash differs from the data.
+    pub changed: bool,
+}
+
+/// Compare the `transfers` with the flash, sector by sector, using the
+/// CRC32 computed by the bootloader (`CMD_CRC32`), or reading the flash
+/// back on families that don't support it.
+///
+/// Returns the sectors covered by the `transfers`, in order.
+pub fn diff_flash<P>(
+    device: &mut Device<P>,
+    transfers: &[Transfer<'_>],
+) -> Result<Vec<SectorDiff>>
+where
+    P: serial::SerialPort,
+{
+    let sector_size = device.sector_size();
+    let mut sectors = BTreeMap::new();
+    for (transfer, changed) in compare_sectors(device, transfers)? {
+        let start = transfer.start_address;
+        let end = start + transfer.data.len() as u32;
+        let sector = sectors.entry(start / sector_size).or_insert(SectorDiff {
+            range: start..end,
+            changed: false,
+        });
+        sector.range.start = sector.range.start.min(start);
+        sector.range.end = sector.range.end.max(end);
+        sector.changed |= changed;
+    }
+
+    Ok(sectors.into_iter().map(|(_, sector)| sector).collect())
+}
+
+/// Split the `transfers` by sector and keep the sectors that differ from
+/// the flash, see [`diff_flash`], so only those are erased and written
+/// again.
+///
+/// When a sector differs, every part of the `transfers` in it is kept, as
+/// erasing it erases them too. Transfers that don't expect an ACK, like the
+/// CCFG, are compared whole.
+pub fn changed_sectors<'a, P>(
+    device: &mut Device<P>,
+    transfers: &[Transfer<'a>],
+) -> Result<Vec<Transfer<'a>>>
+where
+    P: serial::SerialPort,
+{
+    let sector_size = device.sector_size();
+    let compared = compare_sectors(device, transfers)?;
+    let sector = |t: &Transfer<'_>| {
+        let first = t.start_address / sector_size;
+        let last = (t.start_address + t.data.len() as u32 - 1) / sector_size;
+        first..=last
+    };
+
+    let changed = compared
+        .iter()
+        .filter(|(_, changed)| *changed)
+        .flat_map(|(t, _)| sector(t))
+        .collect::<BTreeSet<_>>();
+    let sectors = compared
+        .iter()
+        .flat_map(|(t, _)| sector(t))
+        .collect::<BTreeSet<_>>();
+    log::info!("{} of {} sectors differ", changed.len(), sectors.len());
+
+    Ok(compared
+        .into_iter()
+        .map(|(t, _)| t)
+        .filter(|t| sector(t).any(|sector| changed.contains(&sector)))
+        .collect())
+}
+
+/// Split the `transfers` by sector, except the ones that don't expect an
+/// ACK, and compare each part with the flash.
+fn compare_sectors<'a, P>(
+    device: &mut Device<P>,
+    transfers: &[Transfer<'a>],
+) -> Result<Vec<(Transfer<'a>, bool)>>
+where
+    P: serial::SerialPort,
+{
+    let split = split_sectors(device, transfers)?;
+    let use_crc = device.family().supports_command(CommandId::Crc32);
+    let mut compared = Vec::with_capacity(split.len());
+    for transfer in split {
+        let len = transfer.data.len() as u32;
+        let changed = if use_crc {
+            device.crc32(transfer.start_address, len, 0)?
+                != crc32(transfer.data)
+        } else {
+            read_memory(device, transfer.start_address, len)? != transfer.data
+        };
+        compared.push((transfer, changed));
+    }
+
+    Ok(compared)
+}
+
+/// Split the `transfers` by sector, except the ones that don't expect an
+/// ACK, which are kept whole.
+fn split_sectors<'a, P>(
+    device: &Device<P>,
+    transfers: &[Transfer<'a>],
+) -> Result<Vec<Transfer<'a>>>
+where
+    P: serial::SerialPort,
+{
+    let mut split = Vec::with_capacity(transfers.len());
+    for transfer in transfers {
+        if !transfer.expect_ack {
+            split.push(*transfer);
+            continue;
+        }
+
+        let len = transfer.data.len() as u32;
+        let end = transfer.start_address.wrapping_add(len);
+        for page in device.pages_in_range(transfer.start_address, len)? {
+            let start = page.address.max(transfer.start_address);
+            let offset = (start - transfer.start_address) as usize;
+            let len = ((page.address + page.len).min(end) - start) as usize;
+            split.push(Transfer {
+                data: &transfer.data[offset..offset + len],
+                start_address: start,
+                expect_ack: true,
+            });
+        }
+    }
+
+    split.retain(|t| !t.data.is_empty());
+    Ok(split)
+}
+
+/// Recovery options of [`write_flash_range_with_recovery`].
+///
+/// The default value disables the recovery.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Recovery {
+    /// Maximum number of recoveries during the whole operation.
+    pub max_attempts: usize,
+    /// Invoke the bootloader before synchronizing again with the device,
+    /// see [`invoke_bootloader`](crate::invoke_bootloader).
+    pub invoke_bootloader: bool,
+    /// Used when `invoke_bootloader` is `true`, whether the DTR/RTS lines
+    /// are inverted.
+    pub bootloader_inverted: bool,
+    /// Used when `invoke_bootloader` is `true`, whether the bootloader pin
+    /// is active high.
+    pub bootloader_active_high: bool,
+}
+
+/// Progress of [`write_flash_range`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct WriteProgress {
+    /// Index of the transfer being written.
+    pub transfer: usize,
+    /// Number of transfers to write.
+    pub transfers: usize,
+    /// Index of the chunk being written in the transfer.
+    pub chunk: u32,
+    /// Address of the chunk being written.
+    pub address: u32,
+    /// Number of bytes written so far, over all the transfers.
+    pub bytes_done: usize,
+    /// Number of bytes to write, over all the transfers.
+    pub bytes_total: usize,
+}
+
+impl WriteProgress {
+    /// Percentage done.
+    pub fn percent(&self) -> f32 {
+        percent(self.bytes_done, self.bytes_total)
+    }
+}
+
+/// Percentage of `done` over `total`, 100 if there's nothing to do.
+fn percent(done: usize, total: usize) -> f32 {
+    if total == 0 {
+        100.0
+    } else {
+        (100.0 * done as f64 / total as f64) as f32
+    }
+}
+
 /// Write the flash.
+///
+/// The `progress` is reported before writing each chunk and once all the
+/// transfers are written, pass `|_| ()` to ignore it.
+///
+/// The operation can be cancelled using `cancel`.
 pub fn write_flash_range<'a, P, F>(
     device: &mut Device<P>,
     transfers: &[Transfer<'a>],
-    mut progress: F,
-) -> io::Result<()>
+    cancel: &CancelToken,
+    progress: F,
+) -> Result<WriteStats>
 where
     P: serial::SerialPort,
-    F: FnMut(usize, f32, u32, u32),
+    F: FnMut(WriteProgress),
 {
-    let family = device.family();
+    write_flash_range_with_recovery(
+        device,
+        transfers,
+        cancel,
+        &Recovery::default(),
+        progress,
+    )
+}
 
+/// Write the flash, recovering from communication errors.
+///
+/// When the communication with the device is lost (e.g.: the USB serial
+/// adapter had a hiccup), the bootloader is invoked again (if enabled in
+/// `recovery`), the device is resynchronized and the transfer is resumed
+/// from the last acknowledged chunk.
+///
+/// Errors reported by the bootloader (NACKs and error status codes) are not
+/// recovered.
+///
+/// The `progress` is reported as in [`write_flash_range`], a resumed chunk is
+/// reported again.
+///
+/// The operation can be cancelled using `cancel`.
+pub fn write_flash_range_with_recovery<'a, P, F>(
+    device: &mut Device<P>,
+    transfers: &[Transfer<'a>],
+    cancel: &CancelToken,
+    recovery: &Recovery,
+    mut progress: F,
+) -> Result<WriteStats>
+where
+    P: serial::SerialPort,
+    F: FnMut(WriteProgress),
+{
     log::info!("{} transfers", transfers.len());
 
+    let bytes_total = transfers.iter().map(|t| t.data.len()).sum();
+    let mut bytes_done = 0;
+
+    let start = Instant::now();
+    let mut stats = WriteStats::default();
+    let mut attempts = 0;
     for (txfer_index, transfer) in transfers.iter().enumerate() {
-        let chunks = transfer.data.len() / MAX_BYTES_PER_TRANSFER;
-        log::info!("Chunks for transfer #{}: {}", txfer_index, chunks);
+        cancel.check()?;
 
-        // Prepare device for flash download.
-        device.download(
+        let mut data_offset = 0;
+        loop {
+            let ret = write_transfer(
+                device,
+                txfer_index,
+                transfer,
+                &mut data_offset,
+                &mut stats,
+                cancel,
+                &mut |chunk, address, offset| {
+                    progress(WriteProgress {
+                        transfer: txfer_index,
+                        transfers: transfers.len(),
+                        chunk,
+                        address,
+                        bytes_done: bytes_done + offset,
+                        bytes_total,
+                    })
+                },
+            );
+
+            match ret {
+                Ok(()) => break,
+                Err(e)
+                    if is_link_error(&e)
+                        && attempts < recovery.max_attempts =>
+                {
+                    attempts += 1;
+                    log::warn!(
+                        "Transfer #{} failed at address {:#X}: {}, recovering (attempt {} of {})",
+                        txfer_index,
+                        transfer.start_address + data_offset as u32,
+                        e,
+                        attempts,
+                        recovery.max_attempts,
+                    );
+
+                    if recovery.invoke_bootloader {
+                        device.invoke_bootloader(
+                            recovery.bootloader_inverted,
+                            recovery.bootloader_active_high,
+                        )?;
+                    }
+                    device.resynchronize()?;
+
+                    // The chunk that failed is sent again.
+                    stats.retransmissions += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        bytes_done += transfer.data.len();
+    }
+
+    progress(WriteProgress {
+        transfer: transfers.len(),
+        transfers: transfers.len(),
+        chunk: 0,
+        address: transfers
+            .last()
+            .map_or(0, |t| t.start_address + t.data.len() as u32),
+        bytes_done,
+        bytes_total,
+    });
+
+    stats.elapsed = start.elapsed();
+    log::info!(
+        "Written {} bytes in {} chunks ({} retransmissions) in {:?}, {:.0} B/s",
+        stats.bytes_written,
+        stats.chunks,
+        stats.retransmissions,
+        stats.elapsed,
+        stats.throughput()
+    );
+
+    Ok(stats)
+}
+
+/// Statistics of [`write_flash_range`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WriteStats {
+    /// Number of bytes written.
+    pub bytes_written: usize,
+    /// Number of chunks written.
+    pub chunks: usize,
+    /// Number of chunks sent again after recovering the communication, see
+    /// [`write_flash_range_with_recovery`].
+    pub retransmissions: usize,
+    /// Duration of the whole operation.
+    pub elapsed: Duration,
+}
+
+impl WriteStats {
+    /// Effective throughput, in bytes per second.
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes_written as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Verify the flash after writing the `transfers`, comparing the CRC32 of
+/// each one computed by the bootloader (`CMD_CRC32`) with the CRC32 of its
+/// data.
+///
+/// Transfers that don't expect an ACK (the CCFG) are skipped, the device may
+/// not answer anymore once they're written.
+///
+/// # Errors
+///
+/// Returns [`Error::Verification`] listing the transfers that don't match.
+pub fn verify_flash_range<P>(
+    device: &mut Device<P>,
+    transfers: &[Transfer<'_>],
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let mut mismatches = Vec::new();
+    for transfer in transfers.iter().filter(|t| t.expect_ack) {
+        let len = transfer.data.len() as u32;
+        let expected = crc32(transfer.data);
+        let actual = device.crc32(transfer.start_address, len, 0)?;
+        log::debug!(
+            "CRC32 of {:#X}..{:#X}: {:#010X}, expected {:#010X}",
             transfer.start_address,
-            transfer.data.len().try_into().unwrap(),
-        )?;
+            transfer.start_address + len,
+            actual,
+            expected
+        );
 
-        // Each download command requires to check the latest
-        // status to verify it worked.
-        let ret = device.get_status()?;
+        if actual != expected {
+            mismatches.push(Mismatch {
+                address: transfer.start_address,
+                len,
+                expected,
+                actual,
+                first_difference: None,
+            });
+        }
+    }
 
-        if ret != COMMAND_RET_SUCCESS {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "CMD_DOWNLOAD failed: `{}` ({:#X})",
-                    status_code_to_str(ret),
-                    ret
-                ),
-            ));
+    if !mismatches.is_empty() {
+        return Err(Error::Verification(VerificationError { mismatches }));
+    }
+
+    Ok(())
+}
+
+/// Verify the flash after writing the `transfers` by reading it back and
+/// comparing it byte per byte with their data, slower than
+/// [`verify_flash_range`] but it finds the exact address of the first
+/// difference. The memory read commands must be enabled, see
+/// [`read_security_state`].
+///
+/// Transfers that don't expect an ACK (the CCFG) are skipped, the device may
+/// not answer anymore once they're written.
+///
+/// # Errors
+///
+/// Returns [`Error::Verification`] listing the transfers that don't match.
+pub fn verify_flash_range_readback<P>(
+    device: &mut Device<P>,
+    transfers: &[Transfer<'_>],
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let mut mismatches = Vec::new();
+    for transfer in transfers.iter().filter(|t| t.expect_ack) {
+        let len = transfer.data.len() as u32;
+        let data = read_memory(device, transfer.start_address, len)?;
+        if let Some(i) =
+            data.iter().zip(transfer.data).position(|(a, b)| a != b)
+        {
+            mismatches.push(Mismatch {
+                address: transfer.start_address,
+                len,
+                expected: crc32(transfer.data),
+                actual: crc32(&data),
+                first_difference: Some(transfer.start_address + i as u32),
+            });
         }
+    }
 
-        let mut bytes_left = transfer.data.len();
-        let mut data_offset = 0;
-        let mut chunk_index = 0;
+    if !mismatches.is_empty() {
+        return Err(Error::Verification(VerificationError { mismatches }));
+    }
 
-        while bytes_left > 0 {
-            let bytes_in_transfer = MAX_BYTES_PER_TRANSFER.min(bytes_left);
-            let chunk = &transfer.data[data_offset..];
-            let chunk = &chunk[..bytes_in_transfer];
+    Ok(())
+}
 
-            let chunk_addr = transfer.start_address + data_offset as u32;
-            log::info!(
-                "Writing chunk #{} ({} B) at address {:#X}",
-                chunk_index,
-                chunk.len(),
-                chunk_addr
-            );
+/// How the flash is verified after writing it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VerifyMethod {
+    /// Compare the CRC32 computed by the bootloader, see
+    /// [`verify_flash_range`].
+    Crc32,
+    /// Read back the flash, see [`verify_flash_range_readback`].
+    Readback,
+    /// Compare the CRC32 of a percentage of the sectors, see
+    /// [`verify_flash_range_sampled`].
+    Sampled(u8),
+}
 
-            progress(
-                txfer_index,
-                (100.0 * chunk_index as f32) / chunks as f32,
-                chunk_index,
-                chunk_addr,
-            );
+/// Verify the flash after writing the `transfers`, comparing the CRC32 of
+/// `percent` of their sectors spread evenly over them, starting with the
+/// first one, for quick smoke tests of large images. See
+/// [`verify_flash_range`], the CCFG isn't verified either.
+///
+/// # Errors
+///
+/// - Returns [`Error::InvalidPercentage`] if `percent` is not in the
+///   `1..=100` range.
+/// - Returns [`Error::Verification`] listing the sampled sectors that don't
+///   match.
+pub fn verify_flash_range_sampled<P>(
+    device: &mut Device<P>,
+    transfers: &[Transfer<'_>],
+    percent: u8,
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    if percent == 0 || percent > 100 {
+        return Err(Error::InvalidPercentage(percent));
+    }
 
-            let ack = device.send_data(&chunk)?;
-            if transfer.expect_ack {
-                if !ack {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "Chunk #{} of size {} not acknowledged at address {:#X} (page: {}) at transfer #{}",
-                            chunk_index, chunk.len(), chunk_addr, family.address_to_page(chunk_addr),
-                            txfer_index,
-                        )
-                    ));
-                }
+    let split = split_sectors(device, transfers)?
+        .into_iter()
+        .filter(|t| t.expect_ack)
+        .collect::<Vec<_>>();
+    let sampled = split
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| is_sampled(*i, percent))
+        .map(|(_, t)| *t)
+        .collect::<Vec<_>>();
+    log::info!("Verifying {} of {} sectors", sampled.len(), split.len());
 
-                let ret = device.get_status()?;
-                if ret != COMMAND_RET_SUCCESS {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "CMD_SEND_DATA failed: `{}` ({:#X})",
-                            status_code_to_str(ret),
-                            ret
-                        ),
-                    ));
-                }
+    verify_flash_range(device, &sampled)
+}
+
+/// Whether the sector at `index` is verified when sampling `percent` of the
+/// sectors, it's the case each time the number of sampled sectors rounded
+/// up increases.
+fn is_sampled(index: usize, percent: u8) -> bool {
+    let percent = usize::from(percent);
+    ((index + 1) * percent + 99) / 100 > (index * percent + 99) / 100
+}
+
+/// Verify the flash after writing the `transfers` with the given `method`.
+///
+/// # Errors
+///
+/// Returns [`Error::Verification`] listing the transfers that don't match.
+pub fn verify_flash<P>(
+    device: &mut Device<P>,
+    transfers: &[Transfer<'_>],
+    method: VerifyMethod,
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    match method {
+        VerifyMethod::Crc32 => verify_flash_range(device, transfers),
+        VerifyMethod::Readback => {
+            verify_flash_range_readback(device, transfers)
+        }
+        VerifyMethod::Sampled(percent) => {
+            verify_flash_range_sampled(device, transfers, percent)
+        }
+    }
+}
+
+/// Check that the flash `range` is erased (all `0xFF`), sector by sector,
+/// e.g.: before programming it or after an erase failed.
+///
+/// # Errors
+///
+/// Returns [`Error::Verification`] listing the sectors of the range that
+/// aren't blank, with the first byte that isn't `0xFF` when using
+/// [`VerifyMethod::Readback`].
+pub fn blank_check<P>(
+    device: &mut Device<P>,
+    range: Range<u32>,
+    method: VerifyMethod,
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let blank = vec![0xFFu8; device.sector_size() as usize];
+    let len = range.end.saturating_sub(range.start);
+    let transfers = device
+        .pages_in_range(range.start, len)?
+        .map(|page| {
+            let start = page.address.max(range.start);
+            let end = (page.address + page.len).min(range.end);
+            Transfer {
+                data: &blank[..(end - start) as usize],
+                start_address: start,
+                expect_ack: true,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    log::info!("Blank checking {:#X}..{:#X}", range.start, range.end);
+    verify_flash(device, &transfers, method)
+}
+
+/// Whether the error is caused by a communication problem, instead of being
+/// reported by the bootloader. A passed deadline isn't, recovering would
+/// only take longer.
+fn is_link_error(e: &Error) -> bool {
+    matches!(
+        *e,
+        Error::Io(_)
+            | Error::Timeout
+            | Error::InvalidResponse
+            | Error::InvalidChecksum
+            | Error::ResponseLength { .. }
+    )
+}
+
+/// Write a transfer starting from `data_offset`, which is updated after
+/// each written chunk. The `progress` is called with the index, address and
+/// offset of each chunk before writing it.
+fn write_transfer<P, F>(
+    device: &mut Device<P>,
+    txfer_index: usize,
+    transfer: &Transfer<'_>,
+    data_offset: &mut usize,
+    stats: &mut WriteStats,
+    cancel: &CancelToken,
+    progress: &mut F,
+) -> Result<()>
+where
+    P: serial::SerialPort,
+    F: FnMut(u32, u32, usize),
+{
+    let chunks = transfer.data.len() / MAX_BYTES_PER_TRANSFER;
+    log::info!("Chunks for transfer #{}: {}", txfer_index, chunks);
+
+    // Prepare device for flash download.
+    device.download(
+        transfer.start_address + *data_offset as u32,
+        (transfer.data.len() - *data_offset).try_into().unwrap(),
+    )?;
+
+    // Each download command requires to check the latest
+    // status to verify it worked.
+    let ret = device.get_status()?;
+
+    if ret != StatusCode::Success {
+        return Err(Error::Status(ret));
+    }
+
+    let mut bytes_left = transfer.data.len() - *data_offset;
+    let mut chunk_index = (*data_offset / MAX_BYTES_PER_TRANSFER) as u32;
+
+    while bytes_left > 0 {
+        cancel.check()?;
+
+        let bytes_in_transfer = MAX_BYTES_PER_TRANSFER.min(bytes_left);
+        let chunk = &transfer.data[*data_offset..];
+        let chunk = &chunk[..bytes_in_transfer];
+
+        let chunk_addr = transfer.start_address + *data_offset as u32;
+        log::info!(
+            "Writing chunk #{} ({} B) at address {:#X}",
+            chunk_index,
+            chunk.len(),
+            chunk_addr
+        );
+
+        progress(chunk_index, chunk_addr, *data_offset);
+
+        let ack = device.send_data(&chunk)?;
+        if transfer.expect_ack {
+            if ack != AckResponse::Ack {
+                log::error!(
+                    "Chunk #{} of size {} not acknowledged at address {:#X} at transfer #{}: {:?}",
+                    chunk_index, chunk.len(), chunk_addr, txfer_index, ack,
+                );
+
+                return match ack {
+                    AckResponse::Timeout { .. } => Err(Error::Timeout),
+                    _ => Err(Error::Nack(CommandId::SendData)),
+                };
             }
 
-            bytes_left -= bytes_in_transfer;
-            data_offset += bytes_in_transfer;
-            chunk_index += 1;
+            let ret = device.get_status()?;
+            if ret != StatusCode::Success {
+                return Err(Error::Status(ret));
+            }
+        }
+
+        bytes_left -= bytes_in_transfer;
+        *data_offset += bytes_in_transfer;
+        chunk_index += 1;
+
+        stats.bytes_written += bytes_in_transfer;
+        stats.chunks += 1;
+    }
+
+    Ok(())
+}
+
+/// Write memory using 32-bit accesses.
+///
+/// The `data` is split in as many [`Device::memory_write_32`] commands as
+/// needed, the status of each command is checked before sending the next.
+///
+/// # Errors
+///
+/// - Returns [`Error::InvalidLength`] if the length of the `data` slice is
+///   not divisible by 4.
+/// - Returns [`Error::UnalignedAddress`] if the `address` is not aligned to
+///   32-bits.
+pub fn memory_write_32<P>(
+    device: &mut Device<P>,
+    address: u32,
+    data: &[u8],
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    if data.len() % 4 != 0 {
+        return Err(Error::InvalidLength(data.len()));
+    }
+
+    for (i, chunk) in data.chunks(MAX_MEMORY_WRITE_32_BYTES).enumerate() {
+        let chunk_addr = address + (i * MAX_MEMORY_WRITE_32_BYTES) as u32;
+        log::debug!(
+            "Writing memory chunk #{} ({} B) at address {:#X}",
+            i,
+            chunk.len(),
+            chunk_addr
+        );
+
+        device.memory_write_32(chunk_addr, chunk)?;
+
+        let ret = device.get_status()?;
+        if ret != StatusCode::Success {
+            return Err(Error::Status(ret));
         }
     }
 
     Ok(())
 }
 
-/// Reads the flash size from the memory.
-pub fn read_flash_size<P>(device: &mut Device<P>) -> io::Result<u32>
+/// Read memory using 32-bit accesses, of any length.
+///
+/// The `data` is split in as many [`Device::memory_read_32`] commands as
+/// needed.
+///
+/// # Errors
+///
+/// - Returns [`Error::InvalidLength`] if the length of the `data` slice is
+///   not divisible by 4.
+/// - Returns [`Error::UnalignedAddress`] if the `address` is not aligned to
+///   32-bits.
+pub fn memory_read_32<P>(
+    device: &mut Device<P>,
+    address: u32,
+    data: &mut [u8],
+) -> Result<()>
 where
     P: serial::SerialPort,
 {
-    let addr = match device.family() {
-        Family::CC2538 => CC2538_FLASH_CTRL_O_DIECFG0,
-        Family::CC26X0 | Family::CC26X2 => CC26XX_FLASH_O_FLASH_SIZE,
+    if data.len() % 4 != 0 {
+        return Err(Error::InvalidLength(data.len()));
+    }
+
+    for (i, chunk) in data.chunks_mut(MAX_MEMORY_READ_32_BYTES).enumerate() {
+        let chunk_addr = address + (i * MAX_MEMORY_READ_32_BYTES) as u32;
+        log::debug!(
+            "Reading memory chunk #{} ({} B) at address {:#X}",
+            i,
+            chunk.len(),
+            chunk_addr
+        );
+
+        device.memory_read_32(chunk_addr, chunk)?;
+    }
+
+    Ok(())
+}
+
+/// Progress of [`read_flash`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ReadProgress {
+    /// Index of the sector being read.
+    pub sector: usize,
+    /// Number of sectors to read.
+    pub sectors: usize,
+    /// Address of the sector being read, or of the start of the range in the
+    /// first sector.
+    pub address: u32,
+    /// Number of bytes read so far.
+    pub bytes_done: u32,
+    /// Number of bytes to read.
+    pub bytes_total: u32,
+}
+
+impl ReadProgress {
+    /// Percentage done.
+    pub fn percent(&self) -> f32 {
+        percent(self.bytes_done as usize, self.bytes_total as usize)
+    }
+}
+
+/// Read the flash `range` and write it to the `sink`, a sector at a time
+/// using [`memory_read_32`], e.g.: to back up the contents of a device. The
+/// memory read commands must be enabled, see [`read_security_state`].
+///
+/// The `progress` is reported before reading each sector and once the range
+/// is read, pass `|_| ()` to ignore it.
+///
+/// # Errors
+///
+/// Returns [`Error::Io`] if writing to the `sink` fails.
+pub fn read_flash<P, W, F>(
+    device: &mut Device<P>,
+    range: Range<u32>,
+    sink: &mut W,
+    mut progress: F,
+) -> Result<()>
+where
+    P: serial::SerialPort,
+    W: io::Write,
+    F: FnMut(ReadProgress),
+{
+    let len = range.end.saturating_sub(range.start);
+    let pages = device.pages_in_range(range.start, len)?;
+    let sectors = pages.len();
+    for (sector, page) in pages.enumerate() {
+        let start = page.address.max(range.start);
+        let end = (page.address + page.len).min(range.end);
+        progress(ReadProgress {
+            sector,
+            sectors,
+            address: start,
+            bytes_done: start - range.start,
+            bytes_total: len,
+        });
+
+        sink.write_all(&read_memory(device, start, end - start)?)?;
+    }
+    progress(ReadProgress {
+        sector: sectors,
+        sectors,
+        address: range.end,
+        bytes_done: len,
+        bytes_total: len,
+    });
+
+    Ok(())
+}
+
+/// Read the sectors that writing the `transfers` erases and write them to the
+/// `sink`, from the first to the last sector, so they can be flashed again if
+/// something goes wrong, see [`read_flash`].
+///
+/// Returns the range of the flash that was read.
+pub fn backup_flash<P, W, F>(
+    device: &mut Device<P>,
+    transfers: &[Transfer<'_>],
+    sink: &mut W,
+    progress: F,
+) -> Result<Range<u32>>
+where
+    P: serial::SerialPort,
+    W: io::Write,
+    F: FnMut(ReadProgress),
+{
+    let start = transfers.iter().map(|t| t.start_address).min();
+    let end = transfers
+        .iter()
+        .map(|t| t.start_address + t.data.len() as u32)
+        .max();
+    let range = match (start, end) {
+        (Some(start), Some(end)) if start < end => {
+            let mut pages = device.pages_in_range(start, end - start)?;
+            let first = pages.next().unwrap();
+            let last = pages.last().unwrap_or(first);
+            first.address..last.address + last.len
+        }
+        _ => return Ok(0..0),
     };
 
+    log::info!("Backing up {:#X}..{:#X}", range.start, range.end);
+    read_flash(device, range.clone(), sink, progress)?;
+
+    Ok(range)
+}
+
+/// Read `len` bytes of memory at `address`, which don't need to be aligned,
+/// see [`memory_read_32`].
+fn read_memory<P>(
+    device: &mut Device<P>,
+    address: u32,
+    len: u32,
+) -> Result<Vec<u8>>
+where
+    P: serial::SerialPort,
+{
+    // Memory reads are done in aligned 32-bit words.
+    let start = address & !3;
+    let end = (address + len + 3) & !3;
+    let mut data = vec![0u8; (end - start) as usize];
+    memory_read_32(device, start, &mut data)?;
+
+    let offset = (address - start) as usize;
+    data.truncate(offset + len as usize);
+    data.drain(..offset);
+    Ok(data)
+}
+
+/// Identify the family of the connected device.
+///
+/// The chip ID distinguishes the CC2538 from the CC13xx/CC26xx devices, these
+/// are told apart by the wafer ID of the `ICEPICK_DEVICE_ID` register and the
+/// flash size. CC26x0R2 devices share the wafer ID of the CC26x0, they're
+/// told apart by the `PG_REV` field of the `ICEPICK_DEVICE_ID` register.
+///
+/// The family of the `device` is only used to synchronize, it can be any.
+///
+/// # Errors
+///
+/// Returns [`Error::UnknownChip`] if the device isn't recognized.
+pub fn detect_family<P>(device: &mut Device<P>) -> Result<Family>
+where
+    P: serial::SerialPort,
+{
+    // First ICEPICK_DEVICE_ID.PG_REV of the CC26x0R2 parts.
+    const CC26X0R2_PG_REV: u32 = 0xB;
+    // 704 KB, flash size of CC13x2x7/CC26x2x7 parts.
+    const CC26X2X7_FLASH_SIZE: u32 = 0xB0000;
+
+    let chip_id = device.get_chip_id()?;
+    log::debug!("Chip ID: {:#X}", chip_id);
+    if chip_id == 0xB964 || chip_id == 0xB965 {
+        return Ok(Family::CC2538);
+    }
+
+    // MEMORY_READ is sent directly, the family of the device may be wrong.
     let mut reg = [0u8; REG32_SIZE];
-    device.memory_read_32(addr, &mut reg)?;
+    read_reg32_cc26xx(device, CC26XX_FCFG1_O_ICEPICK_DEVICE_ID, &mut reg)?;
+    let device_id = u32::from_le_bytes(reg);
+    let wafer_id = (device_id >> 12) & 0xFFFF;
+    log::debug!("Device ID: {:#X}, wafer ID: {:#X}", device_id, wafer_id);
+
+    match wafer_id {
+        // CC26x0R2, from the ChipInfo_GetChipFamily() of the TI driverlib.
+        0xB99A if device_id >> 28 >= CC26X0R2_PG_REV => Ok(Family::CC26X0R2),
+        // CC26x0 and CC13x0.
+        0xB99A | 0xB9BE => Ok(Family::CC26X0),
+        // CC26x2 and CC13x2.
+        0xBB20 | 0xBB41 => {
+            read_reg32_cc26xx(device, CC26XX_FLASH_O_FLASH_SIZE, &mut reg)?;
+            let sectors = u32::from_le_bytes(reg) & 0xFF;
+            if sectors * Family::CC26X2.sector_size() >= CC26X2X7_FLASH_SIZE {
+                Ok(Family::CC26X2X7)
+            } else {
+                Ok(Family::CC26X2)
+            }
+        }
+        _ => Err(Error::UnknownChip(chip_id)),
+    }
+}
+
+/// Read a 32-bit register of a CC13xx/CC26xx device.
+fn read_reg32_cc26xx<P>(
+    device: &mut Device<P>,
+    address: u32,
+    reg: &mut [u8; REG32_SIZE],
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let mut cmd = [0u8; 6];
+    cmd[..4].copy_from_slice(&address.to_be_bytes());
+    cmd[4] = 1; /* access type */
+    cmd[5] = 1; /* number of accesses */
+
+    match device.send_raw_command(CommandId::MemoryRead.value(), &cmd)? {
+        Some(response) if response.len() == REG32_SIZE => {
+            reg.copy_from_slice(&response);
+            Ok(())
+        }
+        Some(response) => Err(Error::ResponseLength {
+            expected: REG32_SIZE,
+            found: response.len(),
+        }),
+        None => Err(Error::Timeout),
+    }
+}
+
+/// Identify the connected part.
+///
+/// See [`chipdb::identify`]. On families without
+/// [`CommandId::GetChipId`] the part is identified from the family alone.
+pub fn read_chip_info<P>(device: &mut Device<P>) -> Result<ChipInfo>
+where
+    P: serial::SerialPort,
+{
+    let chip_id = if device.family().supports_command(CommandId::GetChipId) {
+        device.get_chip_id()?
+    } else {
+        0
+    };
+    let flash_size = read_flash_size(device)?;
+
+    Ok(chipdb::identify(device.family(), chip_id, flash_size))
+}
+
+/// Whether the memory of the device can be read back, see
+/// [`read_security_state`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SecurityState {
+    /// The memory read commands work.
+    Unlocked,
+    /// The memory read commands are rejected, the flash contents can only
+    /// be checked with [`Device::crc32`].
+    ReadLocked,
+    /// The family doesn't support [`CommandId::MemoryRead`], e.g.:
+    /// [`Family::TM4C`], the state can't be probed.
+    Unknown,
+}
+
+/// Information about the connected device, see [`read_device_info`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DeviceInfo {
+    /// The identified part.
+    pub chip: ChipInfo,
+    /// Whether the memory can be read back.
+    pub security: SecurityState,
+}
+
+impl DeviceInfo {
+    /// Whether the flash contents can be read back to verify them,
+    /// otherwise [`Device::crc32`] has to be used.
+    #[inline]
+    pub fn memory_readable(&self) -> bool {
+        self.security == SecurityState::Unlocked
+    }
+}
 
-    match device.family() {
-        Family::CC2538 => {
+/// Probe whether the memory read commands are disabled, e.g.: on a device
+/// with its debug access locked.
+///
+/// The first word of the flash is read, the bootloader doesn't acknowledge
+/// the command if reads are disabled.
+///
+/// # Errors
+///
+/// Returns [`Error::UnsupportedCommand`] if the family doesn't support
+/// [`CommandId::MemoryRead`].
+pub fn read_security_state<P>(device: &mut Device<P>) -> Result<SecurityState>
+where
+    P: serial::SerialPort,
+{
+    let address = device.family().flash_base();
+    let mut word = [0u8; REG32_SIZE];
+    match device.memory_read_32(address, &mut word) {
+        Ok(()) => Ok(SecurityState::Unlocked),
+        Err(Error::Nack(CommandId::MemoryRead)) => {
+            log::debug!("Memory read not acknowledged, reads are disabled");
+            Ok(SecurityState::ReadLocked)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Identify the connected part and probe its security state.
+///
+/// See [`read_chip_info`] and [`read_security_state`]. The flash size of
+/// the [`ChipInfo`] is zero if it can't be read on a read locked device.
+/// The security state is [`SecurityState::Unknown`] on families without
+/// [`CommandId::MemoryRead`].
+pub fn read_device_info<P>(device: &mut Device<P>) -> Result<DeviceInfo>
+where
+    P: serial::SerialPort,
+{
+    let security = if device.family().supports_command(CommandId::MemoryRead) {
+        read_security_state(device)?
+    } else {
+        SecurityState::Unknown
+    };
+    let chip_id = if device.family().supports_command(CommandId::GetChipId) {
+        device.get_chip_id()?
+    } else {
+        0
+    };
+    let flash_size = match read_flash_size(device) {
+        Ok(flash_size) => flash_size,
+        Err(Error::Nack(CommandId::MemoryRead))
+            if security == SecurityState::ReadLocked =>
+        {
+            0
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok(DeviceInfo {
+        chip: chipdb::identify(device.family(), chip_id, flash_size),
+        security,
+    })
+}
+
+/// Read and decode the chip ID.
+///
+/// On CC13xx/CC26xx devices the `FCFG1.ICEPICK_DEVICE_ID` register is read
+/// too, for the part number.
+///
+/// # Errors
+///
+/// Returns [`Error::UnsupportedCommand`] if the family doesn't support
+/// [`CommandId::GetChipId`].
+pub fn read_chip_id<P>(device: &mut Device<P>) -> Result<ChipId>
+where
+    P: serial::SerialPort,
+{
+    let family = device.family();
+    let mut chip_id = ChipId::new(family, device.get_chip_id()?);
+    if family.has_ccfg() {
+        let mut reg = [0u8; REG32_SIZE];
+        device.memory_read_32(CC26XX_FCFG1_O_ICEPICK_DEVICE_ID, &mut reg)?;
+        chip_id.set_device_id(u32::from_le_bytes(reg));
+    }
+
+    Ok(chip_id)
+}
+
+/// Read the flash size and build the [`FlashLayout`] of the device.
+pub fn read_flash_layout<P>(device: &mut Device<P>) -> Result<FlashLayout>
+where
+    P: serial::SerialPort,
+{
+    let flash_size = read_flash_size(device)?;
+
+    Ok(FlashLayout::new(device.family(), flash_size)
+        .with_sector_size(device.sector_size()))
+}
+
+/// Reads the flash size from the memory.
+///
+/// On [`Family::CC26X4`] the size of the main flash is returned, the CCFG is
+/// outside of it.
+pub fn read_flash_size<P>(device: &mut Device<P>) -> Result<u32>
+where
+    P: serial::SerialPort,
+{
+    let mut reg = [0u8; REG32_SIZE];
+    match device.family().descriptor().flash_size {
+        FlashSizeSource::Fixed(flash_size) => Ok(flash_size),
+        FlashSizeSource::Diecfg0(addr) => {
+            device.memory_read_32(addr, &mut reg)?;
             let flash_ctrl = u32::from_le_bytes(reg);
             let flash_size = (flash_ctrl >> 4) & 0x07;
             match flash_size {
@@ -231,35 +1438,36 @@ where
                 _ => Ok(0x10000), // All invalid values are interpreted as 64 KB
             }
         }
-        Family::CC26X0 | Family::CC26X2 => {
+        FlashSizeSource::Sectors(addr) => {
+            device.memory_read_32(addr, &mut reg)?;
             let mut flash_size = u32::from_le_bytes(reg);
             flash_size &= 0xFF;
 
-            Ok(flash_size * device.family().sector_size())
+            Ok(flash_size * device.sector_size())
         }
     }
 }
 
 /// Read IEEE 802.15.4g MAC address.
+///
+/// # Errors
+///
+/// Returns [`Error::UnsupportedFamily`] on [`Family::CC26X4`].
 pub fn read_ieee_address<P>(
     device: &mut Device<P>,
-) -> io::Result<([u8; 8], [u8; 8])>
+) -> Result<([u8; 8], [u8; 8])>
 where
     P: serial::SerialPort,
 {
-    let primary_addr_offset = match device.family() {
-        Family::CC2538 => 0x00280028,
-        Family::CC26X0 | Family::CC26X2 => CC26XX_FCFG1_O_MAC_15_4_0,
-    };
+    let family = device.family();
+    if family.descriptor().ieee_primary.is_none() {
+        return Err(Error::UnsupportedFamily(family));
+    }
 
-    let secondary_addr_offset = match device.family() {
-        Family::CC2538 => 0x0027ffcc,
-        Family::CC26X0 | Family::CC26X2 => {
-            let ccfg_offset = read_flash_size(device)? - CCFG_SIZE as u32;
-
-            ccfg_offset + 0x20
-        }
-    };
+    let flash_size = read_flash_size(device)?;
+    let (primary_addr_offset, secondary_addr_offset) = family
+        .ieee_addresses(flash_size)
+        .ok_or(Error::UnsupportedFamily(family))?;
 
     let mut primary = [0u8; 8];
     device.memory_read_32(primary_addr_offset, &mut primary)?;
@@ -270,13 +1478,629 @@ where
     Ok((primary, secondary))
 }
 
-pub fn status_code_to_str(ret: u8) -> &'static str {
-    match ret {
-        COMMAND_RET_SUCCESS => "COMMAND_RET_SUCCESS",
-        COMMAND_RET_UNKNOWN_CMD => "COMMAND_RET_UNKNOWN_CMD",
-        COMMAND_RET_INVALID_CMD => "COMMAND_RET_INVALID_CMD",
-        COMMAND_RET_INVALID_ADR => "COMMAND_RET_INVALID_ADR",
-        COMMAND_RET_FLASH_FAIL => "COMMAND_RET_FLASH_FAIL",
-        _ => "Unknown",
+/// Read the BLE addresses, the factory (primary) one from the FCFG and the
+/// override (secondary) one from the CCFG, [`INVALID_ADDR`] if it's unset.
+///
+/// The bytes are in memory order, only the first 6 bytes are used.
+///
+/// # Errors
+///
+/// Returns [`Error::UnsupportedFamily`] if the family doesn't have BLE or a
+/// CCFG of [`CCFG_SIZE`] bytes.
+pub fn read_ble_address<P>(device: &mut Device<P>) -> Result<([u8; 8], [u8; 8])>
+where
+    P: serial::SerialPort,
+{
+    let family = device.family();
+    if family.ccfg_size() != CCFG_SIZE as u32 {
+        return Err(Error::UnsupportedFamily(family));
+    }
+
+    let mut primary = [0u8; 8];
+    device.memory_read_32(CC26XX_FCFG1_O_MAC_BLE_0, &mut primary)?;
+
+    let secondary = read_ccfg(device)?.ble_address();
+
+    Ok((primary, secondary))
+}
+
+/// Read the CCFG of the device.
+///
+/// # Errors
+///
+/// Returns [`Error::UnsupportedFamily`] if the family doesn't have a CCFG
+/// with the layout of [`Ccfg`], e.g.: [`Family::CC2538`] or
+/// [`Family::CC26X4`].
+pub fn read_ccfg<P>(device: &mut Device<P>) -> Result<Ccfg>
+where
+    P: serial::SerialPort,
+{
+    let family = device.family();
+    if family.ccfg_size() != CCFG_SIZE as u32 {
+        return Err(Error::UnsupportedFamily(family));
+    }
+
+    let flash_size = read_flash_size(device)?;
+    let address = family.ccfg_address(flash_size).unwrap();
+    let mut data = [0u8; CCFG_SIZE];
+    device.memory_read_32(address, &mut data)?;
+
+    Ccfg::parse(&data)
+}
+
+/// Read the CCA (Customer Configuration Area) of the device.
+///
+/// # Errors
+///
+/// Returns [`Error::UnsupportedFamily`] on families other than
+/// [`Family::CC2538`].
+pub fn read_cca<P>(device: &mut Device<P>) -> Result<Cca>
+where
+    P: serial::SerialPort,
+{
+    let family = device.family();
+    let cca = read_flash_layout(device)?
+        .cca()
+        .ok_or(Error::UnsupportedFamily(family))?;
+
+    let mut data = [0u8; CCA_SIZE];
+    device.memory_read_32(cca.start, &mut data)?;
+
+    Cca::parse(&data)
+}
+
+/// Check that none of the flash pages containing the `len` bytes starting
+/// at `address` are write protected, before erasing or writing them.
+///
+/// The protection is read from the `CCFG_PROT_*` registers of the CCFG on
+/// CC13xx/CC26xx and from the lock bits of the CCA on [`Family::CC2538`],
+/// nothing is checked on other families.
+///
+/// # Errors
+///
+/// Returns [`Error::ProtectedPages`] with the protected pages of the range.
+pub fn check_write_protection<P>(
+    device: &mut Device<P>,
+    address: u32,
+    len: u32,
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let family = device.family();
+    let pages = device.pages_in_range(address, len)?;
+    let protected: Vec<Page> = if family == Family::CC2538 {
+        let cca = read_cca(device)?;
+        pages
+            .filter(|page| page.index < 256 && cca.page_locked(page.index))
+            .collect()
+    } else if family.ccfg_size() == CCFG_SIZE as u32 {
+        let ccfg = read_ccfg(device)?;
+        pages
+            .filter(|page| {
+                page.index < PROT_SECTORS && ccfg.sector_protected(page.index)
+            })
+            .collect()
+    } else {
+        return Ok(());
+    };
+
+    if !protected.is_empty() {
+        return Err(Error::ProtectedPages(protected));
+    }
+
+    Ok(())
+}
+
+/// Sanity check the Cortex-M vector table at the start of an `image` flashed
+/// at the flash base of the `layout`: the initial stack pointer must point
+/// into the SRAM and the reset vector to a Thumb address in the flash.
+///
+/// Catches obviously wrong files before erasing anything, e.g.: an ELF or
+/// HEX file flashed as a raw binary, or an image built for another family.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidVectorTable`] with the first problem found.
+pub fn check_vector_table(layout: &FlashLayout, image: &[u8]) -> Result<()> {
+    if image.len() < 8 {
+        return Err(Error::InvalidVectorTable("image too short"));
+    }
+    let stack_pointer =
+        u32::from_le_bytes([image[0], image[1], image[2], image[3]]);
+    let reset_vector =
+        u32::from_le_bytes([image[4], image[5], image[6], image[7]]);
+
+    // The stack grows down, it usually starts at the end of the SRAM.
+    let in_sram = layout.family().memory_map().iter().any(|region| {
+        region.kind == MemoryKind::Sram
+            && stack_pointer > region.start
+            && stack_pointer - region.start <= region.len
+    });
+    if !in_sram || stack_pointer % 4 != 0 {
+        return Err(Error::InvalidVectorTable(
+            "initial stack pointer outside of the SRAM",
+        ));
+    }
+    if reset_vector & 1 == 0 {
+        return Err(Error::InvalidVectorTable(
+            "reset vector isn't a Thumb address",
+        ));
+    }
+    if !layout.flash().contains(&(reset_vector & !1)) {
+        return Err(Error::InvalidVectorTable(
+            "reset vector outside of the flash",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Write only the CCFG of the device.
+///
+/// The sector holding the CCFG is erased and programmed again, the rest of
+/// the sector is read first and written back. The CCFG is sent in its own
+/// transfer that doesn't expect an ACK, the device may lock itself once
+/// it's written.
+///
+/// # Errors
+///
+/// Returns [`Error::UnsupportedFamily`] if the family doesn't have a CCFG
+/// of [`CCFG_SIZE`] bytes, e.g.: [`Family::CC2538`] or
+/// [`Family::CC26X4`].
+pub fn write_ccfg<P>(
+    device: &mut Device<P>,
+    ccfg: &[u8; CCFG_SIZE],
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let family = device.family();
+    if family.ccfg_size() != CCFG_SIZE as u32 {
+        return Err(Error::UnsupportedFamily(family));
+    }
+
+    let layout = read_flash_layout(device)?;
+    let ccfg_range = layout.ccfg().unwrap();
+    let sector_start = ccfg_range.start
+        - (ccfg_range.start - layout.flash().start) % layout.sector_size();
+    let ccfg_offset = (ccfg_range.start - sector_start) as usize;
+
+    let mut sector = vec![0u8; ccfg_offset + CCFG_SIZE];
+    memory_read_32(device, sector_start, &mut sector[..ccfg_offset])?;
+    sector[ccfg_offset..].copy_from_slice(ccfg);
+
+    log::debug!("Erasing the CCFG sector {:#X}", sector_start);
+    erase_flash_range(
+        device,
+        sector_start,
+        sector.len() as u32,
+        &CancelToken::new(),
+        |_| (),
+    )?;
+
+    let mut transfers = Vec::with_capacity(2);
+    if ccfg_offset != 0 {
+        transfers.push(Transfer {
+            data: &sector[..ccfg_offset],
+            start_address: sector_start,
+            expect_ack: true,
+        });
+    }
+    transfers.push(Transfer {
+        data: &sector[ccfg_offset..],
+        start_address: ccfg_range.start,
+        expect_ack: false,
+    });
+    write_flash_range(device, &transfers, &CancelToken::new(), |_| ())?;
+
+    Ok(())
+}
+
+/// Erase the whole flash with [`Device::bank_erase`], optionally keeping
+/// the CCFG.
+///
+/// When `keep_ccfg` is set the CCFG is read before the erase and written
+/// back afterwards, otherwise the bootloader and backdoor configuration
+/// return to their erased (default) values.
+///
+/// # Errors
+///
+/// - Returns [`Error::UnsupportedCommand`] if the family doesn't support
+///   [`CommandId::BankErase`].
+/// - Returns [`Error::UnsupportedFamily`] if `keep_ccfg` is set and the
+///   family doesn't have a CCFG of [`CCFG_SIZE`] bytes.
+pub fn bank_erase<P>(device: &mut Device<P>, keep_ccfg: bool) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let ccfg = if keep_ccfg {
+        Some(read_ccfg(device)?.to_bytes())
+    } else {
+        None
+    };
+
+    device.bank_erase()?;
+    let ret = device.get_status()?;
+    if ret != StatusCode::Success {
+        return Err(Error::Status(ret));
+    }
+
+    if let Some(ccfg) = ccfg {
+        log::debug!("Restoring the CCFG");
+        let layout = read_flash_layout(device)?;
+        let transfer = Transfer {
+            data: &ccfg,
+            start_address: layout.ccfg().unwrap().start,
+            expect_ack: false,
+        };
+        write_flash_range(device, &[transfer], &CancelToken::new(), |_| ())?;
+    }
+
+    Ok(())
+}
+
+/// Write the secondary IEEE 802.15.4 address on the CCFG, it overrides the
+/// factory (primary) address.
+///
+/// The bytes are in memory order, as returned by [`read_ieee_address`].
+/// The CCFG is rewritten with [`write_ccfg`].
+///
+/// # Errors
+///
+/// Returns [`Error::UnsupportedFamily`] if the family doesn't have a CCFG
+/// of [`CCFG_SIZE`] bytes.
+pub fn write_secondary_ieee_address<P>(
+    device: &mut Device<P>,
+    address: [u8; 8],
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let mut ccfg = read_ccfg(device)?;
+    ccfg.set_ieee_address(address);
+    write_ccfg(device, &ccfg.to_bytes())
+}
+
+/// Write the BLE address override on the CCFG, BLE stacks use it instead of
+/// the factory address.
+///
+/// The bytes are in memory order, only the first 6 bytes are used by the
+/// BLE stacks. The CCFG is rewritten with [`write_ccfg`].
+///
+/// # Errors
+///
+/// Returns [`Error::UnsupportedFamily`] if the family doesn't have a CCFG
+/// of [`CCFG_SIZE`] bytes.
+pub fn write_ble_address<P>(
+    device: &mut Device<P>,
+    address: [u8; 8],
+) -> Result<()>
+where
+    P: serial::SerialPort,
+{
+    let mut ccfg = read_ccfg(device)?;
+    ccfg.set_ble_address(address);
+    write_ccfg(device, &ccfg.to_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        protocol,
+        trace::{Capture, ReplayPort},
+        AckTimeouts, DeviceBuilder,
+    };
+
+    #[test]
+    fn test_is_sampled() {
+        let sampled = |percent| {
+            (0..40)
+                .filter(|i| is_sampled(*i, percent))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sampled(10), [0, 10, 20, 30]);
+        assert_eq!(sampled(25), [0, 4, 8, 12, 16, 20, 24, 28, 32, 36]);
+        assert_eq!(sampled(100).len(), 40);
+        assert!(sampled(0).is_empty());
+    }
+
+    #[test]
+    fn test_write_progress_percent() {
+        let progress = |bytes_done, bytes_total| WriteProgress {
+            transfer: 0,
+            transfers: 1,
+            chunk: 0,
+            address: 0,
+            bytes_done,
+            bytes_total,
+        };
+        assert_eq!(progress(0, 4096).percent(), 0.0);
+        assert_eq!(progress(1024, 4096).percent(), 25.0);
+        assert_eq!(progress(0, 0).percent(), 100.0);
+    }
+
+    #[test]
+    fn test_check_vector_table() {
+        let layout = FlashLayout::new(Family::CC26X2, 0x58000);
+        let mut image = [0u8; 8];
+        image[..4].copy_from_slice(&0x2001_4000u32.to_le_bytes());
+        image[4..].copy_from_slice(&0x0000_10C1u32.to_le_bytes());
+        assert!(check_vector_table(&layout, &image).is_ok());
+
+        image[4..].copy_from_slice(&0x0000_10C0u32.to_le_bytes());
+        assert!(check_vector_table(&layout, &image).is_err());
+        image[4..].copy_from_slice(&0x0006_0001u32.to_le_bytes());
+        assert!(check_vector_table(&layout, &image).is_err());
+        // An ELF file flashed as a raw binary.
+        assert!(check_vector_table(&layout, b"\x7FELF\x01\x01\x01\0").is_err());
+    }
+
+    #[test]
+    fn test_skip_blank_chunks() {
+        let mut data = vec![0xFFu8; 5 * MAX_BYTES_PER_TRANSFER];
+        data[0] = 0;
+        data[3 * MAX_BYTES_PER_TRANSFER + 1] = 0;
+        data[4 * MAX_BYTES_PER_TRANSFER] = 0;
+        let ccfg = [0xFFu8; 88];
+        let transfers = [
+            Transfer {
+                data: &data,
+                start_address: 0x1000,
+                expect_ack: true,
+            },
+            Transfer {
+                data: &ccfg,
+                start_address: 0x57FA8,
+                expect_ack: false,
+            },
+        ];
+
+        let split = skip_blank_chunks(&transfers);
+        let split = split
+            .iter()
+            .map(|t| (t.start_address, t.data.len(), t.expect_ack))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            split,
+            [
+                (0x1000, MAX_BYTES_PER_TRANSFER, true),
+                (
+                    0x1000 + 3 * MAX_BYTES_PER_TRANSFER as u32,
+                    2 * MAX_BYTES_PER_TRANSFER,
+                    true
+                ),
+                (0x57FA8, 88, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_flash_progress() {
+        // The range spans the end of a sector and the start of the next.
+        let capture = Capture::new()
+            .memory_read(0x1FFC, &[1, 2, 3, 4])
+            .memory_read(0x2000, &[5, 6, 7, 8]);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+        let mut data = Vec::new();
+        let mut progress = Vec::new();
+        read_flash(&mut device, 0x1FFC..0x2004, &mut data, |p| {
+            progress.push(p)
+        })
+        .unwrap();
+        assert_eq!(data, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(device.port().is_finished());
+
+        let progress = progress
+            .iter()
+            .map(|p| (p.sector, p.sectors, p.address, p.bytes_done))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            progress,
+            [(0, 2, 0x1FFC, 0), (1, 2, 0x2000, 4), (2, 2, 0x2004, 8)]
+        );
+    }
+
+    #[test]
+    fn test_pages_below_flash() {
+        // The flash of the CC2538 starts at 0x00200000.
+        let mut device =
+            Device::new(Capture::new().port(), Family::CC2538).unwrap();
+        assert!(matches!(
+            check_write_protection(&mut device, 0x0, 0x800),
+            Err(Error::AddressOutOfRange { address: 0x0, .. })
+        ));
+        assert!(matches!(
+            backup_flash(
+                &mut device,
+                &[Transfer {
+                    data: &[0; 4],
+                    start_address: 0x0,
+                    expect_ack: true,
+                }],
+                &mut Vec::new(),
+                |_| (),
+            ),
+            Err(Error::AddressOutOfRange { .. })
+        ));
+        assert!(device.port().is_finished());
+    }
+
+    /// A device whose writes time out quickly, to inject link errors.
+    fn recovery_device(capture: Capture) -> Device<ReplayPort> {
+        DeviceBuilder::new(Family::CC26X2)
+            .ack_timeouts(AckTimeouts {
+                write: Duration::from_millis(10),
+                ..Default::default()
+            })
+            .build(capture.port())
+            .unwrap()
+    }
+
+    /// A `COMMAND_SEND_DATA` of the `data` never acknowledged.
+    fn send_data_lost(capture: Capture, data: &[u8]) -> Capture {
+        let pkt = protocol::command_packet(CommandId::SendData.value(), data)
+            .unwrap();
+        capture.tx(&pkt)
+    }
+
+    #[test]
+    fn test_write_recovery() {
+        let data = (0..MAX_BYTES_PER_TRANSFER + 4)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let (first, second) = data.split_at(MAX_BYTES_PER_TRANSFER);
+        let capture = Capture::new()
+            .download(0x1000, data.len())
+            .command(CommandId::SendData, first)
+            .success();
+        // The second chunk is lost, the device is synchronized again and
+        // the transfer resumed from it.
+        let capture = send_data_lost(capture, second)
+            .tx(&[0x03, 0x00, 0x00])
+            .rx(&[0x00, 0x33])
+            .write(0x1000 + MAX_BYTES_PER_TRANSFER as u32, second);
+        let mut device = recovery_device(capture);
+
+        let recovery = Recovery {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        let stats = write_flash_range_with_recovery(
+            &mut device,
+            &[Transfer {
+                data: &data,
+                start_address: 0x1000,
+                expect_ack: true,
+            }],
+            &CancelToken::new(),
+            &recovery,
+            |_| (),
+        )
+        .unwrap();
+        assert_eq!(stats.bytes_written, data.len());
+        assert_eq!(stats.chunks, 2);
+        assert_eq!(stats.retransmissions, 1);
+        assert_eq!(device.diagnostics().resyncs, 1);
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_write_recovery_exhausted() {
+        let data = [0x55u8; 8];
+        let capture = Capture::new().download(0x1000, data.len());
+        let capture = send_data_lost(capture, &data)
+            .tx(&[0x03, 0x00, 0x00])
+            .rx(&[0x00, 0x33])
+            .download(0x1000, data.len());
+        let capture = send_data_lost(capture, &data);
+        let mut device = recovery_device(capture);
+
+        let recovery = Recovery {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        let ret = write_flash_range_with_recovery(
+            &mut device,
+            &[Transfer {
+                data: &data,
+                start_address: 0x1000,
+                expect_ack: true,
+            }],
+            &CancelToken::new(),
+            &recovery,
+            |_| (),
+        );
+        assert!(matches!(ret, Err(Error::Timeout)));
+        assert_eq!(device.diagnostics().resyncs, 1);
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_detect_family() {
+        let detect = |device_id: u32| {
+            let capture = Capture::new()
+                .command(CommandId::GetChipId, &[])
+                .response(&[0x00, 0x00, 0xB9, 0x9A])
+                .memory_read(
+                    CC26XX_FCFG1_O_ICEPICK_DEVICE_ID,
+                    &device_id.to_le_bytes(),
+                );
+            let mut device =
+                Device::new(capture.port(), Family::CC26X2).unwrap();
+            let family = detect_family(&mut device).unwrap();
+            assert!(device.port().is_finished());
+            family
+        };
+        // CC2650, PG2.3.
+        assert_eq!(detect(0x7B99_A02F), Family::CC26X0);
+        // CC2640R2F.
+        assert_eq!(detect(0xBB99_A02F), Family::CC26X0R2);
+    }
+
+    #[test]
+    fn test_device_info_tm4c() {
+        // The TM4C has neither COMMAND_GET_CHIP_ID nor COMMAND_MEMORY_READ.
+        let mut device =
+            Device::new(Capture::new().port(), Family::TM4C).unwrap();
+        let info = read_device_info(&mut device).unwrap();
+        assert_eq!(info.chip.family, Family::TM4C);
+        assert_eq!(info.chip.flash, 0x4_0000);
+        assert_eq!(info.security, SecurityState::Unknown);
+        assert!(!info.memory_readable());
+        assert!(device.port().is_finished());
+    }
+
+    #[test]
+    fn test_verify_sampled() {
+        let data = [0u8; 3 * 0x2000];
+        let transfers = [Transfer {
+            data: &data,
+            start_address: 0x0,
+            expect_ack: true,
+        }];
+        let crc = crc32(&data[..0x2000]);
+
+        // The first and last sectors are sampled.
+        let capture = Capture::new()
+            .crc32(0x0, 0x2000, crc)
+            .crc32(0x4000, 0x2000, crc);
+        let mut device = Device::new(capture.port(), Family::CC26X2).unwrap();
+        verify_flash_range_sampled(&mut device, &transfers, 50).unwrap();
+        assert!(device.port().is_finished());
+
+        for percent in &[0, 101] {
+            assert!(matches!(
+                verify_flash_range_sampled(&mut device, &transfers, *percent),
+                Err(Error::InvalidPercentage(p)) if p == *percent
+            ));
+        }
+    }
+
+    #[test]
+    fn test_deadline_not_recovered() {
+        let mut device =
+            Device::new(Capture::new().port(), Family::CC26X2).unwrap();
+        let transfer = Transfer {
+            data: &[0; 4],
+            start_address: 0x0,
+            expect_ack: true,
+        };
+        let recovery = Recovery {
+            max_attempts: 3,
+            ..Default::default()
+        };
+        let ret = device.with_deadline(Duration::from_millis(0), |device| {
+            write_flash_range_with_recovery(
+                device,
+                &[transfer],
+                &CancelToken::new(),
+                &recovery,
+                |_| (),
+            )
+        });
+        assert!(matches!(ret, Err(Error::DeadlineExceeded)));
+        assert_eq!(device.diagnostics().resyncs, 0);
     }
 }
@@ -0,0 +1,77 @@
+// Copyright 2021 Locha Mesh Developers <contact@locha.io>
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    error,
+    fmt::{self, Display, Formatter},
+    ops::Range,
+};
+
+/// A programmed range whose contents on the device don't match the image.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Mismatch {
+    /// Start address of the range.
+    pub address: u32,
+    /// Length of the range in bytes.
+    pub len: u32,
+    /// CRC32 of the image.
+    pub expected: u32,
+    /// CRC32 of the flash contents.
+    pub actual: u32,
+    /// Address of the first byte that differs, only known when the flash
+    /// was read back.
+    pub first_difference: Option<u32>,
+}
+
+impl Mismatch {
+    /// The address range that doesn't match.
+    #[inline]
+    pub fn range(&self) -> Range<u32> {
+        self.address..self.address + self.len
+    }
+}
+
+/// The flash contents don't match the image, see
+/// [`util::verify_flash_range`](crate::util::verify_flash_range).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VerificationError {
+    /// The ranges that don't match, sorted by address.
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Display for VerificationError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> fmt::Result {
+        write!(fmt, "flash contents differ at")?;
+        for (i, mismatch) in self.mismatches.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(
+                fmt,
+                "{}{:#X}..{:#X} (CRC32 {:#010X}, expected {:#010X}",
+                separator,
+                mismatch.address,
+                mismatch.range().end,
+                mismatch.actual,
+                mismatch.expected
+            )?;
+            if let Some(address) = mismatch.first_difference {
+                write!(fmt, ", first difference at {:#X}", address)?;
+            }
+            write!(fmt, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl error::Error for VerificationError {}